@@ -0,0 +1,322 @@
+//! # Blind Schnorr Signatures
+//!
+//! This module provides a blind variant of Schnorr signatures over the same prime order
+//! subgroup used by the Chaum-Pedersen protocol. A signer holding a secret key `x` (with public
+//! key `y = g^x mod p`) can sign a message without ever seeing it, and without being able to
+//! link the resulting signature to the signing session it took part in.
+//!
+//! ## Usage
+//!
+//! 1. The signer calls [`BlindSigner::commit`], keeps the nonce secret and sends the commitment `R`.
+//! 2. The requester calls [`blind`] with `R` and its message, and sends the blinded challenge.
+//! 3. The signer answers with [`BlindSigner::sign_blinded`], consuming the nonce.
+//! 4. The requester calls [`unblind`] to obtain a [`BlindSignature`], verifiable with [`verify`].
+//!
+//! Notice that a signer nonce must never be used for more than one blinded challenge, as that
+//! would leak the signer's secret key.
+use anyhow::{anyhow, Result};
+use num_bigint::BigInt;
+use sha3::{Digest, Sha3_256};
+
+use crate::{modulo, random_scalar, transcript::Transcript, Parameters};
+
+/// Domain separation label for hashing commitments and messages into challenges.
+const BLIND_SIGNATURE_LABEL: &[u8] = b"chaum-pedersen/blind-schnorr-signature/v1";
+
+/// Protocol label of the transcripts deriving signer secret keys from seeds.
+const SIGNER_KEY_LABEL: &[u8] = b"chaum-pedersen/blind-schnorr-signer-key/v1";
+
+/// A blind Schnorr signature `(r, s)` on a message, where `r` is the (blinded) commitment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlindSignature {
+    pub r: BigInt,
+    pub s: BigInt,
+}
+
+/// The secret nonce and public commitment generated by the signer for a single signing session.
+pub struct SignerCommitment {
+    pub(crate) k: BigInt,
+    pub(crate) r: BigInt,
+}
+
+impl SignerCommitment {
+    pub fn new(k: BigInt, r: BigInt) -> Self {
+        Self { k, r }
+    }
+
+    pub fn get_nonce(&self) -> &BigInt {
+        &self.k
+    }
+
+    pub fn get_commitment(&self) -> &BigInt {
+        &self.r
+    }
+}
+
+/// Requester side state, which must be kept until the signer answers the blinded challenge.
+pub struct BlindingFactors {
+    pub(crate) alpha: BigInt,
+    pub(crate) blinded_r: BigInt,
+}
+
+/// Signer of blind Schnorr signatures.
+pub struct BlindSigner {
+    // Cryptographic parameters
+    parameters: Parameters,
+    // Signing secret key
+    secret_key: BigInt,
+    // Signing public key, `g^secret_key mod p`
+    public_key: BigInt,
+}
+
+impl BlindSigner {
+    /// Creates a new signer, with a freshly generated random key pair.
+    pub fn new(parameters: Parameters) -> Self {
+        let secret_key = random_scalar(&parameters.q);
+        Self::from_secret_key(parameters, secret_key)
+    }
+
+    /// Creates a signer whose secret key is derived from `seed`, e.g. the contents of a key file, so that
+    /// the same seed always yields the same key pair for the same parameters.
+    pub fn from_seed(parameters: Parameters, seed: &[u8]) -> Self {
+        let mut transcript = Transcript::new(SIGNER_KEY_LABEL);
+        transcript.append_parameters(&parameters);
+        transcript.append_message(b"seed", seed);
+        let secret_key = loop {
+            let secret_key = transcript.challenge_scalar(b"secret_key", &parameters.q);
+            if secret_key != BigInt::from(0) {
+                break secret_key;
+            }
+        };
+        Self::from_secret_key(parameters, secret_key)
+    }
+
+    /// Creates a new signer from an existing secret key.
+    pub fn from_secret_key(parameters: Parameters, secret_key: BigInt) -> Self {
+        let public_key = parameters.g.modpow(&secret_key, &parameters.p);
+        Self {
+            parameters,
+            secret_key,
+            public_key,
+        }
+    }
+
+    pub fn public_key(&self) -> &BigInt {
+        &self.public_key
+    }
+
+    /// Generates a fresh nonce `k` and its commitment `R = g^k mod p`.
+    pub fn commit(&self) -> SignerCommitment {
+        let k = random_scalar(&self.parameters.q);
+        let r = self.parameters.g.modpow(&k, &self.parameters.p);
+        SignerCommitment { k, r }
+    }
+
+    /// Answers a blinded challenge `c`, with `s = k + c * x (mod q)`.
+    ///
+    /// The commitment is consumed, so that its nonce can't be reused.
+    pub fn sign_blinded(&self, commitment: SignerCommitment, blinded_challenge: &BigInt) -> BigInt {
+        modulo(
+            &(commitment.k + blinded_challenge * &self.secret_key),
+            &self.parameters.q,
+        )
+    }
+}
+
+/// Blinds a signer commitment `R` for a `message`.
+///
+/// It picks random blinding factors `alpha` and `beta`, computes `R' = R * g^alpha * y^beta mod p`,
+/// `c' = H(R', message)`, and returns the blinded challenge `c = c' + beta (mod q)`, which is what
+/// the signer gets to see, together with the factors needed to later unblind its answer.
+pub fn blind(
+    parameters: &Parameters,
+    public_key: &BigInt,
+    r: &BigInt,
+    message: &[u8],
+) -> (BlindingFactors, BigInt) {
+    let alpha = random_scalar(&parameters.q);
+    let beta = random_scalar(&parameters.q);
+
+    let blinded_r =
+        (r * parameters.g.modpow(&alpha, &parameters.p) * public_key.modpow(&beta, &parameters.p))
+            % &parameters.p;
+    let challenge = hash_to_challenge(parameters, &blinded_r, message);
+    let blinded_challenge = modulo(&(challenge + beta), &parameters.q);
+
+    (BlindingFactors { alpha, blinded_r }, blinded_challenge)
+}
+
+/// Unblinds the signer answer `s`, producing the signature `(R', s + alpha (mod q))`.
+pub fn unblind(parameters: &Parameters, factors: BlindingFactors, s: &BigInt) -> BlindSignature {
+    BlindSignature {
+        r: factors.blinded_r,
+        s: modulo(&(s + factors.alpha), &parameters.q),
+    }
+}
+
+/// Verifies a blind signature on a `message`, by checking that `g^s = R * y^H(R, message) mod p`.
+///
+/// The commitment `R` must lie in `[1, p)` and `s` in `[0, q)`, so that a signature has a single
+/// encoding.
+pub fn verify(
+    parameters: &Parameters,
+    public_key: &BigInt,
+    message: &[u8],
+    signature: &BlindSignature,
+) -> Result<()> {
+    let zero = BigInt::from(0);
+    if signature.r <= zero
+        || signature.r >= parameters.p
+        || signature.s < zero
+        || signature.s >= parameters.q
+    {
+        return Err(anyhow!(
+            "Failed to verify blind signature, signature out of range"
+        ));
+    }
+    let challenge = hash_to_challenge(parameters, &signature.r, message);
+    let lhs = parameters.g.modpow(&signature.s, &parameters.p);
+    let rhs = (&signature.r * public_key.modpow(&challenge, &parameters.p)) % &parameters.p;
+
+    if lhs != rhs {
+        return Err(anyhow!(
+            "Failed to verify blind signature, invalid signature"
+        ));
+    }
+    Ok(())
+}
+
+/// Computes `H(label || len(R) || R || message) (mod q)`, using `SHA3-256`.
+fn hash_to_challenge(parameters: &Parameters, r: &BigInt, message: &[u8]) -> BigInt {
+    let r_bytes = r.to_bytes_be().1;
+    let mut hasher = Sha3_256::new();
+    hasher.update(BLIND_SIGNATURE_LABEL);
+    hasher.update((r_bytes.len() as u64).to_be_bytes());
+    hasher.update(&r_bytes);
+    hasher.update(message);
+    let digest = hasher.finalize();
+    BigInt::from_bytes_be(num_bigint::Sign::Plus, &digest) % &parameters.q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_PARAMS;
+
+    #[test]
+    fn test_blind_signature_in_success_case() {
+        let parameters = DEFAULT_PARAMS.clone();
+        let signer = BlindSigner::new(parameters.clone());
+        let message = b"credential serial number";
+
+        let commitment = signer.commit();
+        let (factors, blinded_challenge) = blind(
+            &parameters,
+            signer.public_key(),
+            commitment.get_commitment(),
+            message,
+        );
+        let s = signer.sign_blinded(commitment, &blinded_challenge);
+        let signature = unblind(&parameters, factors, &s);
+
+        assert!(verify(&parameters, signer.public_key(), message, &signature).is_ok());
+    }
+
+    #[test]
+    fn test_blind_signature_out_of_range_fails() {
+        let parameters = DEFAULT_PARAMS.clone();
+        let signer = BlindSigner::new(parameters.clone());
+        let message = b"credential serial number";
+
+        let commitment = signer.commit();
+        let (factors, blinded_challenge) = blind(
+            &parameters,
+            signer.public_key(),
+            commitment.get_commitment(),
+            message,
+        );
+        let s = signer.sign_blinded(commitment, &blinded_challenge);
+        let signature = unblind(&parameters, factors, &s);
+
+        // the same signature, encoded with `s + q` or `R + p`, is rejected
+        for signature in [
+            BlindSignature {
+                s: &signature.s + &parameters.q,
+                ..signature.clone()
+            },
+            BlindSignature {
+                r: &signature.r + &parameters.p,
+                ..signature.clone()
+            },
+            BlindSignature {
+                r: BigInt::from(0),
+                ..signature.clone()
+            },
+        ] {
+            assert!(
+                verify(&parameters, signer.public_key(), message, &signature)
+                    .unwrap_err()
+                    .to_string()
+                    .contains("out of range")
+            );
+        }
+    }
+
+    #[test]
+    fn test_signer_from_seed_is_stable() {
+        let parameters = DEFAULT_PARAMS.clone();
+        let signer = BlindSigner::from_seed(parameters.clone(), &[7u8; 32]);
+        assert_eq!(
+            signer.public_key(),
+            BlindSigner::from_seed(parameters.clone(), &[7u8; 32]).public_key()
+        );
+        assert_ne!(
+            signer.public_key(),
+            BlindSigner::from_seed(parameters, &[8u8; 32]).public_key()
+        );
+    }
+
+    #[test]
+    fn test_blind_signature_is_unlinkable_to_signing_session() {
+        let parameters = DEFAULT_PARAMS.clone();
+        let signer = BlindSigner::new(parameters.clone());
+        let message = b"credential serial number";
+
+        let commitment = signer.commit();
+        let r = commitment.get_commitment().clone();
+        let (factors, blinded_challenge) = blind(&parameters, signer.public_key(), &r, message);
+        let s = signer.sign_blinded(commitment, &blinded_challenge);
+        let signature = unblind(&parameters, factors, &s);
+
+        // the signer's view `(R, s)` differs from the final signature `(R', s')`
+        assert_ne!(signature.r, r);
+        assert_ne!(signature.s, s);
+    }
+
+    #[test]
+    fn test_blind_signature_fails_for_different_message_or_key() {
+        let parameters = DEFAULT_PARAMS.clone();
+        let signer = BlindSigner::new(parameters.clone());
+        let other_signer = BlindSigner::new(parameters.clone());
+        let message = b"credential serial number";
+
+        let commitment = signer.commit();
+        let (factors, blinded_challenge) = blind(
+            &parameters,
+            signer.public_key(),
+            commitment.get_commitment(),
+            message,
+        );
+        let s = signer.sign_blinded(commitment, &blinded_challenge);
+        let signature = unblind(&parameters, factors, &s);
+
+        assert!(verify(
+            &parameters,
+            signer.public_key(),
+            b"other message",
+            &signature
+        )
+        .is_err());
+        assert!(verify(&parameters, other_signer.public_key(), message, &signature).is_err());
+    }
+}
@@ -8,15 +8,33 @@
 //!
 //! To use this module, create an instance of the `ChaumPedersen` struct and utilize its methods
 //! to perform cryptographic operations as per the Chaum-Pedersen protocol.
+//!
+//! Challenges, both for the interactive and the non-interactive (Fiat-Shamir) flows, are derived from a
+//! [`Transcript`] bound to the protocol, the parameters, the service domain, the user and its public key.
 use anyhow::{anyhow, Result};
 use num_bigint::{BigInt, RandBigInt, ToBigInt};
 use rand::{rngs::StdRng, SeedableRng};
 
-use crate::{Parameters, DEFAULT_PARAMS};
+use crate::{random_scalar, transcript::Transcript, Parameters, DEFAULT_PARAMS};
 
 pub type RandomValue = BigInt;
 pub type Solution = BigInt;
 
+/// Protocol label absorbed by every Chaum-Pedersen authentication transcript.
+pub const PROTOCOL_LABEL: &[u8] = b"chaum-pedersen/zkp-auth/v1";
+
+/// Default service domain, absorbed by every proof transcript so that proofs can't be replayed across services.
+pub const DEFAULT_DOMAIN: &str = "zkp_auth.Auth";
+
+/// A non-interactive Chaum-Pedersen proof, made of the commitments `r1`, `r2` and the response `s`.
+/// The challenge is recomputed by the verifier from the transcript.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NonInteractiveProof {
+    pub r1: BigInt,
+    pub r2: BigInt,
+    pub s: BigInt,
+}
+
 pub struct ChaumPedersenExponents {
     pub(crate) r1: BigInt,
     pub(crate) r2: BigInt,
@@ -76,6 +94,58 @@ pub trait ChaumPedersenInterface {
         s: &BigInt,
         c: &BigInt,
     ) -> Result<()>;
+
+    /// Creates a transcript bound to the protocol, the parameters and the proof context.
+    ///
+    /// # Arguments
+    /// * `domain`: A label identifying the service the proof is meant for.
+    /// * `user`: The user name.
+    /// * `y1`, `y2`: The user's public key.
+    ///
+    /// # Returns
+    /// A `Transcript`, from which challenges can be derived.
+    fn transcript(&self, domain: &[u8], user: &str, y1: &BigInt, y2: &BigInt) -> Transcript;
+
+    /// Generates the verifier challenge of an interactive proof.
+    ///
+    /// The commitments and a fresh random verifier nonce are absorbed by the transcript, so that the
+    /// challenge is both bound to the proof context and unpredictable for the prover.
+    ///
+    /// # Arguments
+    /// * `transcript`: The proof transcript.
+    /// * `r1`, `r2`: The prover's commitments.
+    ///
+    /// # Returns
+    /// The challenge value, in `[0, q)`.
+    fn generate_challenge(&self, transcript: &mut Transcript, r1: &BigInt, r2: &BigInt) -> BigInt;
+
+    /// Generates a non-interactive proof of knowledge of `x`, using the Fiat-Shamir transform.
+    ///
+    /// # Arguments
+    /// * `transcript`: The proof transcript.
+    /// * `x`: The secret value.
+    ///
+    /// # Returns
+    /// A `NonInteractiveProof`.
+    fn prove_non_interactive(&self, transcript: &mut Transcript, x: &BigInt)
+        -> NonInteractiveProof;
+
+    /// Verifies a non-interactive proof, against a transcript built from the same context as the prover's.
+    ///
+    /// # Arguments
+    /// * `transcript`: The proof transcript.
+    /// * `y1`, `y2`: Committed values.
+    /// * `proof`: The non-interactive proof.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or an error message.
+    fn verify_non_interactive(
+        &self,
+        transcript: &mut Transcript,
+        y1: &BigInt,
+        y2: &BigInt,
+        proof: &NonInteractiveProof,
+    ) -> Result<()>;
 }
 
 /// Main structure for the Chaum-Pedersen protocol operations.
@@ -85,10 +155,13 @@ pub struct ChaumPedersen {
 }
 
 impl ChaumPedersen {
-    #[allow(dead_code)]
-    fn new(parameters: Parameters) -> Self {
+    pub fn new(parameters: Parameters) -> Self {
         Self { parameters }
     }
+
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
 }
 
 impl Default for ChaumPedersen {
@@ -153,6 +226,50 @@ impl ChaumPedersenInterface for ChaumPedersen {
         }
         Ok(())
     }
+
+    fn transcript(&self, domain: &[u8], user: &str, y1: &BigInt, y2: &BigInt) -> Transcript {
+        let mut transcript = Transcript::new(PROTOCOL_LABEL);
+        transcript.append_parameters(&self.parameters);
+        transcript.append_message(b"domain", domain);
+        transcript.append_message(b"user", user.as_bytes());
+        transcript.append_bigint(b"y1", y1);
+        transcript.append_bigint(b"y2", y2);
+        transcript
+    }
+
+    fn generate_challenge(&self, transcript: &mut Transcript, r1: &BigInt, r2: &BigInt) -> BigInt {
+        transcript.append_bigint(b"r1", r1);
+        transcript.append_bigint(b"r2", r2);
+        transcript.append_bigint(b"verifier_nonce", &self.generate_random());
+        transcript.challenge_scalar(b"c", &self.parameters.q)
+    }
+
+    fn prove_non_interactive(
+        &self,
+        transcript: &mut Transcript,
+        x: &BigInt,
+    ) -> NonInteractiveProof {
+        let k = random_scalar(&self.parameters.q);
+        let ChaumPedersenExponents { r1, r2 } = self.commit(&k);
+        transcript.append_bigint(b"r1", &r1);
+        transcript.append_bigint(b"r2", &r2);
+        let c = transcript.challenge_scalar(b"c", &self.parameters.q);
+        let s = self.solve_challenge(x, &k, &c);
+        NonInteractiveProof { r1, r2, s }
+    }
+
+    fn verify_non_interactive(
+        &self,
+        transcript: &mut Transcript,
+        y1: &BigInt,
+        y2: &BigInt,
+        proof: &NonInteractiveProof,
+    ) -> Result<()> {
+        transcript.append_bigint(b"r1", &proof.r1);
+        transcript.append_bigint(b"r2", &proof.r2);
+        let c = transcript.challenge_scalar(b"c", &self.parameters.q);
+        self.verify(y1, y2, &proof.r1, &proof.r2, &proof.s, &c)
+    }
 }
 
 #[cfg(test)]
@@ -187,4 +304,87 @@ mod tests {
         let solution = cp.solve_challenge(&client_secret1, &k, &challenge);
         assert!(cp.verify(y1, y2, &r1, &r2, &solution, &challenge).is_err());
     }
+
+    #[test]
+    fn test_non_interactive_proof_in_success_case() {
+        let cp = ChaumPedersen::default();
+
+        let client_secret = cp.generate_random();
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&client_secret);
+
+        let proof = cp.prove_non_interactive(
+            &mut cp.transcript(b"domain", "user", &y1, &y2),
+            &client_secret,
+        );
+        assert!(cp
+            .verify_non_interactive(
+                &mut cp.transcript(b"domain", "user", &y1, &y2),
+                &y1,
+                &y2,
+                &proof
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_non_interactive_proof_can_not_be_replayed_in_other_context() {
+        let cp = ChaumPedersen::default();
+
+        let client_secret = cp.generate_random();
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&client_secret);
+
+        let proof = cp.prove_non_interactive(
+            &mut cp.transcript(b"domain", "user", &y1, &y2),
+            &client_secret,
+        );
+
+        // other service domain
+        assert!(cp
+            .verify_non_interactive(
+                &mut cp.transcript(b"other domain", "user", &y1, &y2),
+                &y1,
+                &y2,
+                &proof
+            )
+            .is_err());
+        // other user
+        assert!(cp
+            .verify_non_interactive(
+                &mut cp.transcript(b"domain", "other user", &y1, &y2),
+                &y1,
+                &y2,
+                &proof
+            )
+            .is_err());
+        // other parameter set
+        let mut other_parameters = DEFAULT_PARAMS.clone();
+        other_parameters.bit_size = 255;
+        let other_cp = ChaumPedersen::new(other_parameters);
+        assert!(other_cp
+            .verify_non_interactive(
+                &mut other_cp.transcript(b"domain", "user", &y1, &y2),
+                &y1,
+                &y2,
+                &proof
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_interactive_challenges_are_unpredictable() {
+        let cp = ChaumPedersen::default();
+
+        let client_secret = cp.generate_random();
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&client_secret);
+        let k = cp.generate_random();
+        let ChaumPedersenExponents { r1, r2 } = cp.commit(&k);
+
+        let transcript = cp.transcript(b"domain", "user", &y1, &y2);
+        let c1 = cp.generate_challenge(&mut transcript.clone(), &r1, &r2);
+        let c2 = cp.generate_challenge(&mut transcript.clone(), &r1, &r2);
+        assert_ne!(c1, c2);
+
+        let solution = cp.solve_challenge(&client_secret, &k, &c1);
+        assert!(cp.verify(&y1, &y2, &r1, &r2, &solution, &c1).is_ok());
+    }
 }
@@ -0,0 +1,325 @@
+//! # Key Rotation Proofs
+//!
+//! This module provides a non-interactive proof that the prover knows both the secret `x` behind a
+//! user's current public key `(y1, y2) = (g^x, h^x)` and the secret `x'` behind a new public key
+//! `(y1', y2') = (g^x', h^x')`. Both Chaum-Pedersen proofs share a single transcript and challenge,
+//! so that neither of them can be lifted out and combined with another proof.
+//!
+//! The transcript binds the protocol label, the parameters, the service domain, the user, a nonce
+//! issued by the verifier and both public keys, so a rotation proof can only ever move that user from
+//! the old key to the new one, once: the verifier never accepts the same nonce twice, so a proof can't
+//! be replayed after the user rotated back to its old key.
+use anyhow::{bail, Result};
+use num_bigint::BigInt;
+
+use crate::{
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenExponents, ChaumPedersenInterface},
+    random_scalar,
+    registration::is_subgroup_element,
+    transcript::Transcript,
+};
+
+/// Protocol label absorbed by every key rotation transcript.
+pub const KEY_ROTATION_LABEL: &[u8] = b"chaum-pedersen/key-rotation/v1";
+
+/// A proof of knowledge of both the old and the new secret of a user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRotationProof {
+    pub old_r1: BigInt,
+    pub old_r2: BigInt,
+    pub new_r1: BigInt,
+    pub new_r2: BigInt,
+    pub old_s: BigInt,
+    pub new_s: BigInt,
+}
+
+/// Public keys involved in a key rotation.
+pub struct KeyRotation<'a> {
+    pub old_y1: &'a BigInt,
+    pub old_y2: &'a BigInt,
+    pub new_y1: &'a BigInt,
+    pub new_y2: &'a BigInt,
+}
+
+/// Creates a key rotation transcript, bound to the service `domain`, the `user`, a single-use `nonce`
+/// issued by the verifier and both public keys.
+pub fn key_rotation_transcript(
+    cp: &ChaumPedersen,
+    domain: &[u8],
+    user: &str,
+    nonce: &[u8],
+    keys: &KeyRotation,
+) -> Transcript {
+    let mut transcript = Transcript::new(KEY_ROTATION_LABEL);
+    transcript.append_parameters(cp.parameters());
+    transcript.append_message(b"domain", domain);
+    transcript.append_message(b"user", user.as_bytes());
+    transcript.append_message(b"nonce", nonce);
+    transcript.append_bigint(b"old_y1", keys.old_y1);
+    transcript.append_bigint(b"old_y2", keys.old_y2);
+    transcript.append_bigint(b"new_y1", keys.new_y1);
+    transcript.append_bigint(b"new_y2", keys.new_y2);
+    transcript
+}
+
+/// Proves knowledge of the old secret `old_x` and of the new secret `new_x`.
+pub fn prove_key_rotation(
+    cp: &ChaumPedersen,
+    transcript: &mut Transcript,
+    old_x: &BigInt,
+    new_x: &BigInt,
+) -> KeyRotationProof {
+    let q = &cp.parameters().q;
+    let old_k = random_scalar(q);
+    let new_k = random_scalar(q);
+    let ChaumPedersenExponents {
+        r1: old_r1,
+        r2: old_r2,
+    } = cp.commit(&old_k);
+    let ChaumPedersenExponents {
+        r1: new_r1,
+        r2: new_r2,
+    } = cp.commit(&new_k);
+
+    append_commitments(transcript, &old_r1, &old_r2, &new_r1, &new_r2);
+    let c = transcript.challenge_scalar(b"c", q);
+
+    KeyRotationProof {
+        old_s: cp.solve_challenge(old_x, &old_k, &c),
+        new_s: cp.solve_challenge(new_x, &new_k, &c),
+        old_r1,
+        old_r2,
+        new_r1,
+        new_r2,
+    }
+}
+
+/// Verifies a key rotation proof, against a transcript built from the same context as the prover's.
+/// The new public key must be made of non-trivial elements of the order `q` subgroup, as at registration.
+pub fn verify_key_rotation(
+    cp: &ChaumPedersen,
+    transcript: &mut Transcript,
+    keys: &KeyRotation,
+    proof: &KeyRotationProof,
+) -> Result<()> {
+    if !is_subgroup_element(cp.parameters(), keys.new_y1)
+        || !is_subgroup_element(cp.parameters(), keys.new_y2)
+    {
+        bail!("Failed to verify key rotation, new public key is not in the subgroup");
+    }
+    append_commitments(
+        transcript,
+        &proof.old_r1,
+        &proof.old_r2,
+        &proof.new_r1,
+        &proof.new_r2,
+    );
+    let c = transcript.challenge_scalar(b"c", &cp.parameters().q);
+
+    cp.verify(
+        keys.old_y1,
+        keys.old_y2,
+        &proof.old_r1,
+        &proof.old_r2,
+        &proof.old_s,
+        &c,
+    )?;
+    cp.verify(
+        keys.new_y1,
+        keys.new_y2,
+        &proof.new_r1,
+        &proof.new_r2,
+        &proof.new_s,
+        &c,
+    )
+}
+
+fn append_commitments(
+    transcript: &mut Transcript,
+    old_r1: &BigInt,
+    old_r2: &BigInt,
+    new_r1: &BigInt,
+    new_r2: &BigInt,
+) {
+    transcript.append_bigint(b"old_r1", old_r1);
+    transcript.append_bigint(b"old_r2", old_r2);
+    transcript.append_bigint(b"new_r1", new_r1);
+    transcript.append_bigint(b"new_r2", new_r2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestKeys {
+        old_x: BigInt,
+        new_x: BigInt,
+        old: ChaumPedersenExponents,
+        new: ChaumPedersenExponents,
+    }
+
+    fn generate_keys(cp: &ChaumPedersen) -> TestKeys {
+        let old_x = cp.generate_random();
+        let new_x = cp.generate_random();
+        TestKeys {
+            old: cp.commit(&old_x),
+            new: cp.commit(&new_x),
+            old_x,
+            new_x,
+        }
+    }
+
+    fn rotation<'a>(
+        old: &'a ChaumPedersenExponents,
+        new: &'a ChaumPedersenExponents,
+    ) -> KeyRotation<'a> {
+        KeyRotation {
+            old_y1: old.get_first_exponent(),
+            old_y2: old.get_second_exponent(),
+            new_y1: new.get_first_exponent(),
+            new_y2: new.get_second_exponent(),
+        }
+    }
+
+    #[test]
+    fn test_key_rotation_proof_in_success_case() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+        let rotation = rotation(&keys.old, &keys.new);
+
+        let proof = prove_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &keys.old_x,
+            &keys.new_x,
+        );
+        assert!(verify_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &rotation,
+            &proof
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_key_rotation_proof_fails_without_old_secret() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+        let rotation = rotation(&keys.old, &keys.new);
+
+        let wrong_old_x = cp.generate_random();
+        let proof = prove_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &wrong_old_x,
+            &keys.new_x,
+        );
+        assert!(verify_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &rotation,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_proof_fails_without_new_secret() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+        let rotation = rotation(&keys.old, &keys.new);
+
+        let wrong_new_x = cp.generate_random();
+        let proof = prove_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &keys.old_x,
+            &wrong_new_x,
+        );
+        assert!(verify_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &rotation,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_proof_can_not_be_replayed_for_other_user() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+        let rotation = rotation(&keys.old, &keys.new);
+
+        let proof = prove_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &keys.old_x,
+            &keys.new_x,
+        );
+        assert!(verify_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "other user", b"nonce", &rotation),
+            &rotation,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_proof_can_not_be_replayed_with_other_nonce() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+        let rotation = rotation(&keys.old, &keys.new);
+
+        let proof = prove_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+            &keys.old_x,
+            &keys.new_x,
+        );
+        assert!(verify_key_rotation(
+            &cp,
+            &mut key_rotation_transcript(&cp, b"domain", "user", b"other nonce", &rotation),
+            &rotation,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_key_rotation_to_trivial_key_is_rejected() {
+        let cp = ChaumPedersen::default();
+        let keys = generate_keys(&cp);
+
+        for trivial in [BigInt::from(0), BigInt::from(1)] {
+            let rotation = KeyRotation {
+                old_y1: keys.old.get_first_exponent(),
+                old_y2: keys.old.get_second_exponent(),
+                new_y1: &trivial,
+                new_y2: &trivial,
+            };
+            // with the key (0, 0), any response verifies against commitments (0, 0)
+            let proof = KeyRotationProof {
+                new_r1: BigInt::from(0),
+                new_r2: BigInt::from(0),
+                ..prove_key_rotation(
+                    &cp,
+                    &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+                    &keys.old_x,
+                    &BigInt::from(0),
+                )
+            };
+            assert!(verify_key_rotation(
+                &cp,
+                &mut key_rotation_transcript(&cp, b"domain", "user", b"nonce", &rotation),
+                &rotation,
+                &proof
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("not in the subgroup"));
+        }
+    }
+}
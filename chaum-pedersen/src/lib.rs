@@ -1,9 +1,16 @@
 use once_cell::sync::Lazy;
 use std::str::FromStr;
 
-use num_bigint::BigInt;
+use num_bigint::{BigInt, RandBigInt};
+use rand::{rngs::StdRng, SeedableRng};
 
+pub mod blind_signature;
 pub mod chaum_pedersen;
+pub mod key_rotation;
+pub mod parameters;
+pub mod registration;
+pub mod simulator;
+pub mod transcript;
 
 /// Parameters for Chaum-Pedersen zk protocol. These include:
 ///     `bit_size` - number of bits for the base prime field.
@@ -11,6 +18,7 @@ pub mod chaum_pedersen;
 ///     `q` - order of the cyclic subgroup generated by the two generators of the multiplicative subgroup of the base prime field (these generators have the same order necessarily). Notice that q | p - 1.
 ///     `g` - a generator of the multiplicative subgroup of the base prime field of order `q`.
 ///     `h` - a generator of the multiplicative subgroup of the base prime field of order `q`.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Parameters {
     pub bit_size: u64,
     pub p: BigInt,
@@ -33,3 +41,18 @@ pub static DEFAULT_PARAMS: Lazy<Parameters> = Lazy::new(|| Parameters {
     g: BigInt::from_str("4").unwrap(),
     h: BigInt::from_str("9").unwrap(),
 });
+
+/// Samples a uniformly random scalar in `[1, q)`.
+pub(crate) fn random_scalar(q: &BigInt) -> BigInt {
+    let mut rng = StdRng::from_entropy();
+    rng.gen_bigint_range(&BigInt::from(1), q)
+}
+
+/// Reduces `value` into `[0, q)`.
+pub(crate) fn modulo(value: &BigInt, q: &BigInt) -> BigInt {
+    let mut result = value % q;
+    if result < BigInt::from(0) {
+        result += q;
+    }
+    result
+}
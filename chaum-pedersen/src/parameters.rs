@@ -0,0 +1,205 @@
+//! Generation and validation of `Parameters`.
+//!
+//! Generated parameters use a safe prime `p = 2q + 1`, so that the quadratic residues modulo `p` form
+//! the subgroup of prime order `q`. The generators `g` and `h` are random squares, whose discrete
+//! logarithm with respect to each other is unknown to everyone, including the generating party.
+use anyhow::{anyhow, Result};
+use num_bigint::{BigInt, RandBigInt};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::Parameters;
+
+/// Minimum size, in bits, of the prime `p` of generated and validated parameters, that of the default
+/// parameters.
+pub const MIN_BIT_SIZE: u64 = 255;
+
+/// Number of Miller-Rabin rounds of the primality tests, for an error probability below `4^-64`.
+const MILLER_RABIN_ROUNDS: usize = 64;
+
+/// Small primes, sieving out most candidates before running Miller-Rabin tests.
+const SMALL_PRIMES: [u32; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// Tests whether `n` is prime, with a negligible probability of error.
+fn is_probable_prime(n: &BigInt, rng: &mut StdRng) -> bool {
+    let two = BigInt::from(2);
+    if n < &two {
+        return false;
+    }
+    for small_prime in SMALL_PRIMES.iter().map(|&p| BigInt::from(p)).chain([two]) {
+        if n == &small_prime {
+            return true;
+        }
+        if (n % &small_prime) == BigInt::from(0) {
+            return false;
+        }
+    }
+
+    // n - 1 = d * 2^r, with d odd
+    let n_minus_one: BigInt = n - 1;
+    let r = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> r;
+    'witness: for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = rng.gen_bigint_range(&BigInt::from(2), &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x == BigInt::from(1) || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..r {
+            x = x.modpow(&BigInt::from(2), n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Samples a random generator of the subgroup of order `q` of the quadratic residues modulo `p`.
+fn random_generator(p: &BigInt, rng: &mut StdRng) -> BigInt {
+    loop {
+        let g = rng
+            .gen_bigint_range(&BigInt::from(2), &(p - 1))
+            .modpow(&BigInt::from(2), p);
+        if g != BigInt::from(1) {
+            return g;
+        }
+    }
+}
+
+impl Parameters {
+    /// Generates fresh parameters, over a safe prime field of `bit_size` bits.
+    ///
+    /// # Errors
+    /// Returns an error if `bit_size` is below `MIN_BIT_SIZE`.
+    pub fn generate(bit_size: u64) -> Result<Self> {
+        if bit_size < MIN_BIT_SIZE {
+            return Err(anyhow!(
+                "Failed to generate parameters, bit size must be at least {MIN_BIT_SIZE}"
+            ));
+        }
+        let mut rng = StdRng::from_entropy();
+        let (p, q) = loop {
+            // an odd q of exactly `bit_size - 1` bits, for p to have exactly `bit_size` bits
+            let mut q = rng.gen_bigint_range(
+                &(BigInt::from(1) << (bit_size - 2)),
+                &(BigInt::from(1) << (bit_size - 1)),
+            );
+            if !q.bit(0) {
+                q += 1;
+            }
+            let p = &q * 2 + 1;
+            if is_probable_prime(&q, &mut rng) && is_probable_prime(&p, &mut rng) {
+                break (p, q);
+            }
+        };
+        let g = random_generator(&p, &mut rng);
+        let h = loop {
+            let h = random_generator(&p, &mut rng);
+            if h != g {
+                break h;
+            }
+        };
+        Ok(Self {
+            bit_size,
+            p,
+            q,
+            g,
+            h,
+        })
+    }
+
+    /// Checks that the parameters are sound: `p` is at least `MIN_BIT_SIZE` bits long, `p` and `q` are
+    /// primes with `q | p - 1`, random values of `bit_size` bits cover `[0, q)`, and `g` and `h` are
+    /// distinct generators of the subgroup of order `q`.
+    ///
+    /// # Errors
+    /// Returns an error describing the first unsound parameter.
+    pub fn validate(&self) -> Result<()> {
+        let mut rng = StdRng::from_entropy();
+        if self.p.bits() < MIN_BIT_SIZE {
+            return Err(anyhow!(
+                "Invalid parameters, p must be at least {MIN_BIT_SIZE} bits long"
+            ));
+        }
+        if self.bit_size < self.q.bits() {
+            return Err(anyhow!(
+                "Invalid parameters, bit size must cover the {} bits of q",
+                self.q.bits()
+            ));
+        }
+        if !is_probable_prime(&self.p, &mut rng) || !is_probable_prime(&self.q, &mut rng) {
+            return Err(anyhow!("Invalid parameters, p and q must be prime"));
+        }
+        if (&self.p - 1) % &self.q != BigInt::from(0) {
+            return Err(anyhow!("Invalid parameters, q must divide p - 1"));
+        }
+        for generator in [&self.g, &self.h] {
+            if generator <= &BigInt::from(1)
+                || generator >= &self.p
+                || generator.modpow(&self.q, &self.p) != BigInt::from(1)
+            {
+                return Err(anyhow!(
+                    "Invalid parameters, g and h must generate the subgroup of order q"
+                ));
+            }
+        }
+        if self.g == self.h {
+            return Err(anyhow!("Invalid parameters, g and h must be distinct"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_PARAMS;
+
+    #[test]
+    fn test_default_parameters_are_valid() {
+        DEFAULT_PARAMS.validate().unwrap();
+    }
+
+    #[test]
+    fn test_generated_parameters_are_valid() {
+        let parameters = Parameters::generate(MIN_BIT_SIZE).unwrap();
+        assert_eq!(parameters.p.bits(), MIN_BIT_SIZE);
+        assert_eq!(parameters.p, &parameters.q * 2 + 1);
+        assert!(parameters.validate().is_ok());
+
+        assert!(Parameters::generate(MIN_BIT_SIZE - 1).is_err());
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let with = |f: fn(&mut Parameters)| {
+            let mut parameters = DEFAULT_PARAMS.clone();
+            f(&mut parameters);
+            parameters.validate()
+        };
+        assert!(with(|parameters| parameters.bit_size = 128).is_err());
+        assert!(with(|parameters| parameters.p += 2).is_err());
+        assert!(with(|parameters| parameters.q = BigInt::from(7)).is_err());
+        // -1 has order 2, outside of the subgroup of order q
+        assert!(with(|parameters| parameters.g = &parameters.p - 1).is_err());
+        assert!(with(|parameters| parameters.h = parameters.g.clone()).is_err());
+        assert!(with(|parameters| parameters.h = BigInt::from(1)).is_err());
+
+        // sound, but too small parameters
+        let small_parameters = Parameters {
+            bit_size: 5,
+            p: BigInt::from(23),
+            q: BigInt::from(11),
+            g: BigInt::from(4),
+            h: BigInt::from(9),
+        };
+        assert!(small_parameters
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("at least"));
+    }
+}
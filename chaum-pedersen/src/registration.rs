@@ -0,0 +1,166 @@
+//! # Registration Proofs
+//!
+//! This module provides a non-interactive proof of possession of the secret `x` behind a public key
+//! `(y1, y2) = (g^x, h^x)`, sent along a registration request. It shows that `log_g(y1) = log_h(y2)`,
+//! and that the registering client knows this discrete logarithm, so that no account can be created
+//! with a malformed public key (which could never authenticate) or with a public key copied from
+//! another user.
+//!
+//! The transcript binds a registration specific protocol label, the parameters, the service domain,
+//! the user and its public key, so a registration proof can't be replayed for another user name, nor
+//! be passed off as a proof of any other protocol.
+use anyhow::{anyhow, Result};
+use num_bigint::BigInt;
+
+use crate::{
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface, NonInteractiveProof},
+    transcript::Transcript,
+    Parameters,
+};
+
+/// Protocol label absorbed by every registration transcript.
+pub const REGISTRATION_LABEL: &[u8] = b"chaum-pedersen/registration/v1";
+
+/// Creates a registration transcript, bound to the service `domain`, the `user` and its public key.
+pub fn registration_transcript(
+    cp: &ChaumPedersen,
+    domain: &[u8],
+    user: &str,
+    y1: &BigInt,
+    y2: &BigInt,
+) -> Transcript {
+    let mut transcript = Transcript::new(REGISTRATION_LABEL);
+    transcript.append_parameters(cp.parameters());
+    transcript.append_message(b"domain", domain);
+    transcript.append_message(b"user", user.as_bytes());
+    transcript.append_bigint(b"y1", y1);
+    transcript.append_bigint(b"y2", y2);
+    transcript
+}
+
+/// Proves possession of the secret `x` behind the public key absorbed by the transcript.
+pub fn prove_registration(
+    cp: &ChaumPedersen,
+    transcript: &mut Transcript,
+    x: &BigInt,
+) -> NonInteractiveProof {
+    cp.prove_non_interactive(transcript, x)
+}
+
+/// Verifies a registration proof, against a transcript built from the same context as the prover's.
+///
+/// Both public key components are first checked to be elements of the order `q` subgroup, as the
+/// proof only implies equality of discrete logarithms within it.
+pub fn verify_registration(
+    cp: &ChaumPedersen,
+    transcript: &mut Transcript,
+    y1: &BigInt,
+    y2: &BigInt,
+    proof: &NonInteractiveProof,
+) -> Result<()> {
+    if !is_subgroup_element(cp.parameters(), y1) || !is_subgroup_element(cp.parameters(), y2) {
+        return Err(anyhow!("Failed to verify registration, invalid public key"));
+    }
+    cp.verify_non_interactive(transcript, y1, y2, proof)
+        .map_err(|_| anyhow!("Failed to verify registration, invalid proof of possession"))
+}
+
+/// Checks that `y` is a non-trivial element of the order `q` subgroup of `Z_p^*`.
+pub fn is_subgroup_element(parameters: &Parameters, y: &BigInt) -> bool {
+    let one = BigInt::from(1);
+    y > &one && y < &parameters.p && y.modpow(&parameters.q, &parameters.p) == one
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registration_proof_in_success_case() {
+        let cp = ChaumPedersen::default();
+        let x = cp.generate_random();
+        let exponents = cp.commit(&x);
+        let (y1, y2) = (
+            exponents.get_first_exponent(),
+            exponents.get_second_exponent(),
+        );
+
+        let proof = prove_registration(
+            &cp,
+            &mut registration_transcript(&cp, b"domain", "user", y1, y2),
+            &x,
+        );
+        assert!(verify_registration(
+            &cp,
+            &mut registration_transcript(&cp, b"domain", "user", y1, y2),
+            y1,
+            y2,
+            &proof
+        )
+        .is_ok());
+
+        // the proof is bound to the user name
+        assert!(verify_registration(
+            &cp,
+            &mut registration_transcript(&cp, b"domain", "other user", y1, y2),
+            y1,
+            y2,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registration_proof_fails_with_mismatched_discrete_logs() {
+        let cp = ChaumPedersen::default();
+        let x = cp.generate_random();
+        let other_x = cp.generate_random();
+        let y1 = cp.commit(&x).get_first_exponent().clone();
+        let y2 = cp.commit(&other_x).get_second_exponent().clone();
+
+        let proof = prove_registration(
+            &cp,
+            &mut registration_transcript(&cp, b"domain", "user", &y1, &y2),
+            &x,
+        );
+        assert!(verify_registration(
+            &cp,
+            &mut registration_transcript(&cp, b"domain", "user", &y1, &y2),
+            &y1,
+            &y2,
+            &proof
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_registration_proof_fails_with_invalid_public_key() {
+        let cp = ChaumPedersen::default();
+        let x = cp.generate_random();
+        let proof = prove_registration(
+            &cp,
+            &mut registration_transcript(
+                &cp,
+                b"domain",
+                "user",
+                &BigInt::from(1),
+                &BigInt::from(1),
+            ),
+            &x,
+        );
+        assert!(verify_registration(
+            &cp,
+            &mut registration_transcript(
+                &cp,
+                b"domain",
+                "user",
+                &BigInt::from(1),
+                &BigInt::from(1)
+            ),
+            &BigInt::from(1),
+            &BigInt::from(1),
+            &proof
+        )
+        .is_err());
+    }
+}
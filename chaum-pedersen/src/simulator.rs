@@ -0,0 +1,187 @@
+//! # Honest-Verifier Zero-Knowledge Simulator
+//!
+//! This module provides a simulator for Chaum-Pedersen transcripts `(r1, r2, c, s)`. Simulated
+//! transcripts are produced from the public values `(y1, y2)` alone, without any knowledge of the
+//! secret `x`, and yet are distributed exactly as honest transcripts in which the verifier picks
+//! its challenge uniformly at random. This is what makes the protocol (honest-verifier)
+//! zero-knowledge: a transcript reveals nothing that its observer couldn't have produced on its own.
+//!
+//! Besides testing the zero-knowledge property, simulated transcripts can be handed out as decoys,
+//! as they are indistinguishable from (and verify exactly like) real ones.
+use num_bigint::{BigInt, RandBigInt};
+use rand::thread_rng;
+
+use crate::Parameters;
+
+/// A Chaum-Pedersen protocol transcript, consisting of the prover's commitments `r1` and `r2`,
+/// the verifier's challenge `c` and the prover's response `s`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChaumPedersenTranscript {
+    pub r1: BigInt,
+    pub r2: BigInt,
+    pub c: BigInt,
+    pub s: BigInt,
+}
+
+/// Simulator of Chaum-Pedersen transcripts.
+pub struct Simulator {
+    // Cryptographic parameters
+    parameters: Parameters,
+}
+
+impl Simulator {
+    pub fn new(parameters: Parameters) -> Self {
+        Self { parameters }
+    }
+
+    /// Simulates a transcript for the public values `y1` and `y2`, with a uniformly random challenge.
+    pub fn simulate(&self, y1: &BigInt, y2: &BigInt) -> ChaumPedersenTranscript {
+        let c = self.sample_scalar();
+        self.simulate_with_challenge(y1, y2, c)
+    }
+
+    /// Simulates a transcript for the public values `y1` and `y2`, with a given challenge `c`.
+    ///
+    /// It samples the response `s` uniformly in `[0, q)` and computes the commitments backwards,
+    /// as `r1 = g^s * y1^c mod p` and `r2 = h^s * y2^c mod p`.
+    pub fn simulate_with_challenge(
+        &self,
+        y1: &BigInt,
+        y2: &BigInt,
+        c: BigInt,
+    ) -> ChaumPedersenTranscript {
+        let Parameters { p, g, h, .. } = &self.parameters;
+        let s = self.sample_scalar();
+
+        let r1 = (g.modpow(&s, p) * y1.modpow(&c, p)) % p;
+        let r2 = (h.modpow(&s, p) * y2.modpow(&c, p)) % p;
+
+        ChaumPedersenTranscript { r1, r2, c, s }
+    }
+
+    /// Samples a uniformly random scalar in `[0, q)`.
+    fn sample_scalar(&self) -> BigInt {
+        thread_rng().gen_bigint_range(&BigInt::from(0), &self.parameters.q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        chaum_pedersen::{ChaumPedersen, ChaumPedersenExponents, ChaumPedersenInterface},
+        DEFAULT_PARAMS,
+    };
+
+    /// Number of transcripts sampled from each distribution in the statistical test.
+    const NUM_SAMPLES: usize = 10_000;
+
+    /// Small test group, with `p = 2q + 1` and generators `g`, `h` of order `q`.
+    fn small_test_parameters() -> Parameters {
+        Parameters {
+            bit_size: 4,
+            p: BigInt::from(23),
+            q: BigInt::from(11),
+            g: BigInt::from(4),
+            h: BigInt::from(9),
+        }
+    }
+
+    fn sample_scalar(q: &BigInt) -> BigInt {
+        thread_rng().gen_bigint_range(&BigInt::from(0), q)
+    }
+
+    fn real_transcript(cp: &ChaumPedersen, x: &BigInt) -> ChaumPedersenTranscript {
+        let k = sample_scalar(&cp.parameters().q);
+        let ChaumPedersenExponents { r1, r2 } = cp.commit(&k);
+        let c = sample_scalar(&cp.parameters().q);
+        let s = cp.solve_challenge(x, &k, &c);
+        ChaumPedersenTranscript { r1, r2, c, s }
+    }
+
+    fn histogram(
+        transcripts: impl Iterator<Item = ChaumPedersenTranscript>,
+    ) -> HashMap<ChaumPedersenTranscript, usize> {
+        let mut histogram = HashMap::new();
+        for transcript in transcripts {
+            *histogram.entry(transcript).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Total variation distance between two empirical distributions with `NUM_SAMPLES` samples each.
+    fn total_variation_distance(
+        lhs: &HashMap<ChaumPedersenTranscript, usize>,
+        rhs: &HashMap<ChaumPedersenTranscript, usize>,
+    ) -> f64 {
+        let distance: usize = lhs
+            .keys()
+            .chain(rhs.keys())
+            .collect::<std::collections::HashSet<_>>()
+            .into_iter()
+            .map(|t| lhs.get(t).unwrap_or(&0).abs_diff(*rhs.get(t).unwrap_or(&0)))
+            .sum();
+        distance as f64 / (2 * NUM_SAMPLES) as f64
+    }
+
+    #[test]
+    fn test_simulated_transcripts_verify() {
+        let cp = ChaumPedersen::default();
+        let simulator = Simulator::new(DEFAULT_PARAMS.clone());
+
+        let x = cp.generate_random();
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&x);
+
+        for _ in 0..10 {
+            let ChaumPedersenTranscript { r1, r2, c, s } = simulator.simulate(&y1, &y2);
+            assert!(cp.verify(&y1, &y2, &r1, &r2, &s, &c).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_simulated_and_real_transcripts_are_identically_distributed() {
+        let parameters = small_test_parameters();
+        let cp = ChaumPedersen::new(parameters.clone());
+        let simulator = Simulator::new(parameters.clone());
+
+        let x = BigInt::from(7);
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&x);
+
+        let real = histogram((0..NUM_SAMPLES).map(|_| real_transcript(&cp, &x)));
+        let simulated = histogram((0..NUM_SAMPLES).map(|_| simulator.simulate(&y1, &y2)));
+
+        // both distributions are uniform over the `q^2` accepting transcripts
+        assert_eq!(real.len(), 121);
+        assert_eq!(simulated.len(), 121);
+        assert!(simulated
+            .keys()
+            .all(|t| cp.verify(&y1, &y2, &t.r1, &t.r2, &t.s, &t.c).is_ok()));
+
+        // with `q^2 = 121` outcomes and 10_000 samples each, the expected empirical distance between two
+        // identical distributions is around `0.06`
+        let distance = total_variation_distance(&real, &simulated);
+        assert!(distance < 0.1, "total variation distance = {distance}");
+    }
+
+    #[test]
+    fn test_statistical_test_detects_different_distributions() {
+        let parameters = small_test_parameters();
+        let cp = ChaumPedersen::new(parameters.clone());
+        let simulator = Simulator::new(parameters.clone());
+
+        let x = BigInt::from(7);
+        let ChaumPedersenExponents { r1: y1, r2: y2 } = cp.commit(&x);
+
+        let real = histogram((0..NUM_SAMPLES).map(|_| real_transcript(&cp, &x)));
+        // a biased simulator, which only ever uses challenges in `{0, 1}`
+        let biased = histogram(
+            (0..NUM_SAMPLES)
+                .map(|i| simulator.simulate_with_challenge(&y1, &y2, BigInt::from(i % 2))),
+        );
+
+        let distance = total_variation_distance(&real, &biased);
+        assert!(distance > 0.5, "total variation distance = {distance}");
+    }
+}
@@ -0,0 +1,207 @@
+//! Administration client of the authentication server, calling its `Admin` service with the admin token
+//! in `ADMIN_TOKEN`.
+#![allow(clippy::result_large_err)]
+
+use clap::{Parser, Subcommand};
+use client::{
+    client_auth::{
+        admin_client::AdminClient, DeleteUserRequest, DisableUserRequest, EnableUserRequest,
+        GetUserRequest, ListSessionsRequest, ListUsersRequest, PurgeChallengesRequest,
+        RevokeSessionRequest, UnlockUserRequest, UserInfo,
+    },
+    tls::connect_from_env,
+};
+use tonic::{metadata::MetadataValue, Request};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Address of the server, e.g. `http://localhost:5001`
+    #[arg(long, env = "CLIENT_DEST_SERVER_ADDR")]
+    server_addr: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Lists every registered user, ordered by name
+    ListUsers {
+        /// Number of users fetched per request
+        #[arg(long, default_value_t = 100)]
+        page_size: u32,
+    },
+    /// Describes a user
+    GetUser {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Disables a user, revoking its sessions, until it is enabled again
+    DisableUser {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Enables a disabled user
+    EnableUser {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Deletes a user, revoking its sessions
+    DeleteUser {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Lifts the lockout of a user, after too many failed authentication attempts
+    UnlockUser {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Lists the sessions of a user, identified by their fingerprint
+    ListSessions {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Revokes a session of a user
+    RevokeSession {
+        /// User name
+        #[arg(short, long)]
+        name: String,
+        /// Fingerprint of the session, as listed by `list-sessions`
+        #[arg(short, long)]
+        fingerprint: String,
+    },
+    /// Removes expired authentication challenges, or all of them
+    PurgeChallenges {
+        /// Remove every pending challenge, not only the expired ones
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+fn print_user(user: &UserInfo) {
+    println!(
+        "{}\tdisabled={}\tlocked_out={}\tsessions={}\tpending_challenge={}",
+        user.user, user.disabled, user.locked_out, user.active_sessions, user.pending_challenge
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // a `.env` file is optional, the settings may as well come from the environment
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    // the admin token is only read from the environment, so that it doesn't show in the process list
+    let admin_token = std::env::var("ADMIN_TOKEN")
+        .map_err(|_| "Failed to retrieve the admin token, `ADMIN_TOKEN` must be set")?;
+    let authorization: MetadataValue<_> = format!("Bearer {admin_token}").parse()?;
+
+    let channel = connect_from_env(&cli.server_addr).await?;
+    let mut client = AdminClient::with_interceptor(channel, move |mut request: Request<()>| {
+        request
+            .metadata_mut()
+            .insert("authorization", authorization.clone());
+        Ok(request)
+    });
+
+    match cli.command {
+        Command::ListUsers { page_size } => {
+            let mut page_token = String::new();
+            loop {
+                let response = client
+                    .list_users(ListUsersRequest {
+                        page_size,
+                        page_token,
+                    })
+                    .await?
+                    .into_inner();
+                response.users.iter().for_each(print_user);
+                if response.next_page_token.is_empty() {
+                    break;
+                }
+                page_token = response.next_page_token;
+            }
+        }
+        Command::GetUser { name } => {
+            let response = client
+                .get_user(GetUserRequest { user: name })
+                .await?
+                .into_inner();
+            if let Some(user) = response.user {
+                print_user(&user);
+            }
+        }
+        Command::DisableUser { name } => {
+            let response = client
+                .disable_user(DisableUserRequest { user: name })
+                .await?
+                .into_inner();
+            println!(
+                "User disabled, revoked {} session(s)",
+                response.revoked_sessions
+            );
+        }
+        Command::EnableUser { name } => {
+            client.enable_user(EnableUserRequest { user: name }).await?;
+            println!("User enabled");
+        }
+        Command::DeleteUser { name } => {
+            let response = client
+                .delete_user(DeleteUserRequest { user: name })
+                .await?
+                .into_inner();
+            println!(
+                "User deleted, revoked {} session(s)",
+                response.revoked_sessions
+            );
+        }
+        Command::UnlockUser { name } => {
+            let response = client
+                .unlock_user(UnlockUserRequest { user: name })
+                .await?
+                .into_inner();
+            match response.was_locked {
+                true => println!("User unlocked"),
+                false => println!("User was not locked out"),
+            }
+        }
+        Command::ListSessions { name } => {
+            let response = client
+                .list_sessions(ListSessionsRequest { user: name })
+                .await?
+                .into_inner();
+            for session in response.sessions {
+                println!(
+                    "{}\tissued_at={}\texpires_at={}\tscopes={}",
+                    session.fingerprint,
+                    session.issued_at,
+                    session.expires_at,
+                    session.scopes.join(" ")
+                );
+            }
+        }
+        Command::RevokeSession { name, fingerprint } => {
+            client
+                .revoke_session(RevokeSessionRequest {
+                    user: name,
+                    fingerprint,
+                })
+                .await?;
+            println!("Session revoked");
+        }
+        Command::PurgeChallenges { all } => {
+            let response = client
+                .purge_challenges(PurgeChallengesRequest { all })
+                .await?
+                .into_inner();
+            println!("Purged {} challenge(s)", response.purged);
+        }
+    }
+
+    Ok(())
+}
@@ -1,12 +1,49 @@
-use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
+use chaum_pedersen::{
+    blind_signature::{self, BlindSignature},
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface, DEFAULT_DOMAIN},
+    key_rotation::{self, KeyRotation},
+    registration, Parameters,
+};
 use log::info;
 use num_bigint::BigInt;
-use tonic::{async_trait, transport::Channel, Request};
+use rand::RngCore;
+use tonic::{
+    async_trait,
+    transport::{Channel, ClientTlsConfig, Endpoint},
+    Request,
+};
 
 use crate::client_auth::{
     auth_client::AuthClient, AuthenticationAnswerRequest, AuthenticationChallengeRequest,
-    RegisterRequest,
+    BlindedCredentialRequest, CredentialCommitmentRequest, CredentialPresentationRequest,
+    KeyRotationProof, LoginParametersRequest, LogoutRequest, NonInteractiveProof,
+    ParametersRequest, RefreshSessionRequest, RegisterRequest, RevokeAllSessionsRequest,
+    RotateCredentialRequest,
 };
+use crate::KdfParameters;
+
+/// Size, in bytes, of the random serial number of a credential.
+const CREDENTIAL_SERIAL_SIZE: usize = 32;
+
+/// An anonymous credential, consisting of a message blindly signed by the server: its expiry, as a big
+/// endian Unix timestamp, followed by a random serial number.
+///
+/// Presenting it proves that the holder is some authenticated user, without linking it to the login
+/// or session used to obtain it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credential {
+    pub message: Vec<u8>,
+    pub signature: BlindSignature,
+}
+
+impl Credential {
+    /// Returns the Unix timestamp the credential expires at, after which the server rejects it.
+    pub fn expires_at(&self) -> u64 {
+        let mut expiry = [0u8; 8];
+        expiry.copy_from_slice(&self.message[..8]);
+        u64::from_be_bytes(expiry)
+    }
+}
 
 /// Trait definition for the asynchronous interface of a client handling authentication
 /// using Chaum-Pedersen ZK protocol.
@@ -16,7 +53,9 @@ pub trait AuthZKPClient {
     ///
     /// # Arguments
     /// * `user`: A string slice representing the username.
-    /// * `x`: A `BigInt` representing the user's secret, currently as a `Blake3` 32-byte hash (in big-endian format).
+    /// * `x`: A `BigInt` representing the user's secret, as derived by `derive_secret`.
+    /// * `salt`: The user's random salt, used to derive `x`.
+    /// * `kdf_parameters`: The Argon2id cost parameters used to derive `x`.
     ///
     /// # Returns
     /// A `Result` indicating the success or failure of the registration process.
@@ -27,13 +66,30 @@ pub trait AuthZKPClient {
         &mut self,
         user: &str,
         x: &BigInt,
+        salt: &[u8],
+        kdf_parameters: &KdfParameters,
     ) -> Result<(), Box<dyn std::error::Error>>;
 
+    /// Retrieves the salt and the key derivation parameters of a user, needed to derive its secret.
+    ///
+    /// # Arguments
+    /// * `user`: A string slice representing the username.
+    ///
+    /// # Returns
+    /// A `Result` containing the user's salt and Argon2id cost parameters.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    async fn get_login_parameters(
+        &mut self,
+        user: &str,
+    ) -> Result<(Vec<u8>, KdfParameters), Box<dyn std::error::Error>>;
+
     /// Authenticates a user.
     ///
     /// # Arguments
     /// * `user`: A string slice representing the username.
-    /// * `x`: A `BigInt` representing the user's secret, currently as a `Blake3` 32-byte hash (in big-endian format).
+    /// * `x`: A `BigInt` representing the user's secret, as derived by `derive_secret`.
     ///
     /// # Returns
     /// A `Result` containing a string (e.g., a token) upon successful authentication, or an error.
@@ -45,24 +101,166 @@ pub trait AuthZKPClient {
         user: &str,
         x: &BigInt,
     ) -> Result<String, Box<dyn std::error::Error>>;
+
+    /// Obtains an anonymous credential, blindly signed by the server.
+    ///
+    /// # Arguments
+    /// * `session_id`: A string slice representing the session id of an authenticated user.
+    ///
+    /// # Returns
+    /// A `Result` containing the unblinded `Credential`, or an error.
+    ///
+    /// # Errors
+    /// Returns an error if the session is invalid, or if the server's signature does not verify.
+    async fn obtain_credential(
+        &mut self,
+        session_id: &str,
+    ) -> Result<Credential, Box<dyn std::error::Error>>;
+
+    /// Presents an anonymous credential to the server.
+    ///
+    /// # Arguments
+    /// * `credential`: A reference to a `Credential` previously obtained through `obtain_credential`.
+    ///
+    /// # Returns
+    /// A `Result` indicating whether the server accepted the credential.
+    ///
+    /// # Errors
+    /// Returns an error if the credential is invalid, or if it has already been presented.
+    async fn present_credential(
+        &mut self,
+        credential: &Credential,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Rotates a user's secret, keeping the same account. The user's salt and key derivation
+    /// parameters are kept, so both secrets must be derived with them. The rotation consumes the
+    /// given session.
+    ///
+    /// # Arguments
+    /// * `session_id`: A string slice representing the session id of the authenticated user.
+    /// * `user`: A string slice representing the username.
+    /// * `old_x`: A `BigInt` representing the user's current secret.
+    /// * `new_x`: A `BigInt` representing the user's new secret.
+    ///
+    /// # Returns
+    /// A `Result` indicating the success or failure of the rotation.
+    ///
+    /// # Errors
+    /// Returns an error if the session is invalid, or if the server rejects the key rotation proof.
+    async fn rotate_credential(
+        &mut self,
+        session_id: &str,
+        user: &str,
+        old_x: &BigInt,
+        new_x: &BigInt,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Extends the lifetime of a session, before it expires.
+    ///
+    /// # Arguments
+    /// * `session_id`: A string slice representing the session id of an authenticated user.
+    ///
+    /// # Returns
+    /// A `Result` containing the new expiry of the session, as a Unix timestamp in seconds.
+    ///
+    /// # Errors
+    /// Returns an error if the session is invalid or has expired.
+    async fn refresh_session(
+        &mut self,
+        session_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Ends a session.
+    ///
+    /// # Arguments
+    /// * `session_id`: A string slice representing the session id to end.
+    ///
+    /// # Returns
+    /// A `Result` indicating the success or failure of the logout.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    async fn logout(&mut self, session_id: &str) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Ends every session of the user owning a session, including this one.
+    ///
+    /// # Arguments
+    /// * `session_id`: A string slice representing the session id of an authenticated user.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of revoked sessions.
+    ///
+    /// # Errors
+    /// Returns an error if the session is invalid or has expired.
+    async fn revoke_all_sessions(
+        &mut self,
+        session_id: &str,
+    ) -> Result<u32, Box<dyn std::error::Error>>;
 }
 
 /// A client for handling user authentication using the Chaum-Pedersen ZKP protocol.
 pub struct ChaumPedersenAuthClient {
-    /// The Chaum-Pedersen protocol instance.
+    /// The Chaum-Pedersen protocol instance, over the server's parameters.
     cp_zkp_protocol: ChaumPedersen,
     /// An authentication client.
     client: AuthClient<Channel>,
+    /// Service domain, bound to every proof transcript.
+    domain: String,
 }
 
 impl ChaumPedersenAuthClient {
     pub async fn new<T: ToString>(destination: T) -> Result<Self, Box<dyn std::error::Error>> {
         let client = AuthClient::connect(destination.to_string()).await?;
+        Self::with_client(client).await
+    }
+
+    /// Connects to an `https://` destination over TLS, configured by `tls_config`.
+    pub async fn with_tls<T: ToString>(
+        destination: T,
+        tls_config: ClientTlsConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let channel = Endpoint::from_shared(destination.to_string())?
+            .tls_config(tls_config)?
+            .connect()
+            .await?;
+        Self::with_client(AuthClient::new(channel)).await
+    }
+
+    /// Retrieves the group parameters of the server, which must be sound, to run the protocol in the
+    /// same group.
+    async fn with_client(
+        mut client: AuthClient<Channel>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let parameters = client
+            .get_parameters(Request::new(ParametersRequest {}))
+            .await?
+            .into_inner();
+        let to_bigint = |bytes: &[u8]| BigInt::from_bytes_be(num_bigint::Sign::Plus, bytes);
+        let parameters = Parameters {
+            bit_size: parameters.bit_size,
+            p: to_bigint(&parameters.p),
+            q: to_bigint(&parameters.q),
+            g: to_bigint(&parameters.g),
+            h: to_bigint(&parameters.h),
+        };
+        parameters.validate()?;
         Ok(Self {
-            cp_zkp_protocol: ChaumPedersen::default(),
+            cp_zkp_protocol: ChaumPedersen::new(parameters),
             client,
+            domain: DEFAULT_DOMAIN.to_string(),
         })
     }
+
+    /// Returns the group parameters of the server, e.g. to derive secrets with `derive_secret`.
+    pub fn parameters(&self) -> &Parameters {
+        self.cp_zkp_protocol.parameters()
+    }
+
+    /// Sets the service domain bound to every proof transcript, which must match the server's.
+    pub fn with_domain<T: ToString>(mut self, domain: T) -> Self {
+        self.domain = domain.to_string();
+        self
+    }
 }
 
 #[async_trait]
@@ -71,22 +269,57 @@ impl AuthZKPClient for ChaumPedersenAuthClient {
         &mut self,
         user: &str,
         x: &BigInt,
+        salt: &[u8],
+        kdf_parameters: &KdfParameters,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let exponents = self.cp_zkp_protocol.commit(x);
         let (y1, y2) = (
             exponents.get_first_exponent(),
             exponents.get_second_exponent(),
         );
+        // prove possession of `x`, bound to the service domain and the user name
+        let mut transcript = registration::registration_transcript(
+            &self.cp_zkp_protocol,
+            self.domain.as_bytes(),
+            user,
+            y1,
+            y2,
+        );
+        let proof = registration::prove_registration(&self.cp_zkp_protocol, &mut transcript, x);
         let register_request = RegisterRequest {
             user: user.to_string(),
             y1: y1.to_bytes_be().1,
             y2: y2.to_bytes_be().1,
+            salt: salt.to_vec(),
+            kdf: Some(kdf_parameters.clone().into()),
+            proof: Some(NonInteractiveProof {
+                r1: proof.r1.to_bytes_be().1,
+                r2: proof.r2.to_bytes_be().1,
+                s: proof.s.to_bytes_be().1,
+            }),
         };
 
         self.client.register(Request::new(register_request)).await?;
         Ok(())
     }
 
+    async fn get_login_parameters(
+        &mut self,
+        user: &str,
+    ) -> Result<(Vec<u8>, KdfParameters), Box<dyn std::error::Error>> {
+        let login_parameters_response = self
+            .client
+            .get_login_parameters(Request::new(LoginParametersRequest {
+                user: user.to_string(),
+            }))
+            .await?
+            .into_inner();
+        let kdf_parameters = login_parameters_response
+            .kdf
+            .ok_or("Missing key derivation parameters in server response")?;
+        Ok((login_parameters_response.salt, kdf_parameters.into()))
+    }
+
     async fn authenticate_user(
         &mut self,
         user: &str,
@@ -127,4 +360,150 @@ impl AuthZKPClient for ChaumPedersenAuthClient {
 
         Ok(auth_answer_response.session_id)
     }
+
+    async fn obtain_credential(
+        &mut self,
+        session_id: &str,
+    ) -> Result<Credential, Box<dyn std::error::Error>> {
+        let commitment_response = self
+            .client
+            .create_credential_commitment(Request::new(CredentialCommitmentRequest {
+                session_id: session_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+
+        let r = BigInt::from_bytes_be(num_bigint::Sign::Plus, &commitment_response.r);
+        let public_key =
+            BigInt::from_bytes_be(num_bigint::Sign::Plus, &commitment_response.public_key);
+
+        let mut serial = [0u8; CREDENTIAL_SERIAL_SIZE];
+        rand::thread_rng().fill_bytes(&mut serial);
+        let message = [
+            &commitment_response.credential_expires_at.to_be_bytes()[..],
+            &serial,
+        ]
+        .concat();
+
+        let parameters = self.cp_zkp_protocol.parameters();
+        let (blinding_factors, c) = blind_signature::blind(parameters, &public_key, &r, &message);
+
+        let blinded_credential_response = self
+            .client
+            .sign_blinded_credential(Request::new(BlindedCredentialRequest {
+                session_id: session_id.to_string(),
+                credential_id: commitment_response.credential_id,
+                c: c.to_bytes_be().1,
+            }))
+            .await?
+            .into_inner();
+
+        info!("Successfully obtained a blinded credential signature from server");
+
+        let s = BigInt::from_bytes_be(num_bigint::Sign::Plus, &blinded_credential_response.s);
+        let parameters = self.cp_zkp_protocol.parameters();
+        let signature = blind_signature::unblind(parameters, blinding_factors, &s);
+        blind_signature::verify(parameters, &public_key, &message, &signature)?;
+
+        Ok(Credential { message, signature })
+    }
+
+    async fn present_credential(
+        &mut self,
+        credential: &Credential,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let credential_presentation_request = CredentialPresentationRequest {
+            message: credential.message.clone(),
+            r: credential.signature.r.to_bytes_be().1,
+            s: credential.signature.s.to_bytes_be().1,
+        };
+        self.client
+            .present_credential(Request::new(credential_presentation_request))
+            .await?;
+        Ok(())
+    }
+
+    async fn rotate_credential(
+        &mut self,
+        session_id: &str,
+        user: &str,
+        old_x: &BigInt,
+        new_x: &BigInt,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let old_exponents = self.cp_zkp_protocol.commit(old_x);
+        let new_exponents = self.cp_zkp_protocol.commit(new_x);
+        let keys = KeyRotation {
+            old_y1: old_exponents.get_first_exponent(),
+            old_y2: old_exponents.get_second_exponent(),
+            new_y1: new_exponents.get_first_exponent(),
+            new_y2: new_exponents.get_second_exponent(),
+        };
+
+        let mut transcript = key_rotation::key_rotation_transcript(
+            &self.cp_zkp_protocol,
+            self.domain.as_bytes(),
+            user,
+            session_id.as_bytes(),
+            &keys,
+        );
+        let proof =
+            key_rotation::prove_key_rotation(&self.cp_zkp_protocol, &mut transcript, old_x, new_x);
+
+        let rotate_credential_request = RotateCredentialRequest {
+            user: user.to_string(),
+            new_y1: keys.new_y1.to_bytes_be().1,
+            new_y2: keys.new_y2.to_bytes_be().1,
+            proof: Some(KeyRotationProof {
+                old_r1: proof.old_r1.to_bytes_be().1,
+                old_r2: proof.old_r2.to_bytes_be().1,
+                new_r1: proof.new_r1.to_bytes_be().1,
+                new_r2: proof.new_r2.to_bytes_be().1,
+                old_s: proof.old_s.to_bytes_be().1,
+                new_s: proof.new_s.to_bytes_be().1,
+            }),
+            session_id: session_id.to_string(),
+        };
+        self.client
+            .rotate_credential(Request::new(rotate_credential_request))
+            .await?;
+        Ok(())
+    }
+
+    async fn refresh_session(
+        &mut self,
+        session_id: &str,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let refresh_session_request = RefreshSessionRequest {
+            session_id: session_id.to_string(),
+        };
+        let refresh_session_response = self
+            .client
+            .refresh_session(Request::new(refresh_session_request))
+            .await?
+            .into_inner();
+        Ok(refresh_session_response.expires_at)
+    }
+
+    async fn logout(&mut self, session_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let logout_request = LogoutRequest {
+            session_id: session_id.to_string(),
+        };
+        self.client.logout(Request::new(logout_request)).await?;
+        Ok(())
+    }
+
+    async fn revoke_all_sessions(
+        &mut self,
+        session_id: &str,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        let revoke_all_sessions_request = RevokeAllSessionsRequest {
+            session_id: session_id.to_string(),
+        };
+        let revoke_all_sessions_response = self
+            .client
+            .revoke_all_sessions(Request::new(revoke_all_sessions_request))
+            .await?
+            .into_inner();
+        Ok(revoke_all_sessions_response.revoked)
+    }
 }
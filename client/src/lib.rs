@@ -1,18 +1,136 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chaum_pedersen::Parameters;
 use num_bigint::BigInt;
+use rand::RngCore;
 use zeroize::Zeroize;
 
 pub mod client;
+pub mod tls;
 
 pub mod client_auth {
     tonic::include_proto!("zkp_auth");
 }
 
-#[doc(hidden)]
-pub fn calculate_password_hash<T: ToString + Zeroize>(mut password: T) -> BigInt {
-    let secret_bytes = blake3::hash(password.to_string().as_bytes());
+/// Size, in bytes, of a freshly generated user salt.
+pub const SALT_SIZE: usize = 16;
+
+/// Size, in bytes, of the Argon2id output, chosen large enough to make the bias of the reduction
+/// modulo `q` negligible.
+const KDF_OUTPUT_SIZE: usize = 64;
+
+/// Argon2id cost parameters, for deriving a user's secret from its password.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KdfParameters {
+    /// Memory cost, in KiB.
+    pub memory_kib: u32,
+    /// Number of iterations.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for KdfParameters {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl From<client_auth::KdfParameters> for KdfParameters {
+    fn from(kdf: client_auth::KdfParameters) -> Self {
+        Self {
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+        }
+    }
+}
+
+impl From<KdfParameters> for client_auth::KdfParameters {
+    fn from(kdf: KdfParameters) -> Self {
+        Self {
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+        }
+    }
+}
+
+/// Generates a fresh random salt, for a new user.
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Derives a user's secret `x` from its password, using the memory-hard Argon2id KDF with the user's
+/// `salt`. The KDF output is reduced into `Z_q`, for the server's `parameters`.
+pub fn derive_secret<T: ToString + Zeroize>(
+    mut password: T,
+    salt: &[u8],
+    kdf_parameters: &KdfParameters,
+    parameters: &Parameters,
+) -> Result<BigInt, Box<dyn std::error::Error>> {
+    let params = Params::new(
+        kdf_parameters.memory_kib,
+        kdf_parameters.iterations,
+        kdf_parameters.parallelism,
+        Some(KDF_OUTPUT_SIZE),
+    )
+    .map_err(|e| e.to_string())?;
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+    let mut password_string = password.to_string();
     // zeroize password
     password.zeroize();
-    // blake3's `Hash` bytes representation is big endian
-    let secret_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, secret_bytes.as_bytes());
-    secret_bigint
+
+    let mut output = [0u8; KDF_OUTPUT_SIZE];
+    let result = argon2.hash_password_into(password_string.as_bytes(), salt, &mut output);
+    password_string.zeroize();
+    result.map_err(|e| e.to_string())?;
+
+    // the output bytes are interpreted in big endian form
+    let secret_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &output) % &parameters.q;
+    output.zeroize();
+    Ok(secret_bigint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaum_pedersen::DEFAULT_PARAMS;
+
+    fn test_kdf_parameters() -> KdfParameters {
+        KdfParameters {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_derive_secret_is_salted_and_reduced() {
+        let salt = vec![7u8; SALT_SIZE];
+        let other_salt = vec![8u8; SALT_SIZE];
+
+        let derive = |salt| {
+            derive_secret(
+                "password".to_string(),
+                salt,
+                &test_kdf_parameters(),
+                &DEFAULT_PARAMS,
+            )
+            .unwrap()
+        };
+        let secret = derive(&salt);
+        let same_secret = derive(&salt);
+        let other_secret = derive(&other_salt);
+
+        assert_eq!(secret, same_secret);
+        assert_ne!(secret, other_secret);
+        assert!(secret < DEFAULT_PARAMS.q);
+    }
 }
@@ -1,6 +1,8 @@
 use client::{
-    calculate_password_hash,
     client::{AuthZKPClient, ChaumPedersenAuthClient},
+    derive_secret, generate_salt,
+    tls::client_tls_config_from_env,
+    KdfParameters,
 };
 use log::info;
 
@@ -23,6 +25,15 @@ enum Commands {
         // user password
         #[arg(short, long)]
         password: String,
+        // Argon2id memory cost, in KiB
+        #[arg(long, default_value_t = KdfParameters::default().memory_kib)]
+        kdf_memory_kib: u32,
+        // Argon2id number of iterations
+        #[arg(long, default_value_t = KdfParameters::default().iterations)]
+        kdf_iterations: u32,
+        // Argon2id degree of parallelism
+        #[arg(long, default_value_t = KdfParameters::default().parallelism)]
+        kdf_parallelism: u32,
     },
     // user authentication
     Login {
@@ -33,6 +44,33 @@ enum Commands {
         #[arg(short, long)]
         password: String,
     },
+    // user password rotation
+    Rotate {
+        // user name
+        #[arg(short, long)]
+        name: String,
+        // current user password
+        #[arg(short, long)]
+        password: String,
+        // new user password
+        #[arg(long)]
+        new_password: String,
+    },
+    // session refresh
+    Refresh {
+        // session id
+        #[arg(short, long)]
+        session_id: String,
+    },
+    // session logout
+    Logout {
+        // session id
+        #[arg(short, long)]
+        session_id: String,
+        // end every session of the user
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 #[tokio::main]
@@ -45,24 +83,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .expect("Failed to retrieve `CLIENT_DEST_SERVER_ADDR` .env variable");
 
     info!("Connecting to server at address {server_addr}... ");
-    let mut client = ChaumPedersenAuthClient::new(server_addr).await?;
+    let mut client = if server_addr.starts_with("https://") {
+        ChaumPedersenAuthClient::with_tls(server_addr, client_tls_config_from_env()?).await?
+    } else {
+        ChaumPedersenAuthClient::new(server_addr).await?
+    };
 
     match cli.command {
-        Commands::Register { name, password } => {
+        Commands::Register {
+            name,
+            password,
+            kdf_memory_kib,
+            kdf_iterations,
+            kdf_parallelism,
+        } => {
             info!("Registering user with name: {name} ...");
-            let secret = calculate_password_hash(password);
-            client.register_user(&name, &secret).await?;
+            let salt = generate_salt();
+            let kdf_parameters = KdfParameters {
+                memory_kib: kdf_memory_kib,
+                iterations: kdf_iterations,
+                parallelism: kdf_parallelism,
+            };
+            let secret = derive_secret(password, &salt, &kdf_parameters, client.parameters())?;
+            client
+                .register_user(&name, &secret, &salt, &kdf_parameters)
+                .await?;
             println!("User registered successfully !")
         }
         Commands::Login { name, password } => {
             info!("User {name} logging in ...");
-            let secret = calculate_password_hash(password);
+            let (salt, kdf_parameters) = client.get_login_parameters(&name).await?;
+            let secret = derive_secret(password, &salt, &kdf_parameters, client.parameters())?;
             let session_id = client.authenticate_user(&name, &secret).await?;
             println!(
                 "User is successfully authenticated, with session_id = {}",
                 session_id
             );
         }
+        Commands::Rotate {
+            name,
+            password,
+            new_password,
+        } => {
+            info!("Rotating password of user {name} ...");
+            let (salt, kdf_parameters) = client.get_login_parameters(&name).await?;
+            let old_secret = derive_secret(password, &salt, &kdf_parameters, client.parameters())?;
+            let new_secret =
+                derive_secret(new_password, &salt, &kdf_parameters, client.parameters())?;
+            // the rotation must be authorized by a session, which it consumes
+            let session_id = client.authenticate_user(&name, &old_secret).await?;
+            client
+                .rotate_credential(&session_id, &name, &old_secret, &new_secret)
+                .await?;
+            println!("User password rotated successfully !")
+        }
+        Commands::Refresh { session_id } => {
+            info!("Refreshing session {session_id} ...");
+            let expires_at = client.refresh_session(&session_id).await?;
+            println!("Session refreshed successfully, expires at = {expires_at}");
+        }
+        Commands::Logout { session_id, all } => {
+            if all {
+                info!("Revoking all sessions of session {session_id} ...");
+                let revoked = client.revoke_all_sessions(&session_id).await?;
+                println!("Revoked {revoked} session(s) successfully !");
+            } else {
+                info!("Logging out of session {session_id} ...");
+                client.logout(&session_id).await?;
+                println!("Logged out successfully !");
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,74 @@
+//! # TLS
+//!
+//! Loads the TLS configuration of the client: the CA certificates trusted to issue the server's
+//! certificate (on top of the system roots), the client's own certificate and key for mutual TLS, and
+//! the name expected in the server's certificate, when it differs from the host the client connects to.
+use std::{error::Error, fs, io, path::Path};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+/// Loads the TLS configuration of the client.
+///
+/// # Arguments
+/// * `ca_path`: Path to the PEM certificates of the CA issuing the server's certificate, if it isn't
+///   trusted by the system.
+/// * `identity_paths`: Paths to the PEM certificate chain and private key of the client, if the server
+///   requires mutual TLS.
+/// * `domain_name`: Name to verify the server's certificate against, and to send as SNI, instead of the
+///   host of the server address.
+///
+/// # Returns
+/// A `Result` containing the `ClientTlsConfig`, or an `io::Error` if a file can't be read.
+pub fn client_tls_config<P: AsRef<Path>>(
+    ca_path: Option<P>,
+    identity_paths: Option<(P, P)>,
+    domain_name: Option<&str>,
+) -> io::Result<ClientTlsConfig> {
+    let mut tls_config = ClientTlsConfig::new();
+    if let Some(ca_path) = ca_path {
+        tls_config = tls_config.ca_certificate(Certificate::from_pem(fs::read(ca_path)?));
+    }
+    if let Some((cert_path, key_path)) = identity_paths {
+        tls_config = tls_config.identity(Identity::from_pem(
+            fs::read(cert_path)?,
+            fs::read(key_path)?,
+        ));
+    }
+    if let Some(domain_name) = domain_name {
+        tls_config = tls_config.domain_name(domain_name);
+    }
+    Ok(tls_config)
+}
+
+/// Loads the TLS configuration of the client from the environment: it trusts the CA in
+/// `CLIENT_TLS_CA_PATH` on top of the system roots, authenticates with the certificate and key in
+/// `CLIENT_TLS_CERT_PATH` and `CLIENT_TLS_KEY_PATH` for mutual TLS, and expects the server's certificate
+/// to name `CLIENT_TLS_DOMAIN` rather than the address host.
+///
+/// # Returns
+/// A `Result` containing the `ClientTlsConfig`, or an error if only one of the certificate and key is
+/// set, or if a file can't be read.
+pub fn client_tls_config_from_env() -> Result<ClientTlsConfig, Box<dyn Error>> {
+    let identity_paths = match (
+        std::env::var("CLIENT_TLS_CERT_PATH"),
+        std::env::var("CLIENT_TLS_KEY_PATH"),
+    ) {
+        (Ok(cert_path), Ok(key_path)) => Some((cert_path, key_path)),
+        (Err(_), Err(_)) => None,
+        _ => return Err("Failed to configure TLS, both `CLIENT_TLS_CERT_PATH` and `CLIENT_TLS_KEY_PATH` must be set".into()),
+    };
+    Ok(client_tls_config(
+        std::env::var("CLIENT_TLS_CA_PATH").ok(),
+        identity_paths,
+        std::env::var("CLIENT_TLS_DOMAIN").ok().as_deref(),
+    )?)
+}
+
+/// Connects to `server_addr`, over TLS configured from the environment if it is an `https://` address,
+/// see `client_tls_config_from_env`.
+pub async fn connect_from_env(server_addr: &str) -> Result<Channel, Box<dyn Error>> {
+    let mut endpoint = Endpoint::from_shared(server_addr.to_string())?;
+    if server_addr.starts_with("https://") {
+        endpoint = endpoint.tls_config(client_tls_config_from_env()?)?;
+    }
+    Ok(endpoint.connect().await?)
+}
@@ -1,4 +1,10 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../proto/zkp_auth.proto")?;
+    // the file descriptor set is served by the reflection service
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("zkp_auth_descriptor.bin"))
+        .compile(&["../proto/zkp_auth.proto"], &["../proto"])?;
     Ok(())
 }
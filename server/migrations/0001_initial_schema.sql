@@ -0,0 +1,37 @@
+CREATE TABLE users (
+    id TEXT PRIMARY KEY NOT NULL,
+    y1 BLOB NOT NULL,
+    y2 BLOB NOT NULL,
+    salt BLOB NOT NULL,
+    kdf_memory_kib INTEGER NOT NULL,
+    kdf_iterations INTEGER NOT NULL,
+    kdf_parallelism INTEGER NOT NULL,
+    auth_id TEXT,
+    session_id TEXT
+);
+
+CREATE TABLE challenges (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    r1 BLOB NOT NULL,
+    r2 BLOB NOT NULL,
+    c BLOB NOT NULL
+);
+
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
+
+CREATE TABLE pending_credentials (
+    id TEXT PRIMARY KEY NOT NULL,
+    session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+    k BLOB NOT NULL,
+    r BLOB NOT NULL
+);
+
+CREATE TABLE spent_credentials (
+    message BLOB PRIMARY KEY NOT NULL
+);
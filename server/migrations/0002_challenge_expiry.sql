@@ -0,0 +1,7 @@
+-- challenges are short lived, so any challenge left over from before expiry tracking is dropped
+DELETE FROM challenges;
+UPDATE users SET auth_id = NULL;
+
+ALTER TABLE challenges ADD COLUMN expires_at INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX challenges_expires_at ON challenges (expires_at);
@@ -0,0 +1,8 @@
+-- sessions created before session expiry was tracked are considered expired, and are purged
+ALTER TABLE sessions ADD COLUMN issued_at INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN expires_at INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX sessions_expires_at ON sessions (expires_at);
+
+-- a user's sessions are tracked through the `sessions` table only
+ALTER TABLE users DROP COLUMN session_id;
@@ -0,0 +1,2 @@
+-- scopes granted to a session, separated by spaces
+ALTER TABLE sessions ADD COLUMN scopes TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,2 @@
+-- users disabled by an administrator can't authenticate
+ALTER TABLE users ADD COLUMN disabled INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- credential commitments are short lived, so any commitment left over from before expiry tracking is dropped
+DELETE FROM pending_credentials;
+
+ALTER TABLE pending_credentials ADD COLUMN expires_at INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX pending_credentials_expires_at ON pending_credentials (expires_at);
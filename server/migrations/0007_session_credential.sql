@@ -0,0 +1,8 @@
+-- the pending credential commitment of a session, if any, removed by key together with the session
+ALTER TABLE sessions ADD COLUMN credential_id TEXT;
+
+UPDATE sessions SET credential_id = (
+    SELECT id FROM pending_credentials WHERE pending_credentials.session_id = sessions.id
+);
+
+CREATE INDEX pending_credentials_session_id ON pending_credentials (session_id);
@@ -0,0 +1,4 @@
+-- credentials now carry their expiry, so those spent before are dropped: they can no longer be presented
+ALTER TABLE spent_credentials ADD COLUMN expires_at INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX spent_credentials_expires_at ON spent_credentials (expires_at);
@@ -0,0 +1,410 @@
+use crate::{
+    audit::{AuditEvent, AuditLog, RevocationCause},
+    clock::Clock,
+    logging::{fingerprint, record_user},
+    rate_limit::RateLimiter,
+    server_auth::{
+        admin_server::Admin, DeleteUserRequest, DeleteUserResponse, DisableUserRequest,
+        DisableUserResponse, EnableUserRequest, EnableUserResponse, GetUserRequest,
+        GetUserResponse, ListSessionsRequest, ListSessionsResponse, ListUsersRequest,
+        ListUsersResponse, PurgeChallengesRequest, PurgeChallengesResponse, RevokeSessionRequest,
+        RevokeSessionResponse, SessionSummary, UnlockUserRequest, UnlockUserResponse, UserInfo,
+    },
+    state::user_not_found,
+    storage::{AuthStorage, InMemoryStorage},
+    types::User,
+};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+/// Metadata key of the admin credential, sent as `Bearer <admin token>`.
+pub const ADMIN_AUTHORIZATION_HEADER: &str = "authorization";
+
+/// Minimum length, in bytes, of the admin token, e.g. 32 hexadecimal digits of randomness.
+pub const MIN_ADMIN_TOKEN_LEN: usize = 32;
+
+/// Number of users listed per page, unless the request asks for fewer.
+pub const DEFAULT_USERS_PAGE_SIZE: usize = 100;
+
+/// Maximum number of users listed per page.
+pub const MAX_USERS_PAGE_SIZE: usize = 1_000;
+
+/// Serves the administration gRPC service, next to a `PedersenChaumAuthServer` whose state it shares.
+///
+/// Every request must carry the admin token in its `authorization` metadata, as `Bearer <admin token>`.
+/// Session ids are bearer credentials, so the service never discloses them: sessions are identified by
+/// their fingerprint, as logged by the authentication server.
+pub struct PedersenChaumAdminServer<S: AuthStorage = InMemoryStorage> {
+    /// Secret token authenticating administrators
+    admin_token: String,
+    /// The storage backend of the authentication server
+    state: Arc<S>,
+    /// Source of the current time, shared with the authentication server
+    clock: Arc<dyn Clock>,
+    /// Rate limiter of the authentication server, if enabled
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Audit log of the authentication server, if enabled
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl<S: AuthStorage> PedersenChaumAdminServer<S> {
+    pub(crate) fn new(
+        admin_token: String,
+        state: Arc<S>,
+        clock: Arc<dyn Clock>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        audit_log: Option<Arc<AuditLog>>,
+    ) -> Self {
+        Self {
+            admin_token,
+            state,
+            clock,
+            rate_limiter,
+            audit_log,
+        }
+    }
+
+    /// Records an event in the audit log, if enabled.
+    fn audit(&self, event: AuditEvent) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(self.clock.now(), event);
+        }
+    }
+
+    /// Checks that a request carries the admin token.
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get(ADMIN_AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(Status::unauthenticated(
+                "Failed to authorize admin request, missing admin token",
+            ))?;
+        if !bool::from(token.as_bytes().ct_eq(self.admin_token.as_bytes())) {
+            return Err(Status::permission_denied(
+                "Failed to authorize admin request, invalid admin token",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Describes a user, as returned to administrators.
+    fn user_info(&self, user: &User) -> UserInfo {
+        UserInfo {
+            user: user.id.clone(),
+            disabled: user.disabled,
+            active_sessions: user.session_ids.len() as u32,
+            pending_challenge: user.auth_id.is_some(),
+            locked_out: self.rate_limiter.as_ref().is_some_and(|rate_limiter| {
+                rate_limiter
+                    .check_lockout(&user.id, self.clock.now())
+                    .is_err()
+            }),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<S: AuthStorage> Admin for PedersenChaumAdminServer<S> {
+    /// Lifts the lockout of a user, after too many failed authentication attempts.
+    ///
+    /// # Arguments
+    ///
+    /// * `unlock_user_request`: A `Request<UnlockUserRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<UnlockUserResponse>`, telling whether the user was locked out.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the admin token is invalid.
+    async fn unlock_user(
+        &self,
+        unlock_user_request: Request<UnlockUserRequest>,
+    ) -> Result<Response<UnlockUserResponse>, Status> {
+        self.authorize(&unlock_user_request)?;
+        let UnlockUserRequest { user } = unlock_user_request.into_inner();
+        record_user(&user);
+
+        let was_locked = self
+            .rate_limiter
+            .as_ref()
+            .is_some_and(|rate_limiter| rate_limiter.unlock(&user, self.clock.now()));
+        if was_locked {
+            self.audit(AuditEvent::UserUnlocked { user });
+        }
+
+        info!("Admin unlocked user, which was locked out: {was_locked}");
+        Ok(Response::new(UnlockUserResponse { was_locked }))
+    }
+
+    /// Lists registered users, ordered by name, a page at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `list_users_request`: A `Request<ListUsersRequest>` object containing the page size, `DEFAULT_USERS_PAGE_SIZE` if zero and at most `MAX_USERS_PAGE_SIZE`, and the token of the page, empty for the first page.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<ListUsersResponse>`, with the users of the page and the token of the next page, empty on the last page.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the admin token is invalid.
+    async fn list_users(
+        &self,
+        list_users_request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        self.authorize(&list_users_request)?;
+        let ListUsersRequest {
+            page_size,
+            page_token,
+        } = list_users_request.into_inner();
+        let page_size = match page_size as usize {
+            0 => DEFAULT_USERS_PAGE_SIZE,
+            page_size => page_size.min(MAX_USERS_PAGE_SIZE),
+        };
+
+        // the page token is the name of the last user of the previous page
+        let after = (!page_token.is_empty()).then_some(page_token.as_str());
+        let users = self.state.list_users(after, page_size).await?;
+        let next_page_token = match users.last() {
+            Some(last_user) if users.len() == page_size => last_user.id.clone(),
+            _ => String::new(),
+        };
+        Ok(Response::new(ListUsersResponse {
+            users: users.iter().map(|user| self.user_info(user)).collect(),
+            next_page_token,
+        }))
+    }
+
+    /// Describes a registered user.
+    ///
+    /// # Arguments
+    ///
+    /// * `get_user_request`: A `Request<GetUserRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<GetUserResponse>`, with whether the user is disabled or locked out, and its sessions and challenge.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn get_user(
+        &self,
+        get_user_request: Request<GetUserRequest>,
+    ) -> Result<Response<GetUserResponse>, Status> {
+        self.authorize(&get_user_request)?;
+        let GetUserRequest { user } = get_user_request.into_inner();
+        record_user(&user);
+
+        let user_data = self
+            .state
+            .get_user(&user)
+            .await?
+            .ok_or_else(|| user_not_found(&user))?;
+        Ok(Response::new(GetUserResponse {
+            user: Some(self.user_info(&user_data)),
+        }))
+    }
+
+    /// Disables a user, which can no longer authenticate nor rotate its credential until enabled again. Its sessions and pending authentication challenge are removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `disable_user_request`: A `Request<DisableUserRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<DisableUserResponse>`, with the number of revoked sessions.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn disable_user(
+        &self,
+        disable_user_request: Request<DisableUserRequest>,
+    ) -> Result<Response<DisableUserResponse>, Status> {
+        self.authorize(&disable_user_request)?;
+        let DisableUserRequest { user } = disable_user_request.into_inner();
+        record_user(&user);
+
+        let revoked_sessions = self.state.set_user_disabled(&user, true).await?;
+        self.audit(AuditEvent::UserDisabled { user: user.clone() });
+        self.audit(AuditEvent::SessionsRevoked {
+            user,
+            count: revoked_sessions,
+            cause: RevocationCause::UserDisabled,
+        });
+
+        info!("Admin disabled user, revoking {revoked_sessions} sessions");
+        Ok(Response::new(DisableUserResponse {
+            revoked_sessions: revoked_sessions as u32,
+        }))
+    }
+
+    /// Enables a user disabled by an administrator.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable_user_request`: A `Request<EnableUserRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<EnableUserResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn enable_user(
+        &self,
+        enable_user_request: Request<EnableUserRequest>,
+    ) -> Result<Response<EnableUserResponse>, Status> {
+        self.authorize(&enable_user_request)?;
+        let EnableUserRequest { user } = enable_user_request.into_inner();
+        record_user(&user);
+
+        self.state.set_user_disabled(&user, false).await?;
+        self.audit(AuditEvent::UserEnabled { user });
+
+        info!("Admin enabled user");
+        Ok(Response::new(EnableUserResponse {}))
+    }
+
+    /// Deletes a user, together with its sessions and pending authentication challenge. Its name can then be registered again.
+    ///
+    /// # Arguments
+    ///
+    /// * `delete_user_request`: A `Request<DeleteUserRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<DeleteUserResponse>`, with the number of revoked sessions.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn delete_user(
+        &self,
+        delete_user_request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        self.authorize(&delete_user_request)?;
+        let DeleteUserRequest { user } = delete_user_request.into_inner();
+        record_user(&user);
+
+        let revoked_sessions = self.state.delete_user(&user).await?;
+        self.audit(AuditEvent::UserDeleted { user: user.clone() });
+        self.audit(AuditEvent::SessionsRevoked {
+            user,
+            count: revoked_sessions,
+            cause: RevocationCause::UserDeleted,
+        });
+
+        info!("Admin deleted user, revoking {revoked_sessions} sessions");
+        Ok(Response::new(DeleteUserResponse {
+            revoked_sessions: revoked_sessions as u32,
+        }))
+    }
+
+    /// Lists the sessions of a user, from the oldest to the most recent, identified by their fingerprint.
+    ///
+    /// # Arguments
+    ///
+    /// * `list_sessions_request`: A `Request<ListSessionsRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<ListSessionsResponse>`, with the sessions of the user, including expired sessions not swept yet.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn list_sessions(
+        &self,
+        list_sessions_request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        self.authorize(&list_sessions_request)?;
+        let ListSessionsRequest { user } = list_sessions_request.into_inner();
+        record_user(&user);
+
+        let sessions = self.state.user_sessions(&user).await?;
+        Ok(Response::new(ListSessionsResponse {
+            sessions: sessions
+                .into_iter()
+                .map(|session| SessionSummary {
+                    fingerprint: fingerprint(&session.id),
+                    issued_at: session.issued_at,
+                    expires_at: session.expires_at,
+                    scopes: session.scopes,
+                })
+                .collect(),
+        }))
+    }
+
+    /// Revokes a session of a user, identified by its fingerprint.
+    ///
+    /// # Arguments
+    ///
+    /// * `revoke_session_request`: A `Request<RevokeSessionRequest>` object containing the user name and the fingerprint of the session.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<RevokeSessionResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user holds no such session.
+    async fn revoke_session(
+        &self,
+        revoke_session_request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        self.authorize(&revoke_session_request)?;
+        let RevokeSessionRequest {
+            user,
+            fingerprint: session_fingerprint,
+        } = revoke_session_request.into_inner();
+        record_user(&user);
+
+        let session = self
+            .state
+            .user_sessions(&user)
+            .await?
+            .into_iter()
+            .find(|session| fingerprint(&session.id) == session_fingerprint)
+            .ok_or(Status::not_found(
+                "Failed to revoke session, user holds no session with this fingerprint",
+            ))?;
+        if self.state.remove_session(&session.id).await?.is_some() {
+            self.audit(AuditEvent::SessionRevoked {
+                user,
+                session: session_fingerprint.clone(),
+                cause: RevocationCause::Admin,
+            });
+        }
+
+        info!(session = %session_fingerprint, "Admin revoked session");
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+
+    /// Removes the pending authentication challenges held by the storage backend: the expired ones, or all of them. Stateless challenges are not held by the server, so they can't be purged.
+    ///
+    /// # Arguments
+    ///
+    /// * `purge_challenges_request`: A `Request<PurgeChallengesRequest>` object telling whether to purge all challenges, rather than the expired ones only.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<PurgeChallengesResponse>`, with the number of purged challenges.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the admin token is invalid.
+    async fn purge_challenges(
+        &self,
+        purge_challenges_request: Request<PurgeChallengesRequest>,
+    ) -> Result<Response<PurgeChallengesResponse>, Status> {
+        self.authorize(&purge_challenges_request)?;
+        let PurgeChallengesRequest { all } = purge_challenges_request.into_inner();
+
+        let purged = if all {
+            self.state.purge_all_challenges().await?
+        } else {
+            self.state
+                .purge_expired_challenges(self.clock.now())
+                .await?
+        };
+        self.audit(AuditEvent::ChallengesPurged { count: purged });
+
+        info!("Admin purged {purged} authentication challenges");
+        Ok(Response::new(PurgeChallengesResponse {
+            purged: purged as u32,
+        }))
+    }
+}
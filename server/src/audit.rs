@@ -0,0 +1,412 @@
+//! # Audit Log
+//!
+//! The audit log records authentication events as append-only JSON lines: registrations, challenges,
+//! verification successes and failures, session creations and revocations, and administrator actions.
+//! Every record holds the hash of the previous record, and its own hash over its contents and that
+//! link, so that editing, inserting, reordering or removing a record breaks the chain from there on.
+//!
+//! Dropping the last records leaves a valid, shorter chain. The hash of the last record, the head of
+//! the chain, is logged when the server opens and closes the audit log, so that it can be kept
+//! elsewhere and checked later with `verify-audit-log --expected-head`: a truncated log no longer
+//! contains it.
+//!
+//! Like log lines, records never hold protocol values or credentials, only user names and
+//! fingerprints. Failing to write a record is logged, but does not fail the request being audited.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+use tracing::error;
+
+use crate::clock::Timestamp;
+
+/// Previous hash of the first record of a chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An audited event. Challenges and sessions are referred to by fingerprint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    UserRegistered {
+        user: String,
+    },
+    CredentialRotated {
+        user: String,
+    },
+    ChallengeCreated {
+        user: String,
+        auth_id: String,
+    },
+    AuthenticationSucceeded {
+        user: String,
+        auth_id: String,
+    },
+    /// The user is unknown when the challenge itself could not be found.
+    AuthenticationFailed {
+        user: Option<String>,
+        auth_id: String,
+        reason: String,
+    },
+    SessionCreated {
+        user: String,
+        session: String,
+        expires_at: Timestamp,
+    },
+    SessionRevoked {
+        user: String,
+        session: String,
+        cause: RevocationCause,
+    },
+    /// Every session of a user was revoked at once.
+    SessionsRevoked {
+        user: String,
+        count: usize,
+        cause: RevocationCause,
+    },
+    UserUnlocked {
+        user: String,
+    },
+    UserDisabled {
+        user: String,
+    },
+    UserEnabled {
+        user: String,
+    },
+    UserDeleted {
+        user: String,
+    },
+    ChallengesPurged {
+        count: usize,
+    },
+}
+
+/// Why sessions were revoked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationCause {
+    /// The user logged out.
+    Logout,
+    /// The user revoked all of its sessions.
+    RevokeAll,
+    /// The user went over its maximum number of sessions.
+    Evicted,
+    /// The user rotated its credential, consuming the session authorizing the rotation.
+    CredentialRotated,
+    /// An administrator revoked the session.
+    Admin,
+    /// An administrator disabled the user.
+    UserDisabled,
+    /// An administrator deleted the user.
+    UserDeleted,
+}
+
+/// A record of the audit log, linked to the previous one by `prev_hash`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position of the record in the chain, starting at 0
+    pub seq: u64,
+    pub timestamp: Timestamp,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Hash of the previous record, or `GENESIS_HASH` for the first one
+    pub prev_hash: String,
+    /// SHA-256 hash, in hex, of `prev_hash` and of the other fields of the record
+    pub hash: String,
+}
+
+/// The length and head of an audit log chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditLogSummary {
+    pub records: u64,
+    /// Hash of the last record, or `GENESIS_HASH` for an empty log
+    pub head: String,
+}
+
+/// An append-only audit log file, resuming its chain from the last record already written.
+pub struct AuditLog {
+    chain: Mutex<AuditChain>,
+}
+
+struct AuditChain {
+    file: File,
+    summary: AuditLogSummary,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, creating it if it does not exist.
+    ///
+    /// # Returns
+    /// A `Result` containing the audit log, or an `io::Error` if the file can't be opened, or if its last
+    /// record is corrupt, e.g. after a torn write. Only the last record is checked: the whole chain is
+    /// checked with `verify_audit_log`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut summary = AuditLogSummary {
+            records: 0,
+            head: GENESIS_HASH.to_string(),
+        };
+        let mut last_line = None;
+        for line in BufReader::new(&mut file).lines() {
+            last_line = Some(line?);
+            summary.records += 1;
+        }
+        if let Some(line) = last_line {
+            let record = parse_record(&line).map_err(|e| {
+                invalid_data(format!(
+                    "Corrupt last record {}: {e}",
+                    summary.records.saturating_sub(1)
+                ))
+            })?;
+            if record.seq + 1 != summary.records {
+                return Err(invalid_data(format!(
+                    "Last record has sequence number {}, expected {}",
+                    record.seq,
+                    summary.records - 1
+                )));
+            }
+            summary.head = record.hash;
+        }
+        Ok(Self {
+            chain: Mutex::new(AuditChain { file, summary }),
+        })
+    }
+
+    /// Returns the current length and head of the chain.
+    pub fn summary(&self) -> AuditLogSummary {
+        self.lock().summary.clone()
+    }
+
+    /// Appends a record of `event`, logging any failure to write it.
+    pub fn record(&self, timestamp: Timestamp, event: AuditEvent) {
+        if let Err(e) = self.append(timestamp, event) {
+            error!(error = %e, "Failed to write audit record");
+        }
+    }
+
+    /// Appends a record of `event`, linked to the head of the chain, which it then becomes.
+    ///
+    /// # Returns
+    /// A `Result` containing the appended record, or an `io::Error` if it can't be written, in which case
+    /// the chain is left unchanged.
+    pub fn append(&self, timestamp: Timestamp, event: AuditEvent) -> io::Result<AuditRecord> {
+        let mut chain = self.lock();
+        let mut record = AuditRecord {
+            seq: chain.summary.records,
+            timestamp,
+            event,
+            prev_hash: chain.summary.head.clone(),
+            hash: String::new(),
+        };
+        record.hash = record_hash(serde_json::to_value(&record)?)?;
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        // a single write per record, so that concurrent writers never interleave within a line
+        chain.file.write_all(line.as_bytes())?;
+        chain.file.flush()?;
+        chain.summary = AuditLogSummary {
+            records: record.seq + 1,
+            head: record.hash.clone(),
+        };
+        Ok(record)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AuditChain> {
+        self.chain
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Checks the whole chain of the audit log at `path`: every record must hash to its `hash`, link to
+/// the previous record, and follow its sequence number. With `expected_head`, a head recorded earlier,
+/// the chain must also still contain a record with that hash, which detects truncation.
+///
+/// # Returns
+/// A `Result` containing the length and head of the chain, or an `io::Error` describing the first
+/// broken record.
+pub fn verify_audit_log<P: AsRef<Path>>(
+    path: P,
+    expected_head: Option<&str>,
+) -> io::Result<AuditLogSummary> {
+    let mut summary = AuditLogSummary {
+        records: 0,
+        head: GENESIS_HASH.to_string(),
+    };
+    let mut found_expected_head = expected_head.is_none() || expected_head == Some(GENESIS_HASH);
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let record = parse_record(&line)
+            .map_err(|e| invalid_data(format!("Record {}: {e}", summary.records)))?;
+        if record.seq != summary.records {
+            return Err(invalid_data(format!(
+                "Record {}: unexpected sequence number {}, records were removed or reordered",
+                summary.records, record.seq
+            )));
+        }
+        if record.prev_hash != summary.head {
+            return Err(invalid_data(format!(
+                "Record {}: not linked to the previous record, records were removed or reordered",
+                summary.records
+            )));
+        }
+        found_expected_head |= expected_head == Some(record.hash.as_str());
+        summary = AuditLogSummary {
+            records: record.seq + 1,
+            head: record.hash,
+        };
+    }
+    if !found_expected_head {
+        return Err(invalid_data(format!(
+            "Expected head not found in the {} records of the chain, the audit log was truncated",
+            summary.records
+        )));
+    }
+    Ok(summary)
+}
+
+/// Parses a record, checking its hash against its contents.
+fn parse_record(line: &str) -> Result<AuditRecord, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("malformed record, {e}"))?;
+    let record: AuditRecord =
+        serde_json::from_value(value.clone()).map_err(|e| format!("malformed record, {e}"))?;
+    let hash = record_hash(value).map_err(|e| e.to_string())?;
+    if hash != record.hash {
+        return Err("hash mismatch, the record was edited".to_string());
+    }
+    Ok(record)
+}
+
+/// Hashes a record, given as a JSON object, over all its fields but `hash`.
+fn record_hash(mut record: Value) -> io::Result<String> {
+    record
+        .as_object_mut()
+        .ok_or_else(|| invalid_data("Audit record is not an object"))?
+        .remove("hash");
+    let digest = Sha256::digest(serde_json::to_vec(&record)?);
+    Ok(digest.iter().fold(String::new(), |mut hash, byte| {
+        let _ = write!(hash, "{byte:02x}");
+        hash
+    }))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+    use uuid::Uuid;
+
+    fn temporary_path() -> PathBuf {
+        std::env::temp_dir().join(format!("zkp-audit-{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn write_records(path: &Path, count: u64) -> AuditLogSummary {
+        let audit_log = AuditLog::open(path).unwrap();
+        for timestamp in 0..count {
+            audit_log
+                .append(
+                    timestamp,
+                    AuditEvent::UserRegistered {
+                        user: format!("user_{timestamp}"),
+                    },
+                )
+                .unwrap();
+        }
+        audit_log.summary()
+    }
+
+    fn rewrite_lines(path: &Path, rewrite: impl FnOnce(&mut Vec<String>)) {
+        let mut lines = fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        rewrite(&mut lines);
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_chain_verifies_and_resumes() {
+        let path = temporary_path();
+        let summary = write_records(&path, 3);
+        assert_eq!(summary.records, 3);
+        assert_eq!(verify_audit_log(&path, None).unwrap(), summary);
+
+        // reopening the log continues the same chain
+        let resumed = write_records(&path, 2);
+        assert_eq!(resumed.records, 5);
+        assert_eq!(verify_audit_log(&path, None).unwrap(), resumed);
+        assert_eq!(
+            verify_audit_log(&path, Some(&summary.head)).unwrap(),
+            resumed
+        );
+
+        let line = fs::read_to_string(&path).unwrap();
+        let first = line.lines().next().unwrap();
+        assert!(first.contains(r#""event":"user_registered""#));
+        assert!(first.contains(GENESIS_HASH));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_edits_are_detected() {
+        let path = temporary_path();
+        write_records(&path, 3);
+        rewrite_lines(&path, |lines| {
+            lines[1] = lines[1].replace("user_1", "user_9");
+        });
+        let error = verify_audit_log(&path, None).unwrap_err();
+        assert!(error.to_string().contains("Record 1: hash mismatch"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_removals_are_detected() {
+        let path = temporary_path();
+        write_records(&path, 3);
+        rewrite_lines(&path, |lines| {
+            lines.remove(1);
+        });
+        let error = verify_audit_log(&path, None).unwrap_err();
+        assert!(error.to_string().contains("Record 1: unexpected sequence"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_truncation_is_detected() {
+        let path = temporary_path();
+        let summary = write_records(&path, 3);
+        rewrite_lines(&path, |lines| {
+            lines.pop();
+        });
+        // a shorter chain is still a valid chain
+        assert_eq!(verify_audit_log(&path, None).unwrap().records, 2);
+        let error = verify_audit_log(&path, Some(&summary.head)).unwrap_err();
+        assert!(error.to_string().contains("truncated"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_last_record_is_rejected_on_open() {
+        let path = temporary_path();
+        write_records(&path, 2);
+        rewrite_lines(&path, |lines| {
+            lines[1].truncate(20);
+        });
+        assert!(AuditLog::open(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Unix timestamp, in seconds.
+pub type Timestamp = u64;
+
+/// Source of the current time, for every expiry decision of the server.
+///
+/// The server never reads the system time directly, so that tests can control the passage of time
+/// with a `ManualClock`.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current Unix timestamp, in seconds.
+    fn now(&self) -> Timestamp;
+}
+
+/// A `Clock` backed by the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// A `Clock` that only moves forward when told to, mostly useful for testing.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a new clock, stopped at the given timestamp.
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    /// Moves the clock forward by `seconds`.
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_advances() {
+        let clock = ManualClock::new(1_000);
+        assert_eq!(clock.now(), 1_000);
+        clock.advance(30);
+        assert_eq!(clock.now(), 1_030);
+    }
+}
@@ -0,0 +1,768 @@
+//! # Configuration
+//!
+//! The server reads its settings from a TOML file, in which every setting is optional and falls back
+//! to its default. Environment variables override the file (with the names of the former `.env` based
+//! configuration, e.g. `SERVER_ADDR` or `CHALLENGE_TTL_SECS`), and command line flags override both.
+//!
+//! ```toml
+//! [server]
+//! listen_addr = "0.0.0.0:5001"
+//!
+//! [protocol]
+//! parameters_path = "parameters.toml"
+//!
+//! [sessions]
+//! ttl_secs = 3600
+//! scopes = ["profile"]
+//!
+//! [storage]
+//! backend = "sqlite"
+//! path = "zkp_auth.sqlite"
+//! ```
+use chaum_pedersen::{Parameters, DEFAULT_PARAMS};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    admin::MIN_ADMIN_TOKEN_LEN,
+    logging::LogFormat,
+    rate_limit::{RateLimit, RateLimitConfig},
+    server::{
+        DEFAULT_CHALLENGE_TTL, DEFAULT_CREDENTIAL_TTL, DEFAULT_MAX_CREDENTIAL_COMMITMENTS,
+        DEFAULT_MAX_SESSIONS_PER_USER, DEFAULT_SESSION_TTL,
+    },
+    verification_pool::DEFAULT_QUEUE_CAPACITY,
+};
+
+/// Default address the server listens on.
+pub const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:5001";
+
+/// Configuration of the server.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ServerSettings,
+    pub admin: AdminSettings,
+    pub protocol: ProtocolSettings,
+    pub challenges: ChallengeSettings,
+    pub sessions: SessionSettings,
+    pub rate_limit: RateLimitSettings,
+    pub storage: StorageSettings,
+    pub tls: TlsSettings,
+    pub logging: LoggingSettings,
+    pub metrics: MetricsSettings,
+    pub audit: AuditSettings,
+    pub verification: VerificationSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Address of the authentication service (`SERVER_ADDR`)
+    pub listen_addr: SocketAddr,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            listen_addr: DEFAULT_LISTEN_ADDR.parse().unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    /// Token authenticating administrators, at least `MIN_ADMIN_TOKEN_LEN` bytes long, serving the admin
+    /// service if set (`ADMIN_TOKEN`)
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProtocolSettings {
+    /// Parameters file, as written by `server gen-params`, instead of the default parameter set
+    /// (`PARAMETERS_PATH`)
+    pub parameters_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChallengeSettings {
+    /// Time to live of authentication challenges (`CHALLENGE_TTL_SECS`)
+    pub ttl_secs: u64,
+    /// Key sealing stateless challenges, if enabled (`CHALLENGE_KEY_PATH`)
+    pub key_path: Option<PathBuf>,
+    /// Secret deriving decoy users, if enabled (`DECOY_SECRET_PATH`)
+    pub decoy_secret_path: Option<PathBuf>,
+}
+
+impl Default for ChallengeSettings {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_CHALLENGE_TTL.as_secs(),
+            key_path: None,
+            decoy_secret_path: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+    /// Time to live of sessions (`SESSION_TTL_SECS`)
+    pub ttl_secs: u64,
+    /// Maximum number of concurrent sessions per user (`MAX_SESSIONS_PER_USER`)
+    pub max_per_user: usize,
+    /// Scopes granted to every session, without whitespace (`SESSION_SCOPES`, separated by commas)
+    pub scopes: Vec<String>,
+    /// Key signing session tokens, if enabled (`SESSION_TOKEN_KEY_PATH`)
+    pub token_key_path: Option<PathBuf>,
+    /// Retired token signing keys, whose public keys stay published
+    /// (`SESSION_TOKEN_RETIRED_KEY_PATHS`, separated by commas)
+    pub retired_token_key_paths: Vec<PathBuf>,
+    /// Key blindly signing anonymous credentials, generated at startup if unset (`CREDENTIAL_KEY_PATH`)
+    pub credential_key_path: Option<PathBuf>,
+    /// Maximum number of credential commitments open at once, across all sessions
+    /// (`MAX_CREDENTIAL_COMMITMENTS`)
+    pub max_credential_commitments: usize,
+    /// Period on which credential expiries are aligned, credentials living one to two periods
+    /// (`CREDENTIAL_TTL_SECS`)
+    pub credential_ttl_secs: u64,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            ttl_secs: DEFAULT_SESSION_TTL.as_secs(),
+            max_per_user: DEFAULT_MAX_SESSIONS_PER_USER,
+            scopes: vec![],
+            token_key_path: None,
+            retired_token_key_paths: vec![],
+            credential_key_path: None,
+            max_credential_commitments: DEFAULT_MAX_CREDENTIAL_COMMITMENTS,
+            credential_ttl_secs: DEFAULT_CREDENTIAL_TTL.as_secs(),
+        }
+    }
+}
+
+/// Rate limits and lockouts, see `RateLimitConfig`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    /// `RATE_LIMIT_USER_BURST`
+    pub user_burst: u32,
+    /// `RATE_LIMIT_USER_REFILL_SECS`
+    pub user_refill_secs: u64,
+    /// `RATE_LIMIT_IP_BURST`
+    pub ip_burst: u32,
+    /// `RATE_LIMIT_IP_REFILL_SECS`
+    pub ip_refill_secs: u64,
+    /// `LOCKOUT_MAX_FAILURES`
+    pub lockout_max_failures: u32,
+    /// `LOCKOUT_BASE_SECS`
+    pub lockout_base_secs: u64,
+    /// `LOCKOUT_MAX_SECS`
+    pub lockout_max_secs: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        let config = RateLimitConfig::default();
+        Self {
+            user_burst: config.user_limit.burst,
+            user_refill_secs: config.user_limit.refill_interval.as_secs(),
+            ip_burst: config.ip_limit.burst,
+            ip_refill_secs: config.ip_limit.refill_interval.as_secs(),
+            lockout_max_failures: config.max_failures,
+            lockout_base_secs: config.base_lockout.as_secs(),
+            lockout_max_secs: config.max_lockout.as_secs(),
+        }
+    }
+}
+
+impl From<&RateLimitSettings> for RateLimitConfig {
+    fn from(settings: &RateLimitSettings) -> Self {
+        Self {
+            user_limit: RateLimit {
+                burst: settings.user_burst,
+                refill_interval: Duration::from_secs(settings.user_refill_secs),
+            },
+            ip_limit: RateLimit {
+                burst: settings.ip_burst,
+                refill_interval: Duration::from_secs(settings.ip_refill_secs),
+            },
+            max_failures: settings.lockout_max_failures,
+            base_lockout: Duration::from_secs(settings.lockout_base_secs),
+            max_lockout: Duration::from_secs(settings.lockout_max_secs),
+        }
+    }
+}
+
+/// Storage backend of the server state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// In memory, lost whenever the server stops
+    #[default]
+    Memory,
+    /// In a SQLite database
+    Sqlite,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "memory" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(format!(
+                "unknown storage backend `{value}`, expected `memory` or `sqlite`"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    /// `STORAGE_BACKEND`
+    pub backend: StorageBackend,
+    /// Path of the SQLite database (`DATABASE_PATH`, which also selects the SQLite backend)
+    pub path: Option<PathBuf>,
+    /// Snapshot of the in-memory backend, restored (then deleted) on startup and written on shutdown
+    /// (`SNAPSHOT_PATH`)
+    pub snapshot_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM certificate chain of the server, serving over TLS if set (`TLS_CERT_PATH`)
+    pub cert_path: Option<PathBuf>,
+    /// PEM private key of the server (`TLS_KEY_PATH`)
+    pub key_path: Option<PathBuf>,
+    /// PEM certificates of the CA issuing client certificates, for mutual TLS (`TLS_CLIENT_CA_PATH`)
+    pub client_ca_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    /// Log filter, in `tracing_subscriber::EnvFilter` syntax (`RUST_LOG`)
+    pub level: String,
+    /// Format of the log lines, `text` or `json` (`LOG_FORMAT`)
+    pub format: LogFormat,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    /// Address of the Prometheus metrics HTTP endpoint, serving metrics if set (`METRICS_ADDR`)
+    pub listen_addr: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSettings {
+    /// Audit log of authentication events, appended to if set (`AUDIT_LOG_PATH`)
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationSettings {
+    /// Worker threads running proof verifications, one per available CPU if unset
+    /// (`VERIFICATION_WORKERS`)
+    pub workers: Option<usize>,
+    /// Verifications waiting for a worker, beyond which they are rejected with `RESOURCE_EXHAUSTED`
+    /// (`VERIFICATION_QUEUE_CAPACITY`)
+    pub queue_capacity: usize,
+}
+
+impl Default for VerificationSettings {
+    fn default() -> Self {
+        Self {
+            workers: None,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+fn invalid_data<E: Display>(context: &str, e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{context}: {e}"))
+}
+
+/// Splits a list of values separated by commas.
+fn split_list<T: From<String>>(value: &str) -> Vec<T> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| T::from(item.to_string()))
+        .collect()
+}
+
+/// Environment variables overriding the configuration.
+struct Env<F: Fn(&str) -> Option<String>>(F);
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    /// Parses the variable `name`, if set.
+    fn get<T>(&self, name: &str) -> io::Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        (self.0)(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| invalid_data(&format!("Invalid value of `{name}`"), e))
+            })
+            .transpose()
+    }
+
+    /// Overrides `target` with the variable `name`, if set.
+    fn set<T>(&self, name: &str, target: &mut T) -> io::Result<()>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if let Some(value) = self.get(name)? {
+            *target = value;
+        }
+        Ok(())
+    }
+
+    /// Overrides the optional `target` with the variable `name`, if set.
+    fn set_some<T>(&self, name: &str, target: &mut Option<T>) -> io::Result<()>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if let Some(value) = self.get(name)? {
+            *target = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Overrides `target` with the list of values separated by commas in the variable `name`, if set.
+    fn set_list<T: From<String>>(&self, name: &str, target: &mut Vec<T>) {
+        if let Some(value) = (self.0)(name) {
+            *target = split_list(&value);
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration from a TOML file.
+    ///
+    /// # Arguments
+    /// * `path`: Path to the TOML file.
+    ///
+    /// # Returns
+    /// A `Result` containing the `ServerConfig`, or an `io::Error` if the file can't be read or holds
+    /// an invalid configuration.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        toml::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            invalid_data(
+                &format!("Failed to parse configuration `{}`", path.display()),
+                e,
+            )
+        })
+    }
+
+    /// Overrides the configuration with environment variables.
+    ///
+    /// # Arguments
+    /// * `env`: Lookup of environment variables, e.g. `|name| std::env::var(name).ok()`.
+    ///
+    /// # Returns
+    /// A `Result` containing the overridden `ServerConfig`, or an `io::Error` if a variable holds an
+    /// invalid value.
+    pub fn with_env<F: Fn(&str) -> Option<String>>(mut self, env: F) -> io::Result<Self> {
+        let env = Env(env);
+        env.set("SERVER_ADDR", &mut self.server.listen_addr)?;
+        env.set_some("ADMIN_TOKEN", &mut self.admin.token)?;
+        env.set_some("PARAMETERS_PATH", &mut self.protocol.parameters_path)?;
+
+        env.set("CHALLENGE_TTL_SECS", &mut self.challenges.ttl_secs)?;
+        env.set_some("CHALLENGE_KEY_PATH", &mut self.challenges.key_path)?;
+        env.set_some("DECOY_SECRET_PATH", &mut self.challenges.decoy_secret_path)?;
+
+        env.set("SESSION_TTL_SECS", &mut self.sessions.ttl_secs)?;
+        env.set("MAX_SESSIONS_PER_USER", &mut self.sessions.max_per_user)?;
+        env.set_list("SESSION_SCOPES", &mut self.sessions.scopes);
+        env.set_some("SESSION_TOKEN_KEY_PATH", &mut self.sessions.token_key_path)?;
+        env.set_list(
+            "SESSION_TOKEN_RETIRED_KEY_PATHS",
+            &mut self.sessions.retired_token_key_paths,
+        );
+        env.set_some(
+            "CREDENTIAL_KEY_PATH",
+            &mut self.sessions.credential_key_path,
+        )?;
+        env.set(
+            "MAX_CREDENTIAL_COMMITMENTS",
+            &mut self.sessions.max_credential_commitments,
+        )?;
+        env.set(
+            "CREDENTIAL_TTL_SECS",
+            &mut self.sessions.credential_ttl_secs,
+        )?;
+
+        let rate_limit = &mut self.rate_limit;
+        env.set("RATE_LIMIT_USER_BURST", &mut rate_limit.user_burst)?;
+        env.set(
+            "RATE_LIMIT_USER_REFILL_SECS",
+            &mut rate_limit.user_refill_secs,
+        )?;
+        env.set("RATE_LIMIT_IP_BURST", &mut rate_limit.ip_burst)?;
+        env.set("RATE_LIMIT_IP_REFILL_SECS", &mut rate_limit.ip_refill_secs)?;
+        env.set("LOCKOUT_MAX_FAILURES", &mut rate_limit.lockout_max_failures)?;
+        env.set("LOCKOUT_BASE_SECS", &mut rate_limit.lockout_base_secs)?;
+        env.set("LOCKOUT_MAX_SECS", &mut rate_limit.lockout_max_secs)?;
+
+        env.set("STORAGE_BACKEND", &mut self.storage.backend)?;
+        // a database path alone selects the SQLite backend, as with the former `.env` configuration
+        if let Some(path) = env.get("DATABASE_PATH")? {
+            self.storage.backend = StorageBackend::Sqlite;
+            self.storage.path = Some(path);
+        }
+        env.set_some("SNAPSHOT_PATH", &mut self.storage.snapshot_path)?;
+
+        env.set_some("TLS_CERT_PATH", &mut self.tls.cert_path)?;
+        env.set_some("TLS_KEY_PATH", &mut self.tls.key_path)?;
+        env.set_some("TLS_CLIENT_CA_PATH", &mut self.tls.client_ca_path)?;
+
+        env.set("RUST_LOG", &mut self.logging.level)?;
+        env.set("LOG_FORMAT", &mut self.logging.format)?;
+        env.set_some("METRICS_ADDR", &mut self.metrics.listen_addr)?;
+        env.set_some("AUDIT_LOG_PATH", &mut self.audit.path)?;
+        env.set_some("VERIFICATION_WORKERS", &mut self.verification.workers)?;
+        env.set(
+            "VERIFICATION_QUEUE_CAPACITY",
+            &mut self.verification.queue_capacity,
+        )?;
+        Ok(self)
+    }
+
+    /// Checks the consistency of the configuration, without reading any of the files it refers to.
+    ///
+    /// # Returns
+    /// A `Result` containing `()`, or an `io::Error` describing the first inconsistent setting.
+    pub fn check(&self) -> io::Result<()> {
+        let invalid_input = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            return Err(invalid_input(
+                "Failed to configure TLS, both `tls.cert_path` and `tls.key_path` must be set",
+            ));
+        }
+        if self.tls.client_ca_path.is_some() && self.tls.cert_path.is_none() {
+            return Err(invalid_input(
+                "Failed to configure TLS, mutual TLS requires `tls.cert_path` and `tls.key_path`",
+            ));
+        }
+        if self.storage.backend == StorageBackend::Sqlite && self.storage.path.is_none() {
+            return Err(invalid_input(
+                "Failed to configure storage, the SQLite backend requires `storage.path`",
+            ));
+        }
+        if self.storage.backend == StorageBackend::Sqlite && self.storage.snapshot_path.is_some() {
+            return Err(invalid_input(
+                "Failed to configure storage, `storage.snapshot_path` only applies to the in-memory backend",
+            ));
+        }
+        if self.metrics.listen_addr.is_some()
+            && self.metrics.listen_addr == Some(self.server.listen_addr)
+        {
+            return Err(invalid_input(
+                "Failed to configure metrics, they must be served on another address than the service",
+            ));
+        }
+        if self.challenges.ttl_secs == 0
+            || self.sessions.ttl_secs == 0
+            || self.sessions.credential_ttl_secs == 0
+        {
+            return Err(invalid_input(
+                "Failed to configure TTLs, challenges, sessions and credentials must live at least a second",
+            ));
+        }
+        if self
+            .admin
+            .token
+            .as_ref()
+            .is_some_and(|token| token.len() < MIN_ADMIN_TOKEN_LEN)
+        {
+            return Err(invalid_input(&format!(
+                "Failed to configure admin service, token must be at least {MIN_ADMIN_TOKEN_LEN} bytes long",
+            )));
+        }
+        if self.sessions.max_credential_commitments == 0 {
+            return Err(invalid_input(
+                "Failed to configure sessions, at least one credential commitment must be allowed",
+            ));
+        }
+        // scopes are stored space separated, as in OAuth, so a scope can't hold whitespace
+        if self
+            .sessions
+            .scopes
+            .iter()
+            .any(|scope| scope.is_empty() || scope.contains(char::is_whitespace))
+        {
+            return Err(invalid_input(
+                "Failed to configure sessions, scopes must be non-empty and free of whitespace",
+            ));
+        }
+        if self.verification.workers == Some(0) || self.verification.queue_capacity == 0 {
+            return Err(invalid_input(
+                "Failed to configure verification, at least one worker and one queued verification are needed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Loads the parameter set, from `protocol.parameters_path` if set.
+    ///
+    /// # Returns
+    /// A `Result` containing the validated `Parameters`, or an `io::Error` if the parameters file can't
+    /// be read or holds unsound parameters.
+    pub fn parameters(&self) -> io::Result<Parameters> {
+        match &self.protocol.parameters_path {
+            Some(path) => load_parameters(path),
+            None => Ok(DEFAULT_PARAMS.clone()),
+        }
+    }
+
+    /// Serializes the configuration into TOML, with its secrets redacted.
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+        if config.admin.token.is_some() {
+            config.admin.token = Some("<redacted>".to_string());
+        }
+        toml::to_string_pretty(&config).expect("Failed to serialize configuration")
+    }
+}
+
+/// A parameter set, as stored in a parameters file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParametersFile {
+    bit_size: u64,
+    /// Big integers, as decimal strings
+    p: String,
+    q: String,
+    g: String,
+    h: String,
+}
+
+/// Serializes a parameter set into a TOML parameters file.
+pub fn parameters_to_toml(parameters: &Parameters) -> String {
+    toml::to_string(&ParametersFile {
+        bit_size: parameters.bit_size,
+        p: parameters.p.to_string(),
+        q: parameters.q.to_string(),
+        g: parameters.g.to_string(),
+        h: parameters.h.to_string(),
+    })
+    .expect("Failed to serialize parameters")
+}
+
+/// Loads a parameter set from a TOML parameters file, as written by `parameters_to_toml`.
+///
+/// # Arguments
+/// * `path`: Path to the parameters file.
+///
+/// # Returns
+/// A `Result` containing the validated `Parameters`, or an `io::Error` if the file can't be read or
+/// holds unsound parameters.
+pub fn load_parameters<P: AsRef<Path>>(path: P) -> io::Result<Parameters> {
+    let path = path.as_ref();
+    let context = format!("Failed to load parameters `{}`", path.display());
+    let file: ParametersFile =
+        toml::from_str(&fs::read_to_string(path)?).map_err(|e| invalid_data(&context, e))?;
+    let parse = |value: &str| {
+        value
+            .parse()
+            .map_err(|e| invalid_data(&context, format!("invalid integer `{value}`: {e}")))
+    };
+    let parameters = Parameters {
+        bit_size: file.bit_size,
+        p: parse(&file.p)?,
+        q: parse(&file.q)?,
+        g: parse(&file.g)?,
+        h: parse(&file.h)?,
+    };
+    parameters
+        .validate()
+        .map_err(|e| invalid_data(&context, e))?;
+    Ok(parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [server]
+            listen_addr = "127.0.0.1:6001"
+
+            [sessions]
+            ttl_secs = 120
+            scopes = ["profile", "email"]
+
+            [rate_limit]
+            ip_burst = 50
+
+            [storage]
+            backend = "sqlite"
+            path = "zkp_auth.sqlite"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.listen_addr, "127.0.0.1:6001".parse().unwrap());
+        assert_eq!(config.sessions.ttl_secs, 120);
+        assert_eq!(config.sessions.scopes, vec!["profile", "email"]);
+        assert_eq!(
+            config.sessions.max_per_user, DEFAULT_MAX_SESSIONS_PER_USER,
+            "unset settings keep their default"
+        );
+        assert_eq!(config.rate_limit.ip_burst, 50);
+        assert_eq!(
+            config.rate_limit.user_burst,
+            RateLimitSettings::default().user_burst
+        );
+        assert_eq!(config.storage.backend, StorageBackend::Sqlite);
+        assert!(config.check().is_ok());
+
+        // the default configuration round-trips through TOML
+        let config = ServerConfig::default();
+        assert_eq!(
+            toml::from_str::<ServerConfig>(&config.to_redacted_toml()).unwrap(),
+            config
+        );
+
+        assert!(toml::from_str::<ServerConfig>("[server]\nlisten_adr = \"\"").is_err());
+        assert!(toml::from_str::<ServerConfig>("[storage]\nbackend = \"postgres\"").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let config = ServerConfig::default()
+            .with_env(env(&[
+                ("SERVER_ADDR", "127.0.0.1:7001"),
+                ("SESSION_SCOPES", "profile, email,"),
+                ("LOCKOUT_MAX_FAILURES", "3"),
+                ("DATABASE_PATH", "zkp_auth.sqlite"),
+                ("ADMIN_TOKEN", "secret"),
+                ("AUDIT_LOG_PATH", "audit.jsonl"),
+                ("VERIFICATION_WORKERS", "2"),
+                ("CREDENTIAL_TTL_SECS", "3600"),
+            ]))
+            .unwrap();
+        assert_eq!(config.server.listen_addr, "127.0.0.1:7001".parse().unwrap());
+        assert_eq!(config.sessions.scopes, vec!["profile", "email"]);
+        assert_eq!(config.rate_limit.lockout_max_failures, 3);
+        assert_eq!(config.storage.backend, StorageBackend::Sqlite);
+        assert_eq!(config.storage.path, Some(PathBuf::from("zkp_auth.sqlite")));
+        assert_eq!(RateLimitConfig::from(&config.rate_limit).max_failures, 3);
+        assert_eq!(config.audit.path, Some(PathBuf::from("audit.jsonl")));
+        assert_eq!(config.verification.workers, Some(2));
+        assert_eq!(config.verification.queue_capacity, DEFAULT_QUEUE_CAPACITY);
+        assert_eq!(config.sessions.credential_ttl_secs, 3600);
+        assert!(!config.to_redacted_toml().contains("secret"));
+
+        assert!(ServerConfig::default()
+            .with_env(env(&[("CHALLENGE_TTL_SECS", "soon")]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_config() {
+        let mut config = ServerConfig::default();
+        config.tls.cert_path = Some("server.pem".into());
+        assert!(config.check().is_err());
+        config.tls.key_path = Some("server.key".into());
+        assert!(config.check().is_ok());
+
+        config.storage.backend = StorageBackend::Sqlite;
+        assert!(config.check().is_err());
+        config.storage.path = Some("zkp_auth.sqlite".into());
+        assert!(config.check().is_ok());
+        config.storage.snapshot_path = Some("zkp_auth.snapshot.json".into());
+        assert!(config.check().is_err());
+
+        let mut config = ServerConfig::default();
+        config.verification.workers = Some(0);
+        assert!(config.check().is_err());
+
+        let mut config = ServerConfig::default();
+        config.admin.token = Some(String::new());
+        assert!(config.check().is_err());
+        config.admin.token = Some("a".repeat(MIN_ADMIN_TOKEN_LEN - 1));
+        assert!(config.check().is_err());
+        config.admin.token = Some("a".repeat(MIN_ADMIN_TOKEN_LEN));
+        assert!(config.check().is_ok());
+
+        let mut config = ServerConfig::default();
+        config.sessions.max_credential_commitments = 0;
+        assert!(config.check().is_err());
+
+        let mut config = ServerConfig::default();
+        config.sessions.credential_ttl_secs = 0;
+        assert!(config.check().is_err());
+
+        let mut config = ServerConfig::default();
+        config.sessions.scopes = vec!["read write".to_string()];
+        assert!(config.check().is_err());
+        config.sessions.scopes = vec!["read".to_string(), "write".to_string()];
+        assert!(config.check().is_ok());
+    }
+
+    #[test]
+    fn test_parameters_file() {
+        let path =
+            std::env::temp_dir().join(format!("zkp_auth_params_{}.toml", std::process::id()));
+        fs::write(&path, parameters_to_toml(&DEFAULT_PARAMS)).unwrap();
+        assert_eq!(load_parameters(&path).unwrap(), *DEFAULT_PARAMS);
+
+        let mut parameters = DEFAULT_PARAMS.clone();
+        parameters.h = parameters.g.clone();
+        fs::write(&path, parameters_to_toml(&parameters)).unwrap();
+        assert_eq!(
+            load_parameters(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
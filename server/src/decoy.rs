@@ -0,0 +1,318 @@
+//! # Decoy Users
+//!
+//! To keep user names from being enumerated, the server can answer requests about unknown users as if
+//! they were registered: each unknown user name is given a decoy salt and public key, derived from a
+//! server secret, so that they are stable across requests and indistinguishable from real ones. Login
+//! parameters and authentication challenges are then issued as usual, and the verification runs the
+//! same proof check as for registered users, which fails just like a wrong password would.
+//!
+//! Decoy users share a single registration profile, a salt length and key derivation parameters, which
+//! registered users are held to while decoys are enabled, so that login parameters don't tell them apart.
+//!
+//! Stored challenges of decoy users are kept apart from the storage backend, in memory, with the same
+//! single use and replacement semantics as real challenges.
+use chaum_pedersen::{transcript::Transcript, Parameters};
+use num_bigint::BigInt;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+    sync::Mutex,
+};
+use tonic::Status;
+
+use crate::{
+    clock::Timestamp,
+    types::{Challenge, KdfParameters, User, MAX_SALT_LEN, MIN_SALT_LEN},
+};
+
+/// Size, in bytes, of the decoy secret.
+pub const DECOY_SECRET_SIZE: usize = 32;
+
+/// Default maximum number of pending decoy challenges.
+pub const DEFAULT_DECOY_CHALLENGE_CAPACITY: usize = 100_000;
+
+/// Protocol label of the transcripts deriving decoy users.
+const DECOY_LABEL: &[u8] = b"zkp_auth/decoy-user/v1";
+
+#[derive(Default)]
+struct DecoyChallenges {
+    challenges: HashMap<String, Challenge>,
+    /// Pending challenge of each decoy user
+    auth_ids: HashMap<String, String>,
+    /// Expiry and id of every challenge, ordered by expiry
+    expiries: BTreeSet<(Timestamp, String)>,
+}
+
+impl DecoyChallenges {
+    fn insert(&mut self, challenge: Challenge) {
+        if let Some(auth_id) = self
+            .auth_ids
+            .insert(challenge.user_id.clone(), challenge.id.clone())
+        {
+            if let Some(replaced) = self.challenges.remove(&auth_id) {
+                self.expiries.remove(&(replaced.expires_at, auth_id));
+            }
+        }
+        self.expiries
+            .insert((challenge.expires_at, challenge.id.clone()));
+        self.challenges.insert(challenge.id.clone(), challenge);
+    }
+
+    fn take(&mut self, auth_id: &str) -> Option<Challenge> {
+        let challenge = self.challenges.remove(auth_id)?;
+        self.expiries
+            .remove(&(challenge.expires_at, auth_id.to_string()));
+        if self.auth_ids.get(&challenge.user_id).map(String::as_str) == Some(auth_id) {
+            self.auth_ids.remove(&challenge.user_id);
+        }
+        Some(challenge)
+    }
+
+    /// Removes the challenge closest to expiry, if any.
+    fn pop_first(&mut self) -> Option<Challenge> {
+        let (_, auth_id) = self.expiries.first()?.clone();
+        self.take(&auth_id)
+    }
+
+    fn purge_expired(&mut self, now: Timestamp) -> usize {
+        let mut purged = 0;
+        while self
+            .expiries
+            .first()
+            .is_some_and(|(expires_at, _)| *expires_at <= now)
+        {
+            self.pop_first();
+            purged += 1;
+        }
+        purged
+    }
+}
+
+/// Derives decoy users from a server secret, and keeps their pending challenges.
+pub struct DecoyUsers {
+    secret: [u8; DECOY_SECRET_SIZE],
+    parameters: Parameters,
+    /// Salt length, in bytes, of every user
+    salt_len: usize,
+    /// Key derivation parameters of every user
+    kdf_parameters: KdfParameters,
+    challenges: Mutex<DecoyChallenges>,
+    /// Maximum number of pending decoy challenges
+    capacity: usize,
+}
+
+impl DecoyUsers {
+    /// Creates decoy users derived from `secret`, which must be shared by every replica and kept
+    /// constant, for decoy users to stay the same over time.
+    pub fn new(secret: &[u8; DECOY_SECRET_SIZE], parameters: Parameters) -> Self {
+        Self {
+            secret: *secret,
+            parameters,
+            salt_len: MIN_SALT_LEN,
+            kdf_parameters: KdfParameters::default(),
+            challenges: Mutex::new(DecoyChallenges::default()),
+            capacity: DEFAULT_DECOY_CHALLENGE_CAPACITY,
+        }
+    }
+
+    /// Loads the decoy secret from a file holding its `DECOY_SECRET_SIZE` raw bytes.
+    pub fn load<P: AsRef<Path>>(path: P, parameters: Parameters) -> io::Result<Self> {
+        let secret: [u8; DECOY_SECRET_SIZE] = fs::read(path)?.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Failed to load decoy secret, secret must be {DECOY_SECRET_SIZE} bytes long"
+                ),
+            )
+        })?;
+        Ok(Self::new(&secret, parameters))
+    }
+
+    /// Sets the maximum number of pending decoy challenges, beyond which those closest to expiry are dropped.
+    pub fn with_challenge_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the registration profile shared by decoy and registered users, replacing the default one: a
+    /// salt of `MIN_SALT_LEN` bytes, as generated by the client, and default key derivation parameters.
+    ///
+    /// # Panics
+    /// Panics if `salt_len` is out of the `MIN_SALT_LEN..=MAX_SALT_LEN` bounds.
+    pub fn with_registration_profile(
+        mut self,
+        salt_len: usize,
+        kdf_parameters: KdfParameters,
+    ) -> Self {
+        assert!(
+            (MIN_SALT_LEN..=MAX_SALT_LEN).contains(&salt_len),
+            "salt length out of bounds"
+        );
+        self.salt_len = salt_len;
+        self.kdf_parameters = kdf_parameters;
+        self
+    }
+
+    /// Checks that a registering user follows the registration profile of decoy users.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or a `Status` error if the salt length or the key derivation
+    /// parameters differ from the profile.
+    pub(crate) fn check_registration(
+        &self,
+        salt: &[u8],
+        kdf_parameters: &KdfParameters,
+    ) -> Result<(), Status> {
+        if salt.len() != self.salt_len || kdf_parameters != &self.kdf_parameters {
+            return Err(Status::invalid_argument(format!(
+                "Failed to register user, the server requires a salt of {} bytes and key derivation \
+                parameters {:?}",
+                self.salt_len, self.kdf_parameters
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the decoy user of the given name, with a salt and a public key derived from the secret,
+    /// following the registration profile.
+    pub(crate) fn user(&self, user_name: &str) -> User {
+        let mut transcript = Transcript::new(DECOY_LABEL);
+        transcript.append_message(b"secret", &self.secret);
+        transcript.append_message(b"user", user_name.as_bytes());
+
+        let salt_modulus = BigInt::from(1) << (8 * self.salt_len);
+        let mut salt = transcript
+            .challenge_scalar(b"salt", &salt_modulus)
+            .to_bytes_be()
+            .1;
+        // restore the leading zero bytes of the salt
+        salt.splice(..0, vec![0u8; self.salt_len - salt.len()]);
+
+        User {
+            id: user_name.to_string(),
+            y1: transcript.challenge_scalar(b"y1", &self.parameters.p),
+            y2: transcript.challenge_scalar(b"y2", &self.parameters.p),
+            salt,
+            kdf_parameters: self.kdf_parameters.clone(),
+            auth_id: None,
+            session_ids: vec![],
+            disabled: false,
+        }
+    }
+
+    /// Keeps a challenge of a decoy user, replacing its previous one. Beyond the capacity, the challenge
+    /// closest to expiry is dropped, in logarithmic time, so that a full table doesn't hold up others.
+    pub(crate) fn create_challenge(&self, challenge: Challenge) {
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.insert(challenge);
+        if challenges.challenges.len() > self.capacity {
+            challenges.pop_first();
+        }
+    }
+
+    /// Removes and returns a challenge of a decoy user, if any.
+    pub(crate) fn take_challenge(&self, auth_id: &str) -> Option<Challenge> {
+        self.challenges.lock().unwrap().take(auth_id)
+    }
+
+    /// Removes every decoy challenge expired at `now`.
+    ///
+    /// # Returns
+    /// The number of removed challenges.
+    pub(crate) fn purge_expired_challenges(&self, now: Timestamp) -> usize {
+        self.challenges.lock().unwrap().purge_expired(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaum_pedersen::DEFAULT_PARAMS;
+
+    fn challenge(auth_id: &str, user_name: &str, expires_at: Timestamp) -> Challenge {
+        Challenge {
+            id: auth_id.to_string(),
+            c: BigInt::from(10_000),
+            r1: BigInt::from(100),
+            r2: BigInt::from(200),
+            user_id: user_name.to_string(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_decoy_users_are_stable() {
+        let decoys = DecoyUsers::new(&[7u8; DECOY_SECRET_SIZE], DEFAULT_PARAMS.clone());
+        let user = decoys.user("user_name");
+        assert_eq!(user, decoys.user("user_name"));
+        assert_eq!(user.salt.len(), MIN_SALT_LEN);
+
+        // but differ across users and secrets
+        let other_user = decoys.user("other_user");
+        assert_ne!(user.salt, other_user.salt);
+        assert_ne!(user.y1, other_user.y1);
+        let other_decoys = DecoyUsers::new(&[8u8; DECOY_SECRET_SIZE], DEFAULT_PARAMS.clone());
+        assert_ne!(user.salt, other_decoys.user("user_name").salt);
+    }
+
+    #[test]
+    fn test_registration_profile() {
+        let kdf_parameters = KdfParameters {
+            iterations: 3,
+            ..KdfParameters::default()
+        };
+        let decoys = DecoyUsers::new(&[7u8; DECOY_SECRET_SIZE], DEFAULT_PARAMS.clone())
+            .with_registration_profile(32, kdf_parameters.clone());
+        let user = decoys.user("user_name");
+        assert_eq!(user.salt.len(), 32);
+        assert_eq!(user.kdf_parameters, kdf_parameters);
+
+        // registered users are held to the same profile
+        assert!(decoys
+            .check_registration(&[1u8; 32], &kdf_parameters)
+            .is_ok());
+        assert!(decoys
+            .check_registration(&[1u8; 16], &kdf_parameters)
+            .is_err());
+        assert!(decoys
+            .check_registration(&[1u8; 32], &KdfParameters::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_decoy_challenges() {
+        let decoys = DecoyUsers::new(&[7u8; DECOY_SECRET_SIZE], DEFAULT_PARAMS.clone())
+            .with_challenge_capacity(2);
+        decoys.create_challenge(challenge("auth_id_1", "user_name", 1_000));
+        // a new challenge replaces the previous one of the user
+        decoys.create_challenge(challenge("auth_id_2", "user_name", 1_000));
+        assert_eq!(decoys.take_challenge("auth_id_1"), None);
+        assert_eq!(
+            decoys.take_challenge("auth_id_2"),
+            Some(challenge("auth_id_2", "user_name", 1_000))
+        );
+        assert_eq!(decoys.take_challenge("auth_id_2"), None);
+
+        decoys.create_challenge(challenge("auth_id_3", "user_1", 500));
+        decoys.create_challenge(challenge("auth_id_4", "user_2", 1_000));
+        assert_eq!(decoys.purge_expired_challenges(500), 1);
+
+        // challenges beyond the capacity are dropped, closest to expiry first, one at a time
+        decoys.create_challenge(challenge("auth_id_5", "user_3", 800));
+        decoys.create_challenge(challenge("auth_id_6", "user_4", 800));
+        assert_eq!(decoys.take_challenge("auth_id_5"), None);
+        assert!(decoys.take_challenge("auth_id_4").is_some());
+        assert!(decoys.take_challenge("auth_id_6").is_some());
+
+        // replaced and taken challenges leave nothing behind
+        decoys.create_challenge(challenge("auth_id_7", "user_5", 900));
+        decoys.create_challenge(challenge("auth_id_8", "user_5", 1_000));
+        let challenges = decoys.challenges.lock().unwrap();
+        assert_eq!(challenges.challenges.len(), 1);
+        assert_eq!(
+            challenges.expiries,
+            BTreeSet::from([(1_000, "auth_id_8".to_string())])
+        );
+    }
+}
@@ -1,9 +1,30 @@
+#![allow(clippy::result_large_err)]
+
+pub mod admin;
+pub mod audit;
+pub mod clock;
+pub mod config;
+pub mod decoy;
+pub mod logging;
+pub mod memory;
+pub mod metrics;
+pub mod rate_limit;
+pub mod sealed_challenge;
 pub mod server;
+pub mod snapshot;
+pub mod sqlite;
 pub mod state;
+pub mod storage;
 #[cfg(test)]
 pub mod tests;
+pub mod tls;
 pub mod types;
+pub mod verification_pool;
 
 pub mod server_auth {
     tonic::include_proto!("zkp_auth");
+
+    /// Encoded file descriptor set of the `zkp_auth` package, served by the reflection service.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("zkp_auth_descriptor");
 }
@@ -0,0 +1,208 @@
+//! # Logging
+//!
+//! The server logs through `tracing`, as text or JSON lines. `RequestTracingLayer` opens a span per RPC
+//! with the fields:
+//!
+//! * `rpc`: the RPC, e.g. `zkp_auth.Auth/Register`
+//! * `request_id`: the `x-request-id` of the request if it carries a well-formed one, or a fresh UUID,
+//!   echoed in the `x-request-id` of the response
+//! * `user`: the user name, recorded by the handlers once known
+//! * `outcome`: the gRPC status code of the response, e.g. `Ok` or `Unauthenticated`
+//!
+//! Protocol values (public keys, commitments, challenges, responses, signatures) and credentials
+//! (session ids, tokens and challenge ids) are never logged as is. When they help to correlate events,
+//! they are logged as a `fingerprint`, from which they can't be recovered.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{field::Empty, info, Instrument, Span, Subscriber};
+use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+use uuid::Uuid;
+
+use crate::metrics::grpc_code;
+
+/// Header carrying the request id, in requests and responses.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Maximum length of a request id supplied by a client.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Number of bytes of the SHA-256 digest kept in a fingerprint.
+const FINGERPRINT_SIZE: usize = 8;
+
+/// Format of the log lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the current span
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown log format `{value}`, expected `text` or `json`"
+            )),
+        }
+    }
+}
+
+/// Builds the subscriber collecting the logs of the server.
+///
+/// # Arguments
+/// * `filter`: Log filter, in `EnvFilter` syntax, e.g. `info` or `info,server=debug`.
+/// * `format`: Format of the log lines.
+/// * `writer`: Destination of the log lines, e.g. `std::io::stderr`.
+///
+/// # Returns
+/// A `Result` containing the subscriber, or an error message if the filter is invalid.
+pub fn subscriber<W>(
+    filter: &str,
+    format: LogFormat,
+    writer: W,
+) -> Result<Box<dyn Subscriber + Send + Sync>, String>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(filter).map_err(|e| format!("invalid log filter: {e}"))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    Ok(match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    })
+}
+
+/// Fingerprints a protocol value or credential, as the first bytes of its SHA-256 digest in hex, so
+/// that log lines can refer to it without disclosing it.
+pub fn fingerprint(value: impl AsRef<[u8]>) -> String {
+    Sha256::digest(value.as_ref())
+        .iter()
+        .take(FINGERPRINT_SIZE)
+        .fold(String::new(), |mut fingerprint, byte| {
+            let _ = write!(fingerprint, "{byte:02x}");
+            fingerprint
+        })
+}
+
+/// Records the user of the current request in its span.
+pub fn record_user(user: &str) {
+    Span::current().record("user", user);
+}
+
+/// Extracts the request id supplied by a client, if well-formed: short and made of alphanumeric
+/// characters, `-` and `_`, so that it can't forge log lines.
+fn client_request_id<B>(request: &http::Request<B>) -> Option<String> {
+    let request_id = request.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let well_formed = !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    well_formed.then(|| request_id.to_string())
+}
+
+/// Tower layer opening a span per request to the gRPC services it wraps, see the module documentation.
+#[derive(Clone, Default)]
+pub struct RequestTracingLayer;
+
+impl<S> Layer<S> for RequestTracingLayer {
+    type Service = RequestTracingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestTracingService { inner }
+    }
+}
+
+/// Service tracing the requests to its inner gRPC service, see `RequestTracingLayer`.
+#[derive(Clone)]
+pub struct RequestTracingService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RequestTracingService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // the service polled ready is the one that must handle the request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let request_id = client_request_id(&request).unwrap_or_else(|| Uuid::new_v4().to_string());
+        let span = tracing::info_span!(
+            "rpc",
+            rpc = request.uri().path().trim_start_matches('/'),
+            request_id = %request_id,
+            user = Empty,
+            outcome = Empty,
+        );
+        let future = span.in_scope(|| inner.call(request));
+        Box::pin(
+            async move {
+                let mut response = future.await?;
+                Span::current().record("outcome", format!("{:?}", grpc_code(&response)));
+                info!("Finished request");
+                if let Ok(request_id) = request_id.parse() {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(b"abc"), "ba7816bf8f01cfea");
+        assert_ne!(fingerprint(b"abc"), fingerprint(b"abd"));
+    }
+
+    #[test]
+    fn test_client_request_id() {
+        let request = |request_id: &str| {
+            http::Request::builder()
+                .header(REQUEST_ID_HEADER, request_id)
+                .body(())
+                .unwrap()
+        };
+        assert_eq!(
+            client_request_id(&request("req-42_a")),
+            Some("req-42_a".to_string())
+        );
+        assert_eq!(client_request_id(&request("")), None);
+        assert_eq!(client_request_id(&request("forged\"}, {\"x")), None);
+        assert_eq!(client_request_id(&request(&"a".repeat(65))), None);
+        assert_eq!(client_request_id(&http::Request::new(())), None);
+    }
+}
@@ -1,24 +1,527 @@
-use log::info;
-use server::{server::PedersenChaumAuthServer, server_auth::auth_server::AuthServer};
-use tonic::transport::Server;
+use chaum_pedersen::{blind_signature::BlindSigner, Parameters};
+use clap::{Parser, Subcommand};
+use server::{
+    audit::{verify_audit_log, AuditLog, AuditLogSummary},
+    config::{parameters_to_toml, ServerConfig, StorageBackend},
+    decoy::DecoyUsers,
+    logging::{self, LogFormat, RequestTracingLayer},
+    metrics::{serve_metrics, Metrics, MetricsLayer},
+    rate_limit::RateLimiter,
+    sealed_challenge::ChallengeSealer,
+    server::PedersenChaumAuthServer,
+    server_auth::{self, admin_server::AdminServer, auth_server::AuthServer},
+    snapshot::StateSnapshot,
+    sqlite::SqliteStorage,
+    state::PedersenChaumAuthServerState,
+    storage::{AuthStorage, InMemoryStorage},
+    tls::server_tls_config,
+    verification_pool::{default_workers, VerificationPool},
+};
+use session_token::{TokenSigningKey, TokenVerifyingKey};
+use std::{
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tonic::transport::{Server, ServerTlsConfig};
+use tower::util::option_layer;
+use tracing::{error, info, warn};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+/// Interval between two probes of the storage backend, reported by the health service.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Size of a credential key file, from which the blind signing key is derived.
+const CREDENTIAL_KEY_SIZE: usize = 32;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// TOML configuration file, every setting falling back to its default if unset
+    #[arg(short, long, global = true, env = "SERVER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address of the authentication service
+    #[arg(long, global = true)]
+    listen_addr: Option<SocketAddr>,
+    /// Storage backend of the server state: `memory` or `sqlite`
+    #[arg(long, global = true)]
+    storage: Option<StorageBackend>,
+    /// Path of the SQLite database, selecting the SQLite backend
+    #[arg(long, global = true)]
+    database_path: Option<PathBuf>,
+    /// Parameters file, as written by `gen-params`
+    #[arg(long, global = true)]
+    parameters_path: Option<PathBuf>,
+    /// Snapshot of the in-memory state, restored on startup and written on shutdown
+    #[arg(long, global = true)]
+    snapshot_path: Option<PathBuf>,
+    /// Address of the Prometheus metrics HTTP endpoint
+    #[arg(long, global = true)]
+    metrics_addr: Option<SocketAddr>,
+    /// Audit log of authentication events, appended to by `serve` and checked by `verify-audit-log`
+    #[arg(long, global = true)]
+    audit_log_path: Option<PathBuf>,
+    /// Log filter, e.g. `info` or `info,server=debug`
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    /// Format of the log lines: `text` or `json`
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serves the authentication service, the default command
+    Serve,
+    /// Generates a fresh parameter set over a safe prime field
+    GenParams {
+        /// Size, in bits, of the prime field
+        #[arg(long, default_value_t = 256)]
+        bits: u64,
+        /// Parameters file to write, instead of the standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Loads the configuration and every file it refers to, then prints it with its secrets redacted
+    CheckConfig,
+    /// Exports the state of the configured storage backend as a JSON snapshot
+    ExportState {
+        /// Snapshot file to write, instead of the standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Checks the hash chain of the audit log, detecting edited, removed or reordered records
+    VerifyAuditLog {
+        /// Head of the chain logged by the server earlier, which the audit log must still contain, so
+        /// that truncation is detected too
+        #[arg(long)]
+        expected_head: Option<String>,
+    },
+}
+
+impl Cli {
+    /// Loads the configuration file, overridden by environment variables and then by flags.
+    fn config(&self) -> Result<ServerConfig, Box<dyn Error>> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        }
+        .with_env(|name| std::env::var(name).ok())?;
+        if let Some(listen_addr) = self.listen_addr {
+            config.server.listen_addr = listen_addr;
+        }
+        if let Some(storage) = self.storage {
+            config.storage.backend = storage;
+        }
+        if let Some(database_path) = &self.database_path {
+            config.storage.backend = StorageBackend::Sqlite;
+            config.storage.path = Some(database_path.clone());
+        }
+        if let Some(snapshot_path) = &self.snapshot_path {
+            config.storage.snapshot_path = Some(snapshot_path.clone());
+        }
+        if let Some(parameters_path) = &self.parameters_path {
+            config.protocol.parameters_path = Some(parameters_path.clone());
+        }
+        if let Some(metrics_addr) = self.metrics_addr {
+            config.metrics.listen_addr = Some(metrics_addr);
+        }
+        if let Some(audit_log_path) = &self.audit_log_path {
+            config.audit.path = Some(audit_log_path.clone());
+        }
+        if let Some(log_level) = &self.log_level {
+            config.logging.level = log_level.clone();
+        }
+        if let Some(log_format) = self.log_format {
+            config.logging.format = log_format;
+        }
+        config.check()?;
+        Ok(config)
+    }
+}
+
+/// Components of the server loaded from the files its configuration refers to.
+struct Components {
+    parameters: Parameters,
+    /// TLS configuration, serving plaintext if unset
+    tls: Option<ServerTlsConfig>,
+    /// Session token signing key, and the public keys of retired signing keys
+    session_tokens: Option<(TokenSigningKey, Vec<TokenVerifyingKey>)>,
+    /// Sealer of stateless authentication challenges
+    challenge_sealer: Option<ChallengeSealer>,
+    /// Decoy users answering for unknown user names
+    decoys: Option<DecoyUsers>,
+    /// Blind signer of anonymous credentials, with a key generated at startup if unset
+    credential_signer: Option<BlindSigner>,
+}
+
+impl Components {
+    fn load(config: &ServerConfig) -> Result<Self, Box<dyn Error>> {
+        let parameters = config.parameters()?;
+        let tls = match (&config.tls.cert_path, &config.tls.key_path) {
+            (Some(cert_path), Some(key_path)) => Some(server_tls_config(
+                cert_path,
+                key_path,
+                config.tls.client_ca_path.as_ref(),
+            )?),
+            _ => None,
+        };
+        let session_tokens = match &config.sessions.token_key_path {
+            Some(key_path) => {
+                let retired_keys = config
+                    .sessions
+                    .retired_token_key_paths
+                    .iter()
+                    .map(|key_path| Ok(TokenSigningKey::load(key_path)?.verifying_key()))
+                    .collect::<Result<_, std::io::Error>>()?;
+                Some((TokenSigningKey::load(key_path)?, retired_keys))
+            }
+            None => None,
+        };
+        let challenge_sealer = config
+            .challenges
+            .key_path
+            .as_ref()
+            .map(ChallengeSealer::load)
+            .transpose()?;
+        let decoys = config
+            .challenges
+            .decoy_secret_path
+            .as_ref()
+            .map(|secret_path| DecoyUsers::load(secret_path, parameters.clone()))
+            .transpose()?;
+        let credential_signer = config
+            .sessions
+            .credential_key_path
+            .as_ref()
+            .map(|key_path| load_credential_signer(key_path, parameters.clone()))
+            .transpose()?;
+        Ok(Self {
+            parameters,
+            tls,
+            session_tokens,
+            challenge_sealer,
+            decoys,
+            credential_signer,
+        })
+    }
+}
+
+/// Loads the blind signer of credentials from a file holding `CREDENTIAL_KEY_SIZE` raw bytes.
+fn load_credential_signer(path: &Path, parameters: Parameters) -> std::io::Result<BlindSigner> {
+    let key: [u8; CREDENTIAL_KEY_SIZE] = fs::read(path)?.try_into().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to load credential key, key must be {CREDENTIAL_KEY_SIZE} bytes long"),
+        )
+    })?;
+    Ok(BlindSigner::from_seed(parameters, &key))
+}
+
+async fn serve<S: AuthStorage>(
+    service: PedersenChaumAuthServer<S>,
+    config: ServerConfig,
+    components: Components,
+) -> Result<(), Box<dyn Error>> {
+    let Components {
+        parameters,
+        tls,
+        session_tokens,
+        challenge_sealer,
+        decoys,
+        credential_signer,
+    } = components;
+    let snapshot_path = config.storage.snapshot_path.clone();
+    let challenge_ttl = Duration::from_secs(config.challenges.ttl_secs);
+    let mut service = service
+        .with_parameters(parameters)
+        .with_challenge_ttl(challenge_ttl)
+        .with_session_ttl(Duration::from_secs(config.sessions.ttl_secs))
+        .with_max_sessions_per_user(config.sessions.max_per_user)
+        .with_max_credential_commitments(config.sessions.max_credential_commitments)
+        .with_credential_ttl(Duration::from_secs(config.sessions.credential_ttl_secs))
+        .with_session_scopes(config.sessions.scopes)
+        .with_rate_limiter(RateLimiter::new((&config.rate_limit).into()));
+    let verification_workers = config.verification.workers.unwrap_or_else(default_workers);
+    info!(
+        "Verifying proofs with {verification_workers} workers, queuing at most {} verifications",
+        config.verification.queue_capacity
+    );
+    service = service.with_verification_pool(VerificationPool::new(
+        verification_workers,
+        config.verification.queue_capacity,
+    ));
+    if let Some((signing_key, retired_keys)) = session_tokens {
+        info!(
+            "Issuing session tokens signed with key: {}",
+            signing_key.key_id()
+        );
+        service = service.with_session_tokens(signing_key, retired_keys);
+    }
+    if let Some(challenge_sealer) = challenge_sealer {
+        info!("Issuing stateless authentication challenges");
+        service = service.with_stateless_challenges(challenge_sealer);
+    }
+    if let Some(decoys) = decoys {
+        info!("Answering for unknown users with decoy users");
+        service = service.with_enumeration_resistance(decoys);
+    }
+    match credential_signer {
+        Some(credential_signer) => {
+            info!("Signing credentials with the configured key");
+            service = service.with_credential_signer(credential_signer);
+        }
+        None => warn!("Signing credentials with a key generated at startup, valid until shutdown"),
+    }
+    let audit_log = match &config.audit.path {
+        Some(audit_log_path) => {
+            let audit_log = Arc::new(AuditLog::open(audit_log_path).map_err(|e| {
+                format!("Failed to open audit log {}: {e}", audit_log_path.display())
+            })?);
+            let AuditLogSummary { records, head } = audit_log.summary();
+            info!(
+                "Appending to audit log at: {}, after {records} records with head: {head}",
+                audit_log_path.display()
+            );
+            service = service.with_audit_log(audit_log.clone());
+            Some(audit_log)
+        }
+        None => None,
+    };
+
+    let metrics = match config.metrics.listen_addr {
+        Some(metrics_addr) => {
+            let metrics = Arc::new(Metrics::new());
+            let listener = std::net::TcpListener::bind(metrics_addr)?;
+            info!("Serving metrics at address: {metrics_addr}");
+            let served_metrics = metrics.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics(listener, served_metrics).await {
+                    error!("Failed to serve metrics: {e}");
+                }
+            });
+            service = service.with_metrics(metrics.clone());
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    // expired challenges are swept at twice the rate they expire
+    service.spawn_sweeper((challenge_ttl / 2).max(Duration::from_secs(1)));
 
-    dotenv::dotenv().expect("Failed to load .env variables");
-    let server_addr = std::env::var("SERVER_ADDR")
-        .expect("Failed to retrieve `SERVER_ADDR` .env variable")
-        .parse()?;
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    service.spawn_health_reporter(health_reporter, HEALTH_CHECK_INTERVAL);
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(server_auth::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
 
-    let service = PedersenChaumAuthServer::new();
+    let admin_service = config.admin.token.map(|admin_token| {
+        info!("Serving the admin service");
+        AdminServer::new(service.admin_server(admin_token))
+    });
 
+    let mut server = Server::builder()
+        .layer(RequestTracingLayer)
+        .layer(option_layer(metrics.map(MetricsLayer::new)));
+    match tls {
+        Some(tls) => {
+            info!("Serving over TLS");
+            server = server.tls_config(tls)?;
+        }
+        None => info!("Serving plaintext, without TLS"),
+    }
+
+    let server_addr = config.server.listen_addr;
     info!("Starting server at address: {server_addr} ...");
 
-    Server::builder()
+    let storage = service.storage();
+    server
         .add_service(AuthServer::new(service))
-        .serve(server_addr)
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_optional_service(admin_service)
+        .serve_with_shutdown(server_addr, shutdown_signal())
         .await?;
+    info!("Server stopped, in-flight requests drained");
+
+    if let Some(audit_log) = audit_log {
+        let AuditLogSummary { records, head } = audit_log.summary();
+        info!("Closed audit log after {records} records with head: {head}");
+    }
+
+    if let Some(snapshot_path) = snapshot_path {
+        let snapshot = StateSnapshot::from(&storage.export_state().await?);
+        snapshot.save(&snapshot_path)?;
+        info!(
+            "Saved a snapshot of {} users and {} sessions to: {}",
+            snapshot.users.len(),
+            snapshot.sessions.len(),
+            snapshot_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Resolves on SIGINT or, on Unix, SIGTERM, on which the server stops accepting connections and drains
+/// in-flight requests.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("Received SIGINT, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
 
+/// Loads the in-memory state from the snapshot at `snapshot_path`, if any, then deletes the snapshot,
+/// so that a server stopping without writing a new one never comes back with a stale state, e.g. with
+/// revoked sessions or old public keys. Spent credentials are dropped unless
+/// `keep_spent_credentials` is set, as they belong to the blind signing key of the previous run, which
+/// is only kept when read from a key file.
+fn restore_state(
+    snapshot_path: &Path,
+    keep_spent_credentials: bool,
+) -> Result<PedersenChaumAuthServerState, Box<dyn Error>> {
+    if !snapshot_path.exists() {
+        info!(
+            "No snapshot at: {}, starting from an empty state",
+            snapshot_path.display()
+        );
+        return Ok(PedersenChaumAuthServerState::default());
+    }
+    let mut snapshot = StateSnapshot::load(snapshot_path)
+        .map_err(|e| format!("Failed to restore {}: {e}", snapshot_path.display()))?;
+    if !keep_spent_credentials {
+        snapshot.spent_credentials.clear();
+    }
+    let (users, sessions) = (snapshot.users.len(), snapshot.sessions.len());
+    let state = snapshot.into_state()?;
+    fs::remove_file(snapshot_path)
+        .map_err(|e| format!("Failed to remove {}: {e}", snapshot_path.display()))?;
+    info!(
+        "Restored {users} users and {sessions} sessions from: {}, and removed it",
+        snapshot_path.display()
+    );
+    Ok(state)
+}
+
+/// Writes `contents` to `output`, or to the standard output if unset.
+fn write_output(output: Option<PathBuf>, contents: &str) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(output) => fs::write(output, contents)?,
+        None => print!("{contents}"),
+    }
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // a `.env` file is optional, its variables override the configuration file
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    if let Some(Command::GenParams { bits, output }) = cli.command {
+        let parameters = Parameters::generate(bits)?;
+        return write_output(output, &parameters_to_toml(&parameters));
+    }
+
+    let config = cli.config()?;
+    tracing::subscriber::set_global_default(logging::subscriber(
+        &config.logging.level,
+        config.logging.format,
+        std::io::stderr,
+    )?)?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            let components = Components::load(&config)?;
+            match (config.storage.backend, &config.storage.path) {
+                (StorageBackend::Sqlite, Some(database_path)) => {
+                    info!("Using SQLite storage at: {}", database_path.display());
+                    let storage = SqliteStorage::open(database_path)?;
+                    serve(
+                        PedersenChaumAuthServer::with_storage(storage),
+                        config,
+                        components,
+                    )
+                    .await
+                }
+                _ => {
+                    info!("Using in-memory storage");
+                    let state = match &config.storage.snapshot_path {
+                        Some(snapshot_path) => restore_state(
+                            snapshot_path,
+                            config.sessions.credential_key_path.is_some(),
+                        )?,
+                        None => PedersenChaumAuthServerState::default(),
+                    };
+                    serve(
+                        PedersenChaumAuthServer::with_storage(InMemoryStorage::from(state)),
+                        config,
+                        components,
+                    )
+                    .await
+                }
+            }
+        }
+        Command::CheckConfig => {
+            Components::load(&config)?;
+            print!("{}", config.to_redacted_toml());
+            Ok(())
+        }
+        Command::ExportState { output } => {
+            let storage = match (config.storage.backend, &config.storage.path) {
+                (StorageBackend::Sqlite, Some(database_path)) => {
+                    SqliteStorage::open(database_path)?
+                }
+                _ => {
+                    return Err(
+                        "Failed to export state, the in-memory backend only lives within a running server"
+                            .into(),
+                    )
+                }
+            };
+            let state = storage.export_state().await?;
+            write_output(output, &StateSnapshot::from(&state).to_json())
+        }
+        Command::VerifyAuditLog { expected_head } => {
+            let audit_log_path = config
+                .audit
+                .path
+                .ok_or("Failed to verify audit log, no audit log is configured")?;
+            let AuditLogSummary { records, head } =
+                verify_audit_log(&audit_log_path, expected_head.as_deref()).map_err(|e| {
+                    format!(
+                        "Audit log {} failed verification: {e}",
+                        audit_log_path.display()
+                    )
+                })?;
+            println!("Audit log is intact: {records} records, head {head}");
+            Ok(())
+        }
+        Command::GenParams { .. } => unreachable!(),
+    }
+}
@@ -0,0 +1,1846 @@
+use dashmap::{mapref::entry::Entry, DashMap};
+use num_bigint::BigInt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tonic::Status;
+
+use crate::{
+    clock::Timestamp,
+    state::{
+        check_enabled, user_not_found, user_not_registered, ChallengeId, CredentialId,
+        PedersenChaumAuthServerState, SessionId, UserId,
+    },
+    storage::AuthStorage,
+    types::{Challenge, KdfParameters, PendingCredential, Session, User},
+};
+
+/// In-memory storage backend, keeping users, challenges and sessions in concurrent hash maps, one per
+/// table. Its contents are lost whenever the server restarts, unless saved to a snapshot.
+///
+/// The maps are sharded, so that requests about different users, challenges or sessions never wait on
+/// each other. A user's entry guards the links to its challenge and sessions: operations spanning
+/// several maps lock the user's entry first, then the entries of the other maps, always in the order
+/// users, challenges, sessions, pending credentials, so that they are atomic without deadlocking.
+#[derive(Debug, Default)]
+pub struct InMemoryStorage {
+    users: DashMap<UserId, User>,
+    challenges: DashMap<ChallengeId, Challenge>,
+    sessions: DashMap<SessionId, Session>,
+    pending_credentials: DashMap<CredentialId, PendingCredential>,
+    /// Number of pending credentials, bounded across every session
+    open_credential_commitments: AtomicUsize,
+    /// Expiry of the credentials already presented, by signed message
+    spent_credentials: DashMap<Vec<u8>, Timestamp>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_name`: A `String` representing the unique name of the user. This serves as the user's identifier.
+    /// * `y1`: A `BigInt` representing the first cryptographic component associated with the user.
+    /// * `y2`: A `BigInt` representing the second cryptographic component associated with the user.
+    /// * `salt`: The user's random salt, used by its client for the password key derivation.
+    /// * `kdf_parameters`: The Argon2id cost parameters used by the user's client for the password key derivation.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or a `Status` error, if a user with the same name is already registered. An existing user's keys can only be replaced through a key rotation, proving knowledge of the old secret.
+    pub(crate) fn register_user(
+        &self,
+        user_name: String,
+        y1: BigInt,
+        y2: BigInt,
+        salt: Vec<u8>,
+        kdf_parameters: KdfParameters,
+    ) -> Result<(), Status> {
+        match self.users.entry(user_name) {
+            Entry::Occupied(_) => Err(Status::already_exists(
+                "Failed to register user, user name is already taken",
+            )),
+            Entry::Vacant(entry) => {
+                let id = entry.key().clone();
+                entry.insert(User {
+                    id,
+                    y1,
+                    y2,
+                    salt,
+                    kdf_parameters,
+                    auth_id: None,
+                    session_ids: vec![],
+                    disabled: false,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates an authentication challenge for a registered user, replacing its previous challenge.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_name`: A `String` representing the name of the user. This should correspond to a user that is already registered.
+    /// * `auth_id`: A `String` representing a unique identifier for the authentication challenge.
+    /// * `r1`: A `BigInt` representing the first cryptographic component of the challenge.
+    /// * `r2`: A `BigInt` representing the second cryptographic component of the challenge.
+    /// * `c`: A `BigInt` representing the challenge value.
+    /// * `expires_at`: The Unix timestamp after which the challenge can no longer be answered.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(())` if the challenge was successfully created.
+    /// - `Err(Status)` if the user is not registered, with an appropriate error message.
+    pub(crate) fn create_authentication_challenge(
+        &self,
+        user_name: String,
+        auth_id: String,
+        r1: BigInt,
+        r2: BigInt,
+        c: BigInt,
+        expires_at: Timestamp,
+    ) -> Result<(), Status> {
+        let mut user = self
+            .users
+            .get_mut(&user_name)
+            .ok_or_else(user_not_registered)?;
+        if let Some(previous_auth_id) = user.auth_id.replace(auth_id.clone()) {
+            self.challenges.remove(&previous_auth_id);
+        }
+        self.challenges.insert(
+            auth_id.clone(),
+            Challenge {
+                id: auth_id,
+                c,
+                r1,
+                r2,
+                user_id: user_name,
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes and returns an authentication challenge.
+    ///
+    /// Challenges are single use: they are removed on the first verification attempt, whether it succeeds or fails, so that an answer can never be replayed.
+    ///
+    /// # Returns
+    ///
+    /// The `Challenge`, or `None` if no such challenge exists.
+    pub(crate) fn take_challenge(&self, auth_id: &str) -> Option<Challenge> {
+        let user_name = self.challenges.get(auth_id)?.user_id.clone();
+        let user = self.users.get_mut(&user_name);
+        let (_, challenge) = self.challenges.remove(auth_id)?;
+        if let Some(mut user) = user {
+            if user.auth_id.as_deref() == Some(auth_id) {
+                user.auth_id = None;
+            }
+        }
+        Some(challenge)
+    }
+
+    /// Removes every authentication challenge expired at `now`.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed challenges.
+    pub(crate) fn purge_expired_challenges(&self, now: Timestamp) -> usize {
+        let expired = self
+            .challenges
+            .iter()
+            .filter(|challenge| challenge.expires_at <= now)
+            .map(|challenge| challenge.id.clone())
+            .collect::<Vec<_>>();
+        expired
+            .iter()
+            .filter_map(|auth_id| self.take_challenge(auth_id))
+            .count()
+    }
+
+    /// Removes every pending authentication challenge, whether expired or not.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed challenges.
+    pub(crate) fn purge_all_challenges(&self) -> usize {
+        let auth_ids = self
+            .challenges
+            .iter()
+            .map(|challenge| challenge.id.clone())
+            .collect::<Vec<_>>();
+        auth_ids
+            .iter()
+            .filter_map(|auth_id| self.take_challenge(auth_id))
+            .count()
+    }
+
+    /// Creates a session for a registered user. If the user then holds more than `max_sessions` sessions, its oldest sessions are revoked.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_name`: A `String` representing the name of the user. This should correspond to a user that is already registered and authenticated.
+    /// * `session_id`: A `String` representing a unique identifier for the new session.
+    /// * `issued_at`: The Unix timestamp at which the session is created.
+    /// * `expires_at`: The Unix timestamp after which the session is no longer valid.
+    /// * `scopes`: The scopes granted to the session.
+    /// * `max_sessions`: The maximum number of concurrent sessions of the user.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(Vec<String>)` with the ids of the sessions revoked to make room for the new one.
+    /// - `Err(Status)` if the user is not registered, with an appropriate error message.
+    pub(crate) fn create_session(
+        &self,
+        user_name: String,
+        session_id: String,
+        issued_at: Timestamp,
+        expires_at: Timestamp,
+        scopes: Vec<String>,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        let mut user = self
+            .users
+            .get_mut(&user_name)
+            .ok_or_else(user_not_registered)?;
+        Ok(self.insert_session(
+            &mut user,
+            Session {
+                id: session_id,
+                user_id: user_name,
+                issued_at,
+                expires_at,
+                scopes,
+                credential_id: None,
+            },
+            max_sessions,
+        ))
+    }
+
+    /// Completes a successful authentication, as a single atomic operation: creates a session for the challenged user.
+    ///
+    /// The challenge must have been taken before the answer was verified, so that concurrent answers to it are verified only once. The user must still hold the public key the answer was verified against, and must not have been disabled in the meantime.
+    ///
+    /// # Arguments
+    ///
+    /// * `y1`, `y2`: The public key the answer was verified against.
+    /// * `session`: The new session.
+    /// * `max_sessions`: The maximum number of concurrent sessions of the user.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(Vec<String>)` with the ids of the sessions revoked to make room for the new one.
+    /// - `Err(Status)` if the user is no longer registered, enabled or holding the same public key.
+    pub(crate) fn complete_authentication(
+        &self,
+        y1: &BigInt,
+        y2: &BigInt,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        let mut user = self
+            .users
+            .get_mut(&session.user_id)
+            .ok_or_else(user_not_registered)?;
+        if &user.y1 != y1 || &user.y2 != y2 {
+            return Err(Status::aborted(
+                "Failed to authenticate, user credential has changed concurrently",
+            ));
+        }
+        check_enabled(&user)?;
+        Ok(self.insert_session(&mut user, session, max_sessions))
+    }
+
+    /// Extends the validity of a session, which must not have expired at `now`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(Session)` with the refreshed session.
+    /// - `Err(Status)` if the session does not exist or has expired, with an appropriate error message.
+    pub(crate) fn refresh_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        expires_at: Timestamp,
+    ) -> Result<Session, Status> {
+        match self.sessions.get_mut(session_id) {
+            Some(mut session) if session.expires_at > now => {
+                session.expires_at = expires_at;
+                Ok(session.clone())
+            }
+            _ => Err(Status::unauthenticated(
+                "Failed to retrieve session data, session is invalid or has expired",
+            )),
+        }
+    }
+
+    /// Removes a session, together with its pending credential signing sessions.
+    ///
+    /// # Returns
+    ///
+    /// The removed `Session`, or `None` if no such session exists.
+    pub(crate) fn remove_session(&self, session_id: &str) -> Option<Session> {
+        let user_name = self.sessions.get(session_id)?.user_id.clone();
+        let user = self.users.get_mut(&user_name);
+        let session = self.drop_session(session_id)?;
+        if let Some(mut user) = user {
+            user.session_ids.retain(|id| id != session_id);
+        }
+        Some(session)
+    }
+
+    /// Removes every session of a user.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed sessions.
+    pub(crate) fn remove_user_sessions(&self, user_name: &str) -> usize {
+        match self.users.get_mut(user_name) {
+            Some(mut user) => self.drop_user_sessions(&mut user),
+            None => 0,
+        }
+    }
+
+    /// Removes every session expired at `now`.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed sessions.
+    pub(crate) fn purge_expired_sessions(&self, now: Timestamp) -> usize {
+        let expired = self
+            .sessions
+            .iter()
+            .filter(|session| session.expires_at <= now)
+            .map(|session| session.id.clone())
+            .collect::<Vec<_>>();
+        expired
+            .iter()
+            .filter_map(|session_id| self.remove_session(session_id))
+            .count()
+    }
+
+    /// Counts the authentication challenges and sessions still active at `now`.
+    pub(crate) fn count_active(&self, now: Timestamp) -> (usize, usize) {
+        let challenges = self
+            .challenges
+            .iter()
+            .filter(|challenge| challenge.expires_at > now)
+            .count();
+        let sessions = self
+            .sessions
+            .iter()
+            .filter(|session| session.expires_at > now)
+            .count();
+        (challenges, sessions)
+    }
+
+    /// Lists registered users, ordered by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `after`: The name of the last user of the previous page, or `None` for the first page.
+    /// * `limit`: The maximum number of users to return.
+    ///
+    /// # Returns
+    ///
+    /// The users named after `after`, at most `limit` of them.
+    pub(crate) fn list_users(&self, after: Option<&str>, limit: usize) -> Vec<User> {
+        let mut users = self
+            .users
+            .iter()
+            .filter(|user| after.is_none_or(|after| user.id.as_str() > after))
+            .map(|user| user.clone())
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        users.truncate(limit);
+        users
+    }
+
+    /// Lists the sessions of a user, from the oldest to the most recent.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the sessions, or a `NOT_FOUND` `Status` if the user is not registered.
+    pub(crate) fn user_sessions(&self, user_name: &str) -> Result<Vec<Session>, Status> {
+        let user = self
+            .users
+            .get(user_name)
+            .ok_or_else(|| user_not_found(user_name))?;
+        Ok(user
+            .session_ids
+            .iter()
+            .filter_map(|session_id| self.sessions.get(session_id).map(|session| session.clone()))
+            .collect())
+    }
+
+    /// Disables or enables a user. Disabling a user also removes its sessions and its pending
+    /// authentication challenge, so that it is logged out everywhere at once.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the number of removed sessions, or a `NOT_FOUND` `Status` if the user
+    /// is not registered.
+    pub(crate) fn set_user_disabled(
+        &self,
+        user_name: &str,
+        disabled: bool,
+    ) -> Result<usize, Status> {
+        let mut user = self
+            .users
+            .get_mut(user_name)
+            .ok_or_else(|| user_not_found(user_name))?;
+        user.disabled = disabled;
+        if !disabled {
+            return Ok(0);
+        }
+        Ok(self.drop_user_links(&mut user))
+    }
+
+    /// Deletes a user, together with its sessions and its pending authentication challenge.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the number of removed sessions, or a `NOT_FOUND` `Status` if the user
+    /// is not registered.
+    pub(crate) fn delete_user(&self, user_name: &str) -> Result<usize, Status> {
+        let mut revoked = 0;
+        self.users
+            .remove_if_mut(user_name, |_, user| {
+                revoked = self.drop_user_links(user);
+                true
+            })
+            .ok_or_else(|| user_not_found(user_name))?;
+        Ok(revoked)
+    }
+
+    /// Replaces the public key of a registered user.
+    ///
+    /// The replacement only happens if the user's current public key still matches the one the key rotation proof was verified against, so that concurrent rotations can't overwrite each other. Any pending authentication challenge of the user is removed, as it was issued for the old public key.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_name`: A `String` representing the name of the user.
+    /// * `old_y1`, `old_y2`: The public key the key rotation proof was verified against.
+    /// * `new_y1`, `new_y2`: The new public key.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(())` if the public key was successfully replaced.
+    /// - `Err(Status)` if the user is not registered, or its public key has changed in the meantime.
+    pub(crate) fn rotate_credential(
+        &self,
+        user_name: String,
+        old_y1: &BigInt,
+        old_y2: &BigInt,
+        new_y1: BigInt,
+        new_y2: BigInt,
+    ) -> Result<(), Status> {
+        let mut user = self
+            .users
+            .get_mut(&user_name)
+            .ok_or_else(user_not_registered)?;
+        if &user.y1 != old_y1 || &user.y2 != old_y2 {
+            return Err(Status::aborted(
+                "Failed to rotate credential, user credential has changed concurrently",
+            ));
+        }
+        user.y1 = new_y1;
+        user.y2 = new_y2;
+        if let Some(auth_id) = user.auth_id.take() {
+            self.challenges.remove(&auth_id);
+        }
+        Ok(())
+    }
+
+    /// Stores the signer commitment of a new blind credential signing session, replacing any pending commitment of the same session.
+    ///
+    /// Credentials can only be requested by users holding a valid session, but notice that the session is never
+    /// linked to the credential once it is unblinded by the client. A session holds at most one open signing session,
+    /// and the server at most `max_open` of them whatever the number of sessions, so that a signer can't be made to
+    /// answer many concurrent signing sessions at once, as ROS attacks on blind Schnorr signatures require.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id`: A `String` representing the session of the user requesting the credential.
+    /// * `credential_id`: A `String` representing a unique identifier for the credential signing session.
+    /// * `k`: A `BigInt` representing the secret signer nonce.
+    /// * `r`: A `BigInt` representing the signer commitment, `g^k mod p`.
+    /// * `expires_at`: The Unix timestamp after which the commitment can no longer be signed.
+    /// * `max_open`: The maximum number of open signing sessions, across every session.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(())` if the commitment was successfully stored.
+    /// - `Err(Status)` if the session does not exist, or `max_open` signing sessions are already open, with an appropriate error message.
+    pub(crate) fn create_credential_commitment(
+        &self,
+        session_id: String,
+        credential_id: String,
+        k: BigInt,
+        r: BigInt,
+        expires_at: Timestamp,
+        max_open: usize,
+    ) -> Result<(), Status> {
+        // the session's entry is held until the commitment is stored, so that removing the session
+        // also removes the commitment, and concurrent commitments of the session are stored one at a time
+        let mut session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or(Status::unauthenticated(
+                "Failed to retrieve session data, user must authenticate first",
+            ))?;
+        if let Some(replaced_id) = session.credential_id.take() {
+            self.remove_credential_commitment(&replaced_id);
+        }
+        self.open_credential_commitments
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < max_open).then_some(open + 1)
+            })
+            .map_err(|_| {
+                Status::resource_exhausted(
+                    "Failed to create credential commitment, too many credentials are being signed, retry later",
+                )
+            })?;
+        session.credential_id = Some(credential_id.clone());
+        self.pending_credentials.insert(
+            credential_id.clone(),
+            PendingCredential {
+                id: credential_id,
+                session_id,
+                k,
+                r,
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes and returns the signer commitment of a blind credential signing session, unless it has expired at `now`.
+    ///
+    /// The commitment is removed so that the signer nonce is never used to sign more than one blinded challenge. Expired commitments are removed all the same.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id`: A `String` representing the session of the user requesting the credential.
+    /// * `credential_id`: A `String` representing the identifier of the credential signing session.
+    /// * `now`: The current Unix timestamp.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(PendingCredential)` with the stored commitment.
+    /// - `Err(Status)` if no such commitment exists for the given session, or if it has expired, with an appropriate error message.
+    pub(crate) fn take_credential_commitment(
+        &self,
+        session_id: &str,
+        credential_id: &str,
+        now: Timestamp,
+    ) -> Result<PendingCredential, Status> {
+        let pending_credential = self
+            .sessions
+            .get_mut(session_id)
+            .filter(|session| session.credential_id.as_deref() == Some(credential_id))
+            .and_then(|mut session| {
+                session.credential_id = None;
+                self.remove_credential_commitment(credential_id)
+            })
+            .ok_or(Status::not_found(
+                "Failed to retrieve credential commitment, user must request a credential commitment first",
+            ))?;
+        if pending_credential.expires_at <= now {
+            return Err(Status::deadline_exceeded(
+                "Failed to sign credential, credential commitment has expired",
+            ));
+        }
+        Ok(pending_credential)
+    }
+
+    /// Removes every credential commitment expired at `now`.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed commitments.
+    pub(crate) fn purge_expired_credential_commitments(&self, now: Timestamp) -> usize {
+        let expired = self
+            .pending_credentials
+            .iter()
+            .filter(|pending_credential| pending_credential.expires_at <= now)
+            .map(|pending_credential| {
+                (
+                    pending_credential.session_id.clone(),
+                    pending_credential.id.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        expired
+            .iter()
+            .filter(|(session_id, credential_id)| {
+                match self.sessions.get_mut(session_id) {
+                    Some(mut session) if session.credential_id.as_ref() == Some(credential_id) => {
+                        session.credential_id = None;
+                    }
+                    _ => {}
+                }
+                self.remove_credential_commitment(credential_id).is_some()
+            })
+            .count()
+    }
+
+    /// Marks a credential as spent, identified by its signed message, until it expires at `expires_at`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` type:
+    /// - `Ok(())` if the credential had not been presented before.
+    /// - `Err(Status)` if the credential has already been presented, with an appropriate error message.
+    pub(crate) fn spend_credential(
+        &self,
+        message: Vec<u8>,
+        expires_at: Timestamp,
+    ) -> Result<(), Status> {
+        match self.spent_credentials.entry(message) {
+            Entry::Occupied(_) => Err(Status::unauthenticated(
+                "Failed to present credential, credential has already been presented",
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(expires_at);
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes every spent credential expired at `now`, which can no longer be presented anyway.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed credentials.
+    pub(crate) fn purge_expired_spent_credentials(&self, now: Timestamp) -> usize {
+        let mut purged = 0;
+        self.spent_credentials.retain(|_, expires_at| {
+            let expired = *expires_at <= now;
+            purged += usize::from(expired);
+            !expired
+        });
+        purged
+    }
+
+    /// Copies the contents of the backend.
+    ///
+    /// Every entry is copied atomically, but the maps are copied one after the other, so that links to
+    /// challenges and sessions created or removed meanwhile are dropped from the copy, which is then
+    /// consistent as a whole.
+    pub(crate) fn export(&self) -> PedersenChaumAuthServerState {
+        let mut users = copy(&self.users);
+        let mut challenges = copy(&self.challenges);
+        let mut sessions = copy(&self.sessions);
+        let mut pending_credentials = copy(&self.pending_credentials);
+        challenges.retain(|auth_id, challenge| {
+            users
+                .get(&challenge.user_id)
+                .is_some_and(|user| user.auth_id.as_ref() == Some(auth_id))
+        });
+        sessions.retain(|session_id, session| {
+            users
+                .get(&session.user_id)
+                .is_some_and(|user| user.session_ids.contains(session_id))
+        });
+        for user in users.values_mut() {
+            user.auth_id = user
+                .auth_id
+                .take()
+                .filter(|auth_id| challenges.contains_key(auth_id));
+            user.session_ids
+                .retain(|session_id| sessions.contains_key(session_id));
+        }
+        pending_credentials.retain(|credential_id, pending_credential| {
+            sessions
+                .get(&pending_credential.session_id)
+                .is_some_and(|session| session.credential_id.as_ref() == Some(credential_id))
+        });
+        for session in sessions.values_mut() {
+            session.credential_id = session
+                .credential_id
+                .take()
+                .filter(|credential_id| pending_credentials.contains_key(credential_id));
+        }
+        PedersenChaumAuthServerState {
+            users,
+            challenges,
+            sessions,
+            pending_credentials,
+            spent_credentials: self
+                .spent_credentials
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect(),
+        }
+    }
+
+    /// Links a new session to its user, whose entry the caller holds, revoking the user's oldest
+    /// sessions over `max_sessions`.
+    ///
+    /// # Returns
+    ///
+    /// The ids of the revoked sessions.
+    fn insert_session(
+        &self,
+        user: &mut User,
+        session: Session,
+        max_sessions: usize,
+    ) -> Vec<String> {
+        user.session_ids.push(session.id.clone());
+        let evicted = user.session_ids.len().saturating_sub(max_sessions.max(1));
+        let evicted_session_ids = user.session_ids.drain(..evicted).collect::<Vec<_>>();
+        self.sessions.insert(session.id.clone(), session);
+        for session_id in &evicted_session_ids {
+            self.drop_session(session_id);
+        }
+        evicted_session_ids
+    }
+
+    /// Removes the pending challenge and the sessions of a user, whose entry the caller holds.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed sessions.
+    fn drop_user_links(&self, user: &mut User) -> usize {
+        if let Some(auth_id) = user.auth_id.take() {
+            self.challenges.remove(&auth_id);
+        }
+        self.drop_user_sessions(user)
+    }
+
+    /// Removes the sessions of a user, whose entry the caller holds.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed sessions.
+    fn drop_user_sessions(&self, user: &mut User) -> usize {
+        let session_ids = std::mem::take(&mut user.session_ids);
+        for session_id in &session_ids {
+            self.drop_session(session_id);
+        }
+        session_ids.len()
+    }
+
+    /// Removes a session and its pending credential signing session, leaving the link from its user to
+    /// the caller.
+    fn drop_session(&self, session_id: &str) -> Option<Session> {
+        let (_, session) = self.sessions.remove(session_id)?;
+        if let Some(credential_id) = &session.credential_id {
+            self.remove_credential_commitment(credential_id);
+        }
+        Some(session)
+    }
+
+    /// Removes a pending credential, keeping count of the open signing sessions.
+    fn remove_credential_commitment(&self, credential_id: &str) -> Option<PendingCredential> {
+        let (_, pending_credential) = self.pending_credentials.remove(credential_id)?;
+        self.open_credential_commitments
+            .fetch_sub(1, Ordering::SeqCst);
+        Some(pending_credential)
+    }
+}
+
+impl From<PedersenChaumAuthServerState> for InMemoryStorage {
+    fn from(state: PedersenChaumAuthServerState) -> Self {
+        Self {
+            users: state.users.into_iter().collect(),
+            challenges: state.challenges.into_iter().collect(),
+            sessions: state.sessions.into_iter().collect(),
+            open_credential_commitments: AtomicUsize::new(state.pending_credentials.len()),
+            pending_credentials: state.pending_credentials.into_iter().collect(),
+            spent_credentials: state.spent_credentials.into_iter().collect(),
+        }
+    }
+}
+
+/// Copies a concurrent map into a plain hash map.
+fn copy<V: Clone>(map: &DashMap<String, V>) -> std::collections::HashMap<String, V> {
+    map.iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect()
+}
+
+#[tonic::async_trait]
+impl AuthStorage for InMemoryStorage {
+    async fn register_user(&self, user: User) -> Result<(), Status> {
+        InMemoryStorage::register_user(
+            self,
+            user.id,
+            user.y1,
+            user.y2,
+            user.salt,
+            user.kdf_parameters,
+        )
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<User>, Status> {
+        Ok(self.users.get(user_name).map(|user| user.clone()))
+    }
+
+    async fn rotate_credential(
+        &self,
+        user_name: String,
+        old_y1: &BigInt,
+        old_y2: &BigInt,
+        new_y1: BigInt,
+        new_y2: BigInt,
+    ) -> Result<(), Status> {
+        InMemoryStorage::rotate_credential(self, user_name, old_y1, old_y2, new_y1, new_y2)
+    }
+
+    async fn create_authentication_challenge(&self, challenge: Challenge) -> Result<(), Status> {
+        InMemoryStorage::create_authentication_challenge(
+            self,
+            challenge.user_id,
+            challenge.id,
+            challenge.r1,
+            challenge.r2,
+            challenge.c,
+            challenge.expires_at,
+        )
+    }
+
+    async fn take_challenge(&self, auth_id: &str) -> Result<Option<Challenge>, Status> {
+        Ok(InMemoryStorage::take_challenge(self, auth_id))
+    }
+
+    async fn purge_expired_challenges(&self, now: Timestamp) -> Result<usize, Status> {
+        Ok(InMemoryStorage::purge_expired_challenges(self, now))
+    }
+
+    async fn create_session(
+        &self,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        InMemoryStorage::create_session(
+            self,
+            session.user_id,
+            session.id,
+            session.issued_at,
+            session.expires_at,
+            session.scopes,
+            max_sessions,
+        )
+    }
+
+    async fn complete_authentication(
+        &self,
+        y1: &BigInt,
+        y2: &BigInt,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        InMemoryStorage::complete_authentication(self, y1, y2, session, max_sessions)
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>, Status> {
+        Ok(self.sessions.get(session_id).map(|session| session.clone()))
+    }
+
+    async fn refresh_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        expires_at: Timestamp,
+    ) -> Result<Session, Status> {
+        InMemoryStorage::refresh_session(self, session_id, now, expires_at)
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<Option<Session>, Status> {
+        Ok(InMemoryStorage::remove_session(self, session_id))
+    }
+
+    async fn remove_user_sessions(&self, user_name: &str) -> Result<usize, Status> {
+        Ok(InMemoryStorage::remove_user_sessions(self, user_name))
+    }
+
+    async fn purge_expired_sessions(&self, now: Timestamp) -> Result<usize, Status> {
+        Ok(InMemoryStorage::purge_expired_sessions(self, now))
+    }
+
+    async fn count_active(&self, now: Timestamp) -> Result<(usize, usize), Status> {
+        Ok(InMemoryStorage::count_active(self, now))
+    }
+
+    async fn purge_all_challenges(&self) -> Result<usize, Status> {
+        Ok(InMemoryStorage::purge_all_challenges(self))
+    }
+
+    async fn list_users(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>, Status> {
+        Ok(InMemoryStorage::list_users(self, after, limit))
+    }
+
+    async fn user_sessions(&self, user_name: &str) -> Result<Vec<Session>, Status> {
+        InMemoryStorage::user_sessions(self, user_name)
+    }
+
+    async fn set_user_disabled(&self, user_name: &str, disabled: bool) -> Result<usize, Status> {
+        InMemoryStorage::set_user_disabled(self, user_name, disabled)
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<usize, Status> {
+        InMemoryStorage::delete_user(self, user_name)
+    }
+
+    async fn create_credential_commitment(
+        &self,
+        pending_credential: PendingCredential,
+        max_open: usize,
+    ) -> Result<(), Status> {
+        InMemoryStorage::create_credential_commitment(
+            self,
+            pending_credential.session_id,
+            pending_credential.id,
+            pending_credential.k,
+            pending_credential.r,
+            pending_credential.expires_at,
+            max_open,
+        )
+    }
+
+    async fn take_credential_commitment(
+        &self,
+        session_id: &str,
+        credential_id: &str,
+        now: Timestamp,
+    ) -> Result<PendingCredential, Status> {
+        InMemoryStorage::take_credential_commitment(self, session_id, credential_id, now)
+    }
+
+    async fn purge_expired_credential_commitments(&self, now: Timestamp) -> Result<usize, Status> {
+        Ok(InMemoryStorage::purge_expired_credential_commitments(
+            self, now,
+        ))
+    }
+
+    async fn spend_credential(
+        &self,
+        message: Vec<u8>,
+        expires_at: Timestamp,
+    ) -> Result<(), Status> {
+        InMemoryStorage::spend_credential(self, message, expires_at)
+    }
+
+    async fn purge_expired_spent_credentials(&self, now: Timestamp) -> Result<usize, Status> {
+        Ok(InMemoryStorage::purge_expired_spent_credentials(self, now))
+    }
+
+    async fn export_state(&self) -> Result<PedersenChaumAuthServerState, Status> {
+        Ok(self.export())
+    }
+
+    async fn check_ready(&self) -> Result<(), Status> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr, sync::Arc, thread};
+
+    use super::*;
+
+    const ISSUED_AT: Timestamp = 500;
+    const EXPIRES_AT: Timestamp = 1_000;
+    const MAX_SESSIONS: usize = 5;
+    const MAX_CREDENTIAL_COMMITMENTS: usize = 2;
+
+    fn salt() -> Vec<u8> {
+        vec![7u8; 16]
+    }
+
+    fn scopes() -> Vec<String> {
+        vec!["profile".to_string()]
+    }
+
+    #[test]
+    fn test_register_user() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        let should_be_users = HashMap::from_iter([(
+            user_name.clone(),
+            User {
+                id: user_name,
+                y1,
+                y2,
+                salt: salt(),
+                kdf_parameters: KdfParameters::default(),
+                auth_id: None,
+                session_ids: vec![],
+                disabled: false,
+            },
+        )]);
+        assert_eq!(state.export().users, should_be_users);
+
+        assert_eq!(state.export().challenges, HashMap::new());
+        assert_eq!(state.export().sessions, HashMap::new());
+    }
+
+    #[test]
+    fn test_register_user_fails_if_user_exists() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        let result = state.register_user(
+            user_name.clone(),
+            BigInt::from_str("3_000_000_000").unwrap(),
+            BigInt::from_str("4_000_000_000").unwrap(),
+            vec![8u8; 16],
+            KdfParameters::default(),
+        );
+        assert_eq!(result.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        // the existing user is left untouched
+        let user = &state.export().users[&user_name];
+        assert_eq!((&user.y1, &user.y2, &user.salt), (&y1, &y2, &salt()));
+    }
+
+    #[test]
+    fn test_create_authentication_challenge() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let auth_id = "f2m38m2kcj9d-s823".to_string();
+        let r1 = BigInt::from_str("1_000").unwrap();
+        let r2 = BigInt::from_str("2_000").unwrap();
+        let c = BigInt::from_str("10_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        state
+            .create_authentication_challenge(
+                user_name.clone(),
+                auth_id.clone(),
+                r1.clone(),
+                r2.clone(),
+                c.clone(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+
+        let should_be_challenges = HashMap::from_iter([(
+            auth_id.clone(),
+            Challenge {
+                id: auth_id.clone(),
+                c,
+                r1,
+                r2,
+                user_id: user_name.clone(),
+                expires_at: EXPIRES_AT,
+            },
+        )]);
+        assert_eq!(state.export().challenges, should_be_challenges);
+
+        assert_eq!(
+            state.export().users.get(&user_name).unwrap().auth_id,
+            Some(auth_id)
+        );
+        assert_eq!(state.export().sessions, HashMap::new());
+    }
+
+    #[test]
+    fn test_create_authentication_challenge_when_auth_id_exists() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let auth_id = "f2m38m2kcj9d-s823".to_string();
+        let r1 = BigInt::from_str("1_000").unwrap();
+        let r2 = BigInt::from_str("2_000").unwrap();
+        let c = BigInt::from_str("10_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        state
+            .create_authentication_challenge(
+                user_name.clone(),
+                auth_id,
+                r1.clone(),
+                r2.clone(),
+                c.clone(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+
+        // re-authenticate to test if the the new authentication token is updated
+        let new_auth_id = "2sdiofa9013".to_string();
+        state
+            .create_authentication_challenge(
+                user_name.clone(),
+                new_auth_id.clone(),
+                r1.clone(),
+                r2.clone(),
+                c.clone(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+
+        let should_be_challenges = HashMap::from_iter([(
+            new_auth_id.clone(),
+            Challenge {
+                id: new_auth_id.clone(),
+                c,
+                r1,
+                r2,
+                user_id: user_name.clone(),
+                expires_at: EXPIRES_AT,
+            },
+        )]);
+        assert_eq!(state.export().challenges, should_be_challenges);
+
+        assert_eq!(
+            state.export().users.get(&user_name).unwrap().auth_id,
+            Some(new_auth_id)
+        );
+        assert_eq!(state.export().sessions, HashMap::new());
+    }
+
+    #[test]
+    fn test_create_authentication_challenge_fails_if_user_unregistered() {
+        let user_name = "user_name".to_string();
+
+        let auth_id = "f2m38m2kcj9d-s823".to_string();
+        let r1 = BigInt::from_str("1_000").unwrap();
+        let r2 = BigInt::from_str("2_000").unwrap();
+        let c = BigInt::from_str("10_000").unwrap();
+
+        let state = InMemoryStorage::new();
+
+        // user hasn't registered yet
+        assert!(state
+            .create_authentication_challenge(
+                user_name.clone(),
+                auth_id,
+                r1.clone(),
+                r2.clone(),
+                c.clone(),
+                EXPIRES_AT,
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to retrieve user data, user must register first"));
+    }
+
+    #[test]
+    fn test_take_challenge_is_single_use() {
+        let user_name = "user_name".to_string();
+        let auth_id = "f2m38m2kcj9d-s823".to_string();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                BigInt::from_str("1_000_000_000").unwrap(),
+                BigInt::from_str("2_000_000_000").unwrap(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+        state
+            .create_authentication_challenge(
+                user_name.clone(),
+                auth_id.clone(),
+                BigInt::from_str("1_000").unwrap(),
+                BigInt::from_str("2_000").unwrap(),
+                BigInt::from_str("10_000").unwrap(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+
+        assert_eq!(state.take_challenge(&auth_id).unwrap().id, auth_id);
+        assert_eq!(state.take_challenge(&auth_id), None);
+        assert_eq!(state.export().users.get(&user_name).unwrap().auth_id, None);
+    }
+
+    #[test]
+    fn test_purge_expired_challenges() {
+        let state = InMemoryStorage::new();
+        for (user_name, auth_id, expires_at) in [
+            ("user_1", "auth_id_1", EXPIRES_AT),
+            ("user_2", "auth_id_2", EXPIRES_AT + 60),
+        ] {
+            state
+                .register_user(
+                    user_name.to_string(),
+                    BigInt::from_str("1_000_000_000").unwrap(),
+                    BigInt::from_str("2_000_000_000").unwrap(),
+                    salt(),
+                    KdfParameters::default(),
+                )
+                .expect("Failed to register user");
+            state
+                .create_authentication_challenge(
+                    user_name.to_string(),
+                    auth_id.to_string(),
+                    BigInt::from_str("1_000").unwrap(),
+                    BigInt::from_str("2_000").unwrap(),
+                    BigInt::from_str("10_000").unwrap(),
+                    expires_at,
+                )
+                .expect("Failed to create authentication");
+        }
+
+        assert_eq!(state.purge_expired_challenges(EXPIRES_AT - 1), 0);
+        assert_eq!(state.purge_expired_challenges(EXPIRES_AT), 1);
+        assert!(!state.export().challenges.contains_key("auth_id_1"));
+        assert!(state.export().challenges.contains_key("auth_id_2"));
+        assert_eq!(state.export().users.get("user_1").unwrap().auth_id, None);
+        assert_eq!(
+            state.export().users.get("user_2").unwrap().auth_id,
+            Some("auth_id_2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_create_session() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let session_id = "sdfa837djf".to_string();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        state
+            .create_session(
+                user_name.clone(),
+                session_id.clone(),
+                ISSUED_AT,
+                EXPIRES_AT,
+                scopes(),
+                MAX_SESSIONS,
+            )
+            .expect("Failed to create sesssion");
+
+        assert_eq!(
+            state.export().users.get(&user_name).unwrap().session_ids,
+            vec![session_id.clone()]
+        );
+        assert_eq!(state.export().challenges, HashMap::new());
+
+        let should_be_sessions = HashMap::from_iter([(
+            session_id.clone(),
+            Session {
+                id: session_id,
+                user_id: user_name,
+                issued_at: ISSUED_AT,
+                expires_at: EXPIRES_AT,
+                scopes: scopes(),
+                credential_id: None,
+            },
+        )]);
+        assert_eq!(state.export().sessions, should_be_sessions)
+    }
+
+    #[test]
+    fn test_create_session_revokes_oldest_sessions_over_limit() {
+        let user_name = "user_name".to_string();
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                BigInt::from_str("1_000_000_000").unwrap(),
+                BigInt::from_str("2_000_000_000").unwrap(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+
+        for session_id in ["session_1", "session_2", "session_3"] {
+            state
+                .create_session(
+                    user_name.clone(),
+                    session_id.to_string(),
+                    ISSUED_AT,
+                    EXPIRES_AT,
+                    scopes(),
+                    2,
+                )
+                .expect("Failed to create session");
+        }
+
+        // every session is tracked, up to the limit
+        assert_eq!(
+            state.export().users.get(&user_name).unwrap().session_ids,
+            vec!["session_2".to_string(), "session_3".to_string()]
+        );
+        assert!(!state.export().sessions.contains_key("session_1"));
+        assert_eq!(state.export().sessions.len(), 2);
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let user_name = "user_name".to_string();
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                BigInt::from_str("1_000_000_000").unwrap(),
+                BigInt::from_str("2_000_000_000").unwrap(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+        for (session_id, expires_at) in [
+            ("session_1", EXPIRES_AT),
+            ("session_2", EXPIRES_AT + 100),
+            ("session_3", EXPIRES_AT + 100),
+        ] {
+            state
+                .create_session(
+                    user_name.clone(),
+                    session_id.to_string(),
+                    ISSUED_AT,
+                    expires_at,
+                    scopes(),
+                    MAX_SESSIONS,
+                )
+                .expect("Failed to create session");
+        }
+
+        // expired sessions can't be refreshed
+        assert!(state
+            .refresh_session("session_1", EXPIRES_AT, EXPIRES_AT + 200)
+            .is_err());
+        assert_eq!(
+            state
+                .refresh_session("session_2", EXPIRES_AT, EXPIRES_AT + 200)
+                .unwrap()
+                .expires_at,
+            EXPIRES_AT + 200
+        );
+
+        assert_eq!(state.purge_expired_sessions(EXPIRES_AT), 1);
+        assert_eq!(
+            state.remove_session("session_3").unwrap().id,
+            "session_3".to_string()
+        );
+        assert_eq!(state.remove_session("session_3"), None);
+        assert_eq!(
+            state.export().users.get(&user_name).unwrap().session_ids,
+            vec!["session_2".to_string()]
+        );
+
+        assert_eq!(state.remove_user_sessions(&user_name), 1);
+        assert_eq!(state.export().sessions, HashMap::new());
+        assert!(state
+            .export()
+            .users
+            .get(&user_name)
+            .unwrap()
+            .session_ids
+            .is_empty());
+    }
+
+    #[test]
+    fn test_create_session_fails_if_user_unregistered() {
+        let user_name = "user_name".to_string();
+        let session_id = "sdfa837djf".to_string();
+
+        let state = InMemoryStorage::new();
+
+        assert!(state
+            .create_session(
+                user_name.clone(),
+                session_id.clone(),
+                ISSUED_AT,
+                EXPIRES_AT,
+                scopes(),
+                MAX_SESSIONS
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to retrieve user data, user must register first"));
+    }
+
+    #[test]
+    fn test_rotate_credential() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+        let new_y1 = BigInt::from_str("3_000_000_000").unwrap();
+        let new_y2 = BigInt::from_str("4_000_000_000").unwrap();
+
+        let auth_id = "f2m38m2kcj9d-s823".to_string();
+        let r1 = BigInt::from_str("1_000").unwrap();
+        let r2 = BigInt::from_str("2_000").unwrap();
+        let c = BigInt::from_str("10_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                user_name.clone(),
+                y1.clone(),
+                y2.clone(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+        state
+            .create_authentication_challenge(user_name.clone(), auth_id, r1, r2, c, EXPIRES_AT)
+            .expect("Failed to create authentication");
+
+        // rotation against a stale public key fails
+        assert!(state
+            .rotate_credential(user_name.clone(), &new_y1, &new_y2, y1.clone(), y2.clone())
+            .unwrap_err()
+            .to_string()
+            .contains("user credential has changed concurrently"));
+
+        state
+            .rotate_credential(user_name.clone(), &y1, &y2, new_y1.clone(), new_y2.clone())
+            .expect("Failed to rotate credential");
+
+        let should_be_users = HashMap::from_iter([(
+            user_name.clone(),
+            User {
+                id: user_name,
+                y1: new_y1,
+                y2: new_y2,
+                salt: salt(),
+                kdf_parameters: KdfParameters::default(),
+                auth_id: None,
+                session_ids: vec![],
+                disabled: false,
+            },
+        )]);
+        assert_eq!(state.export().users, should_be_users);
+        assert_eq!(state.export().challenges, HashMap::new());
+    }
+
+    #[test]
+    fn test_create_and_take_credential_commitment() {
+        let user_name = "user_name".to_string();
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+
+        let session_id = "sdfa837djf".to_string();
+        let credential_id = "a8s7df6g".to_string();
+        let k = BigInt::from_str("3_000").unwrap();
+        let r = BigInt::from_str("4_000").unwrap();
+
+        let state = InMemoryStorage::new();
+        state
+            .register_user(user_name.clone(), y1, y2, salt(), KdfParameters::default())
+            .expect("Failed to register user");
+        state
+            .create_session(
+                user_name,
+                session_id.clone(),
+                ISSUED_AT,
+                EXPIRES_AT,
+                scopes(),
+                MAX_SESSIONS,
+            )
+            .expect("Failed to create session");
+
+        state
+            .create_credential_commitment(
+                session_id.clone(),
+                credential_id.clone(),
+                k.clone(),
+                r.clone(),
+                EXPIRES_AT,
+                MAX_CREDENTIAL_COMMITMENTS,
+            )
+            .expect("Failed to create credential commitment");
+
+        // a different session can't use the commitment
+        assert!(state
+            .take_credential_commitment("other_session", &credential_id, ISSUED_AT)
+            .is_err());
+
+        let pending_credential = state
+            .take_credential_commitment(&session_id, &credential_id, ISSUED_AT)
+            .expect("Failed to take credential commitment");
+        assert_eq!(
+            pending_credential,
+            PendingCredential {
+                id: credential_id.clone(),
+                session_id: session_id.clone(),
+                k,
+                r,
+                expires_at: EXPIRES_AT,
+            }
+        );
+
+        // the commitment can only be used once
+        assert!(state
+            .take_credential_commitment(&session_id, &credential_id, ISSUED_AT)
+            .unwrap_err()
+            .to_string()
+            .contains("user must request a credential commitment first"));
+    }
+
+    #[test]
+    fn test_credential_commitments_are_replaced_and_expire() {
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                "user_name".to_string(),
+                BigInt::from(1_000),
+                BigInt::from(2_000),
+                salt(),
+                KdfParameters::default(),
+            )
+            .unwrap();
+        state
+            .create_session(
+                "user_name".to_string(),
+                "session_id".to_string(),
+                ISSUED_AT,
+                EXPIRES_AT,
+                scopes(),
+                MAX_SESSIONS,
+            )
+            .unwrap();
+        let commit = |credential_id: &str, expires_at| {
+            state
+                .create_credential_commitment(
+                    "session_id".to_string(),
+                    credential_id.to_string(),
+                    BigInt::from(3_000),
+                    BigInt::from(4_000),
+                    expires_at,
+                    MAX_CREDENTIAL_COMMITMENTS,
+                )
+                .unwrap()
+        };
+
+        // a session holds a single pending commitment
+        commit("credential_1", EXPIRES_AT);
+        commit("credential_2", EXPIRES_AT);
+        assert_eq!(state.export().pending_credentials.len(), 1);
+        assert_eq!(
+            state.export().sessions["session_id"]
+                .credential_id
+                .as_deref(),
+            Some("credential_2")
+        );
+        assert!(state
+            .take_credential_commitment("session_id", "credential_1", ISSUED_AT)
+            .is_err());
+
+        // expired commitments can't be signed, and are purged
+        assert_eq!(
+            state
+                .take_credential_commitment("session_id", "credential_2", EXPIRES_AT)
+                .unwrap_err()
+                .code(),
+            tonic::Code::DeadlineExceeded
+        );
+        commit("credential_3", ISSUED_AT + 10);
+        assert_eq!(state.purge_expired_credential_commitments(ISSUED_AT + 9), 0);
+        assert_eq!(
+            state.purge_expired_credential_commitments(ISSUED_AT + 10),
+            1
+        );
+        assert_eq!(state.export().pending_credentials, HashMap::new());
+        assert_eq!(state.export().sessions["session_id"].credential_id, None);
+    }
+
+    #[test]
+    fn test_credential_commitments_are_bounded_across_sessions() {
+        let state = InMemoryStorage::new();
+        state
+            .register_user(
+                "user_name".to_string(),
+                BigInt::from(1_000),
+                BigInt::from(2_000),
+                salt(),
+                KdfParameters::default(),
+            )
+            .unwrap();
+        for session_id in ["session_1", "session_2", "session_3"] {
+            state
+                .create_session(
+                    "user_name".to_string(),
+                    session_id.to_string(),
+                    ISSUED_AT,
+                    EXPIRES_AT,
+                    scopes(),
+                    MAX_SESSIONS,
+                )
+                .unwrap();
+        }
+        let commit = |session_id: &str, credential_id: &str| {
+            state.create_credential_commitment(
+                session_id.to_string(),
+                credential_id.to_string(),
+                BigInt::from(3_000),
+                BigInt::from(4_000),
+                EXPIRES_AT,
+                MAX_CREDENTIAL_COMMITMENTS,
+            )
+        };
+
+        // every session holds a commitment, up to the limit across sessions
+        assert!(commit("session_1", "credential_1").is_ok());
+        assert!(commit("session_2", "credential_2").is_ok());
+        assert_eq!(
+            commit("session_3", "credential_3").unwrap_err().code(),
+            tonic::Code::ResourceExhausted
+        );
+        // a session can still replace its own commitment
+        assert!(commit("session_1", "credential_4").is_ok());
+
+        // signing or removing the session frees its commitment
+        assert!(state
+            .take_credential_commitment("session_1", "credential_4", ISSUED_AT)
+            .is_ok());
+        assert!(commit("session_3", "credential_3").is_ok());
+        state.remove_session("session_2").unwrap();
+        assert!(commit("session_1", "credential_5").is_ok());
+        assert_eq!(state.export().pending_credentials.len(), 2);
+    }
+
+    #[test]
+    fn test_create_credential_commitment_fails_if_session_does_not_exist() {
+        let state = InMemoryStorage::new();
+
+        assert!(state
+            .create_credential_commitment(
+                "sdfa837djf".to_string(),
+                "a8s7df6g".to_string(),
+                BigInt::from_str("3_000").unwrap(),
+                BigInt::from_str("4_000").unwrap(),
+                EXPIRES_AT,
+                MAX_CREDENTIAL_COMMITMENTS,
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("user must authenticate first"));
+        assert_eq!(state.export().pending_credentials, HashMap::new());
+    }
+
+    #[test]
+    fn test_spend_credential_only_once() {
+        let state = InMemoryStorage::new();
+
+        assert!(state.spend_credential(vec![1, 2, 3], EXPIRES_AT).is_ok());
+        assert!(state
+            .spend_credential(vec![1, 2, 3], EXPIRES_AT)
+            .unwrap_err()
+            .to_string()
+            .contains("credential has already been presented"));
+
+        // spent credentials are kept until they expire
+        assert_eq!(state.purge_expired_spent_credentials(EXPIRES_AT - 1), 0);
+        assert_eq!(state.purge_expired_spent_credentials(EXPIRES_AT), 1);
+        assert_eq!(state.export().spent_credentials, HashMap::new());
+    }
+
+    fn challenged_user(state: &InMemoryStorage, user_name: &str, auth_id: &str) {
+        state
+            .register_user(
+                user_name.to_string(),
+                BigInt::from_str("1_000_000_000").unwrap(),
+                BigInt::from_str("2_000_000_000").unwrap(),
+                salt(),
+                KdfParameters::default(),
+            )
+            .expect("Failed to register user");
+        state
+            .create_authentication_challenge(
+                user_name.to_string(),
+                auth_id.to_string(),
+                BigInt::from_str("1_000").unwrap(),
+                BigInt::from_str("2_000").unwrap(),
+                BigInt::from_str("10_000").unwrap(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+    }
+
+    fn session(user_name: &str, session_id: &str) -> Session {
+        Session {
+            id: session_id.to_string(),
+            user_id: user_name.to_string(),
+            issued_at: ISSUED_AT,
+            expires_at: EXPIRES_AT,
+            scopes: scopes(),
+            credential_id: None,
+        }
+    }
+
+    #[test]
+    fn test_take_challenge_is_claimed_once() {
+        let user_name = "user_name";
+        let auth_id = "f2m38m2kcj9d-s823";
+        let state = Arc::new(InMemoryStorage::new());
+        challenged_user(&state, user_name, auth_id);
+
+        // every answer claims the challenge concurrently, but only one of them gets it
+        let claims = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || state.take_challenge(auth_id))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|claim| claim.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(claims.iter().filter(|claim| claim.is_some()).count(), 1);
+
+        let exported = state.export();
+        assert_eq!(exported.challenges, HashMap::new());
+        assert_eq!(exported.users[user_name].auth_id, None);
+    }
+
+    #[test]
+    fn test_complete_authentication_fails_if_user_changed() {
+        let user_name = "user_name";
+        let y1 = BigInt::from_str("1_000_000_000").unwrap();
+        let y2 = BigInt::from_str("2_000_000_000").unwrap();
+        let new_y1 = BigInt::from_str("3_000_000_000").unwrap();
+        let new_y2 = BigInt::from_str("4_000_000_000").unwrap();
+        let state = InMemoryStorage::new();
+        challenged_user(&state, user_name, "auth_id_1");
+
+        // the user rotated its key since the answer was verified
+        state
+            .rotate_credential(
+                user_name.to_string(),
+                &y1,
+                &y2,
+                new_y1.clone(),
+                new_y2.clone(),
+            )
+            .expect("Failed to rotate credential");
+        assert!(state
+            .complete_authentication(&y1, &y2, session(user_name, "session_1"), MAX_SESSIONS)
+            .unwrap_err()
+            .to_string()
+            .contains("user credential has changed concurrently"));
+
+        // the user was disabled since the answer was verified
+        state
+            .create_authentication_challenge(
+                user_name.to_string(),
+                "auth_id_2".to_string(),
+                BigInt::from_str("1_000").unwrap(),
+                BigInt::from_str("2_000").unwrap(),
+                BigInt::from_str("10_000").unwrap(),
+                EXPIRES_AT,
+            )
+            .expect("Failed to create authentication");
+        state
+            .set_user_disabled(user_name, true)
+            .expect("Failed to disable user");
+        assert_eq!(
+            state
+                .complete_authentication(
+                    &new_y1,
+                    &new_y2,
+                    session(user_name, "session_2"),
+                    MAX_SESSIONS
+                )
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+        // disabling the user removed its challenge
+        assert_eq!(state.export().challenges, HashMap::new());
+        assert_eq!(state.export().sessions, HashMap::new());
+    }
+
+    #[test]
+    fn test_concurrent_sessions_are_limited() {
+        let user_name = "user_name";
+        let state = Arc::new(InMemoryStorage::new());
+        challenged_user(&state, user_name, "auth_id");
+
+        let handles = (0..16)
+            .map(|index| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    state
+                        .create_session(
+                            user_name.to_string(),
+                            format!("session_{index}"),
+                            ISSUED_AT,
+                            EXPIRES_AT,
+                            scopes(),
+                            MAX_SESSIONS,
+                        )
+                        .expect("Failed to create session")
+                        .len()
+                })
+            })
+            .collect::<Vec<_>>();
+        let evicted = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum::<usize>();
+        assert_eq!(evicted, 16 - MAX_SESSIONS);
+
+        let exported = state.export();
+        assert_eq!(exported.sessions.len(), MAX_SESSIONS);
+        assert_eq!(exported.users[user_name].session_ids.len(), MAX_SESSIONS);
+    }
+}
@@ -0,0 +1,374 @@
+//! # Metrics
+//!
+//! The server exposes Prometheus metrics on a separate HTTP port, in the Prometheus text format:
+//!
+//! * `zkp_auth_rpc_requests_total{rpc, outcome}`: requests per RPC (e.g. `zkp_auth.Auth/Register`)
+//!   and outcome (the gRPC status code, e.g. `Ok` or `Unauthenticated`), counted by `MetricsLayer`.
+//!   Requests to paths outside of the served services are all counted as the `unknown` RPC, so that
+//!   clients can't create series at will.
+//! * `zkp_auth_rpc_duration_seconds{rpc}`: latency of each RPC, including `VerifyAuthentication`.
+//! * `zkp_auth_modpow_duration_seconds{operation}`: cost of the modular exponentiations of each proof
+//!   verification and signature, e.g. `verify_authentication`.
+//! * `zkp_auth_active_challenges` and `zkp_auth_active_sessions`: challenges and sessions held by the
+//!   storage backend, as of the last sweep. Stateless and decoy challenges are not counted.
+//! * `zkp_auth_verification_queue_depth`: proof verifications waiting for a worker of the verification
+//!   pool, which rejects further verifications once its queue is full.
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, StatusCode,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    future::Future,
+    net::TcpListener,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tonic::Code;
+use tower::{Layer, Service};
+
+use crate::server_auth;
+
+/// Path of the metrics endpoint.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// RPC label of the requests to paths outside of the served services.
+pub const UNKNOWN_RPC: &str = "unknown";
+
+/// Buckets of the modular exponentiation histogram, in seconds, finer than the default buckets since a
+/// single operation takes well under a millisecond with the default parameters.
+const MODPOW_BUCKETS: [f64; 10] = [
+    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5,
+];
+
+/// Metrics of the server, in their own Prometheus registry.
+pub struct Metrics {
+    registry: Registry,
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    modpow_duration: HistogramVec,
+    active_challenges: IntGauge,
+    active_sessions: IntGauge,
+    verification_queue_depth: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let rpc_requests = IntCounterVec::new(
+            Opts::new(
+                "zkp_auth_rpc_requests_total",
+                "Requests per RPC and outcome",
+            ),
+            &["rpc", "outcome"],
+        )
+        .unwrap();
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new("zkp_auth_rpc_duration_seconds", "Latency of each RPC"),
+            &["rpc"],
+        )
+        .unwrap();
+        let modpow_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "zkp_auth_modpow_duration_seconds",
+                "Cost of the modular exponentiations of each operation",
+            )
+            .buckets(MODPOW_BUCKETS.to_vec()),
+            &["operation"],
+        )
+        .unwrap();
+        let active_challenges = IntGauge::new(
+            "zkp_auth_active_challenges",
+            "Authentication challenges held by the storage backend",
+        )
+        .unwrap();
+        let active_sessions = IntGauge::new("zkp_auth_active_sessions", "Active sessions").unwrap();
+        let verification_queue_depth = IntGauge::new(
+            "zkp_auth_verification_queue_depth",
+            "Proof verifications waiting for a worker",
+        )
+        .unwrap();
+        registry.register(Box::new(rpc_requests.clone())).unwrap();
+        registry.register(Box::new(rpc_duration.clone())).unwrap();
+        registry
+            .register(Box::new(modpow_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(active_challenges.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(active_sessions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(verification_queue_depth.clone()))
+            .unwrap();
+        Self {
+            registry,
+            rpc_requests,
+            rpc_duration,
+            modpow_duration,
+            active_challenges,
+            active_sessions,
+            verification_queue_depth,
+        }
+    }
+
+    /// Records a request to `rpc`, answered with `code` after `seconds`.
+    pub fn record_rpc(&self, rpc: &str, code: Code, seconds: f64) {
+        self.rpc_requests
+            .with_label_values(&[rpc, &format!("{code:?}")])
+            .inc();
+        self.rpc_duration.with_label_values(&[rpc]).observe(seconds);
+    }
+
+    /// Runs `f`, recording its duration as the modular exponentiation cost of `operation`.
+    pub fn time_modpow<T>(&self, operation: &str, f: impl FnOnce() -> T) -> T {
+        let timer = self
+            .modpow_duration
+            .with_label_values(&[operation])
+            .start_timer();
+        let result = f();
+        timer.observe_duration();
+        result
+    }
+
+    /// Sets the number of active challenges and sessions.
+    pub fn set_active(&self, challenges: usize, sessions: usize) {
+        self.active_challenges.set(challenges as i64);
+        self.active_sessions.set(sessions as i64);
+    }
+
+    /// Records a proof verification queued for a worker.
+    pub fn record_verification_queued(&self) {
+        self.verification_queue_depth.inc();
+    }
+
+    /// Records a proof verification taken off the queue, by a worker or because the queue was full.
+    pub fn record_verification_dequeued(&self) {
+        self.verification_queue_depth.dec();
+    }
+
+    /// Encodes every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics");
+        String::from_utf8(buffer).expect("Failed to encode metrics")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves the metrics over HTTP on `listener`, at `METRICS_PATH`.
+///
+/// # Returns
+/// A `Result` that only completes once the HTTP server fails.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let metrics = metrics.clone();
+                async move {
+                    let response = if request.method() == Method::GET
+                        && request.uri().path() == METRICS_PATH
+                    {
+                        hyper::Response::builder()
+                            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+                            .body(Body::from(metrics.encode()))
+                    } else {
+                        hyper::Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                    };
+                    Ok::<_, Infallible>(response.expect("Failed to build metrics response"))
+                }
+            }))
+        }
+    });
+    hyper::Server::from_tcp(listener)?.serve(make_service).await
+}
+
+/// Returns the gRPC status code of a unary call's response. Failed calls carry their status in the
+/// response headers, successful ones in the trailers.
+pub(crate) fn grpc_code<B>(response: &http::Response<B>) -> Code {
+    response
+        .headers()
+        .get("grpc-status")
+        .and_then(|code| code.to_str().ok())
+        .and_then(|code| code.parse().ok())
+        .map(Code::from_i32)
+        .unwrap_or(Code::Ok)
+}
+
+/// Returns the RPCs of every service described by the encoded file descriptor sets, e.g.
+/// `zkp_auth.Auth/Register`.
+fn service_rpcs(file_descriptor_sets: &[&[u8]]) -> HashSet<String> {
+    let mut rpcs = HashSet::new();
+    for file_descriptor_set in file_descriptor_sets {
+        let file_descriptor_set = FileDescriptorSet::decode(*file_descriptor_set)
+            .expect("Failed to decode file descriptor set");
+        for file in file_descriptor_set.file {
+            for service in &file.service {
+                for method in &service.method {
+                    rpcs.insert(format!(
+                        "{}.{}/{}",
+                        file.package(),
+                        service.name(),
+                        method.name()
+                    ));
+                }
+            }
+        }
+    }
+    rpcs
+}
+
+/// Tower layer counting the requests of every gRPC service it wraps, with their outcome and latency.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+    /// RPCs of the served services, the only ones labelled by name
+    rpcs: Arc<HashSet<String>>,
+}
+
+impl MetricsLayer {
+    /// Creates a layer labelling the RPCs of the `Auth`, `Admin`, health and reflection services.
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            rpcs: Arc::new(service_rpcs(&[
+                server_auth::FILE_DESCRIPTOR_SET,
+                tonic_health::pb::FILE_DESCRIPTOR_SET,
+                tonic_reflection::pb::FILE_DESCRIPTOR_SET,
+            ])),
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+            rpcs: self.rpcs.clone(),
+        }
+    }
+}
+
+/// Service recording the metrics of the requests to its inner gRPC service, see `MetricsLayer`.
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+    rpcs: Arc<HashSet<String>>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // the service polled ready is the one that must handle the request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let metrics = self.metrics.clone();
+        let rpc = match request.uri().path().trim_start_matches('/') {
+            rpc if self.rpcs.contains(rpc) => rpc.to_string(),
+            _ => UNKNOWN_RPC.to_string(),
+        };
+        Box::pin(async move {
+            let start = Instant::now();
+            let response = inner.call(request).await?;
+            metrics.record_rpc(&rpc, grpc_code(&response), start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_rpc("zkp_auth.Auth/Register", Code::Ok, 0.01);
+        metrics.record_rpc("zkp_auth.Auth/Register", Code::AlreadyExists, 0.01);
+        metrics.record_rpc("zkp_auth.Auth/Register", Code::Ok, 0.01);
+        assert_eq!(metrics.time_modpow("verify_authentication", || 42), 42);
+        metrics.set_active(3, 2);
+        metrics.record_verification_queued();
+        metrics.record_verification_queued();
+        metrics.record_verification_dequeued();
+
+        let encoded = metrics.encode();
+        assert!(encoded.contains(
+            "zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"zkp_auth.Auth/Register\"} 2"
+        ));
+        assert!(encoded.contains(
+            "zkp_auth_rpc_requests_total{outcome=\"AlreadyExists\",rpc=\"zkp_auth.Auth/Register\"} 1"
+        ));
+        assert!(encoded
+            .contains("zkp_auth_rpc_duration_seconds_count{rpc=\"zkp_auth.Auth/Register\"} 3"));
+        assert!(encoded.contains(
+            "zkp_auth_modpow_duration_seconds_count{operation=\"verify_authentication\"} 1"
+        ));
+        assert!(encoded.contains("zkp_auth_active_challenges 3"));
+        assert!(encoded.contains("zkp_auth_active_sessions 2"));
+        assert!(encoded.contains("zkp_auth_verification_queue_depth 1"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_rpcs_share_a_label() {
+        let metrics = Arc::new(Metrics::new());
+        let mut service = MetricsLayer::new(metrics.clone()).layer(tower::service_fn(
+            |_: http::Request<()>| async { Ok::<_, Infallible>(http::Response::new(())) },
+        ));
+        for path in [
+            "/zkp_auth.Auth/Register",
+            "/grpc.health.v1.Health/Check",
+            "/zkp_auth.Auth/Unknown",
+            "/random/path",
+        ] {
+            let request = http::Request::builder().uri(path).body(()).unwrap();
+            service.call(request).await.unwrap();
+        }
+
+        let encoded = metrics.encode();
+        assert!(encoded.contains(
+            "zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"zkp_auth.Auth/Register\"} 1"
+        ));
+        assert!(encoded.contains(
+            "zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"grpc.health.v1.Health/Check\"} 1"
+        ));
+        assert!(encoded.contains("zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"unknown\"} 2"));
+        assert!(!encoded.contains("random"));
+    }
+}
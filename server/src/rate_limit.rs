@@ -0,0 +1,375 @@
+//! # Rate Limiting
+//!
+//! Online password guessing needs a fresh authentication challenge per guess, so the rate of guesses
+//! is bounded by limiting how often challenges can be requested, with token buckets keyed by user name
+//! and by peer IP address. On top of it, a user is locked out after too many consecutive failed
+//! verifications, for a duration doubling with every lockout, until it authenticates successfully or
+//! an administrator unlocks it.
+//!
+//! Limits are tracked in memory, by each replica independently.
+use std::{collections::HashMap, hash::Hash, net::IpAddr, sync::Mutex, time::Duration};
+use tonic::Status;
+
+use crate::clock::Timestamp;
+
+/// A token bucket limit: up to `burst` requests at once, then one more every `refill_interval`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub refill_interval: Duration,
+}
+
+/// Configuration of a `RateLimiter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Limit of authentication challenges per user name
+    pub user_limit: RateLimit,
+    /// Limit of authentication requests per peer IP address
+    pub ip_limit: RateLimit,
+    /// Number of consecutive failed verifications after which a user is locked out
+    pub max_failures: u32,
+    /// Duration of the first lockout, doubled by every following one
+    pub base_lockout: Duration,
+    /// Maximum duration of a lockout
+    pub max_lockout: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            user_limit: RateLimit {
+                burst: 10,
+                refill_interval: Duration::from_secs(6),
+            },
+            ip_limit: RateLimit {
+                burst: 60,
+                refill_interval: Duration::from_secs(1),
+            },
+            max_failures: 5,
+            base_lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Timestamp,
+}
+
+impl TokenBucket {
+    /// Refills the bucket up to `now`, returning whether it is then full.
+    fn refill(&mut self, limit: &RateLimit, now: Timestamp) -> bool {
+        let elapsed = now.saturating_sub(self.updated_at) as f64;
+        let refill_interval = limit.refill_interval.as_secs().max(1) as f64;
+        self.tokens = (self.tokens + elapsed / refill_interval).min(limit.burst as f64);
+        self.updated_at = now;
+        self.tokens >= limit.burst as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Lockout {
+    /// Consecutive failed verifications since the last lockout
+    failures: u32,
+    /// Number of lockouts since the last successful verification
+    lockouts: u32,
+    locked_until: Timestamp,
+    last_failure_at: Timestamp,
+}
+
+/// Token bucket rate limiter and lockout tracker of authentication attempts.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    user_buckets: Mutex<HashMap<String, TokenBucket>>,
+    ip_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    lockouts: Mutex<HashMap<String, Lockout>>,
+}
+
+/// Takes a token from the bucket of `key`, returning whether one was available.
+fn take_token<K: Eq + Hash>(
+    buckets: &Mutex<HashMap<K, TokenBucket>>,
+    key: K,
+    limit: &RateLimit,
+    now: Timestamp,
+) -> bool {
+    let mut buckets = buckets.lock().unwrap();
+    let bucket = buckets.entry(key).or_insert(TokenBucket {
+        tokens: limit.burst as f64,
+        updated_at: now,
+    });
+    bucket.refill(limit, now);
+    if bucket.tokens < 1.0 {
+        return false;
+    }
+    bucket.tokens -= 1.0;
+    true
+}
+
+fn too_many_requests() -> Status {
+    Status::resource_exhausted("Too many authentication requests, retry later")
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            user_buckets: Mutex::new(HashMap::new()),
+            ip_buckets: Mutex::new(HashMap::new()),
+            lockouts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request from the peer `ip`, if known, against its limit.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or a `RESOURCE_EXHAUSTED` `Status` if the peer exceeded its limit.
+    pub(crate) fn check_peer(&self, ip: Option<IpAddr>, now: Timestamp) -> Result<(), Status> {
+        match ip {
+            Some(ip) if !take_token(&self.ip_buckets, ip, &self.config.ip_limit, now) => {
+                Err(too_many_requests())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts an authentication challenge request for `user` against its limit. Only known (registered or
+    /// decoy) users are counted, so that requests for arbitrary names don't each take a bucket.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or a `Status` error if the user is locked out or exceeded its limit.
+    pub(crate) fn check_user(&self, user: &str, now: Timestamp) -> Result<(), Status> {
+        self.check_lockout(user, now)?;
+        if !take_token(
+            &self.user_buckets,
+            user.to_string(),
+            &self.config.user_limit,
+            now,
+        ) {
+            return Err(too_many_requests());
+        }
+        Ok(())
+    }
+
+    /// Checks that `user` is not locked out.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or a `PERMISSION_DENIED` `Status` if the user is locked out.
+    pub(crate) fn check_lockout(&self, user: &str, now: Timestamp) -> Result<(), Status> {
+        match self.lockouts.lock().unwrap().get(user) {
+            Some(lockout) if lockout.locked_until > now => Err(Status::permission_denied(format!(
+                "Too many failed authentication attempts, user is locked out for {} seconds",
+                lockout.locked_until - now
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a verification for `user`, counted as a failed one until it is recorded as a success, and
+    /// locks the user out once it reaches the maximum number of consecutive failures. The lockout is
+    /// checked and the attempt counted at once, so that concurrent verifications can't exceed the maximum.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or a `PERMISSION_DENIED` `Status` if the user is locked out.
+    pub(crate) fn start_attempt(&self, user: &str, now: Timestamp) -> Result<(), Status> {
+        let mut lockouts = self.lockouts.lock().unwrap();
+        let lockout = lockouts.entry(user.to_string()).or_default();
+        if lockout.locked_until > now {
+            return Err(Status::permission_denied(format!(
+                "Too many failed authentication attempts, user is locked out for {} seconds",
+                lockout.locked_until - now
+            )));
+        }
+        lockout.failures += 1;
+        lockout.last_failure_at = now;
+        if lockout.failures >= self.config.max_failures {
+            let backoff = 2u32.saturating_pow(lockout.lockouts);
+            let duration = self
+                .config
+                .base_lockout
+                .saturating_mul(backoff)
+                .min(self.config.max_lockout);
+            lockout.failures = 0;
+            lockout.lockouts += 1;
+            lockout.locked_until = now + duration.as_secs();
+        }
+        Ok(())
+    }
+
+    /// Records a successful verification for `user`, clearing its failures.
+    pub(crate) fn record_success(&self, user: &str) {
+        self.lockouts.lock().unwrap().remove(user);
+    }
+
+    /// Lifts the lockout of `user`, clearing its failures.
+    ///
+    /// # Returns
+    /// Whether the user was locked out at `now`.
+    pub fn unlock(&self, user: &str, now: Timestamp) -> bool {
+        self.lockouts
+            .lock()
+            .unwrap()
+            .remove(user)
+            .is_some_and(|lockout| lockout.locked_until > now)
+    }
+
+    /// Forgets full token buckets, and the failures of users who haven't failed nor been locked out for
+    /// longer than the maximum lockout duration, so that memory is only used by recently active users and peers.
+    ///
+    /// # Returns
+    /// The number of forgotten entries.
+    pub(crate) fn purge(&self, now: Timestamp) -> usize {
+        let mut purged = 0;
+        let mut user_buckets = self.user_buckets.lock().unwrap();
+        let len = user_buckets.len();
+        user_buckets.retain(|_, bucket| !bucket.refill(&self.config.user_limit, now));
+        purged += len - user_buckets.len();
+        drop(user_buckets);
+
+        let mut ip_buckets = self.ip_buckets.lock().unwrap();
+        let len = ip_buckets.len();
+        ip_buckets.retain(|_, bucket| !bucket.refill(&self.config.ip_limit, now));
+        purged += len - ip_buckets.len();
+        drop(ip_buckets);
+
+        let mut lockouts = self.lockouts.lock().unwrap();
+        let len = lockouts.len();
+        let max_lockout = self.config.max_lockout.as_secs();
+        lockouts.retain(|_, lockout| {
+            lockout.locked_until.max(lockout.last_failure_at) + max_lockout > now
+        });
+        purged + len - lockouts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: Timestamp = 1_000;
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            user_limit: RateLimit {
+                burst: 2,
+                refill_interval: Duration::from_secs(10),
+            },
+            ip_limit: RateLimit {
+                burst: 3,
+                refill_interval: Duration::from_secs(1),
+            },
+            max_failures: 2,
+            base_lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(100),
+        }
+    }
+
+    #[test]
+    fn test_user_token_bucket() {
+        let rate_limiter = RateLimiter::new(config());
+        assert!(rate_limiter.check_user("user", NOW).is_ok());
+        assert!(rate_limiter.check_user("user", NOW).is_ok());
+        assert_eq!(
+            rate_limiter.check_user("user", NOW).unwrap_err().code(),
+            tonic::Code::ResourceExhausted
+        );
+        // buckets are per user
+        assert!(rate_limiter.check_user("other user", NOW).is_ok());
+
+        // one token is refilled every interval
+        assert!(rate_limiter.check_user("user", NOW + 9).is_err());
+        assert!(rate_limiter.check_user("user", NOW + 10).is_ok());
+        assert!(rate_limiter.check_user("user", NOW + 10).is_err());
+    }
+
+    #[test]
+    fn test_peer_token_bucket() {
+        let rate_limiter = RateLimiter::new(config());
+        let ip = "10.0.0.1".parse().ok();
+        for _ in 0..3 {
+            assert!(rate_limiter.check_peer(ip, NOW).is_ok());
+        }
+        assert!(rate_limiter.check_peer(ip, NOW).is_err());
+        assert!(rate_limiter
+            .check_peer("10.0.0.2".parse().ok(), NOW)
+            .is_ok());
+        // requests of unknown peers are not limited
+        for _ in 0..4 {
+            assert!(rate_limiter.check_peer(None, NOW).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_lockout_with_exponential_backoff() {
+        let rate_limiter = RateLimiter::new(config());
+        let mut now = NOW;
+        for lockout in [30, 60, 100, 100] {
+            rate_limiter.start_attempt("user", now).unwrap();
+            assert!(rate_limiter.check_lockout("user", now).is_ok());
+            rate_limiter.start_attempt("user", now).unwrap();
+            assert_eq!(
+                rate_limiter.check_lockout("user", now).unwrap_err().code(),
+                tonic::Code::PermissionDenied
+            );
+            assert!(rate_limiter
+                .check_lockout("user", now + lockout - 1)
+                .is_err());
+            now += lockout;
+            assert!(rate_limiter.check_lockout("user", now).is_ok());
+        }
+
+        // a successful verification resets the backoff
+        rate_limiter.record_success("user");
+        rate_limiter.start_attempt("user", now).unwrap();
+        rate_limiter.start_attempt("user", now).unwrap();
+        assert!(rate_limiter.check_lockout("user", now + 29).is_err());
+        assert!(rate_limiter.check_lockout("user", now + 30).is_ok());
+    }
+
+    #[test]
+    fn test_attempts_are_counted_before_verification() {
+        let rate_limiter = RateLimiter::new(config());
+        // concurrent attempts, none of which has been verified yet, reach the lockout
+        assert!(rate_limiter.start_attempt("user", NOW).is_ok());
+        assert!(rate_limiter.start_attempt("user", NOW).is_ok());
+        assert_eq!(
+            rate_limiter.start_attempt("user", NOW).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+
+        // until a verification succeeds
+        rate_limiter.record_success("user");
+        assert!(rate_limiter.start_attempt("user", NOW).is_ok());
+        rate_limiter.record_success("user");
+        assert!(rate_limiter.start_attempt("user", NOW).is_ok());
+        assert!(rate_limiter.check_lockout("user", NOW).is_ok());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let rate_limiter = RateLimiter::new(config());
+        rate_limiter.start_attempt("user", NOW).unwrap();
+        rate_limiter.start_attempt("user", NOW).unwrap();
+        assert!(rate_limiter.check_user("user", NOW).is_err());
+
+        assert!(rate_limiter.unlock("user", NOW));
+        assert!(rate_limiter.check_user("user", NOW).is_ok());
+        assert!(!rate_limiter.unlock("user", NOW));
+    }
+
+    #[test]
+    fn test_purge() {
+        let rate_limiter = RateLimiter::new(config());
+        rate_limiter.check_user("user", NOW).unwrap();
+        rate_limiter
+            .check_peer("10.0.0.1".parse().ok(), NOW)
+            .unwrap();
+        rate_limiter.start_attempt("user", NOW).unwrap();
+
+        assert_eq!(rate_limiter.purge(NOW), 0);
+        // buckets are full again, but failures are remembered until a lockout would have expired
+        assert_eq!(rate_limiter.purge(NOW + 10), 2);
+        assert_eq!(rate_limiter.purge(NOW + 100), 1);
+    }
+}
@@ -0,0 +1,277 @@
+//! # Sealed Challenges
+//!
+//! In stateless challenge mode, the server keeps no record of the authentication challenges it issues.
+//! Instead, the challenge (user, commitments `r1` and `r2`, challenge `c` and expiry) is encrypted and
+//! authenticated with XChaCha20-Poly1305 under a server key, and the sealed blob is handed to the
+//! client as its `auth_id`. Any replica sharing the key can open it back when the client answers.
+//!
+//! Challenges stay single use through a cache of the random nonces of the challenges answered so far,
+//! each kept until its challenge expires. The cache is local to a replica, so a challenge answered on
+//! one replica could be replayed once on another: deployments relying on single use challenges across
+//! replicas must route both steps of a login to the same replica.
+//!
+//! A nonce is only remembered once the answer passed the checks preceding its verification (the
+//! challenge is unexpired, and its user known and not locked out), so that answers to forged, expired or
+//! locked out challenges don't take room in the cache. Answers to the challenges of decoy users are
+//! remembered like any other, to keep decoys indistinguishable from registered users, so the rate limits
+//! on challenges and answers are what keeps the cache from filling up and answers from being rejected
+//! with `RESOURCE_EXHAUSTED`.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use num_bigint::BigInt;
+use prost::Message;
+use std::{collections::HashMap, fs, io, path::Path, sync::Mutex};
+use tonic::Status;
+
+use crate::{clock::Timestamp, types::Challenge};
+
+/// Size, in bytes, of a challenge sealing key.
+pub const CHALLENGE_KEY_SIZE: usize = 32;
+
+/// Default maximum number of answered challenge nonces remembered at once.
+pub const DEFAULT_SEEN_NONCE_CAPACITY: usize = 100_000;
+
+/// Size, in bytes, of an XChaCha20-Poly1305 nonce.
+const NONCE_SIZE: usize = 24;
+
+/// Content of a sealed challenge.
+#[derive(Clone, PartialEq, Message)]
+struct SealedChallenge {
+    #[prost(string, tag = "1")]
+    user: String,
+    #[prost(bytes = "vec", tag = "2")]
+    r1: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    r2: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    c: Vec<u8>,
+    #[prost(uint64, tag = "5")]
+    expires_at: Timestamp,
+}
+
+/// Seals authentication challenges into self-contained `auth_id`s, and opens them back at most once.
+pub struct ChallengeSealer {
+    cipher: XChaCha20Poly1305,
+    /// Nonces of the challenges answered so far, with the expiry of their challenge
+    seen_nonces: Mutex<HashMap<[u8; NONCE_SIZE], Timestamp>>,
+    /// Maximum number of nonces remembered at once
+    capacity: usize,
+}
+
+impl ChallengeSealer {
+    /// Creates a sealer from its secret key, which must be shared by every replica.
+    pub fn new(key: &[u8; CHALLENGE_KEY_SIZE]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            seen_nonces: Mutex::new(HashMap::new()),
+            capacity: DEFAULT_SEEN_NONCE_CAPACITY,
+        }
+    }
+
+    /// Loads a sealer from a file holding its `CHALLENGE_KEY_SIZE` raw secret key bytes.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let key: [u8; CHALLENGE_KEY_SIZE] = fs::read(path)?.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Failed to load challenge key, key must be {CHALLENGE_KEY_SIZE} bytes long"
+                ),
+            )
+        })?;
+        Ok(Self::new(&key))
+    }
+
+    /// Sets the maximum number of answered challenge nonces remembered at once.
+    pub fn with_seen_nonce_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Seals a challenge, bound to the service `domain`, into an `auth_id`. The challenge's own id is
+    /// ignored.
+    pub(crate) fn seal(&self, domain: &[u8], challenge: &Challenge) -> String {
+        let plaintext = SealedChallenge {
+            user: challenge.user_id.clone(),
+            r1: challenge.r1.to_bytes_be().1,
+            r2: challenge.r2.to_bytes_be().1,
+            c: challenge.c.to_bytes_be().1,
+            expires_at: challenge.expires_at,
+        }
+        .encode_to_vec();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: domain,
+                },
+            )
+            .expect("Failed to seal challenge");
+        URL_SAFE_NO_PAD.encode([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// Opens a sealed challenge, without taking it: it must still be claimed before its answer is verified.
+    ///
+    /// # Returns
+    /// The `Challenge`, with the `auth_id` as its id, or `None` if the `auth_id` was not sealed by this key
+    /// for this `domain`.
+    pub(crate) fn open(&self, domain: &[u8], auth_id: &str) -> Option<Challenge> {
+        let sealed = URL_SAFE_NO_PAD.decode(auth_id).ok()?;
+        if sealed.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: domain,
+                },
+            )
+            .ok()?;
+        let sealed_challenge = SealedChallenge::decode(plaintext.as_slice()).ok()?;
+
+        let to_bigint = |bytes: &[u8]| BigInt::from_bytes_be(num_bigint::Sign::Plus, bytes);
+        Some(Challenge {
+            id: auth_id.to_string(),
+            c: to_bigint(&sealed_challenge.c),
+            r1: to_bigint(&sealed_challenge.r1),
+            r2: to_bigint(&sealed_challenge.r2),
+            user_id: sealed_challenge.user,
+            expires_at: sealed_challenge.expires_at,
+        })
+    }
+
+    /// Claims an opened challenge, which can only be claimed once until it expires at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing whether the challenge was claimed, `false` if it already had been, or a
+    /// `RESOURCE_EXHAUSTED` `Status` if too many unexpired challenges have been claimed.
+    pub(crate) fn claim(&self, challenge: &Challenge, now: Timestamp) -> Result<bool, Status> {
+        let nonce: [u8; NONCE_SIZE] = URL_SAFE_NO_PAD
+            .decode(&challenge.id)
+            .ok()
+            .and_then(|sealed| sealed.get(..NONCE_SIZE)?.try_into().ok())
+            .ok_or(Status::internal(
+                "Failed to claim challenge, challenge was not opened from a sealed challenge",
+            ))?;
+        let mut seen_nonces = self.seen_nonces.lock().unwrap();
+        if seen_nonces.contains_key(&nonce) {
+            return Ok(false);
+        }
+        if seen_nonces.len() >= self.capacity {
+            seen_nonces.retain(|_, expires_at| *expires_at > now);
+            if seen_nonces.len() >= self.capacity {
+                return Err(Status::resource_exhausted(
+                    "Failed to verify challenge, too many challenges are pending, retry later",
+                ));
+            }
+        }
+        seen_nonces.insert(nonce, challenge.expires_at);
+        Ok(true)
+    }
+
+    /// Forgets the nonces of every challenge expired at `now`, which can no longer be answered anyway.
+    ///
+    /// # Returns
+    /// The number of forgotten nonces.
+    pub(crate) fn purge_expired(&self, now: Timestamp) -> usize {
+        let mut seen_nonces = self.seen_nonces.lock().unwrap();
+        let len = seen_nonces.len();
+        seen_nonces.retain(|_, expires_at| *expires_at > now);
+        len - seen_nonces.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: Timestamp = 500;
+
+    fn challenge() -> Challenge {
+        Challenge {
+            id: String::new(),
+            c: BigInt::from(10_000),
+            r1: BigInt::from(100),
+            r2: BigInt::from(200),
+            user_id: "user_name".to_string(),
+            expires_at: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_sealed_challenge_is_single_use() {
+        let sealer = ChallengeSealer::new(&[7u8; CHALLENGE_KEY_SIZE]);
+        let auth_id = sealer.seal(b"domain", &challenge());
+
+        let opened = sealer.open(b"domain", &auth_id).unwrap();
+        assert_eq!(
+            opened,
+            Challenge {
+                id: auth_id.clone(),
+                ..challenge()
+            }
+        );
+        // opening doesn't claim the challenge, which can only be claimed once
+        assert_eq!(sealer.open(b"domain", &auth_id), Some(opened.clone()));
+        assert!(sealer.claim(&opened, NOW).unwrap());
+        assert!(!sealer.claim(&opened, NOW).unwrap());
+
+        // every sealed challenge has its own nonce
+        assert_ne!(
+            sealer.seal(b"domain", &challenge()),
+            sealer.seal(b"domain", &challenge())
+        );
+    }
+
+    #[test]
+    fn test_sealed_challenge_is_authenticated() {
+        let sealer = ChallengeSealer::new(&[7u8; CHALLENGE_KEY_SIZE]);
+        let auth_id = sealer.seal(b"domain", &challenge());
+
+        // sealed for another domain, or with another key
+        assert_eq!(sealer.open(b"other domain", &auth_id), None);
+        let other_sealer = ChallengeSealer::new(&[8u8; CHALLENGE_KEY_SIZE]);
+        assert_eq!(other_sealer.open(b"domain", &auth_id), None);
+
+        // tampered with
+        let mut sealed = URL_SAFE_NO_PAD.decode(&auth_id).unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        let tampered_auth_id = URL_SAFE_NO_PAD.encode(sealed);
+        assert_eq!(sealer.open(b"domain", &tampered_auth_id), None);
+        assert_eq!(sealer.open(b"domain", "not an auth id"), None);
+    }
+
+    #[test]
+    fn test_seen_nonces_are_bounded() {
+        let sealer = ChallengeSealer::new(&[7u8; CHALLENGE_KEY_SIZE]).with_seen_nonce_capacity(2);
+        let short_lived_challenge = Challenge {
+            expires_at: NOW + 1,
+            ..challenge()
+        };
+        for _ in 0..2 {
+            let auth_id = sealer.seal(b"domain", &short_lived_challenge);
+            let opened = sealer.open(b"domain", &auth_id).unwrap();
+            assert!(sealer.claim(&opened, NOW).unwrap());
+        }
+        let auth_id = sealer.seal(b"domain", &challenge());
+        let opened = sealer.open(b"domain", &auth_id).unwrap();
+        assert_eq!(
+            sealer.claim(&opened, NOW).unwrap_err().code(),
+            tonic::Code::ResourceExhausted
+        );
+
+        // nonces of expired challenges are forgotten, making room for new ones
+        assert!(sealer.claim(&opened, NOW + 1).unwrap());
+        assert_eq!(sealer.purge_expired(NOW + 1), 0);
+        assert_eq!(sealer.purge_expired(challenge().expires_at), 1);
+    }
+}
@@ -1,33 +1,483 @@
 use crate::{
+    admin::PedersenChaumAdminServer,
+    audit::{AuditEvent, AuditLog, RevocationCause},
+    clock::{Clock, SystemClock, Timestamp},
+    decoy::DecoyUsers,
+    logging::{fingerprint, record_user},
+    metrics::Metrics,
+    rate_limit::RateLimiter,
+    sealed_challenge::ChallengeSealer,
     server_auth::{
-        auth_server::Auth, AuthenticationAnswerRequest, AuthenticationAnswerResponse,
-        AuthenticationChallengeRequest, AuthenticationChallengeResponse, RegisterRequest,
-        RegisterResponse,
+        auth_server::{Auth, AuthServer},
+        session_validation, AuthenticationAnswerRequest, AuthenticationAnswerResponse,
+        AuthenticationChallengeRequest, AuthenticationChallengeResponse, BlindedCredentialRequest,
+        BlindedCredentialResponse, CredentialCommitmentRequest, CredentialCommitmentResponse,
+        CredentialPresentationRequest, CredentialPresentationResponse, LoginParametersRequest,
+        LoginParametersResponse, LogoutRequest, LogoutResponse, ParametersRequest,
+        ParametersResponse, RefreshSessionRequest, RefreshSessionResponse, RegisterRequest,
+        RegisterResponse, RevokeAllSessionsRequest, RevokeAllSessionsResponse,
+        RotateCredentialRequest, RotateCredentialResponse, SessionError, SessionTokenKey,
+        SessionTokenKeysRequest, SessionTokenKeysResponse, SessionValidation,
+        ValidateSessionRequest, ValidateSessionResponse, ValidateSessionsRequest,
+        ValidateSessionsResponse,
     },
-    state::PedersenChaumAuthServerState,
+    state::check_enabled,
+    storage::{AuthStorage, InMemoryStorage},
+    types::{
+        Challenge, KdfParameters, PendingCredential, Session, User, MAX_SALT_LEN, MIN_SALT_LEN,
+    },
+    verification_pool::VerificationPool,
+};
+use chaum_pedersen::{
+    blind_signature::{self, BlindSignature, BlindSigner, SignerCommitment},
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface, NonInteractiveProof, DEFAULT_DOMAIN},
+    key_rotation::{self, KeyRotation, KeyRotationProof},
+    registration, Parameters, DEFAULT_PARAMS,
 };
-use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
-use log::info;
 use num_bigint::BigInt;
-use tokio::sync::RwLock;
-use tonic::{Request, Response, Status};
+use session_token::{SessionClaims, TokenSigningKey, TokenVerifyingKey};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tonic::{server::NamedService, Request, Response, Status};
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::{error, info};
 use uuid::Uuid;
 
+/// Default time to live of an authentication challenge.
+pub const DEFAULT_CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// Default time to live of a session, extended on every refresh.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Default maximum number of concurrent sessions per user.
+pub const DEFAULT_MAX_SESSIONS_PER_USER: usize = 5;
+
+/// Default maximum number of credential signing sessions open at once, across every session. ROS
+/// attacks forge blind Schnorr signatures out of many concurrent signing sessions, at a cost that
+/// quickly drops as their number grows.
+pub const DEFAULT_MAX_CREDENTIAL_COMMITMENTS: usize = 4;
+
+/// Default validity period of anonymous credentials. Credentials expire at the end of the period
+/// following the one they were issued in, so that all the credentials issued in a period share their
+/// expiry.
+pub const DEFAULT_CREDENTIAL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Size, in bytes, of the expiry prefixing every credential message, a big endian Unix timestamp.
+pub const CREDENTIAL_EXPIRY_SIZE: usize = 8;
+
+/// Maximum number of sessions validated by a single `ValidateSessions` request.
+pub const MAX_VALIDATE_SESSIONS_BATCH: usize = 100;
+
 /// Represents a server for handling authentication using the Chaum-Pedersen Zero-Knowledge Proof (ZKP) protocol.
 ///
-/// This server structure contains the necessary components to manage and execute the Chaum-Pedersen protocol for user authentication. It holds an instance of the Chaum-Pedersen protocol and maintains the server's state.
-pub struct PedersenChaumAuthServer {
-    /// An instance of the `ChaumPedersen` struct
-    cp_zkp_protocol: ChaumPedersen,
-    /// A thread-safe, read-write lock (`RwLock`) guarding the state of the `PedersenChaumAuthServer`
-    pub(crate) state: RwLock<PedersenChaumAuthServerState>,
+/// This server structure contains the necessary components to manage and execute the Chaum-Pedersen protocol for user authentication. It holds an instance of the Chaum-Pedersen protocol and maintains the server's state, in a pluggable storage backend (in memory, by default).
+pub struct PedersenChaumAuthServer<S: AuthStorage = InMemoryStorage> {
+    /// An instance of the `ChaumPedersen` struct, shared with the verification pool
+    cp_zkp_protocol: Arc<ChaumPedersen>,
+    /// Blind signer for anonymous session credentials, with a key pair generated at startup unless loaded
+    /// from a key file
+    credential_signer: BlindSigner,
+    /// Service domain, bound to every proof transcript
+    domain: String,
+    /// Source of the current time, for expiring challenges and sessions
+    clock: Arc<dyn Clock>,
+    /// Time to live of an authentication challenge
+    challenge_ttl: Duration,
+    /// Time to live of a session
+    session_ttl: Duration,
+    /// Maximum number of concurrent sessions per user, beyond which the oldest sessions are revoked
+    max_sessions_per_user: usize,
+    /// Scopes granted to every new session
+    session_scopes: Vec<String>,
+    /// Maximum number of credential signing sessions open at once, across every session
+    max_credential_commitments: usize,
+    /// Validity period of anonymous credentials
+    credential_ttl: Duration,
+    /// Key signing stateless session tokens, if enabled
+    token_signing_key: Option<Arc<TokenSigningKey>>,
+    /// Public keys of retired token signing keys, still published while their tokens may be valid
+    retired_token_keys: Vec<TokenVerifyingKey>,
+    /// Sealer of stateless authentication challenges, if enabled
+    challenge_sealer: Option<Arc<ChallengeSealer>>,
+    /// Rate limiter of authentication attempts, if enabled
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Decoy users answering for unknown user names, if enabled
+    decoys: Option<Arc<DecoyUsers>>,
+    /// Metrics of the server, if enabled
+    metrics: Option<Arc<Metrics>>,
+    /// Audit log of authentication events, if enabled
+    audit_log: Option<Arc<AuditLog>>,
+    /// Worker threads running proof verifications, off the Tokio threads
+    verification_pool: Arc<VerificationPool>,
+    /// The storage backend, holding the state of the `PedersenChaumAuthServer`
+    pub(crate) state: Arc<S>,
 }
 
 impl PedersenChaumAuthServer {
     pub fn new() -> Self {
+        Self::with_storage(InMemoryStorage::new())
+    }
+}
+
+impl<S: AuthStorage> PedersenChaumAuthServer<S> {
+    /// Creates a new server, keeping its state in the given storage backend.
+    pub fn with_storage(storage: S) -> Self {
         Self {
-            cp_zkp_protocol: ChaumPedersen::default(),
-            state: RwLock::new(PedersenChaumAuthServerState::new()),
+            cp_zkp_protocol: Arc::new(ChaumPedersen::default()),
+            credential_signer: BlindSigner::new(DEFAULT_PARAMS.clone()),
+            domain: DEFAULT_DOMAIN.to_string(),
+            clock: Arc::new(SystemClock),
+            challenge_ttl: DEFAULT_CHALLENGE_TTL,
+            session_ttl: DEFAULT_SESSION_TTL,
+            max_sessions_per_user: DEFAULT_MAX_SESSIONS_PER_USER,
+            session_scopes: vec![],
+            max_credential_commitments: DEFAULT_MAX_CREDENTIAL_COMMITMENTS,
+            credential_ttl: DEFAULT_CREDENTIAL_TTL,
+            token_signing_key: None,
+            retired_token_keys: vec![],
+            challenge_sealer: None,
+            rate_limiter: None,
+            decoys: None,
+            metrics: None,
+            audit_log: None,
+            verification_pool: Arc::new(VerificationPool::default()),
+            state: Arc::new(storage),
+        }
+    }
+
+    /// Returns the storage backend of the server, e.g. to export its state once the server has stopped.
+    pub fn storage(&self) -> Arc<S> {
+        self.state.clone()
+    }
+
+    /// Sets the service domain bound to every proof transcript.
+    pub fn with_domain<T: ToString>(mut self, domain: T) -> Self {
+        self.domain = domain.to_string();
+        self
+    }
+
+    /// Sets the group parameters of the protocol, which clients retrieve through `GetParameters`,
+    /// replacing the default ones.
+    /// The blind signing key of credentials is generated anew, in the new group, so a persistent signer must
+    /// be set afterwards.
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.credential_signer = BlindSigner::new(parameters.clone());
+        self.cp_zkp_protocol = Arc::new(ChaumPedersen::new(parameters));
+        self
+    }
+
+    /// Sets the blind signer of anonymous session credentials, e.g. with a key loaded from a file so that
+    /// credentials stay valid across restarts, replacing the key pair generated at startup.
+    pub fn with_credential_signer(mut self, credential_signer: BlindSigner) -> Self {
+        self.credential_signer = credential_signer;
+        self
+    }
+
+    /// Sets the clock used for every expiry decision.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the time to live of authentication challenges.
+    pub fn with_challenge_ttl(mut self, challenge_ttl: Duration) -> Self {
+        self.challenge_ttl = challenge_ttl;
+        self
+    }
+
+    /// Sets the time to live of sessions.
+    pub fn with_session_ttl(mut self, session_ttl: Duration) -> Self {
+        self.session_ttl = session_ttl;
+        self
+    }
+
+    /// Sets the maximum number of concurrent sessions per user.
+    pub fn with_max_sessions_per_user(mut self, max_sessions_per_user: usize) -> Self {
+        self.max_sessions_per_user = max_sessions_per_user;
+        self
+    }
+
+    /// Sets the scopes granted to every new session, reported to relying services by `ValidateSession`.
+    /// Scopes must be non-empty and free of whitespace, as the SQLite backend stores them space separated.
+    pub fn with_session_scopes<T: ToString>(mut self, scopes: impl IntoIterator<Item = T>) -> Self {
+        self.session_scopes = scopes.into_iter().map(|scope| scope.to_string()).collect();
+        self
+    }
+
+    /// Sets the maximum number of credential signing sessions open at once, across every session.
+    pub fn with_max_credential_commitments(mut self, max_credential_commitments: usize) -> Self {
+        self.max_credential_commitments = max_credential_commitments;
+        self
+    }
+
+    /// Sets the validity period of anonymous credentials, which must last at least a second.
+    pub fn with_credential_ttl(mut self, credential_ttl: Duration) -> Self {
+        self.credential_ttl = credential_ttl;
+        self
+    }
+
+    /// Enables stateless session tokens, signed with `signing_key`, which are returned along every new
+    /// or refreshed session. The public keys of `retired_keys` remain published, so that relying
+    /// services can still verify the tokens they signed before the rotation.
+    pub fn with_session_tokens(
+        mut self,
+        signing_key: TokenSigningKey,
+        retired_keys: Vec<TokenVerifyingKey>,
+    ) -> Self {
+        self.token_signing_key = Some(Arc::new(signing_key));
+        self.retired_token_keys = retired_keys;
+        self
+    }
+
+    /// Enables stateless authentication challenges: challenges are sealed into their `auth_id` with
+    /// `sealer`, instead of being written to the storage backend.
+    pub fn with_stateless_challenges(mut self, sealer: ChallengeSealer) -> Self {
+        self.challenge_sealer = Some(Arc::new(sealer));
+        self
+    }
+
+    /// Enables rate limiting of authentication attempts, per user and per peer IP address, and the
+    /// lockout of users after too many failed attempts.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+        self
+    }
+
+    /// Enables user-enumeration resistance: unknown user names are answered with the login parameters
+    /// and authentication challenges of decoy users, whose verification fails like a wrong password.
+    /// New users must then register with the registration profile of the decoy users.
+    pub fn with_enumeration_resistance(mut self, decoys: DecoyUsers) -> Self {
+        self.decoys = Some(Arc::new(decoys));
+        self
+    }
+
+    /// Records the cost of modular exponentiations in the given metrics, as well as the active
+    /// challenges and sessions on every sweep.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Records authentication events, and the actions of administrators, in the given audit log.
+    pub fn with_audit_log(mut self, audit_log: Arc<AuditLog>) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Runs proof verifications on the given pool, instead of one worker per available CPU queuing at
+    /// most `DEFAULT_QUEUE_CAPACITY` verifications.
+    pub fn with_verification_pool(mut self, verification_pool: VerificationPool) -> Self {
+        self.verification_pool = Arc::new(verification_pool);
+        self
+    }
+
+    /// Creates the administration service of this server, authenticating administrators with
+    /// `admin_token`.
+    pub fn admin_server<T: ToString>(&self, admin_token: T) -> PedersenChaumAdminServer<S> {
+        PedersenChaumAdminServer::new(
+            admin_token.to_string(),
+            self.state.clone(),
+            self.clock.clone(),
+            self.rate_limiter.clone(),
+            self.audit_log.clone(),
+        )
+    }
+
+    /// Records an event in the audit log, if enabled.
+    fn audit(&self, event: AuditEvent) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(self.clock.now(), event);
+        }
+    }
+
+    /// Retrieves a registered user or, with enumeration resistance enabled, the decoy user of that name.
+    ///
+    /// # Returns
+    /// A `Result` containing the user, if any, and whether it is a decoy.
+    async fn user_or_decoy(&self, user_name: &str) -> Result<Option<(User, bool)>, Status> {
+        Ok(match self.state.get_user(user_name).await? {
+            Some(user) => Some((user, false)),
+            None => self
+                .decoys
+                .as_ref()
+                .map(|decoys| (decoys.user(user_name), true)),
+        })
+    }
+
+    /// Runs `f`, recording its duration as the modular exponentiation cost of `operation` if metrics are
+    /// enabled.
+    fn time_modpow<T>(&self, operation: &str, f: impl FnOnce() -> T) -> T {
+        match &self.metrics {
+            Some(metrics) => metrics.time_modpow(operation, f),
+            None => f(),
+        }
+    }
+
+    /// Runs the proof verification `f` on the verification pool, recording its duration as the modular
+    /// exponentiation cost of `operation` if metrics are enabled.
+    ///
+    /// # Returns
+    /// A `Result` containing the result of `f`, or a `RESOURCE_EXHAUSTED` `Status` if the pool is
+    /// saturated.
+    async fn run_verification<T, F>(&self, operation: &'static str, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let metrics = self.metrics.clone();
+        self.verification_pool
+            .run(self.metrics.clone(), move || match &metrics {
+                Some(metrics) => metrics.time_modpow(operation, f),
+                None => f(),
+            })
+            .await
+    }
+
+    /// Returns the expiry of the credentials issued at `now`: the end of the validity period following
+    /// the current one.
+    fn credential_expires_at(&self, now: Timestamp) -> Timestamp {
+        let ttl = self.credential_ttl.as_secs().max(1);
+        (now / ttl + 2) * ttl
+    }
+
+    /// Signs a session token for the session, or returns an empty token if session tokens are disabled.
+    fn session_token(&self, session: &Session) -> String {
+        match &self.token_signing_key {
+            Some(signing_key) => signing_key.sign(&SessionClaims {
+                sub: session.user_id.clone(),
+                sid: session.id.clone(),
+                iat: session.issued_at,
+                exp: session.expires_at,
+                scopes: session.scopes.clone(),
+            }),
+            None => String::new(),
+        }
+    }
+
+    /// Spawns a background task removing expired authentication challenges, sessions, credential
+    /// commitments and spent credentials from the storage backend, as well as the nonces of expired stateless challenges, expired decoy challenges and idle rate
+    /// limits, every `interval`. With metrics enabled, it then records the active challenges and sessions.
+    /// The task runs until it is aborted through the returned handle.
+    pub fn spawn_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let state = self.state.clone();
+        let metrics = self.metrics.clone();
+        let clock = self.clock.clone();
+        let challenge_sealer = self.challenge_sealer.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let decoys = self.decoys.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Some(rate_limiter) = &rate_limiter {
+                    rate_limiter.purge(clock.now());
+                }
+                if let Some(challenge_sealer) = &challenge_sealer {
+                    match challenge_sealer.purge_expired(clock.now()) {
+                        0 => {}
+                        purged => info!("Forgot {purged} expired challenge nonces"),
+                    }
+                }
+                if let Some(decoys) = &decoys {
+                    match decoys.purge_expired_challenges(clock.now()) {
+                        0 => {}
+                        purged => info!("Purged {purged} expired decoy challenges"),
+                    }
+                }
+                match state.purge_expired_challenges(clock.now()).await {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Purged {purged} expired authentication challenges"),
+                    Err(e) => error!("Failed to purge expired authentication challenges: {e}"),
+                }
+                match state.purge_expired_sessions(clock.now()).await {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Purged {purged} expired sessions"),
+                    Err(e) => error!("Failed to purge expired sessions: {e}"),
+                }
+                match state
+                    .purge_expired_credential_commitments(clock.now())
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Purged {purged} expired credential commitments"),
+                    Err(e) => error!("Failed to purge expired credential commitments: {e}"),
+                }
+                match state.purge_expired_spent_credentials(clock.now()).await {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Purged {purged} expired spent credentials"),
+                    Err(e) => error!("Failed to purge expired spent credentials: {e}"),
+                }
+                if let Some(metrics) = &metrics {
+                    match state.count_active(clock.now()).await {
+                        Ok((challenges, sessions)) => metrics.set_active(challenges, sessions),
+                        Err(e) => error!("Failed to count active challenges and sessions: {e}"),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Spawns a background task probing the readiness of the storage backend every `interval`, and
+    /// reporting it through `reporter` as the health of both the server and the `Auth` service: `SERVING`
+    /// while the backend is ready, `NOT_SERVING` otherwise. The task runs until it is aborted through the
+    /// returned handle.
+    pub fn spawn_health_reporter(
+        &self,
+        mut reporter: HealthReporter,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut last_status = None;
+            loop {
+                interval.tick().await;
+                // status changes are logged once, not at every probe
+                let status = match state.check_ready().await {
+                    Ok(()) => {
+                        if last_status != Some(ServingStatus::Serving) {
+                            info!("Storage backend is ready");
+                        }
+                        ServingStatus::Serving
+                    }
+                    Err(e) => {
+                        if last_status != Some(ServingStatus::NotServing) {
+                            error!("Storage backend is not ready: {}", e.message());
+                        }
+                        ServingStatus::NotServing
+                    }
+                };
+                last_status = Some(status);
+                for service_name in ["", <AuthServer<Self> as NamedService>::NAME] {
+                    reporter.set_service_status(service_name, status).await;
+                }
+            }
+        })
+    }
+
+    /// Validates a session on behalf of a relying service, which must exist and not have expired.
+    async fn validate_session_id(&self, session_id: &str) -> Result<Session, SessionError> {
+        match self.state.get_session(session_id).await {
+            Ok(Some(session)) if session.expires_at > self.clock.now() => Ok(session),
+            Ok(Some(_)) => Err(SessionError::Expired),
+            Ok(None) => Err(SessionError::NotFound),
+            Err(e) => {
+                error!("Failed to retrieve session data: {e}");
+                Err(SessionError::Unspecified)
+            }
+        }
+    }
+
+    /// Retrieves a session, which must exist and not have expired.
+    async fn active_session(&self, session_id: &str) -> Result<Session, Status> {
+        match self.state.get_session(session_id).await? {
+            Some(session) if session.expires_at > self.clock.now() => Ok(session),
+            Some(_) => Err(Status::unauthenticated(
+                "Failed to retrieve session data, session has expired",
+            )),
+            None => Err(Status::unauthenticated(
+                "Failed to retrieve session data, user must authenticate first",
+            )),
         }
     }
 }
@@ -39,12 +489,13 @@ impl Default for PedersenChaumAuthServer {
 }
 
 #[tonic::async_trait]
-impl Auth for PedersenChaumAuthServer {
+impl<S: AuthStorage> Auth for PedersenChaumAuthServer<S> {
     /// Handles user registration requests for the authentication server.
     ///
     /// This asynchronous function processes registration requests for new users.
-    /// It extracts user data from the request, converts it into the required format,
-    /// and updates the server's state with the new user's information.
+    /// It extracts user data from the request, validates the user's salt and key derivation parameters,
+    /// verifies the proof of possession of the user's secret, bound to the service domain and the user name,
+    /// converts it into the required format, and updates the server's state with the new user's information.
     ///
     /// # Arguments
     ///
@@ -53,25 +504,126 @@ impl Auth for PedersenChaumAuthServer {
     /// # Returns
     ///
     /// A `Result` type that, on success, contains a `Response<RegisterResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. `ALREADY_EXISTS` if the user name is taken. Registered users can only change their keys through `RotateCredential`.
     async fn register(
         &self,
         register_request: Request<RegisterRequest>,
     ) -> Result<Response<RegisterResponse>, Status> {
-        info!("Got a new registration request: {:?}", register_request);
-        let RegisterRequest { user, y1, y2 } = register_request.into_inner();
+        info!("Got a new registration request");
+        let RegisterRequest {
+            user,
+            y1,
+            y2,
+            salt,
+            kdf,
+            proof,
+        } = register_request.into_inner();
+        record_user(&user);
+        if !(MIN_SALT_LEN..=MAX_SALT_LEN).contains(&salt.len()) {
+            return Err(Status::invalid_argument(
+                "Invalid salt, salt length out of bounds",
+            ));
+        }
+        let kdf_parameters = KdfParameters::try_from(kdf.ok_or(Status::invalid_argument(
+            "Invalid key derivation parameters, missing parameters",
+        ))?)?;
+        // users registered with another profile than decoy users would stand out from them
+        if let Some(decoys) = &self.decoys {
+            decoys.check_registration(&salt, &kdf_parameters)?;
+        }
+        let proof = proof.ok_or(Status::invalid_argument(
+            "Failed to register user, missing proof of possession",
+        ))?;
         let y1_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &y1);
         let y2_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &y2);
-        {
-            let mut state_lock = self.state.write().await;
-            state_lock.register_user(user, y1_bigint, y2_bigint);
-        }
-        info!("User successfully registered");
+        let proof = NonInteractiveProof {
+            r1: BigInt::from_bytes_be(num_bigint::Sign::Plus, &proof.r1),
+            r2: BigInt::from_bytes_be(num_bigint::Sign::Plus, &proof.r2),
+            s: BigInt::from_bytes_be(num_bigint::Sign::Plus, &proof.s),
+        };
+
+        let mut transcript = registration::registration_transcript(
+            &self.cp_zkp_protocol,
+            self.domain.as_bytes(),
+            &user,
+            &y1_bigint,
+            &y2_bigint,
+        );
+        let (cp_zkp_protocol, public_y1, public_y2) = (
+            self.cp_zkp_protocol.clone(),
+            y1_bigint.clone(),
+            y2_bigint.clone(),
+        );
+        self.run_verification("verify_registration", move || {
+            registration::verify_registration(
+                &cp_zkp_protocol,
+                &mut transcript,
+                &public_y1,
+                &public_y2,
+                &proof,
+            )
+        })
+        .await?
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        self.state
+            .register_user(User {
+                id: user.clone(),
+                y1: y1_bigint,
+                y2: y2_bigint,
+                salt,
+                kdf_parameters,
+                auth_id: None,
+                session_ids: vec![],
+                disabled: false,
+            })
+            .await?;
+        self.audit(AuditEvent::UserRegistered { user });
+        info!(
+            public_key = %fingerprint([y1, y2].concat()),
+            "User successfully registered"
+        );
         Ok(Response::new(RegisterResponse {}))
     }
 
+    /// Returns the parameters a user's client needs to derive its secret from its password.
+    ///
+    /// This asynchronous function looks up the user's random salt and Argon2id cost parameters, which the client must retrieve before deriving its secret `x` for authentication.
+    ///
+    /// # Arguments
+    ///
+    /// * `login_parameters_request`: A `Request<LoginParametersRequest>` object containing the user name.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<LoginParametersResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered.
+    async fn get_login_parameters(
+        &self,
+        login_parameters_request: Request<LoginParametersRequest>,
+    ) -> Result<Response<LoginParametersResponse>, Status> {
+        info!("Got a new login parameters request");
+        let LoginParametersRequest { user } = login_parameters_request.into_inner();
+        record_user(&user);
+
+        let (user_data, _) = self
+            .user_or_decoy(&user)
+            .await?
+            .ok_or(Status::unauthenticated(
+                "Failed to retrieve user data, user must register first",
+            ))?;
+
+        Ok(Response::new(LoginParametersResponse {
+            salt: user_data.salt,
+            kdf: Some(user_data.kdf_parameters.into()),
+        }))
+    }
+
     /// Creates an authentication challenge for a user.
     ///
-    /// This asynchronous function generates a new authentication challenge as part of the Chaum-Pedersen authentication process. It processes the request, generates a random challenge, and stores the challenge information in the server's state.
+    /// This asynchronous function generates a new authentication challenge as part of the Chaum-Pedersen authentication process. It processes the request, derives a challenge from a transcript bound to the service domain, the user, its public key, the commitments and a fresh random nonce, and stores the challenge information in the server's state. In stateless challenge mode, the challenge information is sealed into the authentication ID instead, and nothing is stored.
     ///
     /// # Arguments
     ///
@@ -86,29 +638,71 @@ impl Auth for PedersenChaumAuthServer {
         &self,
         auth_challenge_request: Request<AuthenticationChallengeRequest>,
     ) -> Result<Response<AuthenticationChallengeResponse>, Status> {
-        info!(
-            "Got a new create authentication challenge request: {:?}",
-            auth_challenge_request
-        );
+        info!("Got a new create authentication challenge request");
+        let peer_ip = auth_challenge_request.remote_addr().map(|addr| addr.ip());
         let AuthenticationChallengeRequest { user, r1, r2 } = auth_challenge_request.into_inner();
+        record_user(&user);
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check_peer(peer_ip, self.clock.now())?;
+        }
 
         let r1_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &r1);
         let r2_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &r2);
-        let c = self.cp_zkp_protocol.generate_random();
-        let auth_id = Uuid::new_v4().to_string();
 
-        {
-            let mut state_lock = self.state.write().await;
-            state_lock.create_authentication_challenge(
-                user,
-                auth_id.clone(),
-                r1_bigint,
-                r2_bigint,
-                c.clone(),
-            )?;
-        }
+        let (c, is_decoy) = {
+            let (user_data, is_decoy) =
+                self.user_or_decoy(&user)
+                    .await?
+                    .ok_or(Status::unauthenticated(
+                        "Failed to retrieve user data, user must register first",
+                    ))?;
+            // only known users are limited, decoys included so that they are limited alike
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.check_user(&user, self.clock.now())?;
+            }
+            let mut transcript = self.cp_zkp_protocol.transcript(
+                self.domain.as_bytes(),
+                &user,
+                &user_data.y1,
+                &user_data.y2,
+            );
+            let c =
+                self.cp_zkp_protocol
+                    .generate_challenge(&mut transcript, &r1_bigint, &r2_bigint);
+            (c, is_decoy)
+        };
+        let challenge = Challenge {
+            id: Uuid::new_v4().to_string(),
+            c: c.clone(),
+            r1: r1_bigint,
+            r2: r2_bigint,
+            user_id: user.clone(),
+            expires_at: self.clock.now() + self.challenge_ttl.as_secs(),
+        };
+        let auth_id = match &self.challenge_sealer {
+            Some(challenge_sealer) => challenge_sealer.seal(self.domain.as_bytes(), &challenge),
+            None => {
+                let auth_id = challenge.id.clone();
+                match &self.decoys {
+                    Some(decoys) if is_decoy => decoys.create_challenge(challenge),
+                    _ => {
+                        self.state
+                            .create_authentication_challenge(challenge)
+                            .await?
+                    }
+                }
+                auth_id
+            }
+        };
+        self.audit(AuditEvent::ChallengeCreated {
+            user,
+            auth_id: fingerprint(&auth_id),
+        });
 
-        info!("Successfully created a new authentication challenge for user");
+        info!(
+            auth_id = %fingerprint(&auth_id),
+            "Successfully created a new authentication challenge for user"
+        );
         Ok(Response::new(AuthenticationChallengeResponse {
             auth_id,
             c: c.to_bytes_be().1,
@@ -117,7 +711,7 @@ impl Auth for PedersenChaumAuthServer {
 
     /// Verifies an authentication response from a user.
     ///
-    /// This asynchronous function checks the validity of a user's response to an authentication challenge as part of the Chaum-Pedersen authentication process. It validates the response and, upon successful verification, creates a new session for the user.
+    /// This asynchronous function checks the validity of a user's response to an authentication challenge as part of the Chaum-Pedersen authentication process. The challenge is claimed by the first answer, before its verification, whether it succeeds or fails, and is rejected once expired, so that concurrent answers to a challenge are verified only once and yield at most one session. With rate limiting enabled, every attempt counts towards the user's lockout from the moment it is verified until it succeeds, so that concurrent answers can't exceed it. With enumeration resistance enabled, answers to the challenges of decoy users are verified against their decoy keys, so that they fail like a wrong password, at the same cost. It validates the response and, upon successful verification, creates a new session for the user.
     ///
     /// # Arguments
     ///
@@ -132,46 +726,681 @@ impl Auth for PedersenChaumAuthServer {
         &self,
         auth_answer_request: Request<AuthenticationAnswerRequest>,
     ) -> Result<Response<AuthenticationAnswerResponse>, Status> {
-        info!(
-            "Got a new verify authentication request: {:?}",
-            auth_answer_request
-        );
+        info!("Got a new verify authentication request");
 
+        let peer_ip = auth_answer_request.remote_addr().map(|addr| addr.ip());
         let AuthenticationAnswerRequest { auth_id, s } = auth_answer_request.into_inner();
-        let s_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &s);
 
-        let user_name = {
-            let state_read_lock = self.state.read().await;
-            let challenge = state_read_lock.challenges.get(&auth_id).ok_or(Status::aborted(
+        // the user is only known once the challenge is retrieved
+        let mut challenge_user = None;
+        let authenticated: Result<(Session, Vec<String>), Status> = async {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.check_peer(peer_ip, self.clock.now())?;
+            }
+            let s_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &s);
+
+            // challenges are single use, whether the verification succeeds or fails: they are claimed
+            // before the verification, so that concurrent answers to a challenge are verified only once
+            // sealed challenges are only opened here, and claimed once their user is known
+            let challenge = match &self.challenge_sealer {
+                Some(challenge_sealer) => challenge_sealer.open(self.domain.as_bytes(), &auth_id),
+                None => match self.state.take_challenge(&auth_id).await? {
+                    None => self
+                        .decoys
+                        .as_ref()
+                        .and_then(|decoys| decoys.take_challenge(&auth_id)),
+                    challenge => challenge,
+                },
+            };
+            let challenge = challenge.ok_or(Status::aborted(
                 "Failed to retrieve user challenge data, user must submit an authentication request",
             ))?;
-            let user = state_read_lock
-                .users
-                .get(&challenge.user_id)
-                .ok_or(Status::aborted(
-                    "Failed to retrieve user data, user must register first",
-                ))?;
-            self.cp_zkp_protocol
-                .verify(
+            challenge_user = Some(challenge.user_id.clone());
+            if challenge.expires_at <= self.clock.now() {
+                return Err(Status::deadline_exceeded(
+                    "Failed to verify challenge, challenge has expired",
+                ));
+            }
+            // decoy users go through the same verification, bound to fail like a wrong password
+            let (user, _) =
+                self.user_or_decoy(&challenge.user_id)
+                    .await?
+                    .ok_or(Status::aborted(
+                        "Failed to retrieve user data, user must register first",
+                    ))?;
+            record_user(&user.id);
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.check_lockout(&user.id, self.clock.now())?;
+            }
+            if let Some(challenge_sealer) = &self.challenge_sealer {
+                if !challenge_sealer.claim(&challenge, self.clock.now())? {
+                    return Err(Status::aborted(
+                        "Failed to retrieve user challenge data, user must submit an authentication request",
+                    ));
+                }
+            }
+            // the attempt counts as a failure until verified, so concurrent answers can't exceed the lockout
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.start_attempt(&user.id, self.clock.now())?;
+            }
+            let (cp_zkp_protocol, y1, y2) = (
+                self.cp_zkp_protocol.clone(),
+                user.y1.clone(),
+                user.y2.clone(),
+            );
+            let verification = self
+                .run_verification("verify_authentication", move || {
+                    cp_zkp_protocol.verify(
+                        &y1,
+                        &y2,
+                        &challenge.r1,
+                        &challenge.r2,
+                        &s_bigint,
+                        &challenge.c,
+                    )
+                })
+                .await?;
+            if let (Some(rate_limiter), Ok(_)) = (&self.rate_limiter, &verification) {
+                rate_limiter.record_success(&user.id);
+            }
+            verification.map_err(|e| Status::unauthenticated(e.to_string()))?;
+            check_enabled(&user)?;
+
+            let issued_at = self.clock.now();
+            let session = Session {
+                id: Uuid::new_v4().to_string(),
+                user_id: user.id,
+                issued_at,
+                expires_at: issued_at + self.session_ttl.as_secs(),
+                scopes: self.session_scopes.clone(),
+                credential_id: None,
+            };
+            // a user disabled or rekeyed during the verification gets no session
+            let evicted_session_ids = self
+                .state
+                .complete_authentication(
                     &user.y1,
                     &user.y2,
-                    &challenge.r1,
-                    &challenge.r2,
-                    &s_bigint,
-                    &challenge.c,
+                    session.clone(),
+                    self.max_sessions_per_user,
                 )
-                .map_err(|e| Status::unauthenticated(e.to_string()))?;
-
-            user.id.clone()
+                .await?;
+            Ok((session, evicted_session_ids))
+        }
+        .await;
+        let (session, evicted_session_ids) = match authenticated {
+            Ok(authenticated) => authenticated,
+            Err(status) => {
+                self.audit(AuditEvent::AuthenticationFailed {
+                    user: challenge_user,
+                    auth_id: fingerprint(&auth_id),
+                    reason: status.message().to_string(),
+                });
+                return Err(status);
+            }
         };
 
-        let session_id = Uuid::new_v4().to_string();
-        {
-            let mut state_lock = self.state.write().await;
-            state_lock.create_session(user_name, session_id.clone())?;
+        self.audit(AuditEvent::AuthenticationSucceeded {
+            user: session.user_id.clone(),
+            auth_id: fingerprint(&auth_id),
+        });
+        self.audit(AuditEvent::SessionCreated {
+            user: session.user_id.clone(),
+            session: fingerprint(&session.id),
+            expires_at: session.expires_at,
+        });
+        for session_id in evicted_session_ids {
+            self.audit(AuditEvent::SessionRevoked {
+                user: session.user_id.clone(),
+                session: fingerprint(&session_id),
+                cause: RevocationCause::Evicted,
+            });
         }
 
-        let response = AuthenticationAnswerResponse { session_id };
+        info!(
+            session = %fingerprint(&session.id),
+            "User successfully authenticated"
+        );
+        let response = AuthenticationAnswerResponse {
+            session_token: self.session_token(&session),
+            session_id: session.id,
+            expires_at: session.expires_at,
+        };
         Ok(Response::new(response))
     }
+
+    /// Extends the validity of a session.
+    ///
+    /// This asynchronous function pushes the expiry of an active session back to a full session time to live from now. Expired sessions can't be refreshed, so the user must authenticate again.
+    ///
+    /// # Arguments
+    ///
+    /// * `refresh_session_request`: A `Request<RefreshSessionRequest>` object containing the session id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<RefreshSessionResponse>`, with the new expiry of the session.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the session does not exist or has expired.
+    async fn refresh_session(
+        &self,
+        refresh_session_request: Request<RefreshSessionRequest>,
+    ) -> Result<Response<RefreshSessionResponse>, Status> {
+        info!("Got a new refresh session request");
+        let RefreshSessionRequest { session_id } = refresh_session_request.into_inner();
+
+        let now = self.clock.now();
+        let session = self
+            .state
+            .refresh_session(&session_id, now, now + self.session_ttl.as_secs())
+            .await?;
+        record_user(&session.user_id);
+
+        info!(
+            session = %fingerprint(&session.id),
+            "Session successfully refreshed"
+        );
+        Ok(Response::new(RefreshSessionResponse {
+            session_token: self.session_token(&session),
+            expires_at: session.expires_at,
+        }))
+    }
+
+    /// Ends a session.
+    ///
+    /// This asynchronous function revokes the given session, together with any of its pending credential signing sessions. Logging out of an unknown or expired session is not an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `logout_request`: A `Request<LogoutRequest>` object containing the session id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<LogoutResponse>`.
+    async fn logout(
+        &self,
+        logout_request: Request<LogoutRequest>,
+    ) -> Result<Response<LogoutResponse>, Status> {
+        info!("Got a new logout request");
+        let LogoutRequest { session_id } = logout_request.into_inner();
+
+        if let Some(session) = self.state.remove_session(&session_id).await? {
+            record_user(&session.user_id);
+            self.audit(AuditEvent::SessionRevoked {
+                user: session.user_id,
+                session: fingerprint(&session_id),
+                cause: RevocationCause::Logout,
+            });
+        }
+
+        info!(
+            session = %fingerprint(&session_id),
+            "User successfully logged out"
+        );
+        Ok(Response::new(LogoutResponse {}))
+    }
+
+    /// Ends every session of a user.
+    ///
+    /// This asynchronous function revokes all sessions of the user owning the given active session, including the session itself, e.g. after a user suspects one of its sessions has been compromised.
+    ///
+    /// # Arguments
+    ///
+    /// * `revoke_all_sessions_request`: A `Request<RevokeAllSessionsRequest>` object containing one of the user's session ids.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<RevokeAllSessionsResponse>`, with the number of revoked sessions.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the session does not exist or has expired.
+    async fn revoke_all_sessions(
+        &self,
+        revoke_all_sessions_request: Request<RevokeAllSessionsRequest>,
+    ) -> Result<Response<RevokeAllSessionsResponse>, Status> {
+        info!("Got a new revoke all sessions request");
+        let RevokeAllSessionsRequest { session_id } = revoke_all_sessions_request.into_inner();
+
+        let session = self.active_session(&session_id).await?;
+        record_user(&session.user_id);
+        let revoked = self.state.remove_user_sessions(&session.user_id).await?;
+        self.audit(AuditEvent::SessionsRevoked {
+            user: session.user_id,
+            count: revoked,
+            cause: RevocationCause::RevokeAll,
+        });
+
+        info!("Successfully revoked {revoked} user sessions");
+        Ok(Response::new(RevokeAllSessionsResponse {
+            revoked: revoked as u32,
+        }))
+    }
+
+    /// Validates a session on behalf of a relying service.
+    ///
+    /// This asynchronous function lets other services check a session id presented by a user, and learn which user it belongs to, when it was issued, when it expires and which scopes it was granted. It does not extend the validity of the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `validate_session_request`: A `Request<ValidateSessionRequest>` object containing the session id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<ValidateSessionResponse>`, with the session's information.
+    ///
+    /// On failure, it returns a `Status` indicating why the session is not valid: `NOT_FOUND` if the session does not exist (e.g. it was revoked), or `UNAUTHENTICATED` if it has expired.
+    async fn validate_session(
+        &self,
+        validate_session_request: Request<ValidateSessionRequest>,
+    ) -> Result<Response<ValidateSessionResponse>, Status> {
+        info!("Got a new validate session request");
+        let ValidateSessionRequest { session_id } = validate_session_request.into_inner();
+        if session_id.is_empty() {
+            return Err(Status::invalid_argument(
+                "Failed to validate session, missing session id",
+            ));
+        }
+
+        let session = match self.validate_session_id(&session_id).await {
+            Ok(session) => session,
+            Err(SessionError::NotFound) => {
+                return Err(Status::not_found(
+                    "Failed to validate session, session does not exist",
+                ))
+            }
+            Err(SessionError::Expired) => {
+                return Err(Status::unauthenticated(
+                    "Failed to validate session, session has expired",
+                ))
+            }
+            Err(SessionError::Unspecified) => {
+                return Err(Status::internal(
+                    "Failed to validate session, storage backend failure",
+                ))
+            }
+        };
+
+        Ok(Response::new(ValidateSessionResponse {
+            session: Some(session.into()),
+        }))
+    }
+
+    /// Validates a batch of sessions on behalf of a relying service.
+    ///
+    /// This asynchronous function validates each session id independently, as `ValidateSession` does, so that a relying service can check many sessions in a single round trip. Results are returned in the order of the request, each holding either the session's information or the reason it is not valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `validate_sessions_request`: A `Request<ValidateSessionsRequest>` object containing up to `MAX_VALIDATE_SESSIONS_BATCH` session ids.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<ValidateSessionsResponse>`, with one result per session id.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the batch is too large.
+    async fn validate_sessions(
+        &self,
+        validate_sessions_request: Request<ValidateSessionsRequest>,
+    ) -> Result<Response<ValidateSessionsResponse>, Status> {
+        info!("Got a new validate sessions request");
+        let ValidateSessionsRequest { session_ids } = validate_sessions_request.into_inner();
+        if session_ids.len() > MAX_VALIDATE_SESSIONS_BATCH {
+            return Err(Status::invalid_argument(format!(
+                "Failed to validate sessions, at most {MAX_VALIDATE_SESSIONS_BATCH} sessions can be validated at once"
+            )));
+        }
+
+        let mut results = Vec::with_capacity(session_ids.len());
+        for session_id in session_ids {
+            let result = match self.validate_session_id(&session_id).await {
+                Ok(session) => session_validation::Result::Session(session.into()),
+                Err(e) => session_validation::Result::Error(e.into()),
+            };
+            results.push(SessionValidation {
+                session_id,
+                result: Some(result),
+            });
+        }
+
+        Ok(Response::new(ValidateSessionsResponse { results }))
+    }
+
+    /// Publishes the public keys verifying session tokens.
+    ///
+    /// This asynchronous function returns the public key of the current token signing key, followed by the public keys of retired signing keys, so that relying services can verify session tokens offline, across key rotations. No key is returned if session tokens are disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `_session_token_keys_request`: A `Request<SessionTokenKeysRequest>` object.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<SessionTokenKeysResponse>`, with the key ids and public keys.
+    async fn get_session_token_keys(
+        &self,
+        _session_token_keys_request: Request<SessionTokenKeysRequest>,
+    ) -> Result<Response<SessionTokenKeysResponse>, Status> {
+        info!("Got a new session token keys request");
+        let keys = self
+            .token_signing_key
+            .iter()
+            .map(|signing_key| signing_key.verifying_key())
+            .chain(self.retired_token_keys.iter().cloned())
+            .map(|key| SessionTokenKey {
+                key_id: key.key_id().to_string(),
+                public_key: key.to_bytes(),
+            })
+            .collect();
+
+        Ok(Response::new(SessionTokenKeysResponse { keys }))
+    }
+
+    /// Starts a blind credential signing session for an authenticated user.
+    ///
+    /// This asynchronous function generates a fresh signer nonce and commitment, stores it in the server's state and returns the commitment, together with the server's credential public key and the expiry to encode in the credential message, so that the client can blind it. A session holds a single pending commitment, which replaces any previous one and expires with the challenge time to live, and the server at most `max_credential_commitments` of them, so that concurrent signing sessions can't be combined into a forgery.
+    ///
+    /// # Arguments
+    ///
+    /// * `credential_commitment_request`: A `Request<CredentialCommitmentRequest>` object containing the user's session id.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<CredentialCommitmentResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the session does not exist or has expired, or `RESOURCE_EXHAUSTED` if too many signing sessions are open.
+    async fn create_credential_commitment(
+        &self,
+        credential_commitment_request: Request<CredentialCommitmentRequest>,
+    ) -> Result<Response<CredentialCommitmentResponse>, Status> {
+        info!("Got a new credential commitment request");
+        let CredentialCommitmentRequest { session_id } = credential_commitment_request.into_inner();
+        self.active_session(&session_id).await?;
+
+        let commitment = self.time_modpow("commit_credential", || self.credential_signer.commit());
+        let r = commitment.get_commitment().clone();
+        let credential_id = Uuid::new_v4().to_string();
+
+        let now = self.clock.now();
+        self.state
+            .create_credential_commitment(
+                PendingCredential {
+                    id: credential_id.clone(),
+                    session_id,
+                    k: commitment.get_nonce().clone(),
+                    r: r.clone(),
+                    expires_at: now + self.challenge_ttl.as_secs(),
+                },
+                self.max_credential_commitments,
+            )
+            .await?;
+
+        info!("Successfully created a new credential commitment");
+        Ok(Response::new(CredentialCommitmentResponse {
+            credential_id,
+            r: r.to_bytes_be().1,
+            public_key: self.credential_signer.public_key().to_bytes_be().1,
+            credential_expires_at: self.credential_expires_at(now),
+        }))
+    }
+
+    /// Signs a blinded credential challenge.
+    ///
+    /// This asynchronous function answers the client's blinded challenge with the signer nonce stored for the credential signing session, which is consumed in the process. The server never learns the credential message nor the final signature.
+    ///
+    /// # Arguments
+    ///
+    /// * `blinded_credential_request`: A `Request<BlindedCredentialRequest>` object containing the session id, the credential signing session id and the blinded challenge.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<BlindedCredentialResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if no commitment exists for the session.
+    async fn sign_blinded_credential(
+        &self,
+        blinded_credential_request: Request<BlindedCredentialRequest>,
+    ) -> Result<Response<BlindedCredentialResponse>, Status> {
+        info!("Got a new blinded credential request");
+        let BlindedCredentialRequest {
+            session_id,
+            credential_id,
+            c,
+        } = blinded_credential_request.into_inner();
+        let c_bigint = BigInt::from_bytes_be(num_bigint::Sign::Plus, &c);
+        self.active_session(&session_id).await?;
+
+        let pending_credential = self
+            .state
+            .take_credential_commitment(&session_id, &credential_id, self.clock.now())
+            .await?;
+
+        let s = self.credential_signer.sign_blinded(
+            SignerCommitment::new(pending_credential.k, pending_credential.r),
+            &c_bigint,
+        );
+
+        info!("Successfully signed a blinded credential");
+        Ok(Response::new(BlindedCredentialResponse {
+            s: s.to_bytes_be().1,
+        }))
+    }
+
+    /// Verifies an unblinded credential presented by a client.
+    ///
+    /// This asynchronous function checks the blind signature on the credential message against the server's credential public key. A successful presentation proves that the client is some authenticated user, without linking it to any login or session. Each credential can only be presented once, until it expires: its message starts with its expiry, which must lie within the current or the next validity period, so that spent credentials are only kept until then.
+    ///
+    /// # Arguments
+    ///
+    /// * `credential_presentation_request`: A `Request<CredentialPresentationRequest>` object containing the credential message and its signature.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<CredentialPresentationResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. an invalid or already presented credential.
+    async fn present_credential(
+        &self,
+        credential_presentation_request: Request<CredentialPresentationRequest>,
+    ) -> Result<Response<CredentialPresentationResponse>, Status> {
+        info!("Got a new credential presentation request");
+        let CredentialPresentationRequest { message, r, s } =
+            credential_presentation_request.into_inner();
+        if message.len() <= CREDENTIAL_EXPIRY_SIZE {
+            return Err(Status::invalid_argument(
+                "Failed to present credential, credential message is too short",
+            ));
+        }
+        let mut expiry = [0u8; CREDENTIAL_EXPIRY_SIZE];
+        expiry.copy_from_slice(&message[..CREDENTIAL_EXPIRY_SIZE]);
+        let expires_at = Timestamp::from_be_bytes(expiry);
+        let now = self.clock.now();
+        if expires_at <= now {
+            return Err(Status::deadline_exceeded(
+                "Failed to present credential, credential has expired",
+            ));
+        }
+        // spent credentials are kept until they expire, so credentials can't outlive them
+        if expires_at > self.credential_expires_at(now) {
+            return Err(Status::invalid_argument(
+                "Failed to present credential, credential expires too late",
+            ));
+        }
+
+        let signature = BlindSignature {
+            r: BigInt::from_bytes_be(num_bigint::Sign::Plus, &r),
+            s: BigInt::from_bytes_be(num_bigint::Sign::Plus, &s),
+        };
+        let (cp_zkp_protocol, public_key) = (
+            self.cp_zkp_protocol.clone(),
+            self.credential_signer.public_key().clone(),
+        );
+        let message = self
+            .run_verification("verify_credential", move || {
+                blind_signature::verify(
+                    cp_zkp_protocol.parameters(),
+                    &public_key,
+                    &message,
+                    &signature,
+                )
+                .map(|_| message)
+            })
+            .await?
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+
+        self.state.spend_credential(message, expires_at).await?;
+
+        info!("Successfully verified a credential presentation");
+        Ok(Response::new(CredentialPresentationResponse {}))
+    }
+
+    /// Rotates the public key of a registered user.
+    ///
+    /// This asynchronous function verifies a proof of knowledge of both the user's current secret and the secret behind the new public key, bound to the service domain, the user, an active session of the user and both public keys. Only if the proof verifies are the stored `y1` and `y2` replaced, so that the user keeps its account (and other sessions) across password changes. The user's salt and key derivation parameters are kept.
+    ///
+    /// Requiring a session keeps the proof from serving as an unauthenticated password guess, and consuming it keeps the proof from being replayed, e.g. after the user rotated back to its old key. The new public key must be made of non-trivial subgroup elements, as at registration.
+    ///
+    /// # Arguments
+    ///
+    /// * `rotate_credential_request`: A `Request<RotateCredentialRequest>` object containing the user name, the new public key, the key rotation proof and the session authorizing the rotation.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<RotateCredentialResponse>`.
+    ///
+    /// On failure, it returns a `Status` indicating the error encountered, e.g. if the user is not registered or the proof is invalid.
+    async fn rotate_credential(
+        &self,
+        rotate_credential_request: Request<RotateCredentialRequest>,
+    ) -> Result<Response<RotateCredentialResponse>, Status> {
+        info!("Got a new rotate credential request");
+        let peer_ip = rotate_credential_request
+            .remote_addr()
+            .map(|addr| addr.ip());
+        let RotateCredentialRequest {
+            user,
+            new_y1,
+            new_y2,
+            proof,
+            session_id,
+        } = rotate_credential_request.into_inner();
+        record_user(&user);
+        // rotations prove knowledge of the user's secret, so they are rate limited like authentications
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check_peer(peer_ip, self.clock.now())?;
+        }
+        let proof = proof.ok_or(Status::invalid_argument(
+            "Failed to rotate credential, missing key rotation proof",
+        ))?;
+        let to_bigint = |bytes: &[u8]| BigInt::from_bytes_be(num_bigint::Sign::Plus, bytes);
+
+        let new_y1 = to_bigint(&new_y1);
+        let new_y2 = to_bigint(&new_y2);
+        let proof = KeyRotationProof {
+            old_r1: to_bigint(&proof.old_r1),
+            old_r2: to_bigint(&proof.old_r2),
+            new_r1: to_bigint(&proof.new_r1),
+            new_r2: to_bigint(&proof.new_r2),
+            old_s: to_bigint(&proof.old_s),
+            new_s: to_bigint(&proof.new_s),
+        };
+
+        if self.active_session(&session_id).await?.user_id != user {
+            return Err(Status::permission_denied(
+                "Failed to rotate credential, session belongs to another user",
+            ));
+        }
+        let user_data = self
+            .state
+            .get_user(&user)
+            .await?
+            .ok_or(Status::unauthenticated(
+                "Failed to retrieve user data, user must register first",
+            ))?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check_user(&user_data.id, self.clock.now())?;
+            rate_limiter.start_attempt(&user_data.id, self.clock.now())?;
+        }
+
+        let (cp_zkp_protocol, domain, user_name, nonce) = (
+            self.cp_zkp_protocol.clone(),
+            self.domain.clone(),
+            user.clone(),
+            session_id.clone(),
+        );
+        let (old_y1, old_y2, rotated_y1, rotated_y2) = (
+            user_data.y1.clone(),
+            user_data.y2.clone(),
+            new_y1.clone(),
+            new_y2.clone(),
+        );
+        let verification = self
+            .run_verification("verify_key_rotation", move || {
+                let keys = KeyRotation {
+                    old_y1: &old_y1,
+                    old_y2: &old_y2,
+                    new_y1: &rotated_y1,
+                    new_y2: &rotated_y2,
+                };
+                let mut transcript = key_rotation::key_rotation_transcript(
+                    &cp_zkp_protocol,
+                    domain.as_bytes(),
+                    &user_name,
+                    nonce.as_bytes(),
+                    &keys,
+                );
+                key_rotation::verify_key_rotation(&cp_zkp_protocol, &mut transcript, &keys, &proof)
+            })
+            .await?;
+        if let (Some(rate_limiter), Ok(_)) = (&self.rate_limiter, &verification) {
+            rate_limiter.record_success(&user_data.id);
+        }
+        verification.map_err(|e| Status::unauthenticated(e.to_string()))?;
+        check_enabled(&user_data)?;
+
+        // the session is the single-use nonce of the proof, so only one rotation can consume it
+        self.state
+            .remove_session(&session_id)
+            .await?
+            .ok_or(Status::unauthenticated(
+                "Failed to retrieve session data, user must authenticate first",
+            ))?;
+        self.audit(AuditEvent::SessionRevoked {
+            user: user_data.id.clone(),
+            session: fingerprint(&session_id),
+            cause: RevocationCause::CredentialRotated,
+        });
+        self.state
+            .rotate_credential(user, &user_data.y1, &user_data.y2, new_y1, new_y2)
+            .await?;
+        self.audit(AuditEvent::CredentialRotated {
+            user: user_data.id.clone(),
+        });
+
+        info!("User credential successfully rotated");
+        Ok(Response::new(RotateCredentialResponse {}))
+    }
+
+    /// Publishes the group parameters of the protocol.
+    ///
+    /// This asynchronous function returns the parameters the server verifies proofs with, so that clients derive their secrets and build their proofs in the same group, whether the server uses the default parameters or a parameters file. Clients should validate them before use.
+    ///
+    /// # Arguments
+    ///
+    /// * `_parameters_request`: A `Request<ParametersRequest>` object.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` type that, on success, contains a `Response<ParametersResponse>`, with the parameters as big endian integers.
+    async fn get_parameters(
+        &self,
+        _parameters_request: Request<ParametersRequest>,
+    ) -> Result<Response<ParametersResponse>, Status> {
+        let parameters = self.cp_zkp_protocol.parameters();
+        Ok(Response::new(ParametersResponse {
+            bit_size: parameters.bit_size,
+            p: parameters.p.to_bytes_be().1,
+            q: parameters.q.to_bytes_be().1,
+            g: parameters.g.to_bytes_be().1,
+            h: parameters.h.to_bytes_be().1,
+        }))
+    }
 }
@@ -0,0 +1,445 @@
+//! # State Snapshots
+//!
+//! A snapshot is a versioned JSON document holding the state of a storage backend: users, pending
+//! authentication challenges, sessions and spent credentials. Big integers are encoded as decimal
+//! strings and byte strings in base64, and entries are sorted by id, so that snapshots of the same
+//! state are identical.
+//!
+//! A user's pending challenge and session ids are not stored with the user, as they follow from the
+//! challenges and sessions themselves. Pending credential commitments are left out: their signer
+//! nonces must never be used twice, which a snapshot restored more than once could not guarantee.
+//!
+//! Snapshots are exported from any backend with the `export-state` command, and carry the in-memory
+//! backend across restarts: written on shutdown and restored on startup.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    clock::Timestamp,
+    state::PedersenChaumAuthServerState,
+    types::{Challenge, KdfParameters, Session, User},
+};
+
+/// Version of the snapshot format, bumped on every incompatible change.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// A versioned snapshot of the state of a storage backend.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub version: u32,
+    pub users: Vec<UserSnapshot>,
+    pub challenges: Vec<ChallengeSnapshot>,
+    pub sessions: Vec<SessionSnapshot>,
+    pub spent_credentials: Vec<SpentCredentialSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSnapshot {
+    pub id: String,
+    pub y1: String,
+    pub y2: String,
+    /// Salt of the user, in base64
+    pub salt: String,
+    pub kdf_memory_kib: u32,
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+    /// Absent from snapshots taken before users could be disabled
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChallengeSnapshot {
+    pub id: String,
+    pub user_id: String,
+    pub r1: String,
+    pub r2: String,
+    pub c: String,
+    pub expires_at: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub id: String,
+    pub user_id: String,
+    pub issued_at: Timestamp,
+    pub expires_at: Timestamp,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpentCredentialSnapshot {
+    /// Signed message of the credential, in base64
+    pub message: String,
+    pub expires_at: Timestamp,
+}
+
+impl From<&User> for UserSnapshot {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.clone(),
+            y1: user.y1.to_string(),
+            y2: user.y2.to_string(),
+            salt: STANDARD.encode(&user.salt),
+            kdf_memory_kib: user.kdf_parameters.memory_kib,
+            kdf_iterations: user.kdf_parameters.iterations,
+            kdf_parallelism: user.kdf_parameters.parallelism,
+            disabled: user.disabled,
+        }
+    }
+}
+
+impl From<&Challenge> for ChallengeSnapshot {
+    fn from(challenge: &Challenge) -> Self {
+        Self {
+            id: challenge.id.clone(),
+            user_id: challenge.user_id.clone(),
+            r1: challenge.r1.to_string(),
+            r2: challenge.r2.to_string(),
+            c: challenge.c.to_string(),
+            expires_at: challenge.expires_at,
+        }
+    }
+}
+
+impl From<&Session> for SessionSnapshot {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id.clone(),
+            user_id: session.user_id.clone(),
+            issued_at: session.issued_at,
+            expires_at: session.expires_at,
+            scopes: session.scopes.clone(),
+        }
+    }
+}
+
+/// Converts map values into snapshot entries, sorted by id.
+fn sorted<'a, T: 'a, S: From<&'a T>>(
+    entries: impl IntoIterator<Item = (&'a String, &'a T)>,
+) -> Vec<S> {
+    let mut entries = entries.into_iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(id, _)| *id);
+    entries
+        .into_iter()
+        .map(|(_, entry)| S::from(entry))
+        .collect()
+}
+
+impl From<&PedersenChaumAuthServerState> for StateSnapshot {
+    fn from(state: &PedersenChaumAuthServerState) -> Self {
+        let mut spent_credentials = state
+            .spent_credentials
+            .iter()
+            .map(|(message, expires_at)| SpentCredentialSnapshot {
+                message: STANDARD.encode(message),
+                expires_at: *expires_at,
+            })
+            .collect::<Vec<_>>();
+        spent_credentials.sort_by(|a, b| a.message.cmp(&b.message));
+        Self {
+            version: SNAPSHOT_VERSION,
+            users: sorted(&state.users),
+            challenges: sorted(&state.challenges),
+            sessions: sorted(&state.sessions),
+            spent_credentials,
+        }
+    }
+}
+
+/// Version header of a snapshot, read before the rest of the snapshot, whose format depends on it.
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_bigint(value: &str) -> io::Result<BigInt> {
+    BigInt::from_str(value)
+        .map_err(|e| invalid_data(format!("Invalid integer `{value}` in state snapshot: {e}")))
+}
+
+fn parse_bytes(value: &str) -> io::Result<Vec<u8>> {
+    STANDARD
+        .decode(value)
+        .map_err(|e| invalid_data(format!("Invalid base64 `{value}` in state snapshot: {e}")))
+}
+
+impl StateSnapshot {
+    /// Serializes the snapshot into pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize state snapshot")
+    }
+
+    /// Deserializes a snapshot from JSON, as written by `to_json`.
+    ///
+    /// # Returns
+    /// A `Result` containing the snapshot, or an `io::Error` if the JSON is malformed or the snapshot
+    /// was written in another version of the format.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let SnapshotVersion { version } = serde_json::from_str(json)
+            .map_err(|e| invalid_data(format!("Invalid state snapshot: {e}")))?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported state snapshot version {version}, expected {SNAPSHOT_VERSION}"
+            )));
+        }
+        serde_json::from_str(json).map_err(|e| invalid_data(format!("Invalid state snapshot: {e}")))
+    }
+
+    /// Loads a snapshot file, as written by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Writes the snapshot to `path`, atomically: it is written to a temporary file next to `path`,
+    /// synced to disk, then renamed, so that a crash while writing never leaves a truncated snapshot
+    /// behind. As snapshots hold session ids, the file is only readable by its owner on Unix.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        // A temporary file left behind by a crash keeps its mode when reopened, so start afresh
+        match fs::remove_file(&temporary_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temporary_path)?;
+        file.write_all(self.to_json().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, path)
+    }
+
+    /// Rebuilds the state the snapshot was taken from, linking every user back to its pending challenge
+    /// and to its sessions, from the oldest to the most recent.
+    ///
+    /// # Returns
+    /// A `Result` containing the state, or an `io::Error` if the snapshot holds malformed values, or
+    /// challenges and sessions of unknown users.
+    pub fn into_state(self) -> io::Result<PedersenChaumAuthServerState> {
+        let mut state = PedersenChaumAuthServerState::new();
+        for user in self.users {
+            let user = User {
+                y1: parse_bigint(&user.y1)?,
+                y2: parse_bigint(&user.y2)?,
+                salt: parse_bytes(&user.salt)?,
+                kdf_parameters: KdfParameters {
+                    memory_kib: user.kdf_memory_kib,
+                    iterations: user.kdf_iterations,
+                    parallelism: user.kdf_parallelism,
+                },
+                auth_id: None,
+                session_ids: vec![],
+                disabled: user.disabled,
+                id: user.id,
+            };
+            state.users.insert(user.id.clone(), user);
+        }
+        for challenge in self.challenges {
+            let user = state.users.get_mut(&challenge.user_id).ok_or_else(|| {
+                invalid_data(format!(
+                    "Challenge of unknown user `{}` in state snapshot",
+                    challenge.user_id
+                ))
+            })?;
+            user.auth_id = Some(challenge.id.clone());
+            let challenge = Challenge {
+                c: parse_bigint(&challenge.c)?,
+                r1: parse_bigint(&challenge.r1)?,
+                r2: parse_bigint(&challenge.r2)?,
+                id: challenge.id,
+                user_id: challenge.user_id,
+                expires_at: challenge.expires_at,
+            };
+            state.challenges.insert(challenge.id.clone(), challenge);
+        }
+        let mut sessions = self.sessions;
+        sessions.sort_by_key(|session| session.issued_at);
+        for session in sessions {
+            let user = state.users.get_mut(&session.user_id).ok_or_else(|| {
+                invalid_data(format!(
+                    "Session of unknown user `{}` in state snapshot",
+                    session.user_id
+                ))
+            })?;
+            user.session_ids.push(session.id.clone());
+            let session = Session {
+                id: session.id,
+                user_id: session.user_id,
+                issued_at: session.issued_at,
+                expires_at: session.expires_at,
+                scopes: session.scopes,
+                credential_id: None,
+            };
+            state.sessions.insert(session.id.clone(), session);
+        }
+        for spent_credential in self.spent_credentials {
+            state.spent_credentials.insert(
+                parse_bytes(&spent_credential.message)?,
+                spent_credential.expires_at,
+            );
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStorage;
+
+    #[test]
+    fn test_snapshot_is_sorted_and_encoded() {
+        let mut state = PedersenChaumAuthServerState::new();
+        for user_name in ["user_b", "user_a"] {
+            state.users.insert(
+                user_name.to_string(),
+                User {
+                    id: user_name.to_string(),
+                    y1: BigInt::from(1_000),
+                    y2: BigInt::from(2_000),
+                    salt: vec![7u8; 16],
+                    kdf_parameters: KdfParameters::default(),
+                    auth_id: None,
+                    session_ids: vec![],
+                    disabled: false,
+                },
+            );
+        }
+        state.spent_credentials.insert(vec![1, 2, 3], 5_000);
+
+        let snapshot = StateSnapshot::from(&state);
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(
+            snapshot
+                .users
+                .iter()
+                .map(|user| user.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["user_a", "user_b"]
+        );
+        assert_eq!(snapshot.users[0].y1, "1000");
+        assert_eq!(snapshot.users[0].salt, "BwcHBwcHBwcHBwcHBwcHBw==");
+        assert_eq!(
+            snapshot.spent_credentials,
+            vec![SpentCredentialSnapshot {
+                message: "AQID".to_string(),
+                expires_at: 5_000,
+            }]
+        );
+
+        let json = snapshot.to_json();
+        assert_eq!(
+            serde_json::from_str::<StateSnapshot>(&json).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restores_state() {
+        let storage = InMemoryStorage::new();
+        for user_name in ["user_a", "user_b"] {
+            storage
+                .register_user(
+                    user_name.to_string(),
+                    BigInt::from(1_000),
+                    BigInt::from(2_000),
+                    vec![7u8; 16],
+                    KdfParameters::default(),
+                )
+                .unwrap();
+        }
+        storage.set_user_disabled("user_a", true).unwrap();
+        storage
+            .create_authentication_challenge(
+                "user_a".to_string(),
+                "auth_id".to_string(),
+                BigInt::from(10),
+                BigInt::from(20),
+                BigInt::from(30),
+                1_000,
+            )
+            .unwrap();
+        // sessions are linked back to their user from the oldest to the most recent, whatever their ids
+        for (session_id, issued_at) in [("session_z", 100), ("session_a", 200)] {
+            storage
+                .create_session(
+                    "user_b".to_string(),
+                    session_id.to_string(),
+                    issued_at,
+                    issued_at + 3_600,
+                    vec!["read".to_string()],
+                    10,
+                )
+                .unwrap();
+        }
+        storage
+            .create_credential_commitment(
+                "session_a".to_string(),
+                "credential_id".to_string(),
+                BigInt::from(40),
+                BigInt::from(50),
+                1_000,
+                10,
+            )
+            .unwrap();
+        storage.spend_credential(vec![1, 2, 3], 5_000).unwrap();
+        let state = storage.export();
+
+        let path =
+            std::env::temp_dir().join(format!("zkp_auth_snapshot_{}.json", std::process::id()));
+        StateSnapshot::from(&state).save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let restored = StateSnapshot::load(&path).unwrap().into_state().unwrap();
+        fs::remove_file(&path).unwrap();
+        // credential commitments are never restored, so that their nonces are used at most once
+        let mut expected = state.clone();
+        expected.pending_credentials.clear();
+        for session in expected.sessions.values_mut() {
+            session.credential_id = None;
+        }
+        assert_eq!(restored, expected);
+        assert_eq!(
+            restored.users["user_b"].session_ids,
+            vec!["session_z", "session_a"]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_version_is_checked() {
+        let mut snapshot = StateSnapshot::from(&PedersenChaumAuthServerState::new());
+        assert!(StateSnapshot::from_json(&snapshot.to_json()).is_ok());
+
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        let error = StateSnapshot::from_json(&snapshot.to_json()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .contains("Unsupported state snapshot version"));
+
+        let error = StateSnapshot::from_json(&format!(
+            r#"{{"version": {SNAPSHOT_VERSION}, "users": []}}"#
+        ))
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,1388 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use num_bigint::BigInt;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use tonic::Status;
+
+use crate::{
+    clock::Timestamp,
+    state::{check_enabled, user_not_found, user_not_registered, PedersenChaumAuthServerState},
+    storage::AuthStorage,
+    types::{Challenge, KdfParameters, PendingCredential, Session, User},
+};
+
+/// Schema migrations, applied in order. The index of the last applied migration (plus one) is
+/// tracked in the database's `user_version` pragma, so each migration is applied exactly once.
+/// Migrations must never be edited once released, only appended.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_initial_schema.sql"),
+    include_str!("../migrations/0002_challenge_expiry.sql"),
+    include_str!("../migrations/0003_session_lifecycle.sql"),
+    include_str!("../migrations/0004_session_scopes.sql"),
+    include_str!("../migrations/0005_disabled_users.sql"),
+    include_str!("../migrations/0006_credential_commitment_expiry.sql"),
+    include_str!("../migrations/0007_session_credential.sql"),
+    include_str!("../migrations/0008_spent_credential_expiry.sql"),
+];
+
+/// Tables read by the readiness check, i.e. every table of the schema.
+const READY_TABLES: &[&str] = &[
+    "users",
+    "challenges",
+    "sessions",
+    "pending_credentials",
+    "spent_credentials",
+];
+
+/// SQLite storage backend, persisting users, challenges and sessions across server restarts.
+///
+/// `rusqlite` connections are synchronous, so every operation runs on Tokio's blocking thread pool,
+/// behind a mutex guarding the single connection. Each operation runs in its own transaction.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens (or creates) a SQLite database at `path`, and applies any pending schema migration.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a new in-memory SQLite database, mostly useful for testing.
+    pub fn open_in_memory() -> Result<Self, rusqlite::Error> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<Self, rusqlite::Error> {
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` with exclusive access to the connection, on the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, Status> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| Status::internal("Storage error: poisoned connection lock"))?;
+            f(&mut connection)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Storage error: {e}")))?
+    }
+}
+
+/// Applies all pending schema migrations, in a single transaction.
+///
+/// A database migrated by a newer server, e.g. before a rollback, is refused rather than used with a
+/// schema this server doesn't know about, and its version is never lowered.
+fn migrate(connection: &mut Connection) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+    let version: usize = transaction.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+            Some(format!(
+                "Failed to migrate database, schema version {version} is newer than the latest known {}",
+                MIGRATIONS.len()
+            )),
+        ));
+    }
+    for migration in MIGRATIONS.iter().skip(version) {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    transaction.commit()
+}
+
+fn storage_error(e: rusqlite::Error) -> Status {
+    Status::internal(format!("Storage error: {e}"))
+}
+
+fn to_bigint(bytes: Vec<u8>) -> BigInt {
+    BigInt::from_bytes_be(num_bigint::Sign::Plus, &bytes)
+}
+
+fn user_from_row(row: &Row) -> Result<User, rusqlite::Error> {
+    Ok(User {
+        id: row.get("id")?,
+        y1: to_bigint(row.get("y1")?),
+        y2: to_bigint(row.get("y2")?),
+        salt: row.get("salt")?,
+        kdf_parameters: KdfParameters {
+            memory_kib: row.get("kdf_memory_kib")?,
+            iterations: row.get("kdf_iterations")?,
+            parallelism: row.get("kdf_parallelism")?,
+        },
+        auth_id: row.get("auth_id")?,
+        session_ids: vec![],
+        disabled: row.get("disabled")?,
+    })
+}
+
+/// Loads a user, together with the identifiers of its sessions.
+fn load_user(connection: &Connection, user_name: &str) -> Result<Option<User>, rusqlite::Error> {
+    let Some(mut user) = connection
+        .query_row(
+            "SELECT * FROM users WHERE id = ?1",
+            params![user_name],
+            user_from_row,
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    user.session_ids = connection
+        .prepare("SELECT id FROM sessions WHERE user_id = ?1 ORDER BY issued_at, rowid")?
+        .query_map(params![user_name], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(Some(user))
+}
+
+fn session_from_row(row: &Row) -> Result<Session, rusqlite::Error> {
+    Ok(Session {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        issued_at: row.get("issued_at")?,
+        expires_at: row.get("expires_at")?,
+        scopes: row
+            .get::<_, String>("scopes")?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect(),
+        credential_id: row.get("credential_id")?,
+    })
+}
+
+fn invalid_session() -> Status {
+    Status::unauthenticated("Failed to retrieve session data, session is invalid or has expired")
+}
+
+fn challenge_from_row(row: &Row) -> Result<Challenge, rusqlite::Error> {
+    Ok(Challenge {
+        id: row.get("id")?,
+        c: to_bigint(row.get("c")?),
+        r1: to_bigint(row.get("r1")?),
+        r2: to_bigint(row.get("r2")?),
+        user_id: row.get("user_id")?,
+        expires_at: row.get("expires_at")?,
+    })
+}
+
+fn pending_credential_from_row(row: &Row) -> Result<PendingCredential, rusqlite::Error> {
+    Ok(PendingCredential {
+        id: row.get("id")?,
+        session_id: row.get("session_id")?,
+        k: to_bigint(row.get("k")?),
+        r: to_bigint(row.get("r")?),
+        expires_at: row.get("expires_at")?,
+    })
+}
+
+/// Inserts a new session, revoking the user's oldest sessions over `max_sessions`.
+///
+/// # Returns
+/// The ids of the revoked sessions.
+fn insert_session(
+    connection: &Connection,
+    session: &Session,
+    max_sessions: usize,
+) -> Result<Vec<String>, rusqlite::Error> {
+    connection.execute(
+        "INSERT INTO sessions (id, user_id, issued_at, expires_at, scopes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            session.id,
+            session.user_id,
+            session.issued_at,
+            session.expires_at,
+            session.scopes.join(" "),
+        ],
+    )?;
+    // revoke the user's oldest sessions, over the limit
+    let mut statement = connection.prepare(
+        "DELETE FROM sessions WHERE user_id = ?1 AND id NOT IN (
+            SELECT id FROM sessions WHERE user_id = ?1 ORDER BY issued_at DESC, rowid DESC LIMIT ?2
+         ) RETURNING id",
+    )?;
+    let evicted_session_ids = statement
+        .query_map(params![session.user_id, max_sessions.max(1)], |row| {
+            row.get(0)
+        })?
+        .collect();
+    evicted_session_ids
+}
+
+#[tonic::async_trait]
+impl AuthStorage for SqliteStorage {
+    async fn register_user(&self, user: User) -> Result<(), Status> {
+        self.with_connection(move |connection| {
+            let inserted = connection
+                .execute(
+                    "INSERT INTO users (id, y1, y2, salt, kdf_memory_kib, kdf_iterations, kdf_parallelism, auth_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, NULL)
+                     ON CONFLICT (id) DO NOTHING",
+                    params![
+                        user.id,
+                        user.y1.to_bytes_be().1,
+                        user.y2.to_bytes_be().1,
+                        user.salt,
+                        user.kdf_parameters.memory_kib,
+                        user.kdf_parameters.iterations,
+                        user.kdf_parameters.parallelism,
+                    ],
+                )
+                .map_err(storage_error)?;
+            if inserted == 0 {
+                return Err(Status::already_exists(
+                    "Failed to register user, user name is already taken",
+                ));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<User>, Status> {
+        let user_name = user_name.to_string();
+        self.with_connection(move |connection| {
+            load_user(connection, &user_name).map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn rotate_credential(
+        &self,
+        user_name: String,
+        old_y1: &BigInt,
+        old_y2: &BigInt,
+        new_y1: BigInt,
+        new_y2: BigInt,
+    ) -> Result<(), Status> {
+        let (old_y1, old_y2) = (old_y1.clone(), old_y2.clone());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let user = load_user(&transaction, &user_name)
+                .map_err(storage_error)?
+                .ok_or_else(user_not_registered)?;
+            if user.y1 != old_y1 || user.y2 != old_y2 {
+                return Err(Status::aborted(
+                    "Failed to rotate credential, user credential has changed concurrently",
+                ));
+            }
+            if let Some(auth_id) = user.auth_id {
+                transaction
+                    .execute("DELETE FROM challenges WHERE id = ?1", params![auth_id])
+                    .map_err(storage_error)?;
+            }
+            transaction
+                .execute(
+                    "UPDATE users SET y1 = ?2, y2 = ?3, auth_id = NULL WHERE id = ?1",
+                    params![user_name, new_y1.to_bytes_be().1, new_y2.to_bytes_be().1],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn create_authentication_challenge(&self, challenge: Challenge) -> Result<(), Status> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let previous_auth_id: Option<String> = transaction
+                .query_row(
+                    "SELECT auth_id FROM users WHERE id = ?1",
+                    params![challenge.user_id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(storage_error)?
+                .ok_or_else(user_not_registered)?;
+            if let Some(previous_auth_id) = previous_auth_id {
+                // if the user has already authenticated, we delete the associated challenge
+                transaction
+                    .execute(
+                        "DELETE FROM challenges WHERE id = ?1",
+                        params![previous_auth_id],
+                    )
+                    .map_err(storage_error)?;
+            }
+            transaction
+                .execute(
+                    "INSERT INTO challenges (id, user_id, r1, r2, c, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        challenge.id,
+                        challenge.user_id,
+                        challenge.r1.to_bytes_be().1,
+                        challenge.r2.to_bytes_be().1,
+                        challenge.c.to_bytes_be().1,
+                        challenge.expires_at,
+                    ],
+                )
+                .map_err(storage_error)?;
+            transaction
+                .execute(
+                    "UPDATE users SET auth_id = ?2 WHERE id = ?1",
+                    params![challenge.user_id, challenge.id],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn take_challenge(&self, auth_id: &str) -> Result<Option<Challenge>, Status> {
+        let auth_id = auth_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let challenge = transaction
+                .query_row(
+                    "SELECT * FROM challenges WHERE id = ?1",
+                    params![auth_id],
+                    challenge_from_row,
+                )
+                .optional()
+                .map_err(storage_error)?;
+            if challenge.is_some() {
+                transaction
+                    .execute("DELETE FROM challenges WHERE id = ?1", params![auth_id])
+                    .map_err(storage_error)?;
+                transaction
+                    .execute(
+                        "UPDATE users SET auth_id = NULL WHERE auth_id = ?1",
+                        params![auth_id],
+                    )
+                    .map_err(storage_error)?;
+            }
+            transaction.commit().map_err(storage_error)?;
+            Ok(challenge)
+        })
+        .await
+    }
+
+    async fn purge_expired_challenges(&self, now: Timestamp) -> Result<usize, Status> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            transaction
+                .execute(
+                    "UPDATE users SET auth_id = NULL WHERE auth_id IN (SELECT id FROM challenges WHERE expires_at <= ?1)",
+                    params![now],
+                )
+                .map_err(storage_error)?;
+            let purged = transaction
+                .execute(
+                    "DELETE FROM challenges WHERE expires_at <= ?1",
+                    params![now],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(purged)
+        })
+        .await
+    }
+
+    async fn create_session(
+        &self,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let user_exists = transaction
+                .query_row(
+                    "SELECT 1 FROM users WHERE id = ?1",
+                    params![session.user_id],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map_err(storage_error)?
+                .is_some();
+            if !user_exists {
+                return Err(user_not_registered());
+            }
+            let evicted_session_ids =
+                insert_session(&transaction, &session, max_sessions).map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(evicted_session_ids)
+        })
+        .await
+    }
+
+    async fn complete_authentication(
+        &self,
+        y1: &BigInt,
+        y2: &BigInt,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status> {
+        let (y1, y2) = (y1.clone(), y2.clone());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let user = load_user(&transaction, &session.user_id).map_err(storage_error)?;
+            match &user {
+                None => return Err(user_not_registered()),
+                Some(user) if user.y1 != y1 || user.y2 != y2 => {
+                    return Err(Status::aborted(
+                        "Failed to authenticate, user credential has changed concurrently",
+                    ))
+                }
+                Some(user) => check_enabled(user)?,
+            }
+            let evicted_session_ids =
+                insert_session(&transaction, &session, max_sessions).map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(evicted_session_ids)
+        })
+        .await
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>, Status> {
+        let session_id = session_id.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT * FROM sessions WHERE id = ?1",
+                    params![session_id],
+                    session_from_row,
+                )
+                .optional()
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn refresh_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        expires_at: Timestamp,
+    ) -> Result<Session, Status> {
+        let session_id = session_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let updated = transaction
+                .execute(
+                    "UPDATE sessions SET expires_at = ?3 WHERE id = ?1 AND expires_at > ?2",
+                    params![session_id, now, expires_at],
+                )
+                .map_err(storage_error)?;
+            if updated == 0 {
+                return Err(invalid_session());
+            }
+            let session = transaction
+                .query_row(
+                    "SELECT * FROM sessions WHERE id = ?1",
+                    params![session_id],
+                    session_from_row,
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(session)
+        })
+        .await
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<Option<Session>, Status> {
+        let session_id = session_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let session = transaction
+                .query_row(
+                    "SELECT * FROM sessions WHERE id = ?1",
+                    params![session_id],
+                    session_from_row,
+                )
+                .optional()
+                .map_err(storage_error)?;
+            transaction
+                .execute("DELETE FROM sessions WHERE id = ?1", params![session_id])
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(session)
+        })
+        .await
+    }
+
+    async fn remove_user_sessions(&self, user_name: &str) -> Result<usize, Status> {
+        let user_name = user_name.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .execute(
+                    "DELETE FROM sessions WHERE user_id = ?1",
+                    params![user_name],
+                )
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn purge_expired_sessions(&self, now: Timestamp) -> Result<usize, Status> {
+        self.with_connection(move |connection| {
+            connection
+                .execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now])
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn count_active(&self, now: Timestamp) -> Result<(usize, usize), Status> {
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT (SELECT COUNT(*) FROM challenges WHERE expires_at > ?1), \
+                     (SELECT COUNT(*) FROM sessions WHERE expires_at > ?1)",
+                    params![now],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn purge_all_challenges(&self) -> Result<usize, Status> {
+        self.with_connection(|connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            transaction
+                .execute("UPDATE users SET auth_id = NULL", [])
+                .map_err(storage_error)?;
+            let purged = transaction
+                .execute("DELETE FROM challenges", [])
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(purged)
+        })
+        .await
+    }
+
+    async fn list_users(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>, Status> {
+        let after = after.map(str::to_string);
+        self.with_connection(move |connection| {
+            // a single read transaction, so that users and their sessions are consistent
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let user_names = transaction
+                .prepare("SELECT id FROM users WHERE ?1 IS NULL OR id > ?1 ORDER BY id LIMIT ?2")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map(params![after, limit], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(storage_error)?;
+            let mut users = Vec::with_capacity(user_names.len());
+            for user_name in user_names {
+                if let Some(user) = load_user(&transaction, &user_name).map_err(storage_error)? {
+                    users.push(user);
+                }
+            }
+            Ok(users)
+        })
+        .await
+    }
+
+    async fn user_sessions(&self, user_name: &str) -> Result<Vec<Session>, Status> {
+        let user_name = user_name.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let user_exists = transaction
+                .query_row(
+                    "SELECT 1 FROM users WHERE id = ?1",
+                    params![user_name],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map_err(storage_error)?
+                .is_some();
+            if !user_exists {
+                return Err(user_not_found(&user_name));
+            }
+            transaction
+                .prepare("SELECT * FROM sessions WHERE user_id = ?1 ORDER BY issued_at, rowid")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map(params![user_name], session_from_row)?
+                        .collect()
+                })
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn set_user_disabled(&self, user_name: &str, disabled: bool) -> Result<usize, Status> {
+        let user_name = user_name.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let updated = transaction
+                .execute(
+                    "UPDATE users SET disabled = ?2 WHERE id = ?1",
+                    params![user_name, disabled],
+                )
+                .map_err(storage_error)?;
+            if updated == 0 {
+                return Err(user_not_found(&user_name));
+            }
+            let mut revoked = 0;
+            if disabled {
+                transaction
+                    .execute(
+                        "UPDATE users SET auth_id = NULL WHERE id = ?1",
+                        params![user_name],
+                    )
+                    .map_err(storage_error)?;
+                transaction
+                    .execute(
+                        "DELETE FROM challenges WHERE user_id = ?1",
+                        params![user_name],
+                    )
+                    .map_err(storage_error)?;
+                revoked = transaction
+                    .execute(
+                        "DELETE FROM sessions WHERE user_id = ?1",
+                        params![user_name],
+                    )
+                    .map_err(storage_error)?;
+            }
+            transaction.commit().map_err(storage_error)?;
+            Ok(revoked)
+        })
+        .await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<usize, Status> {
+        let user_name = user_name.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let revoked: usize = transaction
+                .query_row(
+                    "SELECT COUNT(*) FROM sessions WHERE user_id = ?1",
+                    params![user_name],
+                    |row| row.get(0),
+                )
+                .map_err(storage_error)?;
+            // challenges, sessions and their pending credentials are removed in cascade
+            let deleted = transaction
+                .execute("DELETE FROM users WHERE id = ?1", params![user_name])
+                .map_err(storage_error)?;
+            if deleted == 0 {
+                return Err(user_not_found(&user_name));
+            }
+            transaction.commit().map_err(storage_error)?;
+            Ok(revoked)
+        })
+        .await
+    }
+
+    async fn create_credential_commitment(
+        &self,
+        pending_credential: PendingCredential,
+        max_open: usize,
+    ) -> Result<(), Status> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            // a session holds at most one pending commitment, replaced by the new one
+            let replaced_id = transaction
+                .query_row(
+                    "SELECT credential_id FROM sessions WHERE id = ?1",
+                    params![pending_credential.session_id],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .optional()
+                .map_err(storage_error)?
+                .ok_or(Status::unauthenticated(
+                    "Failed to retrieve session data, user must authenticate first",
+                ))?;
+            if let Some(replaced_id) = replaced_id {
+                transaction
+                    .execute(
+                        "DELETE FROM pending_credentials WHERE id = ?1",
+                        params![replaced_id],
+                    )
+                    .map_err(storage_error)?;
+            }
+            let open: usize = transaction
+                .query_row("SELECT COUNT(*) FROM pending_credentials", [], |row| {
+                    row.get(0)
+                })
+                .map_err(storage_error)?;
+            if open >= max_open {
+                // the replaced commitment stays removed
+                transaction
+                    .execute(
+                        "UPDATE sessions SET credential_id = NULL WHERE id = ?1",
+                        params![pending_credential.session_id],
+                    )
+                    .map_err(storage_error)?;
+                transaction.commit().map_err(storage_error)?;
+                return Err(Status::resource_exhausted(
+                    "Failed to create credential commitment, too many credentials are being signed, retry later",
+                ));
+            }
+            transaction
+                .execute(
+                    "INSERT INTO pending_credentials (id, session_id, k, r, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        pending_credential.id,
+                        pending_credential.session_id,
+                        pending_credential.k.to_bytes_be().1,
+                        pending_credential.r.to_bytes_be().1,
+                        pending_credential.expires_at,
+                    ],
+                )
+                .map_err(storage_error)?;
+            transaction
+                .execute(
+                    "UPDATE sessions SET credential_id = ?2 WHERE id = ?1",
+                    params![pending_credential.session_id, pending_credential.id],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn take_credential_commitment(
+        &self,
+        session_id: &str,
+        credential_id: &str,
+        now: Timestamp,
+    ) -> Result<PendingCredential, Status> {
+        let (session_id, credential_id) = (session_id.to_string(), credential_id.to_string());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let pending_credential = transaction
+                .query_row(
+                    "SELECT * FROM pending_credentials WHERE id = ?1 AND session_id = ?2",
+                    params![credential_id, session_id],
+                    pending_credential_from_row,
+                )
+                .optional()
+                .map_err(storage_error)?
+                .ok_or(Status::not_found(
+                    "Failed to retrieve credential commitment, user must request a credential commitment first",
+                ))?;
+            transaction
+                .execute(
+                    "DELETE FROM pending_credentials WHERE id = ?1",
+                    params![credential_id],
+                )
+                .map_err(storage_error)?;
+            transaction
+                .execute(
+                    "UPDATE sessions SET credential_id = NULL WHERE id = ?1",
+                    params![session_id],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            if pending_credential.expires_at <= now {
+                return Err(Status::deadline_exceeded(
+                    "Failed to sign credential, credential commitment has expired",
+                ));
+            }
+            Ok(pending_credential)
+        })
+        .await
+    }
+
+    async fn purge_expired_credential_commitments(&self, now: Timestamp) -> Result<usize, Status> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            transaction
+                .execute(
+                    "UPDATE sessions SET credential_id = NULL WHERE id IN (
+                        SELECT session_id FROM pending_credentials WHERE expires_at <= ?1
+                     )",
+                    params![now],
+                )
+                .map_err(storage_error)?;
+            let purged = transaction
+                .execute(
+                    "DELETE FROM pending_credentials WHERE expires_at <= ?1",
+                    params![now],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)?;
+            Ok(purged)
+        })
+        .await
+    }
+
+    async fn spend_credential(
+        &self,
+        message: Vec<u8>,
+        expires_at: Timestamp,
+    ) -> Result<(), Status> {
+        self.with_connection(move |connection| {
+            let inserted = connection
+                .execute(
+                    "INSERT OR IGNORE INTO spent_credentials (message, expires_at) VALUES (?1, ?2)",
+                    params![message, expires_at],
+                )
+                .map_err(storage_error)?;
+            if inserted == 0 {
+                return Err(Status::unauthenticated(
+                    "Failed to present credential, credential has already been presented",
+                ));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn purge_expired_spent_credentials(&self, now: Timestamp) -> Result<usize, Status> {
+        self.with_connection(move |connection| {
+            connection
+                .execute(
+                    "DELETE FROM spent_credentials WHERE expires_at <= ?1",
+                    params![now],
+                )
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn export_state(&self) -> Result<PedersenChaumAuthServerState, Status> {
+        self.with_connection(|connection| {
+            // a single read transaction, for a consistent copy
+            let transaction = connection.transaction().map_err(storage_error)?;
+            let mut state = PedersenChaumAuthServerState::new();
+            let user_names = transaction
+                .prepare("SELECT id FROM users")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(storage_error)?;
+            for user_name in user_names {
+                if let Some(user) = load_user(&transaction, &user_name).map_err(storage_error)? {
+                    state.users.insert(user_name, user);
+                }
+            }
+            state.challenges = transaction
+                .prepare("SELECT * FROM challenges")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], challenge_from_row)?
+                        .map(|challenge| {
+                            challenge.map(|challenge| (challenge.id.clone(), challenge))
+                        })
+                        .collect()
+                })
+                .map_err(storage_error)?;
+            state.sessions = transaction
+                .prepare("SELECT * FROM sessions")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], session_from_row)?
+                        .map(|session| session.map(|session| (session.id.clone(), session)))
+                        .collect()
+                })
+                .map_err(storage_error)?;
+            state.pending_credentials = transaction
+                .prepare("SELECT * FROM pending_credentials")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], pending_credential_from_row)?
+                        .map(|pending_credential| {
+                            pending_credential.map(|pending_credential| {
+                                (pending_credential.id.clone(), pending_credential)
+                            })
+                        })
+                        .collect()
+                })
+                .map_err(storage_error)?;
+            state.spent_credentials = transaction
+                .prepare("SELECT message, expires_at FROM spent_credentials")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                        .collect()
+                })
+                .map_err(storage_error)?;
+            Ok(state)
+        })
+        .await
+    }
+
+    async fn check_ready(&self) -> Result<(), Status> {
+        self.with_connection(|connection| {
+            let version: usize = connection
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .map_err(storage_error)?;
+            if version != MIGRATIONS.len() {
+                return Err(Status::unavailable(format!(
+                    "Storage error: schema version {version}, expected {}",
+                    MIGRATIONS.len()
+                )));
+            }
+            for table in READY_TABLES {
+                connection
+                    .prepare(&format!("SELECT 1 FROM {table} LIMIT 1"))
+                    .and_then(|mut statement| statement.exists([]))
+                    .map_err(storage_error)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tonic::Code;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::storage::InMemoryStorage;
+
+    fn test_user(user_name: &str) -> User {
+        User {
+            id: user_name.to_string(),
+            y1: BigInt::from_str("1_000_000_000").unwrap(),
+            y2: BigInt::from_str("2_000_000_000").unwrap(),
+            salt: vec![7u8; 16],
+            kdf_parameters: KdfParameters::default(),
+            auth_id: None,
+            session_ids: vec![],
+            disabled: false,
+        }
+    }
+
+    fn test_session(session_id: &str, user_name: &str) -> Session {
+        Session {
+            id: session_id.to_string(),
+            user_id: user_name.to_string(),
+            issued_at: 500,
+            expires_at: 1_000,
+            scopes: vec!["profile".to_string(), "admin".to_string()],
+            credential_id: None,
+        }
+    }
+
+    fn test_challenge(auth_id: &str, user_name: &str) -> Challenge {
+        Challenge {
+            id: auth_id.to_string(),
+            c: BigInt::from_str("10_000").unwrap(),
+            r1: BigInt::from_str("1_000").unwrap(),
+            r2: BigInt::from_str("2_000").unwrap(),
+            user_id: user_name.to_string(),
+            expires_at: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_migrations_are_applied_once() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        // re-applying migrations is a no-op
+        migrate(&mut connection).unwrap();
+
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        // a schema from a newer server is refused, and left as is
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut connection)
+            .unwrap_err()
+            .to_string()
+            .contains("is newer than the latest known"));
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() + 1);
+    }
+
+    #[tokio::test]
+    async fn test_check_ready() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        assert!(storage.check_ready().await.is_ok());
+
+        storage
+            .with_connection(|connection| {
+                connection
+                    .execute_batch("DROP TABLE spent_credentials")
+                    .map_err(storage_error)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.check_ready().await.unwrap_err().code(),
+            Code::Internal
+        );
+
+        storage
+            .with_connection(|connection| {
+                connection
+                    .pragma_update(None, "user_version", 1)
+                    .map_err(storage_error)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.check_ready().await.unwrap_err().code(),
+            Code::Unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registered_users_survive_restarts() {
+        let path = std::env::temp_dir().join(format!("zkp-auth-{}.sqlite", Uuid::new_v4()));
+        let user = test_user("user_name");
+
+        {
+            let storage = SqliteStorage::open(&path).unwrap();
+            storage.register_user(user.clone()).await.unwrap();
+        }
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(
+            storage.get_user("user_name").await.unwrap(),
+            Some(user.clone())
+        );
+        // registered users are never overwritten
+        assert_eq!(
+            storage.register_user(user).await.unwrap_err().code(),
+            tonic::Code::AlreadyExists
+        );
+        assert_eq!(storage.get_user("other_user").await.unwrap(), None);
+
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_authentication_challenge_replaces_previous_challenge() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.register_user(test_user("user_name")).await.unwrap();
+
+        storage
+            .create_authentication_challenge(test_challenge("auth_id_1", "user_name"))
+            .await
+            .unwrap();
+        storage
+            .create_authentication_challenge(test_challenge("auth_id_2", "user_name"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .get_user("user_name")
+                .await
+                .unwrap()
+                .unwrap()
+                .auth_id,
+            Some("auth_id_2".to_string())
+        );
+        assert_eq!(storage.take_challenge("auth_id_1").await.unwrap(), None);
+        assert_eq!(
+            storage.take_challenge("auth_id_2").await.unwrap(),
+            Some(test_challenge("auth_id_2", "user_name"))
+        );
+        // challenges are single use
+        assert_eq!(storage.take_challenge("auth_id_2").await.unwrap(), None);
+        assert_eq!(
+            storage
+                .get_user("user_name")
+                .await
+                .unwrap()
+                .unwrap()
+                .auth_id,
+            None
+        );
+
+        assert!(storage
+            .create_authentication_challenge(test_challenge("auth_id_3", "other_user"))
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to retrieve user data, user must register first"));
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired_challenges() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.register_user(test_user("user_1")).await.unwrap();
+        storage.register_user(test_user("user_2")).await.unwrap();
+        storage
+            .create_authentication_challenge(test_challenge("auth_id_1", "user_1"))
+            .await
+            .unwrap();
+        storage
+            .create_authentication_challenge(Challenge {
+                expires_at: 2_000,
+                ..test_challenge("auth_id_2", "user_2")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(storage.count_active(999).await.unwrap(), (2, 0));
+        assert_eq!(storage.count_active(1_000).await.unwrap(), (1, 0));
+        assert_eq!(storage.purge_expired_challenges(999).await.unwrap(), 0);
+        assert_eq!(storage.purge_expired_challenges(1_000).await.unwrap(), 1);
+        assert_eq!(
+            storage.get_user("user_1").await.unwrap().unwrap().auth_id,
+            None
+        );
+        assert!(storage.take_challenge("auth_id_2").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_create_session_and_credentials() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.register_user(test_user("user_name")).await.unwrap();
+
+        assert!(storage
+            .create_session(test_session("session_id", "other_user"), 5)
+            .await
+            .is_err());
+        storage
+            .create_session(test_session("session_id", "user_name"), 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get_session("session_id").await.unwrap(),
+            Some(test_session("session_id", "user_name"))
+        );
+
+        let pending_credential = PendingCredential {
+            id: "credential_id".to_string(),
+            session_id: "session_id".to_string(),
+            k: BigInt::from_str("3_000").unwrap(),
+            r: BigInt::from_str("4_000").unwrap(),
+            expires_at: 1_000,
+        };
+        storage
+            .create_credential_commitment(pending_credential.clone(), 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .take_credential_commitment("session_id", "credential_id", 500)
+                .await
+                .unwrap(),
+            pending_credential
+        );
+        assert!(storage
+            .take_credential_commitment("session_id", "credential_id", 500)
+            .await
+            .is_err());
+
+        // a new commitment replaces the pending one of the same session, and expires
+        for id in ["credential_1", "credential_2"] {
+            storage
+                .create_credential_commitment(
+                    PendingCredential {
+                        id: id.to_string(),
+                        ..pending_credential.clone()
+                    },
+                    5,
+                )
+                .await
+                .unwrap();
+        }
+        assert!(storage
+            .take_credential_commitment("session_id", "credential_1", 500)
+            .await
+            .is_err());
+        assert_eq!(
+            storage
+                .take_credential_commitment("session_id", "credential_2", 1_000)
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::DeadlineExceeded
+        );
+        storage
+            .create_credential_commitment(pending_credential.clone(), 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .get_session("session_id")
+                .await
+                .unwrap()
+                .unwrap()
+                .credential_id,
+            Some("credential_id".to_string())
+        );
+        assert_eq!(
+            storage
+                .purge_expired_credential_commitments(999)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            storage
+                .purge_expired_credential_commitments(1_000)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            storage.get_session("session_id").await.unwrap(),
+            Some(test_session("session_id", "user_name"))
+        );
+
+        assert!(storage.spend_credential(vec![1, 2, 3], 1_000).await.is_ok());
+        assert!(storage
+            .spend_credential(vec![1, 2, 3], 1_000)
+            .await
+            .is_err());
+        assert_eq!(
+            storage.purge_expired_spent_credentials(999).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            storage
+                .purge_expired_spent_credentials(1_000)
+                .await
+                .unwrap(),
+            1
+        );
+
+        // open commitments are bounded across sessions
+        storage
+            .create_session(test_session("other_session_id", "user_name"), 5)
+            .await
+            .unwrap();
+        storage
+            .create_credential_commitment(pending_credential.clone(), 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage
+                .create_credential_commitment(
+                    PendingCredential {
+                        id: "other_credential_id".to_string(),
+                        session_id: "other_session_id".to_string(),
+                        ..pending_credential.clone()
+                    },
+                    1,
+                )
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::ResourceExhausted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_state_matches_in_memory_storage() {
+        let sqlite_storage = SqliteStorage::open_in_memory().unwrap();
+        let in_memory_storage = InMemoryStorage::new();
+        for storage in [&sqlite_storage as &dyn AuthStorage, &in_memory_storage] {
+            storage.register_user(test_user("user_name")).await.unwrap();
+            storage
+                .register_user(test_user("other_user"))
+                .await
+                .unwrap();
+            storage
+                .create_authentication_challenge(test_challenge("auth_id", "user_name"))
+                .await
+                .unwrap();
+            for (session_id, user_name) in [("session_1", "user_name"), ("session_2", "other_user")]
+            {
+                storage
+                    .create_session(test_session(session_id, user_name), 5)
+                    .await
+                    .unwrap();
+            }
+            storage
+                .create_credential_commitment(
+                    PendingCredential {
+                        id: "credential_id".to_string(),
+                        session_id: "session_2".to_string(),
+                        k: BigInt::from(3_000),
+                        r: BigInt::from(4_000),
+                        expires_at: 1_000,
+                    },
+                    5,
+                )
+                .await
+                .unwrap();
+            storage
+                .spend_credential(vec![1, 2, 3], 1_000)
+                .await
+                .unwrap();
+        }
+
+        let state = sqlite_storage.export_state().await.unwrap();
+        assert_eq!(state, in_memory_storage.export_state().await.unwrap());
+        assert_eq!(state.users["user_name"].auth_id.as_deref(), Some("auth_id"));
+        assert_eq!(state.users["user_name"].session_ids, vec!["session_1"]);
+        assert_eq!(state.pending_credentials.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.register_user(test_user("user_name")).await.unwrap();
+        let mut evicted_session_ids = vec![];
+        for (session_id, issued_at) in [("session_1", 500), ("session_2", 501), ("session_3", 502)]
+        {
+            evicted_session_ids.extend(
+                storage
+                    .create_session(
+                        Session {
+                            issued_at,
+                            ..test_session(session_id, "user_name")
+                        },
+                        2,
+                    )
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(evicted_session_ids, vec!["session_1".to_string()]);
+
+        // every session is tracked, up to the limit
+        assert_eq!(
+            storage
+                .get_user("user_name")
+                .await
+                .unwrap()
+                .unwrap()
+                .session_ids,
+            vec!["session_2".to_string(), "session_3".to_string()]
+        );
+        assert_eq!(storage.get_session("session_1").await.unwrap(), None);
+
+        assert!(storage
+            .refresh_session("session_2", 1_000, 2_000)
+            .await
+            .is_err());
+        assert_eq!(
+            storage
+                .refresh_session("session_2", 999, 2_000)
+                .await
+                .unwrap()
+                .expires_at,
+            2_000
+        );
+        assert_eq!(storage.purge_expired_sessions(1_000).await.unwrap(), 1);
+        assert_eq!(
+            storage.remove_session("session_2").await.unwrap(),
+            Some(Session {
+                issued_at: 501,
+                expires_at: 2_000,
+                ..test_session("session_2", "user_name")
+            })
+        );
+        assert_eq!(storage.remove_user_sessions("user_name").await.unwrap(), 0);
+    }
+}
@@ -1,23 +1,31 @@
 use std::collections::HashMap;
 
-use num_bigint::BigInt;
 use tonic::Status;
 
-use crate::types::{Challenge, Session, User};
+use crate::{
+    clock::Timestamp,
+    types::{Challenge, PendingCredential, Session, User},
+};
 
 pub type UserId = String;
 pub type ChallengeId = String;
 pub type SessionId = String;
+pub type CredentialId = String;
 
 /// Represents the state of a Pedersen-Chaum authentication server.
 ///
-/// This struct maintains the state of the authentication server, including registered users,
-/// active challenges, and ongoing sessions. It uses hash maps for efficient retrieval and management
-/// of these entities.
+/// This struct holds a copy of the state of the authentication server, including registered users,
+/// active challenges, and ongoing sessions, in plain hash maps. It also holds the pending blind
+/// credential signing sessions, and the credentials that have already been presented. Storage
+/// backends export their contents in this form, which snapshots are written from and restored to.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PedersenChaumAuthServerState {
     pub(crate) users: HashMap<UserId, User>,
     pub(crate) challenges: HashMap<ChallengeId, Challenge>,
     pub(crate) sessions: HashMap<SessionId, Session>,
+    pub(crate) pending_credentials: HashMap<CredentialId, PendingCredential>,
+    /// Expiry of the credentials already presented, by signed message
+    pub(crate) spent_credentials: HashMap<Vec<u8>, Timestamp>,
 }
 
 impl PedersenChaumAuthServerState {
@@ -26,312 +34,38 @@ impl PedersenChaumAuthServerState {
             users: HashMap::new(),
             challenges: HashMap::new(),
             sessions: HashMap::new(),
+            pending_credentials: HashMap::new(),
+            spent_credentials: HashMap::new(),
         }
     }
 }
 
-impl PedersenChaumAuthServerState {
-    /// Registers a new user in the server state.
-    ///
-    /// This function adds a new user to the `PedersenChaumAuthServerState`. It takes the user's name and their cryptographic components (`y1` and `y2`), and stores them as part of the user's information.
-    ///
-    /// # Arguments
-    ///
-    /// * `user_name`: A `String` representing the unique name of the user. This serves as the user's identifier.
-    /// * `y1`: A `BigInt` representing the first cryptographic component associated with the user.
-    /// * `y2`: A `BigInt` representing the second cryptographic component associated with the user.
-    pub(crate) fn register_user(&mut self, user_name: String, y1: BigInt, y2: BigInt) {
-        self.users.insert(
-            user_name.clone(),
-            User {
-                id: user_name,
-                y1,
-                y2,
-                auth_id: None,
-                session_id: None,
-            },
-        );
-    }
-
-    /// Creates an authentication challenge for a registered user.
-    ///
-    /// This method adds a new challenge for a user in the `PedersenChaumAuthServerState`. It associates a user with an authentication challenge, identified by an authentication ID, and stores the challenge's cryptographic components.
-    ///
-    /// # Arguments
-    ///
-    /// * `user_name`: A `String` representing the name of the user. This should correspond to a user that is already registered in the server state.
-    /// * `auth_id`: A `String` representing a unique identifier for the authentication challenge.
-    /// * `r1`: A `BigInt` representing the first cryptographic component of the challenge.
-    /// * `r2`: A `BigInt` representing the second cryptographic component of the challenge.
-    /// * `c`: A `BigInt` representing the challenge value.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` type:
-    /// - `Ok(())` if the challenge was successfully created.
-    /// - `Err(Status)` if the user is not registered, with an appropriate error message.
-    pub(crate) fn create_authentication_challenge(
-        &mut self,
-        user_name: String,
-        auth_id: String,
-        r1: BigInt,
-        r2: BigInt,
-        c: BigInt,
-    ) -> Result<(), Status> {
-        if let Some(user_data) = self.users.get_mut(&user_name) {
-            if let Some(ref user_auth_id) = user_data.auth_id {
-                // if the user has already authenticated, we delete the associated challenge
-                self.challenges.remove(user_auth_id);
-            }
-            user_data.auth_id = Some(auth_id.clone());
-            self.challenges.insert(
-                auth_id.clone(),
-                Challenge {
-                    id: auth_id,
-                    c,
-                    r1,
-                    r2,
-                    user_id: user_name,
-                },
-            );
-        } else {
-            return Err(Status::unauthenticated(
-                "Failed to retrieve user data, user must register first",
-            ));
-        }
-        Ok(())
-    }
-
-    /// Creates a session for a registered user.
-    ///
-    /// This method establishes a new session for a user who has successfully completed authentication. It updates the user's session information in the server state and adds a new session record.
-    ///
-    /// # Arguments
-    ///
-    /// * `user_name`: A `String` representing the name of the user. This should correspond to a user that is already registered and authenticated in the server state.
-    /// * `session_id`: A `String` representing a unique identifier for the new session.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` type:
-    /// - `Ok(())` if the session was successfully created.
-    /// - `Err(Status)` if the user is not registered, with an appropriate error message.
-    pub(crate) fn create_session(
-        &mut self,
-        user_name: String,
-        session_id: String,
-    ) -> Result<(), Status> {
-        if let Some(user) = self.users.get_mut(&user_name) {
-            user.session_id = Some(session_id.clone());
-            self.sessions.insert(
-                session_id.clone(),
-                Session {
-                    id: session_id,
-                    user_id: user_name,
-                },
-            );
-        } else {
-            return Err(Status::unauthenticated(
-                "Failed to retrieve user data, user must register first",
-            ));
-        }
-        Ok(())
-    }
+pub(crate) fn user_not_found(user_name: &str) -> Status {
+    Status::not_found(format!(
+        "Failed to retrieve user data, no user named `{user_name}`"
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
-
-    use super::*;
-
-    #[test]
-    fn test_register_user() {
-        let user_name = "user_name".to_string();
-        let y1 = BigInt::from_str("1_000_000_000").unwrap();
-        let y2 = BigInt::from_str("2_000_000_000").unwrap();
-
-        let mut state = PedersenChaumAuthServerState::new();
-        state.register_user(user_name.clone(), y1.clone(), y2.clone());
-
-        let should_be_users = HashMap::from_iter([(
-            user_name.clone(),
-            User {
-                id: user_name,
-                y1,
-                y2,
-                auth_id: None,
-                session_id: None,
-            },
-        )]);
-        assert_eq!(state.users, should_be_users);
-
-        assert_eq!(state.challenges, HashMap::new());
-        assert_eq!(state.sessions, HashMap::new());
-    }
-
-    #[test]
-    fn test_create_authentication_challenge() {
-        let user_name = "user_name".to_string();
-        let y1 = BigInt::from_str("1_000_000_000").unwrap();
-        let y2 = BigInt::from_str("2_000_000_000").unwrap();
-
-        let auth_id = "f2m38m2kcj9d-s823".to_string();
-        let r1 = BigInt::from_str("1_000").unwrap();
-        let r2 = BigInt::from_str("2_000").unwrap();
-        let c = BigInt::from_str("10_000").unwrap();
-
-        let mut state = PedersenChaumAuthServerState::new();
-        state.register_user(user_name.clone(), y1.clone(), y2.clone());
-
-        state
-            .create_authentication_challenge(
-                user_name.clone(),
-                auth_id.clone(),
-                r1.clone(),
-                r2.clone(),
-                c.clone(),
-            )
-            .expect("Failed to create authentication");
-
-        let should_be_challenges = HashMap::from_iter([(
-            auth_id.clone(),
-            Challenge {
-                id: auth_id.clone(),
-                c,
-                r1,
-                r2,
-                user_id: user_name.clone(),
-            },
-        )]);
-        assert_eq!(state.challenges, should_be_challenges);
-
-        assert_eq!(state.users.get(&user_name).unwrap().auth_id, Some(auth_id));
-        assert_eq!(state.sessions, HashMap::new());
-    }
-
-    #[test]
-    fn test_create_authentication_challenge_when_auth_id_exists() {
-        let user_name = "user_name".to_string();
-        let y1 = BigInt::from_str("1_000_000_000").unwrap();
-        let y2 = BigInt::from_str("2_000_000_000").unwrap();
-
-        let auth_id = "f2m38m2kcj9d-s823".to_string();
-        let r1 = BigInt::from_str("1_000").unwrap();
-        let r2 = BigInt::from_str("2_000").unwrap();
-        let c = BigInt::from_str("10_000").unwrap();
-
-        let mut state = PedersenChaumAuthServerState::new();
-        state.register_user(user_name.clone(), y1.clone(), y2.clone());
-
-        state
-            .create_authentication_challenge(
-                user_name.clone(),
-                auth_id,
-                r1.clone(),
-                r2.clone(),
-                c.clone(),
-            )
-            .expect("Failed to create authentication");
-
-        // re-authenticate to test if the the new authentication token is updated
-        let new_auth_id = "2sdiofa9013".to_string();
-        state
-            .create_authentication_challenge(
-                user_name.clone(),
-                new_auth_id.clone(),
-                r1.clone(),
-                r2.clone(),
-                c.clone(),
-            )
-            .expect("Failed to create authentication");
-
-        let should_be_challenges = HashMap::from_iter([(
-            new_auth_id.clone(),
-            Challenge {
-                id: new_auth_id.clone(),
-                c,
-                r1,
-                r2,
-                user_id: user_name.clone(),
-            },
-        )]);
-        assert_eq!(state.challenges, should_be_challenges);
-
-        assert_eq!(
-            state.users.get(&user_name).unwrap().auth_id,
-            Some(new_auth_id)
-        );
-        assert_eq!(state.sessions, HashMap::new());
-    }
-
-    #[test]
-    fn test_create_authentication_challenge_fails_if_user_unregistered() {
-        let user_name = "user_name".to_string();
-
-        let auth_id = "f2m38m2kcj9d-s823".to_string();
-        let r1 = BigInt::from_str("1_000").unwrap();
-        let r2 = BigInt::from_str("2_000").unwrap();
-        let c = BigInt::from_str("10_000").unwrap();
-
-        let mut state = PedersenChaumAuthServerState::new();
-
-        // user hasn't registered yet
-        assert!(state
-            .create_authentication_challenge(
-                user_name.clone(),
-                auth_id,
-                r1.clone(),
-                r2.clone(),
-                c.clone(),
-            )
-            .unwrap_err()
-            .to_string()
-            .contains("Failed to retrieve user data, user must register first"));
-    }
-
-    #[test]
-    fn test_create_session() {
-        let user_name = "user_name".to_string();
-        let y1 = BigInt::from_str("1_000_000_000").unwrap();
-        let y2 = BigInt::from_str("2_000_000_000").unwrap();
-
-        let session_id = "sdfa837djf".to_string();
-
-        let mut state = PedersenChaumAuthServerState::new();
-        state.register_user(user_name.clone(), y1.clone(), y2.clone());
-
-        state
-            .create_session(user_name.clone(), session_id.clone())
-            .expect("Failed to create sesssion");
-
-        assert_eq!(
-            state.users.get(&user_name).unwrap().session_id,
-            Some(session_id.clone())
-        );
-        assert_eq!(state.challenges, HashMap::new());
+pub(crate) fn user_not_registered() -> Status {
+    Status::unauthenticated("Failed to retrieve user data, user must register first")
+}
 
-        let should_be_sessions = HashMap::from_iter([(
-            session_id.clone(),
-            Session {
-                id: session_id,
-                user_id: user_name,
-            },
-        )]);
-        assert_eq!(state.sessions, should_be_sessions)
+/// Checks that a user has not been disabled by an administrator. It is only checked once the user proved
+/// its identity, so that whether a user is disabled is only disclosed to that user.
+///
+/// # Returns
+/// A `Result` indicating success, or a `PERMISSION_DENIED` `Status` if the user is disabled.
+pub(crate) fn check_enabled(user: &User) -> Result<(), Status> {
+    if user.disabled {
+        return Err(Status::permission_denied(
+            "Failed to authenticate, user has been disabled by an administrator",
+        ));
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_create_session_fails_if_user_unregistered() {
-        let user_name = "user_name".to_string();
-        let session_id = "sdfa837djf".to_string();
-
-        let mut state = PedersenChaumAuthServerState::new();
-
-        assert!(state
-            .create_session(user_name.clone(), session_id.clone())
-            .unwrap_err()
-            .to_string()
-            .contains("Failed to retrieve user data, user must register first"));
+impl Default for PedersenChaumAuthServerState {
+    fn default() -> Self {
+        Self::new()
     }
 }
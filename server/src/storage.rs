@@ -0,0 +1,234 @@
+use num_bigint::BigInt;
+use tonic::Status;
+
+pub use crate::memory::InMemoryStorage;
+use crate::{
+    clock::Timestamp,
+    state::PedersenChaumAuthServerState,
+    types::{Challenge, PendingCredential, Session, User},
+};
+
+/// Defines the interface of a storage backend for the authentication server.
+///
+/// A backend stores registered users, active authentication challenges, ongoing sessions and the
+/// bookkeeping of blind credentials. Each method is a single atomic operation on the backend, so
+/// that concurrent requests never observe partially applied updates.
+#[tonic::async_trait]
+pub trait AuthStorage: Send + Sync + 'static {
+    /// Registers a new user. Existing users are never overwritten.
+    ///
+    /// # Arguments
+    /// * `user`: The `User` to store, with no pending challenge nor session.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or a `Status` error, `ALREADY_EXISTS` if the user name is taken.
+    async fn register_user(&self, user: User) -> Result<(), Status>;
+
+    /// Retrieves a registered user, if any.
+    ///
+    /// # Arguments
+    /// * `user_name`: The name of the user.
+    ///
+    /// # Returns
+    /// A `Result` containing the `User`, or `None` if no such user is registered.
+    async fn get_user(&self, user_name: &str) -> Result<Option<User>, Status>;
+
+    /// Replaces the public key of a registered user, if it still matches `old_y1` and `old_y2`.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or a `Status` error.
+    async fn rotate_credential(
+        &self,
+        user_name: String,
+        old_y1: &BigInt,
+        old_y2: &BigInt,
+        new_y1: BigInt,
+        new_y2: BigInt,
+    ) -> Result<(), Status>;
+
+    /// Stores a new authentication challenge for a registered user, replacing any previous one.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or a `Status` error, if the user is not registered.
+    async fn create_authentication_challenge(&self, challenge: Challenge) -> Result<(), Status>;
+
+    /// Removes and returns an authentication challenge, if any. Challenges are single use.
+    ///
+    /// # Arguments
+    /// * `auth_id`: The identifier of the challenge.
+    ///
+    /// # Returns
+    /// A `Result` containing the `Challenge`, or `None` if no such challenge exists.
+    async fn take_challenge(&self, auth_id: &str) -> Result<Option<Challenge>, Status>;
+
+    /// Removes every authentication challenge expired at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed challenges.
+    async fn purge_expired_challenges(&self, now: Timestamp) -> Result<usize, Status>;
+
+    /// Creates a new session for a registered user, revoking its oldest sessions if it then holds more
+    /// than `max_sessions` sessions.
+    ///
+    /// # Returns
+    /// A `Result` containing the ids of the revoked sessions, or a `Status` error if the user is not registered.
+    async fn create_session(
+        &self,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status>;
+
+    /// Completes a successful authentication, whose challenge has already been taken, as a single atomic
+    /// operation: creates `session` for the challenged user, revoking its oldest sessions if it then
+    /// holds more than `max_sessions` sessions, only if the user still holds the public key `y1`, `y2`
+    /// the answer was verified against, and has not been disabled.
+    ///
+    /// # Returns
+    /// A `Result` containing the ids of the revoked sessions, or a `Status` error if the user is no
+    /// longer registered, enabled or holding the same key.
+    async fn complete_authentication(
+        &self,
+        y1: &BigInt,
+        y2: &BigInt,
+        session: Session,
+        max_sessions: usize,
+    ) -> Result<Vec<String>, Status>;
+
+    /// Retrieves a session, if any.
+    ///
+    /// # Arguments
+    /// * `session_id`: The identifier of the session.
+    ///
+    /// # Returns
+    /// A `Result` containing the `Session`, or `None` if no such session exists.
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>, Status>;
+
+    /// Extends the validity of a session until `expires_at`, unless it has expired at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing the refreshed `Session`, or a `Status` error if the session does not exist or has expired.
+    async fn refresh_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        expires_at: Timestamp,
+    ) -> Result<Session, Status>;
+
+    /// Removes a session, together with its pending credential signing sessions.
+    ///
+    /// # Returns
+    /// A `Result` containing the removed `Session`, or `None` if no such session exists.
+    async fn remove_session(&self, session_id: &str) -> Result<Option<Session>, Status>;
+
+    /// Removes every session of a user.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed sessions.
+    async fn remove_user_sessions(&self, user_name: &str) -> Result<usize, Status>;
+
+    /// Removes every session expired at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed sessions.
+    async fn purge_expired_sessions(&self, now: Timestamp) -> Result<usize, Status>;
+
+    /// Counts the authentication challenges and sessions still active at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of active challenges and the number of active sessions.
+    async fn count_active(&self, now: Timestamp) -> Result<(usize, usize), Status>;
+
+    /// Removes every pending authentication challenge, whether expired or not.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed challenges.
+    async fn purge_all_challenges(&self) -> Result<usize, Status>;
+
+    /// Lists registered users, ordered by name, a page at a time.
+    ///
+    /// # Arguments
+    /// * `after`: The name of the last user of the previous page, or `None` for the first page.
+    /// * `limit`: The maximum number of users to return.
+    ///
+    /// # Returns
+    /// A `Result` containing the users named after `after`, at most `limit` of them.
+    async fn list_users(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>, Status>;
+
+    /// Lists the sessions of a user, from the oldest to the most recent.
+    ///
+    /// # Returns
+    /// A `Result` containing the sessions, or a `NOT_FOUND` `Status` if the user is not registered.
+    async fn user_sessions(&self, user_name: &str) -> Result<Vec<Session>, Status>;
+
+    /// Disables or enables a user. Disabling a user also removes its sessions and its pending
+    /// authentication challenge.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed sessions, or a `NOT_FOUND` `Status` if the user is
+    /// not registered.
+    async fn set_user_disabled(&self, user_name: &str, disabled: bool) -> Result<usize, Status>;
+
+    /// Deletes a user, together with its sessions and its pending authentication challenge.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed sessions, or a `NOT_FOUND` `Status` if the user is
+    /// not registered.
+    async fn delete_user(&self, user_name: &str) -> Result<usize, Status>;
+
+    /// Stores the signer commitment of a new blind credential signing session, replacing any pending
+    /// commitment of the same session, so that a session never holds more than one open signing session,
+    /// and the server never more than `max_open` of them.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or a `Status` error, if the session does not exist, or
+    /// `RESOURCE_EXHAUSTED` if `max_open` signing sessions are already open.
+    async fn create_credential_commitment(
+        &self,
+        pending_credential: PendingCredential,
+        max_open: usize,
+    ) -> Result<(), Status>;
+
+    /// Removes and returns the signer commitment of a blind credential signing session, unless it has
+    /// expired at `now`. Expired commitments are removed all the same.
+    ///
+    /// # Returns
+    /// A `Result` containing the `PendingCredential`, or a `Status` error if no such commitment exists for the session, or if it has expired.
+    async fn take_credential_commitment(
+        &self,
+        session_id: &str,
+        credential_id: &str,
+        now: Timestamp,
+    ) -> Result<PendingCredential, Status>;
+
+    /// Removes every credential commitment expired at `now`.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed commitments.
+    async fn purge_expired_credential_commitments(&self, now: Timestamp) -> Result<usize, Status>;
+
+    /// Marks a credential as spent, identified by its signed message, until it expires at `expires_at`.
+    ///
+    /// # Returns
+    /// A `Result` indicating success or a `Status` error, if the credential has already been spent.
+    async fn spend_credential(&self, message: Vec<u8>, expires_at: Timestamp)
+        -> Result<(), Status>;
+
+    /// Removes every spent credential expired at `now`, which can no longer be presented anyway.
+    ///
+    /// # Returns
+    /// A `Result` containing the number of removed credentials.
+    async fn purge_expired_spent_credentials(&self, now: Timestamp) -> Result<usize, Status>;
+
+    /// Exports the whole content of the backend, e.g. to back it up or to move it to another backend.
+    ///
+    /// # Returns
+    /// A `Result` containing a consistent copy of the backend's state.
+    async fn export_state(&self) -> Result<PedersenChaumAuthServerState, Status>;
+
+    /// Checks that the backend is ready to serve requests, e.g. that its database is reachable and
+    /// its schema up to date.
+    ///
+    /// # Returns
+    /// A `Result` indicating readiness or a `Status` error describing why the backend is not ready.
+    async fn check_ready(&self) -> Result<(), Status>;
+}
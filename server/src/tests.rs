@@ -1,32 +1,56 @@
 use crate::{
-    server::PedersenChaumAuthServer,
+    admin::ADMIN_AUTHORIZATION_HEADER,
+    audit::{verify_audit_log, AuditEvent, AuditLog, AuditRecord, RevocationCause},
+    clock::{Clock, ManualClock, Timestamp},
+    decoy::{DecoyUsers, DECOY_SECRET_SIZE},
+    logging::fingerprint,
+    rate_limit::{RateLimit, RateLimitConfig, RateLimiter},
+    sealed_challenge::{ChallengeSealer, CHALLENGE_KEY_SIZE},
+    server::{
+        PedersenChaumAuthServer, DEFAULT_CHALLENGE_TTL, DEFAULT_SESSION_TTL,
+        MAX_VALIDATE_SESSIONS_BATCH,
+    },
     server_auth::{
-        auth_server::Auth, AuthenticationAnswerRequest, AuthenticationAnswerResponse,
-        AuthenticationChallengeRequest, AuthenticationChallengeResponse, RegisterRequest,
-        RegisterResponse,
+        admin_server::Admin, auth_server::Auth, session_validation, AuthenticationAnswerRequest,
+        AuthenticationAnswerResponse, AuthenticationChallengeRequest,
+        AuthenticationChallengeResponse, BlindedCredentialRequest, CredentialCommitmentRequest,
+        CredentialPresentationRequest, DeleteUserRequest, DisableUserRequest, EnableUserRequest,
+        GetUserRequest, ListSessionsRequest, ListUsersRequest, LoginParametersRequest,
+        LoginParametersResponse, LogoutRequest, PurgeChallengesRequest, RefreshSessionRequest,
+        RegisterRequest, RegisterResponse, RevokeAllSessionsRequest, RevokeSessionRequest,
+        RotateCredentialRequest, SessionError, SessionInfo, SessionTokenKeysRequest,
+        UnlockUserRequest, ValidateSessionRequest, ValidateSessionsRequest,
     },
-    types::{Challenge, Session, User},
+    sqlite::SqliteStorage,
+    storage::AuthStorage,
+    types::{Challenge, KdfParameters, Session, User},
 };
-use chaum_pedersen::chaum_pedersen::{
-    ChaumPedersen, ChaumPedersenInterface,
+use chaum_pedersen::{
+    blind_signature,
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface, DEFAULT_DOMAIN},
+    key_rotation::{self, KeyRotation},
+    registration, DEFAULT_PARAMS,
 };
 use num_bigint::BigInt;
-use std::{collections::HashMap, str::FromStr};
-use tonic::Request;
+use session_token::{SessionClaims, TokenError, TokenSigningKey, TokenVerifier, TokenVerifyingKey};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use tonic::{transport::server::TcpConnectInfo, Request, Response, Status};
+use uuid::Uuid;
 
 #[tokio::test]
 async fn test_register_user() {
     let user = "hello, world";
-    let y1 = BigInt::from_str("1_000_000").unwrap();
-    let y2 = BigInt::from_str("1_000_000").unwrap();
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let exponents = cp.commit(&client_secret);
+    let (y1, y2) = (
+        exponents.get_first_exponent().clone(),
+        exponents.get_second_exponent().clone(),
+    );
 
     let server = PedersenChaumAuthServer::new();
 
-    let register_request = RegisterRequest {
-        user: user.to_string(),
-        y1: y1.to_bytes_be().1,
-        y2: y2.to_bytes_be().1,
-    };
+    let register_request = register_request(user, &client_secret);
 
     let result = server.register(Request::new(register_request)).await;
     assert!(result.is_ok());
@@ -40,31 +64,208 @@ async fn test_register_user() {
             id: user.to_string(),
             y1,
             y2,
+            salt: salt(),
+            kdf_parameters: KdfParameters::default(),
             auth_id: None,
-            session_id: None,
+            session_ids: vec![],
+            disabled: false,
         },
     )]);
-    assert_eq!(server.state.read().await.users, should_be_users);
-    assert_eq!(server.state.read().await.challenges, HashMap::new());
-    assert_eq!(server.state.read().await.sessions, HashMap::new());
+    assert_eq!(server.state.export().users, should_be_users);
+    assert_eq!(server.state.export().challenges, HashMap::new());
+    assert_eq!(server.state.export().sessions, HashMap::new());
+}
+
+#[tokio::test]
+async fn test_register_user_fails_with_invalid_login_parameters() {
+    let user = "hello, world";
+    let client_secret = ChaumPedersen::default().generate_random();
+
+    let server = PedersenChaumAuthServer::new();
+
+    let short_salt_request = RegisterRequest {
+        salt: vec![7u8; 4],
+        ..register_request(user, &client_secret)
+    };
+    assert!(server
+        .register(Request::new(short_salt_request))
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("salt length out of bounds"));
+
+    let weak_kdf_request = RegisterRequest {
+        kdf: Some(crate::server_auth::KdfParameters {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }),
+        ..register_request(user, &client_secret)
+    };
+    assert!(server
+        .register(Request::new(weak_kdf_request))
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("costs out of bounds"));
+
+    assert_eq!(server.state.export().users, HashMap::new());
+}
+
+#[tokio::test]
+async fn test_register_user_fails_without_proof_of_possession() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+
+    let server = PedersenChaumAuthServer::new();
+
+    // missing proof
+    let result = server
+        .register(Request::new(RegisterRequest {
+            proof: None,
+            ..register_request(user, &client_secret)
+        }))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    // public key with mismatched discrete logs
+    let other_exponents = cp.commit(&cp.generate_random());
+    let result = server
+        .register(Request::new(RegisterRequest {
+            y2: other_exponents.get_second_exponent().to_bytes_be().1,
+            ..register_request(user, &client_secret)
+        }))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    // a public key (and proof) copied from another user
+    let result = server
+        .register(Request::new(RegisterRequest {
+            user: "other user".to_string(),
+            ..register_request(user, &client_secret)
+        }))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    assert_eq!(server.state.export().users, HashMap::new());
+}
+
+#[tokio::test]
+async fn test_register_user_fails_if_user_exists() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+
+    let owner_secret = cp.generate_random();
+    let attacker_secret = cp.generate_random();
+    register(&server, user, &owner_secret).await;
+
+    let result = server
+        .register(Request::new(register_request(user, &attacker_secret)))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+    // the account is not taken over
+    assert!(authenticate(&server, user, &owner_secret).await.is_ok());
+    assert!(authenticate(&server, user, &attacker_secret).await.is_err());
+}
+
+#[tokio::test]
+async fn test_rekey_existing_user_requires_old_secret() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+
+    let old_secret = cp.generate_random();
+    let new_secret = cp.generate_random();
+    register(&server, user, &old_secret).await;
+    let session_id = authenticate(&server, user, &old_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    // re-keying without the old secret is rejected
+    let wrong_old_secret = cp.generate_random();
+    let result = server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            &wrong_old_secret,
+            &new_secret,
+            &new_secret,
+        )))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+    assert!(authenticate(&server, user, &new_secret).await.is_err());
+
+    // re-keying with a proof of the old secret succeeds
+    let result = server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            &old_secret,
+            &new_secret,
+            &new_secret,
+        )))
+        .await;
+    assert!(result.is_ok());
+    assert!(authenticate(&server, user, &new_secret).await.is_ok());
+    assert!(authenticate(&server, user, &old_secret).await.is_err());
+}
+
+#[tokio::test]
+async fn test_get_login_parameters() {
+    let user = "hello, world";
+    let server = PedersenChaumAuthServer::new();
+
+    let result = server
+        .get_login_parameters(Request::new(LoginParametersRequest {
+            user: user.to_string(),
+        }))
+        .await;
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Failed to retrieve user data, user must register first"));
+
+    let cp = ChaumPedersen::default();
+    register(&server, user, &cp.generate_random()).await;
+
+    let response = server
+        .get_login_parameters(Request::new(LoginParametersRequest {
+            user: user.to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response,
+        LoginParametersResponse {
+            salt: salt(),
+            kdf: Some(KdfParameters::default().into()),
+        }
+    );
 }
 
 #[tokio::test]
 async fn test_create_authentication_challenge() {
     let user = "hello, world";
-    let y1 = BigInt::from_str("1_000_000").unwrap();
-    let y2 = BigInt::from_str("1_000_000").unwrap();
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let exponents = cp.commit(&client_secret);
+    let (y1, y2) = (
+        exponents.get_first_exponent().clone(),
+        exponents.get_second_exponent().clone(),
+    );
 
     let r1 = BigInt::from_str("1").unwrap();
     let r2 = BigInt::from_str("2").unwrap();
 
-    let server = PedersenChaumAuthServer::new();
+    let server = PedersenChaumAuthServer::new().with_clock(Arc::new(ManualClock::new(NOW)));
 
-    let register_request = RegisterRequest {
-        user: user.to_string(),
-        y1: y1.to_bytes_be().1,
-        y2: y2.to_bytes_be().1,
-    };
+    let register_request = register_request(user, &client_secret);
 
     server
         .register(Request::new(register_request))
@@ -87,7 +288,7 @@ async fn test_create_authentication_challenge() {
     println!("response auth id = {}", response.auth_id);
     println!("response c = {:?}", response.c);
 
-    assert_eq!(response.c.len(), 32);
+    assert!(BigInt::from_bytes_be(num_bigint::Sign::Plus, &response.c) < DEFAULT_PARAMS.q);
 
     let should_be_users = HashMap::from_iter([(
         user.to_string(),
@@ -95,11 +296,14 @@ async fn test_create_authentication_challenge() {
             id: user.to_string(),
             y1,
             y2,
+            salt: salt(),
+            kdf_parameters: KdfParameters::default(),
             auth_id: Some(response.auth_id.clone()),
-            session_id: None,
+            session_ids: vec![],
+            disabled: false,
         },
     )]);
-    assert_eq!(server.state.read().await.users, should_be_users);
+    assert_eq!(server.state.export().users, should_be_users);
 
     let should_be_challenges = HashMap::from_iter([(
         response.auth_id.clone(),
@@ -109,10 +313,11 @@ async fn test_create_authentication_challenge() {
             r2,
             c: BigInt::from_bytes_be(num_bigint::Sign::Plus, &response.c.clone()),
             user_id: user.to_string(),
+            expires_at: NOW + DEFAULT_CHALLENGE_TTL.as_secs(),
         },
     )]);
-    assert_eq!(server.state.read().await.challenges, should_be_challenges);
-    assert_eq!(server.state.read().await.sessions, HashMap::new());
+    assert_eq!(server.state.export().challenges, should_be_challenges);
+    assert_eq!(server.state.export().sessions, HashMap::new());
 }
 
 #[tokio::test]
@@ -153,13 +358,9 @@ async fn test_verify_authentication() {
         r2,
     } = get_client_chaum_pedersen_values();
 
-    let server = PedersenChaumAuthServer::new();
+    let server = PedersenChaumAuthServer::new().with_clock(Arc::new(ManualClock::new(NOW)));
 
-    let register_request = RegisterRequest {
-        user: user.to_string(),
-        y1: y1.to_bytes_be().1,
-        y2: y2.to_bytes_be().1,
-    };
+    let register_request = register_request(user, &client_secret);
 
     server
         .register(Request::new(register_request))
@@ -192,7 +393,14 @@ async fn test_verify_authentication() {
         .await;
     assert!(result.is_ok());
 
-    let AuthenticationAnswerResponse { session_id } = result.unwrap().into_inner();
+    let AuthenticationAnswerResponse {
+        session_id,
+        expires_at,
+        session_token,
+    } = result.unwrap().into_inner();
+    assert_eq!(expires_at, NOW + DEFAULT_SESSION_TTL.as_secs());
+    // session tokens are disabled by default
+    assert_eq!(session_token, "");
 
     let should_be_users = HashMap::from_iter([(
         user.to_string(),
@@ -200,32 +408,1817 @@ async fn test_verify_authentication() {
             id: user.to_string(),
             y1,
             y2,
-            auth_id: Some(auth_id.clone()),
-            session_id: Some(session_id.clone()),
+            salt: salt(),
+            kdf_parameters: KdfParameters::default(),
+            auth_id: None,
+            session_ids: vec![session_id.clone()],
+            disabled: false,
         },
     )]);
-    assert_eq!(server.state.read().await.users, should_be_users);
+    assert_eq!(server.state.export().users, should_be_users);
 
-    let should_be_challenges = HashMap::from_iter([(
-        auth_id.clone(),
-        Challenge {
-            id: auth_id,
-            r1,
-            r2,
-            c,
-            user_id: user.to_string(),
-        },
-    )]);
-    assert_eq!(server.state.read().await.challenges, should_be_challenges);
+    // the challenge is consumed by the verification
+    assert_eq!(server.state.export().challenges, HashMap::new());
 
     let should_be_session = HashMap::from_iter([(
         session_id.clone(),
         Session {
             id: session_id,
             user_id: user.to_string(),
+            issued_at: NOW,
+            expires_at,
+            scopes: vec![],
+            credential_id: None,
         },
     )]);
-    assert_eq!(server.state.read().await.sessions, should_be_session);
+    assert_eq!(server.state.export().sessions, should_be_session);
+}
+
+#[tokio::test]
+async fn test_verify_authentication_can_not_be_replayed() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new();
+    register(&server, user, &client_secret).await;
+
+    let answer = answer_challenge(&server, user, &client_secret)
+        .await
+        .unwrap();
+    assert!(server
+        .verify_authentication(Request::new(answer.clone()))
+        .await
+        .is_ok());
+
+    let result = server.verify_authentication(Request::new(answer)).await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::Aborted);
+    assert_eq!(server.state.export().sessions.len(), 1);
+}
+
+#[tokio::test]
+async fn test_failed_verification_consumes_challenge() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new();
+    register(&server, user, &client_secret).await;
+
+    let answer = answer_challenge(&server, user, &client_secret)
+        .await
+        .unwrap();
+    let wrong_answer = AuthenticationAnswerRequest {
+        s: cp.generate_random().to_bytes_be().1,
+        ..answer.clone()
+    };
+    let result = server
+        .verify_authentication(Request::new(wrong_answer))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    // the correct answer can no longer be submitted
+    let result = server.verify_authentication(Request::new(answer)).await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::Aborted);
+    assert_eq!(server.state.export().challenges, HashMap::new());
+}
+
+#[tokio::test]
+async fn test_verify_authentication_fails_with_expired_challenge() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_challenge_ttl(Duration::from_secs(30));
+    register(&server, user, &client_secret).await;
+
+    let answer = answer_challenge(&server, user, &client_secret)
+        .await
+        .unwrap();
+    clock.advance(29);
+    assert!(server
+        .verify_authentication(Request::new(answer))
+        .await
+        .is_ok());
+
+    let answer = answer_challenge(&server, user, &client_secret)
+        .await
+        .unwrap();
+    clock.advance(30);
+    let result = server.verify_authentication(Request::new(answer)).await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::DeadlineExceeded);
+}
+
+#[tokio::test]
+async fn test_challenge_sweeper_purges_expired_challenges() {
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_challenge_ttl(Duration::from_secs(30));
+    register(&server, "expired user", &client_secret).await;
+    register(&server, "fresh user", &client_secret).await;
+
+    answer_challenge(&server, "expired user", &client_secret)
+        .await
+        .unwrap();
+    clock.advance(20);
+    let fresh_answer = answer_challenge(&server, "fresh user", &client_secret)
+        .await
+        .unwrap();
+    clock.advance(10);
+
+    let sweeper = server.spawn_sweeper(Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sweeper.abort();
+
+    let state = server.state.export();
+    assert_eq!(state.challenges.len(), 1);
+    assert!(state.challenges.contains_key(&fresh_answer.auth_id));
+    assert_eq!(state.users.get("expired user").unwrap().auth_id, None);
+}
+
+#[tokio::test]
+async fn test_stateless_challenges_move_between_replicas() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let replica = || {
+        PedersenChaumAuthServer::new()
+            .with_clock(clock.clone())
+            .with_stateless_challenges(ChallengeSealer::new(&[9u8; CHALLENGE_KEY_SIZE]))
+    };
+    let (replica_a, replica_b) = (replica(), replica());
+    register(&replica_a, user, &client_secret).await;
+    register(&replica_b, user, &client_secret).await;
+
+    // issuing a challenge writes nothing to the server state
+    let auth_answer_request = answer_challenge(&replica_a, user, &client_secret)
+        .await
+        .unwrap();
+    assert_eq!(replica_a.state.export().challenges, HashMap::new());
+    assert_eq!(replica_a.state.export().users[user].auth_id, None);
+
+    // the challenge is answered on another replica, once
+    assert!(replica_b
+        .verify_authentication(Request::new(auth_answer_request.clone()))
+        .await
+        .is_ok());
+    assert_eq!(
+        replica_b
+            .verify_authentication(Request::new(auth_answer_request))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Aborted
+    );
+
+    // forged challenges are rejected
+    let mut auth_answer_request = answer_challenge(&replica_a, user, &client_secret)
+        .await
+        .unwrap();
+    auth_answer_request.auth_id.pop();
+    assert_eq!(
+        replica_b
+            .verify_authentication(Request::new(auth_answer_request))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Aborted
+    );
+
+    // and so are expired challenges
+    let auth_answer_request = answer_challenge(&replica_a, user, &client_secret)
+        .await
+        .unwrap();
+    clock.advance(DEFAULT_CHALLENGE_TTL.as_secs());
+    assert_eq!(
+        replica_b
+            .verify_authentication(Request::new(auth_answer_request))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::DeadlineExceeded
+    );
+}
+
+#[tokio::test]
+async fn test_stateless_challenges_of_locked_out_users_are_not_remembered() {
+    let cp = ChaumPedersen::default();
+    let (secret, wrong_secret) = (cp.generate_random(), cp.generate_random());
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(Arc::new(ManualClock::new(NOW)))
+        .with_rate_limiter(RateLimiter::new(rate_limit_config()))
+        .with_stateless_challenges(
+            ChallengeSealer::new(&[9u8; CHALLENGE_KEY_SIZE]).with_seen_nonce_capacity(3),
+        );
+    register(&server, "locked out user", &secret).await;
+    register(&server, "user", &secret).await;
+
+    // failed answers are remembered, up to the lockout
+    for _ in 0..3 {
+        assert!(authenticate(&server, "locked out user", &wrong_secret)
+            .await
+            .is_err());
+    }
+
+    // which left room for the answers of other users
+    assert!(authenticate(&server, "user", &secret).await.is_ok());
+    assert_eq!(
+        authenticate(&server, "user", &secret)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::ResourceExhausted
+    );
+}
+
+fn rate_limit_config() -> RateLimitConfig {
+    RateLimitConfig {
+        user_limit: RateLimit {
+            burst: 4,
+            refill_interval: Duration::from_secs(60),
+        },
+        ip_limit: RateLimit {
+            burst: 2,
+            refill_interval: Duration::from_secs(60),
+        },
+        max_failures: 2,
+        base_lockout: Duration::from_secs(30),
+        max_lockout: Duration::from_secs(100),
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limiting_per_user_and_peer() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(Arc::new(ManualClock::new(NOW)))
+        .with_rate_limiter(RateLimiter::new(rate_limit_config()));
+    register(&server, user, &client_secret).await;
+    let challenge_request = |user: &str, peer: Option<&str>| {
+        let mut request = Request::new(AuthenticationChallengeRequest {
+            user: user.to_string(),
+            r1: vec![1],
+            r2: vec![1],
+        });
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: peer.map(|peer| peer.parse().unwrap()),
+        });
+        request
+    };
+
+    // challenges are limited per peer
+    for _ in 0..2 {
+        assert!(server
+            .create_authentication_challenge(challenge_request(user, Some("10.0.0.1:5000")))
+            .await
+            .is_ok());
+    }
+    assert_eq!(
+        server
+            .create_authentication_challenge(challenge_request(user, Some("10.0.0.1:5001")))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::ResourceExhausted
+    );
+
+    // and per user, whatever the peer
+    for peer in ["10.0.0.2:5000", "10.0.0.3:5000"] {
+        assert!(server
+            .create_authentication_challenge(challenge_request(user, Some(peer)))
+            .await
+            .is_ok());
+    }
+    assert_eq!(
+        server
+            .create_authentication_challenge(challenge_request(user, Some("10.0.0.4:5000")))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::ResourceExhausted
+    );
+    // unknown users take no bucket, so they are never limited
+    for _ in 0..5 {
+        assert!(server
+            .create_authentication_challenge(challenge_request("other user", None))
+            .await
+            .is_err_and(|e| e.code() == tonic::Code::Unauthenticated));
+    }
+}
+
+#[tokio::test]
+async fn test_lockout_and_admin_unlock() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let wrong_secret = cp.generate_random();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_rate_limiter(RateLimiter::new(RateLimitConfig {
+            user_limit: RateLimit {
+                burst: 10,
+                refill_interval: Duration::from_secs(60),
+            },
+            ..rate_limit_config()
+        }));
+    let admin = server.admin_server("admin token");
+    register(&server, user, &client_secret).await;
+
+    for _ in 0..2 {
+        assert_eq!(
+            authenticate(&server, user, &wrong_secret)
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+    // the user is locked out, even with the right secret
+    assert_eq!(
+        authenticate(&server, user, &client_secret)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::PermissionDenied
+    );
+
+    let unlock_request = |token: Option<&str>| {
+        let mut request = Request::new(UnlockUserRequest {
+            user: user.to_string(),
+        });
+        if let Some(token) = token {
+            request.metadata_mut().insert(
+                ADMIN_AUTHORIZATION_HEADER,
+                format!("Bearer {token}").parse().unwrap(),
+            );
+        }
+        request
+    };
+    assert_eq!(
+        admin
+            .unlock_user(unlock_request(None))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+    assert_eq!(
+        admin
+            .unlock_user(unlock_request(Some("wrong token")))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::PermissionDenied
+    );
+    assert!(
+        admin
+            .unlock_user(unlock_request(Some("admin token")))
+            .await
+            .unwrap()
+            .into_inner()
+            .was_locked
+    );
+    assert!(authenticate(&server, user, &client_secret).await.is_ok());
+
+    // the lockout also expires on its own
+    for _ in 0..2 {
+        assert!(authenticate(&server, user, &wrong_secret).await.is_err());
+    }
+    clock.advance(30);
+    assert!(authenticate(&server, user, &client_secret).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotate_credential_failures_lock_out_user() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let new_secret = cp.generate_random();
+    let wrong_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(Arc::new(ManualClock::new(NOW)))
+        .with_rate_limiter(RateLimiter::new(RateLimitConfig {
+            user_limit: RateLimit {
+                burst: 10,
+                refill_interval: Duration::from_secs(60),
+            },
+            ..rate_limit_config()
+        }));
+    register(&server, user, &client_secret).await;
+    let session_id = authenticate(&server, user, &client_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+    let rotate = |old_secret| {
+        server.rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            old_secret,
+            &new_secret,
+            &new_secret,
+        )))
+    };
+
+    for _ in 0..2 {
+        assert_eq!(
+            rotate(&wrong_secret).await.unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+    // failed rotations lock the user out of both rotations and authentications
+    assert_eq!(
+        rotate(&client_secret).await.unwrap_err().code(),
+        tonic::Code::PermissionDenied
+    );
+    assert_eq!(
+        authenticate(&server, user, &client_secret)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::PermissionDenied
+    );
+}
+
+/// Wraps an admin request, with the admin token of `admin_server("admin token")`.
+fn admin_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert(
+        ADMIN_AUTHORIZATION_HEADER,
+        "Bearer admin token".parse().unwrap(),
+    );
+    request
+}
+
+#[tokio::test]
+async fn test_admin_manages_users_and_sessions() {
+    check_admin_manages_users_and_sessions(PedersenChaumAuthServer::new()).await;
+    check_admin_manages_users_and_sessions(PedersenChaumAuthServer::with_storage(
+        SqliteStorage::open_in_memory().unwrap(),
+    ))
+    .await;
+}
+
+async fn check_admin_manages_users_and_sessions<S: AuthStorage>(
+    server: PedersenChaumAuthServer<S>,
+) {
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = server.with_clock(Arc::new(ManualClock::new(NOW)));
+    let admin = server.admin_server("admin token");
+    for user in ["user_c", "user_a", "user_b"] {
+        register(&server, user, &client_secret).await;
+    }
+
+    // users are listed by name, a page at a time
+    let mut page_token = String::new();
+    let mut pages = vec![];
+    loop {
+        let response = admin
+            .list_users(admin_request(ListUsersRequest {
+                page_size: 2,
+                page_token,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        pages.push(
+            response
+                .users
+                .into_iter()
+                .map(|user| user.user)
+                .collect::<Vec<_>>(),
+        );
+        if response.next_page_token.is_empty() {
+            break;
+        }
+        page_token = response.next_page_token;
+    }
+    assert_eq!(pages, vec![vec!["user_a", "user_b"], vec!["user_c"]]);
+    assert_eq!(
+        admin
+            .list_users(Request::new(ListUsersRequest::default()))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+
+    // sessions are listed and revoked by fingerprint
+    let session_ids = [
+        authenticate(&server, "user_a", &client_secret)
+            .await
+            .unwrap(),
+        authenticate(&server, "user_a", &client_secret)
+            .await
+            .unwrap(),
+    ]
+    .map(|response| response.into_inner().session_id);
+    let sessions = admin
+        .list_sessions(admin_request(ListSessionsRequest {
+            user: "user_a".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .sessions;
+    assert_eq!(
+        sessions
+            .iter()
+            .map(|session| session.fingerprint.clone())
+            .collect::<Vec<_>>(),
+        session_ids.iter().map(fingerprint).collect::<Vec<_>>()
+    );
+    admin
+        .revoke_session(admin_request(RevokeSessionRequest {
+            user: "user_a".to_string(),
+            fingerprint: fingerprint(&session_ids[0]),
+        }))
+        .await
+        .unwrap();
+    assert_eq!(
+        server.state.get_session(&session_ids[0]).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        admin
+            .revoke_session(admin_request(RevokeSessionRequest {
+                user: "user_b".to_string(),
+                fingerprint: fingerprint(&session_ids[1]),
+            }))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::NotFound
+    );
+
+    // disabled users lose their sessions, and can't authenticate until enabled again
+    let response = admin
+        .disable_user(admin_request(DisableUserRequest {
+            user: "user_a".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.revoked_sessions, 1);
+    assert_eq!(
+        server.state.get_session(&session_ids[1]).await.unwrap(),
+        None
+    );
+    let user_info = admin
+        .get_user(admin_request(GetUserRequest {
+            user: "user_a".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .user
+        .unwrap();
+    assert!(user_info.disabled);
+    assert_eq!(user_info.active_sessions, 0);
+    assert_eq!(
+        authenticate(&server, "user_a", &client_secret)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::PermissionDenied
+    );
+    // nor rotate their credential, having lost their sessions
+    let new_secret = cp.generate_random();
+    assert_eq!(
+        server
+            .rotate_credential(Request::new(key_rotation_request(
+                &session_ids[1],
+                "user_a",
+                &client_secret,
+                &new_secret,
+                &new_secret,
+            )))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+    // whether a user is disabled is only disclosed once it proved its identity
+    assert_eq!(
+        authenticate(&server, "user_a", &cp.generate_random())
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+    admin
+        .enable_user(admin_request(EnableUserRequest {
+            user: "user_a".to_string(),
+        }))
+        .await
+        .unwrap();
+    assert!(authenticate(&server, "user_a", &client_secret)
+        .await
+        .is_ok());
+
+    // deleted users can be registered again
+    let response = admin
+        .delete_user(admin_request(DeleteUserRequest {
+            user: "user_a".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.revoked_sessions, 1);
+    assert_eq!(
+        admin
+            .get_user(admin_request(GetUserRequest {
+                user: "user_a".to_string(),
+            }))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::NotFound
+    );
+    register(&server, "user_a", &cp.generate_random()).await;
+
+    // challenges are purged once expired, or all at once
+    answer_challenge(&server, "user_b", &client_secret)
+        .await
+        .unwrap();
+    let purge = |all| admin.purge_challenges(admin_request(PurgeChallengesRequest { all }));
+    assert_eq!(purge(false).await.unwrap().into_inner().purged, 0);
+    assert_eq!(purge(true).await.unwrap().into_inner().purged, 1);
+    assert!(
+        !admin
+            .get_user(admin_request(GetUserRequest {
+                user: "user_b".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .user
+            .unwrap()
+            .pending_challenge
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_users_are_indistinguishable() {
+    let (user, unknown_user) = ("hello, world", "hello, nobody");
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let wrong_secret = cp.generate_random();
+    for stateless in [false, true] {
+        let mut server = PedersenChaumAuthServer::new()
+            .with_clock(Arc::new(ManualClock::new(NOW)))
+            .with_rate_limiter(RateLimiter::new(rate_limit_config()))
+            .with_enumeration_resistance(DecoyUsers::new(
+                &[5u8; DECOY_SECRET_SIZE],
+                DEFAULT_PARAMS.clone(),
+            ));
+        if stateless {
+            server =
+                server.with_stateless_challenges(ChallengeSealer::new(&[9u8; CHALLENGE_KEY_SIZE]));
+        }
+        register(&server, user, &client_secret).await;
+        // users can only register with the profile of decoy users, which would tell them apart
+        let mut custom_profile_request = register_request("hello, custom", &client_secret);
+        custom_profile_request.salt = vec![7u8; 32];
+        assert_eq!(
+            server
+                .register(Request::new(custom_profile_request))
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::InvalidArgument
+        );
+        let login_parameters = |user: &str| {
+            server.get_login_parameters(Request::new(LoginParametersRequest {
+                user: user.to_string(),
+            }))
+        };
+
+        // unknown users get stable login parameters, shaped like those of registered users
+        let parameters = login_parameters(user).await.unwrap().into_inner();
+        let decoy_parameters = login_parameters(unknown_user).await.unwrap().into_inner();
+        assert_eq!(decoy_parameters.salt.len(), parameters.salt.len());
+        assert_eq!(decoy_parameters.kdf, parameters.kdf);
+        assert_eq!(
+            login_parameters(unknown_user).await.unwrap().into_inner(),
+            decoy_parameters
+        );
+
+        // their challenges look alike, and their answers fail like a wrong password
+        let mut errors = vec![];
+        for user in [user, unknown_user] {
+            let auth_answer_request = answer_challenge(&server, user, &wrong_secret)
+                .await
+                .unwrap();
+            let error = server
+                .verify_authentication(Request::new(auth_answer_request.clone()))
+                .await
+                .unwrap_err();
+            let replay_error = server
+                .verify_authentication(Request::new(auth_answer_request.clone()))
+                .await
+                .unwrap_err();
+            errors.push((
+                error.code(),
+                error.message().to_string(),
+                replay_error.code(),
+            ));
+        }
+        assert_eq!(errors[0], errors[1]);
+        assert_eq!(errors[0].0, tonic::Code::Unauthenticated);
+        assert_eq!(errors[0].2, tonic::Code::Aborted);
+
+        // up to the lockout
+        let mut errors = vec![];
+        for user in [user, unknown_user] {
+            let auth_answer_request = answer_challenge(&server, user, &wrong_secret)
+                .await
+                .unwrap();
+            assert!(server
+                .verify_authentication(Request::new(auth_answer_request))
+                .await
+                .is_err());
+            let error = answer_challenge(&server, user, &client_secret)
+                .await
+                .unwrap_err();
+            errors.push((error.code(), error.message().to_string()));
+        }
+        assert_eq!(errors[0], errors[1]);
+        assert_eq!(errors[0].0, tonic::Code::PermissionDenied);
+
+        // nor can credentials of unknown users be rotated
+        let new_secret = cp.generate_random();
+        let mut errors = vec![];
+        for user in [user, unknown_user] {
+            let error = server
+                .rotate_credential(Request::new(key_rotation_request(
+                    "session_id",
+                    user,
+                    &wrong_secret,
+                    &new_secret,
+                    &new_secret,
+                )))
+                .await
+                .unwrap_err();
+            errors.push((error.code(), error.message().to_string()));
+        }
+        assert_eq!(errors[0], errors[1]);
+
+        // and nothing is stored for them
+        let state = server.state.export();
+        assert!(!state.users.contains_key(unknown_user));
+        assert!(state
+            .challenges
+            .values()
+            .all(|challenge| challenge.user_id != unknown_user));
+    }
+}
+
+#[tokio::test]
+async fn test_session_expiry_and_refresh() {
+    let user = "hello, world";
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_session_ttl(Duration::from_secs(100));
+    let session_id = register_and_authenticate(&server, user).await;
+    let commitment_request = || {
+        Request::new(CredentialCommitmentRequest {
+            session_id: session_id.clone(),
+        })
+    };
+
+    clock.advance(99);
+    let response = server
+        .refresh_session(Request::new(RefreshSessionRequest {
+            session_id: session_id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.expires_at, NOW + 199);
+
+    // the refreshed session outlives its original expiry
+    clock.advance(99);
+    assert!(server
+        .create_credential_commitment(commitment_request())
+        .await
+        .is_ok());
+
+    // expired sessions can neither be used nor refreshed
+    clock.advance(1);
+    assert_eq!(
+        server
+            .create_credential_commitment(commitment_request())
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+    assert!(server
+        .refresh_session(Request::new(RefreshSessionRequest { session_id }))
+        .await
+        .is_err());
+
+    let sweeper = server.spawn_sweeper(Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sweeper.abort();
+    assert_eq!(server.state.export().sessions, HashMap::new());
+}
+
+#[tokio::test]
+async fn test_validate_session() {
+    let user = "hello, world";
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_session_ttl(Duration::from_secs(100))
+        .with_session_scopes(["profile", "payments"]);
+    let session_id = register_and_authenticate(&server, user).await;
+    let validate = |session_id: &str| {
+        server.validate_session(Request::new(ValidateSessionRequest {
+            session_id: session_id.to_string(),
+        }))
+    };
+
+    let response = validate(&session_id).await.unwrap().into_inner();
+    assert_eq!(
+        response.session,
+        Some(SessionInfo {
+            session_id: session_id.clone(),
+            user: user.to_string(),
+            issued_at: NOW,
+            expires_at: NOW + 100,
+            scopes: vec!["profile".to_string(), "payments".to_string()],
+        })
+    );
+
+    assert_eq!(
+        validate("").await.unwrap_err().code(),
+        tonic::Code::InvalidArgument
+    );
+    assert_eq!(
+        validate("unknown session").await.unwrap_err().code(),
+        tonic::Code::NotFound
+    );
+
+    // validating a session does not extend it
+    clock.advance(100);
+    assert_eq!(
+        validate(&session_id).await.unwrap_err().code(),
+        tonic::Code::Unauthenticated
+    );
+}
+
+#[tokio::test]
+async fn test_validate_sessions_in_batch() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_session_ttl(Duration::from_secs(100));
+    register(&server, user, &client_secret).await;
+
+    let expired_session_id = authenticate(&server, user, &client_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+    clock.advance(50);
+    let session_id = authenticate(&server, user, &client_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+    clock.advance(50);
+
+    let session_ids = vec![
+        session_id.clone(),
+        expired_session_id.clone(),
+        "unknown session".to_string(),
+    ];
+    let results = server
+        .validate_sessions(Request::new(ValidateSessionsRequest {
+            session_ids: session_ids.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    // results follow the order of the request
+    assert_eq!(
+        results
+            .iter()
+            .map(|result| result.session_id.clone())
+            .collect::<Vec<_>>(),
+        session_ids
+    );
+    assert_eq!(
+        results[0].result,
+        Some(session_validation::Result::Session(SessionInfo {
+            session_id,
+            user: user.to_string(),
+            issued_at: NOW + 50,
+            expires_at: NOW + 150,
+            scopes: vec![],
+        }))
+    );
+    assert_eq!(
+        results[1].result,
+        Some(session_validation::Result::Error(
+            SessionError::Expired.into()
+        ))
+    );
+    assert_eq!(
+        results[2].result,
+        Some(session_validation::Result::Error(
+            SessionError::NotFound.into()
+        ))
+    );
+
+    let oversized_batch = server
+        .validate_sessions(Request::new(ValidateSessionsRequest {
+            session_ids: vec![expired_session_id; MAX_VALIDATE_SESSIONS_BATCH + 1],
+        }))
+        .await;
+    assert_eq!(
+        oversized_batch.unwrap_err().code(),
+        tonic::Code::InvalidArgument
+    );
+}
+
+#[tokio::test]
+async fn test_session_tokens_verify_offline() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let retired_key = TokenSigningKey::generate();
+    let signing_key = TokenSigningKey::generate();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_session_ttl(Duration::from_secs(100))
+        .with_session_scopes(["profile"])
+        .with_session_tokens(
+            TokenSigningKey::from_bytes(&signing_key.to_bytes()),
+            vec![retired_key.verifying_key()],
+        );
+    register(&server, user, &client_secret).await;
+
+    // a relying service builds its verifier from the published keys, current key first
+    let keys = server
+        .get_session_token_keys(Request::new(SessionTokenKeysRequest {}))
+        .await
+        .unwrap()
+        .into_inner()
+        .keys;
+    assert_eq!(
+        keys.iter()
+            .map(|key| key.key_id.as_str())
+            .collect::<Vec<_>>(),
+        vec![signing_key.key_id(), retired_key.key_id()]
+    );
+    let verifier = keys.iter().fold(TokenVerifier::new(), |verifier, key| {
+        verifier.with_key(TokenVerifyingKey::from_bytes(&key.public_key).unwrap())
+    });
+
+    let response = authenticate(&server, user, &client_secret)
+        .await
+        .unwrap()
+        .into_inner();
+    let claims = SessionClaims {
+        sub: user.to_string(),
+        sid: response.session_id.clone(),
+        iat: NOW,
+        exp: NOW + 100,
+        scopes: vec!["profile".to_string()],
+    };
+    assert_eq!(
+        verifier.verify(&response.session_token, NOW),
+        Ok(claims.clone())
+    );
+
+    // tokens signed before a key rotation remain valid
+    let retired_token = retired_key.sign(&claims);
+    assert!(verifier.verify(&retired_token, NOW).is_ok());
+
+    // refreshing the session issues a token with the new expiry
+    clock.advance(50);
+    let refreshed_token = server
+        .refresh_session(Request::new(RefreshSessionRequest {
+            session_id: response.session_id,
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .session_token;
+    clock.advance(50);
+    assert_eq!(
+        verifier.verify(&response.session_token, clock.now()),
+        Err(TokenError::Expired)
+    );
+    assert_eq!(
+        verifier.verify(&refreshed_token, clock.now()),
+        Ok(SessionClaims {
+            exp: NOW + 150,
+            ..claims
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_logout_and_revoke_all_sessions() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new();
+    register(&server, user, &client_secret).await;
+
+    let mut session_ids = vec![];
+    for _ in 0..3 {
+        session_ids.push(
+            authenticate(&server, user, &client_secret)
+                .await
+                .unwrap()
+                .into_inner()
+                .session_id,
+        );
+    }
+    // every session of the user is tracked
+    assert_eq!(
+        server.state.export().users.get(user).unwrap().session_ids,
+        session_ids
+    );
+
+    server
+        .logout(Request::new(LogoutRequest {
+            session_id: session_ids[0].clone(),
+        }))
+        .await
+        .unwrap();
+    assert!(!server.state.export().sessions.contains_key(&session_ids[0]));
+
+    let response = server
+        .revoke_all_sessions(Request::new(RevokeAllSessionsRequest {
+            session_id: session_ids[1].clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.revoked, 2);
+    assert_eq!(server.state.export().sessions, HashMap::new());
+    assert!(server
+        .state
+        .export()
+        .users
+        .get(user)
+        .unwrap()
+        .session_ids
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_concurrent_sessions_are_limited() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let server = PedersenChaumAuthServer::new().with_max_sessions_per_user(2);
+    register(&server, user, &client_secret).await;
+
+    let mut session_ids = vec![];
+    for _ in 0..3 {
+        session_ids.push(
+            authenticate(&server, user, &client_secret)
+                .await
+                .unwrap()
+                .into_inner()
+                .session_id,
+        );
+    }
+
+    // the oldest session is revoked
+    let state = server.state.export();
+    assert_eq!(state.sessions.len(), 2);
+    assert!(!state.sessions.contains_key(&session_ids[0]));
+    assert_eq!(
+        state.users.get(user).unwrap().session_ids,
+        session_ids[1..].to_vec()
+    );
+}
+
+#[tokio::test]
+async fn test_audit_log_records_authentication_events() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    let path = std::env::temp_dir().join(format!("zkp-audit-{}.jsonl", Uuid::new_v4()));
+    let server = PedersenChaumAuthServer::new()
+        .with_max_sessions_per_user(1)
+        .with_audit_log(Arc::new(AuditLog::open(&path).unwrap()));
+    register(&server, user, &client_secret).await;
+
+    let mut session_ids = vec![];
+    for _ in 0..2 {
+        session_ids.push(
+            authenticate(&server, user, &client_secret)
+                .await
+                .unwrap()
+                .into_inner()
+                .session_id,
+        );
+    }
+    let status = authenticate(&server, user, &cp.generate_random())
+        .await
+        .unwrap_err();
+    server
+        .logout(Request::new(LogoutRequest {
+            session_id: session_ids[1].clone(),
+        }))
+        .await
+        .unwrap();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    // records refer to sessions by fingerprint only
+    assert!(session_ids
+        .iter()
+        .all(|session_id| !log.contains(session_id)));
+    let records = log
+        .lines()
+        .map(|line| serde_json::from_str::<AuditRecord>(line).unwrap())
+        .collect::<Vec<_>>();
+    let session = |index: usize| fingerprint(&session_ids[index]);
+    let user = || user.to_string();
+    let mut events = records.into_iter().map(|record| record.event);
+    assert_eq!(
+        events.next(),
+        Some(AuditEvent::UserRegistered { user: user() })
+    );
+    for index in 0..2 {
+        assert!(matches!(
+            events.next(),
+            Some(AuditEvent::ChallengeCreated { .. })
+        ));
+        assert!(matches!(
+            events.next(),
+            Some(AuditEvent::AuthenticationSucceeded { .. })
+        ));
+        assert!(matches!(
+            events.next(),
+            Some(AuditEvent::SessionCreated { session: created, .. }) if created == session(index)
+        ));
+    }
+    assert_eq!(
+        events.next(),
+        Some(AuditEvent::SessionRevoked {
+            user: user(),
+            session: session(0),
+            cause: RevocationCause::Evicted,
+        })
+    );
+    assert!(matches!(
+        events.next(),
+        Some(AuditEvent::ChallengeCreated { .. })
+    ));
+    assert!(matches!(
+        events.next(),
+        Some(AuditEvent::AuthenticationFailed { user: Some(failed_user), reason, .. })
+            if failed_user == user() && reason == status.message()
+    ));
+    assert_eq!(
+        events.next(),
+        Some(AuditEvent::SessionRevoked {
+            user: user(),
+            session: session(1),
+            cause: RevocationCause::Logout,
+        })
+    );
+    assert_eq!(events.next(), None);
+
+    assert_eq!(verify_audit_log(&path, None).unwrap().records, 11);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_obtain_and_present_credential() {
+    let user = "hello, world";
+    let server = PedersenChaumAuthServer::new();
+    let session_id = register_and_authenticate(&server, user).await;
+
+    let commitment_response = server
+        .create_credential_commitment(Request::new(CredentialCommitmentRequest {
+            session_id: session_id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(server.state.export().pending_credentials.len(), 1);
+
+    let r = BigInt::from_bytes_be(num_bigint::Sign::Plus, &commitment_response.r);
+    let public_key = BigInt::from_bytes_be(num_bigint::Sign::Plus, &commitment_response.public_key);
+    let expiry = commitment_response.credential_expires_at.to_be_bytes();
+    let message = [&expiry[..], b"credential serial number"].concat();
+    let (blinding_factors, c) = blind_signature::blind(&DEFAULT_PARAMS, &public_key, &r, &message);
+
+    let blinded_credential_response = server
+        .sign_blinded_credential(Request::new(BlindedCredentialRequest {
+            session_id: session_id.clone(),
+            credential_id: commitment_response.credential_id.clone(),
+            c: c.to_bytes_be().1,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(server.state.export().pending_credentials, HashMap::new());
+
+    // the signer nonce can't be reused for a second blinded challenge
+    assert!(server
+        .sign_blinded_credential(Request::new(BlindedCredentialRequest {
+            session_id,
+            credential_id: commitment_response.credential_id,
+            c: c.to_bytes_be().1,
+        }))
+        .await
+        .is_err());
+
+    let s = BigInt::from_bytes_be(num_bigint::Sign::Plus, &blinded_credential_response.s);
+    let signature = blind_signature::unblind(&DEFAULT_PARAMS, blinding_factors, &s);
+
+    let presentation_request = CredentialPresentationRequest {
+        message: message.clone(),
+        r: signature.r.to_bytes_be().1,
+        s: signature.s.to_bytes_be().1,
+    };
+    assert!(server
+        .present_credential(Request::new(presentation_request.clone()))
+        .await
+        .is_ok());
+
+    // credentials can only be presented once
+    assert!(server
+        .present_credential(Request::new(presentation_request))
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("credential has already been presented"));
+
+    // forged credentials are rejected
+    let forged_presentation_request = CredentialPresentationRequest {
+        message: [&expiry[..], b"another serial number"].concat(),
+        r: signature.r.to_bytes_be().1,
+        s: signature.s.to_bytes_be().1,
+    };
+    assert!(server
+        .present_credential(Request::new(forged_presentation_request))
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("Failed to verify blind signature"));
+}
+
+#[tokio::test]
+async fn test_credential_expiry_is_checked() {
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_credential_ttl(Duration::from_secs(100));
+    let session_id = register_and_authenticate(&server, "user").await;
+
+    let commitment_response = server
+        .create_credential_commitment(Request::new(CredentialCommitmentRequest { session_id }))
+        .await
+        .unwrap()
+        .into_inner();
+    // expiries are aligned on the credential ttl, so they don't tell apart credentials of the same period
+    assert_eq!(
+        commitment_response.credential_expires_at,
+        (NOW / 100 + 2) * 100
+    );
+
+    let present = |message: Vec<u8>| {
+        server.present_credential(Request::new(CredentialPresentationRequest {
+            message,
+            r: vec![1],
+            s: vec![1],
+        }))
+    };
+    let message_expiring_at =
+        |expires_at: Timestamp| [&expires_at.to_be_bytes()[..], b"serial"].concat();
+
+    assert_eq!(
+        present(NOW.to_be_bytes().to_vec())
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::InvalidArgument
+    );
+    assert_eq!(
+        present(message_expiring_at(
+            commitment_response.credential_expires_at + 1
+        ))
+        .await
+        .unwrap_err()
+        .code(),
+        tonic::Code::InvalidArgument
+    );
+    // a credential of the current period gets to the signature check
+    assert_eq!(
+        present(message_expiring_at(
+            commitment_response.credential_expires_at
+        ))
+        .await
+        .unwrap_err()
+        .code(),
+        tonic::Code::Unauthenticated
+    );
+
+    clock.advance(commitment_response.credential_expires_at - NOW);
+    assert_eq!(
+        present(message_expiring_at(
+            commitment_response.credential_expires_at
+        ))
+        .await
+        .unwrap_err()
+        .code(),
+        tonic::Code::DeadlineExceeded
+    );
+}
+
+#[tokio::test]
+async fn test_open_credential_commitments_are_capped() {
+    let server = PedersenChaumAuthServer::new().with_max_credential_commitments(1);
+    let first_session_id = register_and_authenticate(&server, "first user").await;
+    let second_session_id = register_and_authenticate(&server, "second user").await;
+    let create_commitment = |session_id: &String| {
+        server.create_credential_commitment(Request::new(CredentialCommitmentRequest {
+            session_id: session_id.clone(),
+        }))
+    };
+
+    create_commitment(&first_session_id).await.unwrap();
+    assert_eq!(
+        create_commitment(&second_session_id)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::ResourceExhausted
+    );
+    // a session can still replace its own commitment
+    create_commitment(&first_session_id).await.unwrap();
+    assert_eq!(server.state.export().pending_credentials.len(), 1);
+}
+
+#[tokio::test]
+async fn test_credential_commitments_are_bounded_and_expire() {
+    let clock = Arc::new(ManualClock::new(NOW));
+    let server = PedersenChaumAuthServer::new()
+        .with_clock(clock.clone())
+        .with_challenge_ttl(Duration::from_secs(60));
+    let session_id = register_and_authenticate(&server, "user").await;
+    let create_commitment = || async {
+        server
+            .create_credential_commitment(Request::new(CredentialCommitmentRequest {
+                session_id: session_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    };
+    let sign = |credential_id: String| {
+        server.sign_blinded_credential(Request::new(BlindedCredentialRequest {
+            session_id: session_id.clone(),
+            credential_id,
+            c: vec![1],
+        }))
+    };
+
+    // a new commitment replaces the pending one, so signing sessions can't run concurrently
+    let first_commitment = create_commitment().await;
+    let second_commitment = create_commitment().await;
+    assert_eq!(server.state.export().pending_credentials.len(), 1);
+    assert_eq!(
+        sign(first_commitment.credential_id)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::NotFound
+    );
+
+    clock.advance(60);
+    assert_eq!(
+        sign(second_commitment.credential_id)
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::DeadlineExceeded
+    );
+}
+
+#[tokio::test]
+async fn test_create_credential_commitment_fails_without_session() {
+    let server = PedersenChaumAuthServer::new();
+
+    let result = server
+        .create_credential_commitment(Request::new(CredentialCommitmentRequest {
+            session_id: "sdfa837djf".to_string(),
+        }))
+        .await;
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("user must authenticate first"));
+}
+
+#[tokio::test]
+async fn test_authenticate_with_sqlite_storage() {
+    let server = PedersenChaumAuthServer::with_storage(SqliteStorage::open_in_memory().unwrap());
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+
+    register(&server, "user_name", &client_secret).await;
+    let session_id = authenticate(&server, "user_name", &client_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    assert_eq!(
+        server
+            .state
+            .get_session(&session_id)
+            .await
+            .unwrap()
+            .map(|session| session.user_id),
+        Some("user_name".to_string())
+    );
+    assert_eq!(
+        server
+            .state
+            .get_user("user_name")
+            .await
+            .unwrap()
+            .unwrap()
+            .session_ids,
+        vec![session_id]
+    );
+
+    let wrong_secret = cp.generate_random();
+    assert!(authenticate(&server, "user_name", &wrong_secret)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_rotate_credential() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+
+    let old_secret = cp.generate_random();
+    let new_secret = cp.generate_random();
+    register(&server, user, &old_secret).await;
+    let mut session_ids = vec![];
+    for _ in 0..2 {
+        let session_id = authenticate(&server, user, &old_secret)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_id;
+        session_ids.push(session_id);
+    }
+
+    let result = server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_ids[0],
+            user,
+            &old_secret,
+            &new_secret,
+            &new_secret,
+        )))
+        .await;
+    assert!(result.is_ok());
+
+    let exponents = cp.commit(&new_secret);
+    let (y1, y2) = (
+        exponents.get_first_exponent(),
+        exponents.get_second_exponent(),
+    );
+    {
+        let state = server.state.export();
+        let user_data = state.users.get(user).unwrap();
+        assert_eq!(&user_data.y1, y1);
+        assert_eq!(&user_data.y2, y2);
+        // the account history is kept, but for the session consumed by the rotation
+        assert_eq!(user_data.session_ids, vec![session_ids[1].clone()]);
+    }
+
+    assert!(authenticate(&server, user, &new_secret).await.is_ok());
+    assert!(authenticate(&server, user, &old_secret).await.is_err());
+}
+
+#[tokio::test]
+async fn test_rotate_credential_requires_session_of_user() {
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+    let old_secret = cp.generate_random();
+    let new_secret = cp.generate_random();
+    register(&server, "user", &old_secret).await;
+    let other_session_id = register_and_authenticate(&server, "other user").await;
+
+    // without a session, a rotation proof can't be used to guess the password
+    for session_id in ["", "unknown session", other_session_id.as_str()] {
+        assert!(server
+            .rotate_credential(Request::new(key_rotation_request(
+                session_id,
+                "user",
+                &old_secret,
+                &new_secret,
+                &new_secret,
+            )))
+            .await
+            .is_err());
+    }
+    assert!(authenticate(&server, "user", &old_secret).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotate_credential_can_not_be_replayed() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+    let secret_a = cp.generate_random();
+    let secret_b = cp.generate_random();
+    register(&server, user, &secret_a).await;
+    let session_id = authenticate(&server, user, &secret_a)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    let a_to_b = key_rotation_request(&session_id, user, &secret_a, &secret_b, &secret_b);
+    server
+        .rotate_credential(Request::new(a_to_b.clone()))
+        .await
+        .unwrap();
+    let session_id = authenticate(&server, user, &secret_b)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+    server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            &secret_b,
+            &secret_a,
+            &secret_a,
+        )))
+        .await
+        .unwrap();
+
+    // back on the old key, the first rotation can't be replayed, as its session was consumed
+    assert_eq!(
+        server
+            .rotate_credential(Request::new(a_to_b))
+            .await
+            .unwrap_err()
+            .code(),
+        tonic::Code::Unauthenticated
+    );
+    assert!(authenticate(&server, user, &secret_a).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotate_credential_to_trivial_key_is_rejected() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+    let old_secret = cp.generate_random();
+    register(&server, user, &old_secret).await;
+    let session_id = authenticate(&server, user, &old_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    for trivial in [0u8, 1] {
+        // with the key (0, 0), any answer to the commitments (0, 0) would verify
+        let mut request =
+            key_rotation_request(&session_id, user, &old_secret, &old_secret, &old_secret);
+        request.new_y1 = vec![trivial];
+        request.new_y2 = vec![trivial];
+        let error = server
+            .rotate_credential(Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::Unauthenticated);
+        assert!(error.message().contains("not in the subgroup"));
+    }
+    assert!(authenticate(&server, user, &old_secret).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_rotate_credential_fails_with_invalid_proof() {
+    let user = "hello, world";
+    let cp = ChaumPedersen::default();
+    let server = PedersenChaumAuthServer::new();
+
+    let old_secret = cp.generate_random();
+    let new_secret = cp.generate_random();
+    register(&server, user, &old_secret).await;
+    let session_id = authenticate(&server, user, &old_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    // the prover doesn't know the old secret
+    let wrong_secret = cp.generate_random();
+    let result = server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            &wrong_secret,
+            &new_secret,
+            &new_secret,
+        )))
+        .await;
+    assert!(result.is_err());
+
+    // the prover doesn't know the new secret
+    let result = server
+        .rotate_credential(Request::new(key_rotation_request(
+            &session_id,
+            user,
+            &old_secret,
+            &new_secret,
+            &wrong_secret,
+        )))
+        .await;
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Failed to verify challenge"));
+
+    let exponents = cp.commit(&old_secret);
+    let (y1, y2) = (
+        exponents.get_first_exponent(),
+        exponents.get_second_exponent(),
+    );
+    let state = server.state.export();
+    let user_data = state.users.get(user).unwrap();
+    assert_eq!(&user_data.y1, y1);
+    assert_eq!(&user_data.y2, y2);
+}
+
+async fn register_and_authenticate<S: AuthStorage>(
+    server: &PedersenChaumAuthServer<S>,
+    user: &str,
+) -> String {
+    let cp = ChaumPedersen::default();
+    let client_secret = cp.generate_random();
+    register(server, user, &client_secret).await;
+    authenticate(server, user, &client_secret)
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id
+}
+
+async fn register<S: AuthStorage>(
+    server: &PedersenChaumAuthServer<S>,
+    user: &str,
+    client_secret: &BigInt,
+) {
+    server
+        .register(Request::new(register_request(user, client_secret)))
+        .await
+        .unwrap();
+}
+
+fn register_request(user: &str, client_secret: &BigInt) -> RegisterRequest {
+    let cp = ChaumPedersen::default();
+    let exponents = cp.commit(client_secret);
+    let (y1, y2) = (
+        exponents.get_first_exponent(),
+        exponents.get_second_exponent(),
+    );
+    let mut transcript =
+        registration::registration_transcript(&cp, DEFAULT_DOMAIN.as_bytes(), user, y1, y2);
+    let proof = registration::prove_registration(&cp, &mut transcript, client_secret);
+
+    RegisterRequest {
+        user: user.to_string(),
+        y1: y1.to_bytes_be().1,
+        y2: y2.to_bytes_be().1,
+        salt: salt(),
+        kdf: Some(KdfParameters::default().into()),
+        proof: Some(crate::server_auth::NonInteractiveProof {
+            r1: proof.r1.to_bytes_be().1,
+            r2: proof.r2.to_bytes_be().1,
+            s: proof.s.to_bytes_be().1,
+        }),
+    }
+}
+
+async fn authenticate<S: AuthStorage>(
+    server: &PedersenChaumAuthServer<S>,
+    user: &str,
+    client_secret: &BigInt,
+) -> Result<Response<AuthenticationAnswerResponse>, Status> {
+    let answer = answer_challenge(server, user, client_secret).await?;
+    server.verify_authentication(Request::new(answer)).await
+}
+
+/// Requests an authentication challenge and solves it, without submitting the answer.
+async fn answer_challenge<S: AuthStorage>(
+    server: &PedersenChaumAuthServer<S>,
+    user: &str,
+    client_secret: &BigInt,
+) -> Result<AuthenticationAnswerRequest, Status> {
+    let cp = ChaumPedersen::default();
+    let k = cp.generate_random();
+    let commitment = cp.commit(&k);
+    let (r1, r2) = (
+        commitment.get_first_exponent(),
+        commitment.get_second_exponent(),
+    );
+
+    let AuthenticationChallengeResponse { auth_id, c } = server
+        .create_authentication_challenge(Request::new(AuthenticationChallengeRequest {
+            user: user.to_string(),
+            r1: r1.to_bytes_be().1,
+            r2: r2.to_bytes_be().1,
+        }))
+        .await?
+        .into_inner();
+    let c = BigInt::from_bytes_be(num_bigint::Sign::Plus, &c);
+
+    let s = get_solution_to_challenge(&cp, client_secret.clone(), k, c);
+
+    Ok(AuthenticationAnswerRequest {
+        auth_id,
+        s: s.to_bytes_be().1,
+    })
+}
+
+fn key_rotation_request(
+    session_id: &str,
+    user: &str,
+    old_x: &BigInt,
+    new_x: &BigInt,
+    proof_new_x: &BigInt,
+) -> RotateCredentialRequest {
+    let cp = ChaumPedersen::default();
+    let old_exponents = cp.commit(old_x);
+    let new_exponents = cp.commit(new_x);
+    let keys = KeyRotation {
+        old_y1: old_exponents.get_first_exponent(),
+        old_y2: old_exponents.get_second_exponent(),
+        new_y1: new_exponents.get_first_exponent(),
+        new_y2: new_exponents.get_second_exponent(),
+    };
+    let mut transcript = key_rotation::key_rotation_transcript(
+        &cp,
+        DEFAULT_DOMAIN.as_bytes(),
+        user,
+        session_id.as_bytes(),
+        &keys,
+    );
+    let proof = key_rotation::prove_key_rotation(&cp, &mut transcript, old_x, proof_new_x);
+
+    RotateCredentialRequest {
+        user: user.to_string(),
+        new_y1: keys.new_y1.to_bytes_be().1,
+        new_y2: keys.new_y2.to_bytes_be().1,
+        proof: Some(crate::server_auth::KeyRotationProof {
+            old_r1: proof.old_r1.to_bytes_be().1,
+            old_r2: proof.old_r2.to_bytes_be().1,
+            new_r1: proof.new_r1.to_bytes_be().1,
+            new_r2: proof.new_r2.to_bytes_be().1,
+            old_s: proof.old_s.to_bytes_be().1,
+            new_s: proof.new_s.to_bytes_be().1,
+        }),
+        session_id: session_id.to_string(),
+    }
+}
+
+/// Starting time of the tests using a `ManualClock`.
+const NOW: Timestamp = 1_700_000_000;
+
+fn salt() -> Vec<u8> {
+    vec![7u8; 16]
 }
 
 struct TestChaumPedersenClientValues {
@@ -0,0 +1,30 @@
+//! # TLS
+//!
+//! Session ids and tokens are bearer credentials, so the server should only be reached over TLS outside
+//! of local development. The server presents a PEM certificate chain and private key and, for mutual
+//! TLS, only accepts clients presenting a certificate issued by a given CA.
+use std::{fs, io, path::Path};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Loads the TLS configuration of the server.
+///
+/// # Arguments
+/// * `cert_path`: Path to the PEM certificate chain of the server.
+/// * `key_path`: Path to the PEM private key of the server.
+/// * `client_ca_path`: Path to the PEM certificates of the CA issuing client certificates, if clients
+///   must authenticate with mutual TLS.
+///
+/// # Returns
+/// A `Result` containing the `ServerTlsConfig`, or an `io::Error` if a file can't be read.
+pub fn server_tls_config<P: AsRef<Path>>(
+    cert_path: P,
+    key_path: P,
+    client_ca_path: Option<P>,
+) -> io::Result<ServerTlsConfig> {
+    let identity = Identity::from_pem(fs::read(cert_path)?, fs::read(key_path)?);
+    let mut tls_config = ServerTlsConfig::new().identity(identity);
+    if let Some(client_ca_path) = client_ca_path {
+        tls_config = tls_config.client_ca_root(Certificate::from_pem(fs::read(client_ca_path)?));
+    }
+    Ok(tls_config)
+}
@@ -1,12 +1,82 @@
 use num_bigint::BigInt;
+use tonic::Status;
+
+use crate::{clock::Timestamp, server_auth};
+
+/// Minimum salt length, in bytes.
+pub const MIN_SALT_LEN: usize = 16;
+/// Maximum salt length, in bytes.
+pub const MAX_SALT_LEN: usize = 64;
+/// Minimum Argon2id memory cost, in KiB.
+pub const MIN_KDF_MEMORY_KIB: u32 = 8 * 1024;
+/// Maximum Argon2id memory cost, in KiB, so that clients can't be made to allocate unbounded memory.
+pub const MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+/// Maximum Argon2id number of iterations.
+pub const MAX_KDF_ITERATIONS: u32 = 64;
+/// Maximum Argon2id degree of parallelism.
+pub const MAX_KDF_PARALLELISM: u32 = 16;
+
+/// Argon2id cost parameters used by a user's client to derive its secret from its password.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KdfParameters {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParameters {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl TryFrom<server_auth::KdfParameters> for KdfParameters {
+    type Error = Status;
+
+    fn try_from(kdf: server_auth::KdfParameters) -> Result<Self, Self::Error> {
+        if !(1..=MAX_KDF_PARALLELISM).contains(&kdf.parallelism)
+            || !(1..=MAX_KDF_ITERATIONS).contains(&kdf.iterations)
+            || !(MIN_KDF_MEMORY_KIB..=MAX_KDF_MEMORY_KIB).contains(&kdf.memory_kib)
+            || kdf.memory_kib < 8 * kdf.parallelism
+        {
+            return Err(Status::invalid_argument(
+                "Invalid key derivation parameters, costs out of bounds",
+            ));
+        }
+        Ok(Self {
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+        })
+    }
+}
+
+impl From<KdfParameters> for server_auth::KdfParameters {
+    fn from(kdf: KdfParameters) -> Self {
+        Self {
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct User {
     pub id: String,
     pub y1: BigInt,
     pub y2: BigInt,
+    pub salt: Vec<u8>,
+    pub kdf_parameters: KdfParameters,
     pub auth_id: Option<String>,
-    pub session_id: Option<String>,
+    /// Identifiers of the user's active sessions, from the oldest to the most recent.
+    pub session_ids: Vec<String>,
+    /// Whether an administrator disabled the user, which then can't authenticate.
+    pub disabled: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -16,10 +86,43 @@ pub struct Challenge {
     pub r1: BigInt,
     pub r2: BigInt,
     pub user_id: String,
+    /// Unix timestamp, in seconds, after which the challenge can no longer be answered.
+    pub expires_at: Timestamp,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Session {
     pub id: String,
     pub user_id: String,
+    /// Unix timestamp, in seconds, at which the session was created.
+    pub issued_at: Timestamp,
+    /// Unix timestamp, in seconds, after which the session is no longer valid.
+    pub expires_at: Timestamp,
+    /// Scopes granted to the session, reported to relying services.
+    pub scopes: Vec<String>,
+    /// Identifier of the session's pending credential commitment, if any, removed together with the
+    /// session.
+    pub credential_id: Option<String>,
+}
+
+impl From<Session> for server_auth::SessionInfo {
+    fn from(session: Session) -> Self {
+        Self {
+            session_id: session.id,
+            user: session.user_id,
+            issued_at: session.issued_at,
+            expires_at: session.expires_at,
+            scopes: session.scopes,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingCredential {
+    pub id: String,
+    pub session_id: String,
+    pub k: BigInt,
+    pub r: BigInt,
+    /// Unix timestamp, in seconds, after which the commitment can no longer be signed.
+    pub expires_at: Timestamp,
 }
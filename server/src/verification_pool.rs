@@ -0,0 +1,209 @@
+//! # Verification Pool
+//!
+//! Proof verifications are dominated by big integer modular exponentiations, which would block the
+//! Tokio threads serving every other request, more so with larger parameter sets. They run instead on
+//! a dedicated pool of worker threads, fed through a bounded queue. Once the queue is full, further
+//! verifications are rejected with `RESOURCE_EXHAUSTED` rather than queued, so that an overloaded
+//! server sheds load instead of answering every request late.
+use std::{
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tokio::sync::oneshot;
+use tonic::Status;
+
+use crate::metrics::Metrics;
+
+/// Default number of verifications waiting for a worker, beyond which verifications are rejected.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed pool of worker threads running proof verifications, off the Tokio threads.
+///
+/// The workers stop once the pool is dropped and the queued verifications are done.
+pub struct VerificationPool {
+    sender: SyncSender<Job>,
+    /// Number of verifications waiting for a worker
+    queue_depth: Arc<AtomicUsize>,
+}
+
+impl VerificationPool {
+    /// Starts a pool of `workers` threads, queuing at most `queue_capacity` verifications.
+    ///
+    /// # Panics
+    /// If a worker thread can't be spawned.
+    pub fn new(workers: usize, queue_capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("verifier-{index}"))
+                .spawn(move || work(&receiver))
+                .expect("Failed to spawn verification worker");
+        }
+        Self {
+            sender,
+            queue_depth: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of verifications waiting for a worker.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::SeqCst)
+    }
+
+    /// Runs the verification `f` on a worker, recording the queue depth if metrics are enabled.
+    ///
+    /// # Returns
+    /// A `Result` containing the result of `f`, or a `RESOURCE_EXHAUSTED` `Status` if the queue is
+    /// full.
+    pub async fn run<T, F>(&self, metrics: Option<Arc<Metrics>>, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let queue_depth = self.queue_depth.clone();
+        let job_metrics = metrics.clone();
+        let job: Job = Box::new(move || {
+            queue_depth.fetch_sub(1, Ordering::SeqCst);
+            if let Some(metrics) = &job_metrics {
+                metrics.record_verification_dequeued();
+            }
+            // a panicking verification fails its request only, not the worker
+            if let Ok(result) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                // the request may have been cancelled meanwhile
+                let _ = result_sender.send(result);
+            }
+        });
+
+        self.queue_depth.fetch_add(1, Ordering::SeqCst);
+        if let Some(metrics) = &metrics {
+            metrics.record_verification_queued();
+        }
+        if let Err(e) = self.sender.try_send(job) {
+            self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+            if let Some(metrics) = &metrics {
+                metrics.record_verification_dequeued();
+            }
+            return Err(match e {
+                TrySendError::Full(_) => Status::resource_exhausted(
+                    "Failed to verify proof, the server is overloaded, retry later",
+                ),
+                TrySendError::Disconnected(_) => {
+                    Status::unavailable("Failed to verify proof, verification workers have stopped")
+                }
+            });
+        }
+        result_receiver
+            .await
+            .map_err(|_| Status::internal("Failed to verify proof, verification has panicked"))
+    }
+}
+
+impl Default for VerificationPool {
+    /// Starts a pool of one worker per available CPU, queuing at most `DEFAULT_QUEUE_CAPACITY`
+    /// verifications.
+    fn default() -> Self {
+        Self::new(default_workers(), DEFAULT_QUEUE_CAPACITY)
+    }
+}
+
+/// Default number of workers: one per available CPU.
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Runs queued verifications until the pool is dropped.
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // the lock is only held while waiting for the next job, not while running it
+        let job = receiver.lock().expect("Verification queue poisoned").recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn test_run_verification() {
+        let pool = VerificationPool::new(2, 4);
+        assert_eq!(pool.run(None, || 6 * 7).await.unwrap(), 42);
+        assert_eq!(
+            pool.run(None, || -> u32 { panic!("invalid proof") })
+                .await
+                .unwrap_err()
+                .code(),
+            Code::Internal
+        );
+        // the worker survived the panic
+        assert_eq!(pool.run(None, || 6 * 7).await.unwrap(), 42);
+        assert_eq!(pool.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_saturated_pool_rejects_verifications() {
+        let pool = Arc::new(VerificationPool::new(1, 2));
+        let metrics = Arc::new(Metrics::new());
+
+        // the worker is held busy, so that further verifications queue up
+        let barrier = Arc::new(Barrier::new(2));
+        let (started_sender, started) = oneshot::channel();
+        let busy = {
+            let (pool, barrier) = (pool.clone(), barrier.clone());
+            tokio::spawn(async move {
+                pool.run(None, move || {
+                    started_sender.send(()).unwrap();
+                    barrier.wait();
+                })
+                .await
+            })
+        };
+        started.await.unwrap();
+
+        let queued = (0..2)
+            .map(|index| {
+                let (pool, metrics) = (pool.clone(), metrics.clone());
+                tokio::spawn(async move { pool.run(Some(metrics), move || index).await })
+            })
+            .collect::<Vec<_>>();
+        while pool.queue_depth() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert!(metrics
+            .encode()
+            .contains("zkp_auth_verification_queue_depth 2"));
+        assert_eq!(
+            pool.run(Some(metrics.clone()), || 2)
+                .await
+                .unwrap_err()
+                .code(),
+            Code::ResourceExhausted
+        );
+
+        barrier.wait();
+        busy.await.unwrap().unwrap();
+        for (index, queued) in queued.into_iter().enumerate() {
+            assert_eq!(queued.await.unwrap().unwrap(), index);
+        }
+        assert_eq!(pool.queue_depth(), 0);
+        assert!(metrics
+            .encode()
+            .contains("zkp_auth_verification_queue_depth 0"));
+    }
+}
@@ -0,0 +1,168 @@
+use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
+use client::{
+    client::{AuthZKPClient, ChaumPedersenAuthClient},
+    generate_salt, KdfParameters,
+};
+use num_bigint::{BigInt, Sign};
+use server::{
+    server::PedersenChaumAuthServer,
+    server_auth::{
+        auth_client::AuthClient, auth_server::AuthServer, AuthenticationAnswerRequest,
+        AuthenticationChallengeRequest,
+    },
+};
+use std::collections::HashSet;
+use tokio::{net::TcpListener, task::JoinSet};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Code};
+
+const CLIENTS: usize = 32;
+const MAX_SESSIONS: usize = 3;
+
+/// Serves a fresh in-memory server on a local port, returning its address.
+async fn serve() -> String {
+    let service = PedersenChaumAuthServer::new().with_max_sessions_per_user(MAX_SESSIONS);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(AuthServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    server_addr
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_parallel_clients() {
+    let server_addr = serve().await;
+
+    // every client registers and authenticates its own user a few times, all at once
+    let mut clients = JoinSet::new();
+    for index in 0..CLIENTS {
+        let server_addr = server_addr.clone();
+        clients.spawn(async move {
+            let mut client = ChaumPedersenAuthClient::new(&server_addr).await.unwrap();
+            let user = format!("user_{index}");
+            let x = ChaumPedersen::default().generate_random();
+            client
+                .register_user(&user, &x, &generate_salt(), &KdfParameters::default())
+                .await
+                .unwrap();
+            let mut session_ids = vec![];
+            for _ in 0..MAX_SESSIONS + 1 {
+                session_ids.push(client.authenticate_user(&user, &x).await.unwrap());
+            }
+            session_ids
+        });
+    }
+    let mut session_ids = HashSet::new();
+    while let Some(client_session_ids) = clients.join_next().await {
+        session_ids.extend(client_session_ids.unwrap());
+    }
+    assert_eq!(session_ids.len(), CLIENTS * (MAX_SESSIONS + 1));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_answers_yield_a_single_session() {
+    let server_addr = serve().await;
+    let cp = ChaumPedersen::default();
+    let user = "user";
+    let x = cp.generate_random();
+    ChaumPedersenAuthClient::new(&server_addr)
+        .await
+        .unwrap()
+        .register_user(user, &x, &generate_salt(), &KdfParameters::default())
+        .await
+        .unwrap();
+
+    for _ in 0..10 {
+        let mut client = AuthClient::connect(server_addr.clone()).await.unwrap();
+        let k = cp.generate_random();
+        let commitment = cp.commit(&k);
+        let challenge = client
+            .create_authentication_challenge(AuthenticationChallengeRequest {
+                user: user.to_string(),
+                r1: commitment.get_first_exponent().to_bytes_be().1,
+                r2: commitment.get_second_exponent().to_bytes_be().1,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let c = BigInt::from_bytes_be(Sign::Plus, &challenge.c);
+        let s = cp.solve_challenge(&x, &k, &c).to_bytes_be().1;
+
+        // the same valid answer is submitted by many clients at once
+        let mut answers = JoinSet::new();
+        for _ in 0..CLIENTS {
+            let mut client = client.clone();
+            let answer = AuthenticationAnswerRequest {
+                auth_id: challenge.auth_id.clone(),
+                s: s.clone(),
+            };
+            answers.spawn(async move { client.verify_authentication(answer).await });
+        }
+        let mut sessions = 0;
+        while let Some(answer) = answers.join_next().await {
+            if answer.unwrap().is_ok() {
+                sessions += 1;
+            }
+        }
+        assert_eq!(sessions, 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_answers_are_verified_once() {
+    let server_addr = serve().await;
+    let cp = ChaumPedersen::default();
+    let user = "user";
+    let x = cp.generate_random();
+    ChaumPedersenAuthClient::new(&server_addr)
+        .await
+        .unwrap()
+        .register_user(user, &x, &generate_salt(), &KdfParameters::default())
+        .await
+        .unwrap();
+
+    for _ in 0..10 {
+        let mut client = AuthClient::connect(server_addr.clone()).await.unwrap();
+        let k = cp.generate_random();
+        let commitment = cp.commit(&k);
+        let challenge = client
+            .create_authentication_challenge(AuthenticationChallengeRequest {
+                user: user.to_string(),
+                r1: commitment.get_first_exponent().to_bytes_be().1,
+                r2: commitment.get_second_exponent().to_bytes_be().1,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let c = BigInt::from_bytes_be(Sign::Plus, &challenge.c);
+        let s = cp.solve_challenge(&x, &k, &c);
+
+        // many wrong answers race a single right one
+        let mut answers = JoinSet::new();
+        for index in 0..=CLIENTS {
+            let mut client = client.clone();
+            let s = match index {
+                0 => s.clone(),
+                _ => &s + index,
+            };
+            let answer = AuthenticationAnswerRequest {
+                auth_id: challenge.auth_id.clone(),
+                s: s.to_bytes_be().1,
+            };
+            answers.spawn(async move { client.verify_authentication(answer).await });
+        }
+        // an answer is only rejected as wrong, or accepted, once verified
+        let mut verifications = 0;
+        while let Some(answer) = answers.join_next().await {
+            match answer.unwrap() {
+                Ok(_) => verifications += 1,
+                Err(status) if status.code() == Code::Unauthenticated => verifications += 1,
+                Err(status) => assert_eq!(status.code(), Code::Aborted),
+            }
+        }
+        assert_eq!(verifications, 1);
+    }
+}
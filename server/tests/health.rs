@@ -0,0 +1,129 @@
+use rusqlite::Connection;
+use server::{
+    server::PedersenChaumAuthServer,
+    server_auth::{self, auth_server::AuthServer},
+    sqlite::SqliteStorage,
+};
+use std::{path::Path, time::Duration};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic_health::pb::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+use tonic_reflection::pb::{
+    server_reflection_client::ServerReflectionClient, server_reflection_request::MessageRequest,
+    server_reflection_response::MessageResponse, ServerReflectionRequest,
+};
+use uuid::Uuid;
+
+/// Serves a server backed by the SQLite database at `path` on a local port, with the health and
+/// reflection services, returning its address.
+async fn serve(path: &Path) -> String {
+    let service = PedersenChaumAuthServer::with_storage(SqliteStorage::open(path).unwrap());
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    service.spawn_health_reporter(health_reporter, Duration::from_millis(50));
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(server_auth::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(AuthServer::new(service))
+            .add_service(health_service)
+            .add_service(reflection_service)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    // let the health reporter probe the storage backend
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    server_addr
+}
+
+async fn connect(server_addr: String) -> Channel {
+    Channel::from_shared(server_addr)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap()
+}
+
+async fn health(client: &mut HealthClient<Channel>, service: &str) -> ServingStatus {
+    let response = client
+        .check(HealthCheckRequest {
+            service: service.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    ServingStatus::try_from(response.status).unwrap()
+}
+
+#[tokio::test]
+async fn test_health_reflects_storage_readiness() {
+    let path = std::env::temp_dir().join(format!("zkp-auth-{}.sqlite", Uuid::new_v4()));
+    let server_addr = serve(&path).await;
+    let mut client = HealthClient::new(connect(server_addr).await);
+    assert_eq!(health(&mut client, "").await, ServingStatus::Serving);
+    assert_eq!(
+        health(&mut client, "zkp_auth.Auth").await,
+        ServingStatus::Serving
+    );
+
+    // break the database behind the server's back
+    Connection::open(&path)
+        .unwrap()
+        .execute_batch("DROP TABLE spent_credentials")
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(health(&mut client, "").await, ServingStatus::NotServing);
+    assert_eq!(
+        health(&mut client, "zkp_auth.Auth").await,
+        ServingStatus::NotServing
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_reflection_lists_services() {
+    let path = std::env::temp_dir().join(format!("zkp-auth-{}.sqlite", Uuid::new_v4()));
+    let server_addr = serve(&path).await;
+    let mut client = ServerReflectionClient::new(connect(server_addr).await);
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let response = client
+        .server_reflection_info(tokio_stream::iter([request]))
+        .await
+        .unwrap()
+        .into_inner()
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    let Some(MessageResponse::ListServicesResponse(services)) = response.message_response else {
+        panic!("unexpected reflection response: {response:?}");
+    };
+    let services = services
+        .service
+        .into_iter()
+        .map(|service| service.name)
+        .collect::<Vec<_>>();
+    for expected in [
+        "zkp_auth.Auth",
+        "grpc.health.v1.Health",
+        "grpc.reflection.v1alpha.ServerReflection",
+    ] {
+        assert!(
+            services.iter().any(|service| service == expected),
+            "missing `{expected}` in {services:?}"
+        );
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,168 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
+use client::{
+    client::{AuthZKPClient, ChaumPedersenAuthClient},
+    client_auth::{
+        auth_client::AuthClient, AuthenticationAnswerRequest, AuthenticationChallengeRequest,
+    },
+    generate_salt, KdfParameters,
+};
+use num_bigint::BigInt;
+use server::{
+    logging::{self, LogFormat, RequestTracingLayer, REQUEST_ID_HEADER},
+    server::PedersenChaumAuthServer,
+    server_auth::auth_server::AuthServer,
+};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Code, Request};
+
+/// Log lines written by the subscriber under test.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Logs {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+/// Every representation under which a protocol value could leak into the logs.
+fn representations(bytes: &[u8]) -> Vec<String> {
+    let debug_bytes = format!("{:?}", &bytes[..8]);
+    vec![
+        BigInt::from_bytes_be(num_bigint::Sign::Plus, bytes).to_string(),
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+        STANDARD.encode(bytes),
+        // the `Debug` representation of the bytes, as logged by `{:?}` on a request
+        debug_bytes.trim_end_matches(']').to_string(),
+    ]
+}
+
+#[tokio::test]
+async fn test_no_protocol_values_are_logged() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    tracing::subscriber::set_global_default(
+        logging::subscriber("info,server=trace", LogFormat::Json, move || writer.clone()).unwrap(),
+    )
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .layer(RequestTracingLayer)
+            .add_service(AuthServer::new(PedersenChaumAuthServer::new()))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    // register and authenticate through the client
+    let cp = ChaumPedersen::default();
+    let x = cp.generate_random();
+    let mut client = ChaumPedersenAuthClient::new(&server_addr).await.unwrap();
+    client
+        .register_user("user_1", &x, &generate_salt(), &KdfParameters::default())
+        .await
+        .unwrap();
+    let session_id = client.authenticate_user("user_1", &x).await.unwrap();
+    let exponents = cp.commit(&x);
+
+    // answer a challenge with known values, and a wrong response
+    let mut raw_client = AuthClient::connect(server_addr).await.unwrap();
+    let commitment = cp.commit(&cp.generate_random());
+    let (r1, r2) = (
+        commitment.get_first_exponent().to_bytes_be().1,
+        commitment.get_second_exponent().to_bytes_be().1,
+    );
+    let challenge = raw_client
+        .create_authentication_challenge(AuthenticationChallengeRequest {
+            user: "user_1".to_string(),
+            r1: r1.clone(),
+            r2: r2.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let s = cp.generate_random().to_bytes_be().1;
+    let mut request = Request::new(AuthenticationAnswerRequest {
+        auth_id: challenge.auth_id.clone(),
+        s: s.clone(),
+    });
+    request
+        .metadata_mut()
+        .insert(REQUEST_ID_HEADER, "test-request-1".parse().unwrap());
+    let status = raw_client.verify_authentication(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert_eq!(
+        status.metadata().get(REQUEST_ID_HEADER).unwrap(),
+        "test-request-1"
+    );
+
+    let lines = logs.lines();
+    let raw_logs = lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    for value in [
+        exponents.get_first_exponent().to_bytes_be().1,
+        exponents.get_second_exponent().to_bytes_be().1,
+        r1,
+        r2,
+        challenge.c,
+        s,
+    ] {
+        for representation in representations(&value) {
+            assert!(
+                !raw_logs.contains(&representation),
+                "protocol value `{representation}` leaked into the logs:\n{raw_logs}"
+            );
+        }
+    }
+    for credential in [&session_id, &challenge.auth_id] {
+        assert!(
+            !raw_logs.contains(credential.as_str()),
+            "credential `{credential}` leaked into the logs:\n{raw_logs}"
+        );
+    }
+
+    // every request is logged in a span, with its request id, user and outcome
+    let finished_verification = lines
+        .iter()
+        .find(|line| {
+            line["fields"]["message"] == "Finished request"
+                && line["span"]["request_id"] == "test-request-1"
+        })
+        .unwrap_or_else(|| panic!("missing finished request in:\n{raw_logs}"));
+    assert_eq!(
+        finished_verification["span"]["rpc"],
+        "zkp_auth.Auth/VerifyAuthentication"
+    );
+    assert_eq!(finished_verification["span"]["user"], "user_1");
+    assert_eq!(finished_verification["span"]["outcome"], "Unauthenticated");
+    assert!(lines.iter().any(|line| {
+        line["fields"]["message"] == "User successfully registered"
+            && line["span"]["user"] == "user_1"
+            && line["fields"]["public_key"].is_string()
+    }));
+}
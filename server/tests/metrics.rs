@@ -0,0 +1,88 @@
+use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
+use client::{
+    client::{AuthZKPClient, ChaumPedersenAuthClient},
+    generate_salt, KdfParameters,
+};
+use server::{
+    metrics::{serve_metrics, Metrics, MetricsLayer, METRICS_PATH},
+    server::PedersenChaumAuthServer,
+    server_auth::auth_server::AuthServer,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// Serves a fresh in-memory server with metrics on local ports, returning the address of the service
+/// and of the metrics endpoint.
+async fn serve() -> (String, String) {
+    let metrics = Arc::new(Metrics::new());
+    let metrics_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_addr = metrics_listener.local_addr().unwrap().to_string();
+    tokio::spawn(serve_metrics(metrics_listener, metrics.clone()));
+
+    let service = PedersenChaumAuthServer::new().with_metrics(metrics.clone());
+    service.spawn_sweeper(Duration::from_millis(50));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .layer(MetricsLayer::new(metrics))
+            .add_service(AuthServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (server_addr, metrics_addr)
+}
+
+/// Fetches `path` from an HTTP server, returning the status line and the body of the response.
+async fn http_get(addr: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(format!("GET {path} HTTP/1.0\r\nHost: {addr}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let (server_addr, metrics_addr) = serve().await;
+    let mut client = ChaumPedersenAuthClient::new(&server_addr).await.unwrap();
+    let cp = ChaumPedersen::default();
+    let x = cp.generate_random();
+    client
+        .register_user("user_1", &x, &generate_salt(), &KdfParameters::default())
+        .await
+        .unwrap();
+    client.authenticate_user("user_1", &x).await.unwrap();
+    assert!(client
+        .authenticate_user("user_1", &cp.generate_random())
+        .await
+        .is_err());
+    // let the sweeper record the active challenges and sessions
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (status, body) = http_get(&metrics_addr, METRICS_PATH).await;
+    assert!(status.contains("200"), "{status}");
+    for expected in [
+        "zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"zkp_auth.Auth/Register\"} 1",
+        "zkp_auth_rpc_requests_total{outcome=\"Ok\",rpc=\"zkp_auth.Auth/VerifyAuthentication\"} 1",
+        "zkp_auth_rpc_requests_total{outcome=\"Unauthenticated\",rpc=\"zkp_auth.Auth/VerifyAuthentication\"} 1",
+        "zkp_auth_rpc_duration_seconds_count{rpc=\"zkp_auth.Auth/VerifyAuthentication\"} 2",
+        "zkp_auth_modpow_duration_seconds_count{operation=\"verify_authentication\"} 2",
+        "zkp_auth_modpow_duration_seconds_count{operation=\"verify_registration\"} 1",
+        "zkp_auth_active_challenges 0",
+        "zkp_auth_active_sessions 1",
+    ] {
+        assert!(body.contains(expected), "missing `{expected}` in:\n{body}");
+    }
+
+    let (status, _) = http_get(&metrics_addr, "/other").await;
+    assert!(status.contains("404"), "{status}");
+}
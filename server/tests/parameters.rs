@@ -0,0 +1,44 @@
+use chaum_pedersen::{
+    chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface},
+    parameters::MIN_BIT_SIZE,
+    Parameters,
+};
+use client::{
+    client::{AuthZKPClient, ChaumPedersenAuthClient},
+    generate_salt, KdfParameters,
+};
+use server::{server::PedersenChaumAuthServer, server_auth::auth_server::AuthServer};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// Serves a fresh in-memory server over `parameters` on a local port, returning its address.
+async fn serve(parameters: Parameters) -> String {
+    let service = PedersenChaumAuthServer::new().with_parameters(parameters);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(AuthServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    server_addr
+}
+
+#[tokio::test]
+async fn test_client_uses_server_parameters() {
+    let parameters = Parameters::generate(MIN_BIT_SIZE).unwrap();
+    let server_addr = serve(parameters.clone()).await;
+
+    let mut client = ChaumPedersenAuthClient::new(&server_addr).await.unwrap();
+    assert_eq!(client.parameters(), &parameters);
+
+    let x = ChaumPedersen::new(parameters).generate_random();
+    client
+        .register_user("user_1", &x, &generate_salt(), &KdfParameters::default())
+        .await
+        .unwrap();
+    let session_id = client.authenticate_user("user_1", &x).await.unwrap();
+    let credential = client.obtain_credential(&session_id).await.unwrap();
+    client.present_credential(&credential).await.unwrap();
+}
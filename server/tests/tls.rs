@@ -0,0 +1,187 @@
+use chaum_pedersen::chaum_pedersen::{ChaumPedersen, ChaumPedersenInterface};
+use client::{
+    client::{AuthZKPClient, ChaumPedersenAuthClient},
+    generate_salt,
+    tls::client_tls_config,
+    KdfParameters,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+};
+use server::{server::PedersenChaumAuthServer, server_auth::auth_server::AuthServer, tls};
+use std::{fs, path::PathBuf};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// Name of the server, in its certificate.
+const SERVER_NAME: &str = "zkp-auth.test";
+
+/// A certificate and its private key, written to PEM files.
+struct PemFiles {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+/// Temporary directory of the certificates of a test, removed once dropped.
+struct Certificates {
+    dir: PathBuf,
+}
+
+impl Certificates {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("zkp_auth_tls_{test}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn ca(&self, name: &str) -> (Certificate, PathBuf) {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(params).unwrap();
+        let ca_path = self.dir.join(format!("{name}.pem"));
+        fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+        (ca, ca_path)
+    }
+
+    fn issue(&self, ca: &Certificate, name: &str, usage: ExtendedKeyUsagePurpose) -> PemFiles {
+        let mut params = CertificateParams::new(vec![name.to_string()]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![usage];
+        let cert = Certificate::from_params(params).unwrap();
+        let pem_files = PemFiles {
+            cert_path: self.dir.join(format!("{name}.pem")),
+            key_path: self.dir.join(format!("{name}.key")),
+        };
+        fs::write(
+            &pem_files.cert_path,
+            cert.serialize_pem_with_signer(ca).unwrap(),
+        )
+        .unwrap();
+        fs::write(&pem_files.key_path, cert.serialize_private_key_pem()).unwrap();
+        pem_files
+    }
+}
+
+impl Drop for Certificates {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Serves a fresh in-memory server over TLS on a local port, returning its `https://` address.
+async fn serve(identity: &PemFiles, client_ca_path: Option<&PathBuf>) -> String {
+    let tls_config =
+        tls::server_tls_config(&identity.cert_path, &identity.key_path, client_ca_path).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = format!("https://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .tls_config(tls_config)
+            .unwrap()
+            .add_service(AuthServer::new(PedersenChaumAuthServer::new()))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    server_addr
+}
+
+/// Registers and authenticates a new user through a TLS connection, returning its session id.
+async fn register_and_login(
+    server_addr: &str,
+    user: &str,
+    ca_path: &PathBuf,
+    identity: Option<&PemFiles>,
+    domain_name: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let tls_config = client_tls_config(
+        Some(ca_path),
+        identity.map(|identity| (&identity.cert_path, &identity.key_path)),
+        domain_name,
+    )?;
+    let mut client = ChaumPedersenAuthClient::with_tls(server_addr, tls_config).await?;
+    let x = ChaumPedersen::default().generate_random();
+    client
+        .register_user(user, &x, &generate_salt(), &KdfParameters::default())
+        .await?;
+    client.authenticate_user(user, &x).await
+}
+
+#[tokio::test]
+async fn test_tls() {
+    let certificates = Certificates::new("tls");
+    let (ca, ca_path) = certificates.ca("server-ca");
+    let server_identity = certificates.issue(&ca, SERVER_NAME, ExtendedKeyUsagePurpose::ServerAuth);
+    let server_addr = serve(&server_identity, None).await;
+
+    // the server certificate names `SERVER_NAME`, rather than the address the client connects to
+    assert!(
+        register_and_login(&server_addr, "user_3", &ca_path, None, Some(SERVER_NAME))
+            .await
+            .is_ok()
+    );
+    assert!(
+        register_and_login(&server_addr, "user_1", &ca_path, None, None)
+            .await
+            .is_err()
+    );
+
+    // a server certificate issued by an unknown CA is rejected
+    let (_, other_ca_path) = certificates.ca("other-ca");
+    assert!(register_and_login(
+        &server_addr,
+        "user_2",
+        &other_ca_path,
+        None,
+        Some(SERVER_NAME)
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_mutual_tls() {
+    let certificates = Certificates::new("mutual_tls");
+    let (server_ca, server_ca_path) = certificates.ca("server-ca");
+    let (client_ca, client_ca_path) = certificates.ca("client-ca");
+    let server_identity =
+        certificates.issue(&server_ca, SERVER_NAME, ExtendedKeyUsagePurpose::ServerAuth);
+    let server_addr = serve(&server_identity, Some(&client_ca_path)).await;
+
+    let client_identity =
+        certificates.issue(&client_ca, "client", ExtendedKeyUsagePurpose::ClientAuth);
+    assert!(register_and_login(
+        &server_addr,
+        "user_1",
+        &server_ca_path,
+        Some(&client_identity),
+        Some(SERVER_NAME)
+    )
+    .await
+    .is_ok());
+
+    // clients without a certificate, or with one issued by another CA, are rejected
+    assert!(register_and_login(
+        &server_addr,
+        "user_3",
+        &server_ca_path,
+        None,
+        Some(SERVER_NAME)
+    )
+    .await
+    .is_err());
+    let other_client_identity = certificates.issue(
+        &server_ca,
+        "other-client",
+        ExtendedKeyUsagePurpose::ClientAuth,
+    );
+    assert!(register_and_login(
+        &server_addr,
+        "user_2",
+        &server_ca_path,
+        Some(&other_client_identity),
+        Some(SERVER_NAME)
+    )
+    .await
+    .is_err());
+}
@@ -0,0 +1,339 @@
+//! # Session Tokens
+//!
+//! This crate provides stateless, signed session tokens, issued by the authentication server on a
+//! successful login, and verifiable offline by any relying service holding the server's public keys.
+//!
+//! A token is made of five dot separated parts: `zkp.v1.<key id>.<claims>.<signature>`, where the
+//! claims are a base64url encoded JSON object and the signature is an Ed25519 signature over the first
+//! four parts. The key id designates the server key that signed the token, so that the server can
+//! rotate its signing key while tokens signed with a retired key remain verifiable until they expire,
+//! as long as relying services keep its public key.
+//!
+//! Tokens can't be revoked before they expire: relying services that must honour logouts immediately
+//! should validate session ids with the server instead.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+/// Prefix of every token, identifying the token format and its version.
+pub const TOKEN_PREFIX: &str = "zkp.v1";
+
+/// Size, in bytes, of a signing key file.
+pub const SIGNING_KEY_SIZE: usize = 32;
+
+/// Size, in bytes, of the public key hash prefix that makes up a key id.
+const KEY_ID_SIZE: usize = 8;
+
+/// Claims carried by a session token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// Name of the authenticated user.
+    pub sub: String,
+    /// Identifier of the server-side session the token was issued for.
+    pub sid: String,
+    /// Unix timestamp, in seconds, at which the token was issued.
+    pub iat: u64,
+    /// Unix timestamp, in seconds, after which the token is no longer valid.
+    pub exp: u64,
+    /// Scopes granted to the session.
+    pub scopes: Vec<String>,
+}
+
+/// Reasons a token fails verification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    /// The token is not a well formed session token.
+    Malformed,
+    /// The token was signed by a key the verifier does not know, e.g. a key retired long ago.
+    UnknownKey(String),
+    /// The token's signature does not verify, so it was forged or tampered with.
+    InvalidSignature,
+    /// The token has expired.
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "Failed to verify session token, malformed token"),
+            TokenError::UnknownKey(key_id) => write!(
+                f,
+                "Failed to verify session token, unknown signing key {key_id}"
+            ),
+            TokenError::InvalidSignature => {
+                write!(f, "Failed to verify session token, invalid signature")
+            }
+            TokenError::Expired => write!(f, "Failed to verify session token, token has expired"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Derives the id of a public key, as the base64url encoded prefix of its SHA3-256 hash.
+fn key_id(verifying_key: &VerifyingKey) -> String {
+    let digest = Sha3_256::digest(verifying_key.as_bytes());
+    URL_SAFE_NO_PAD.encode(&digest[..KEY_ID_SIZE])
+}
+
+/// A server key, signing session tokens.
+pub struct TokenSigningKey {
+    key_id: String,
+    signing_key: SigningKey,
+}
+
+impl TokenSigningKey {
+    /// Creates a signing key from its secret bytes.
+    pub fn from_bytes(bytes: &[u8; SIGNING_KEY_SIZE]) -> Self {
+        let signing_key = SigningKey::from_bytes(bytes);
+        Self {
+            key_id: key_id(&signing_key.verifying_key()),
+            signing_key,
+        }
+    }
+
+    /// Generates a new random signing key.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; SIGNING_KEY_SIZE];
+        OsRng.fill_bytes(&mut bytes);
+        Self::from_bytes(&bytes)
+    }
+
+    /// Loads a signing key from a file holding its `SIGNING_KEY_SIZE` raw secret bytes.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes: [u8; SIGNING_KEY_SIZE] = fs::read(path)?.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to load session token signing key, key must be {SIGNING_KEY_SIZE} bytes long"),
+            )
+        })?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Returns the secret bytes of the key, e.g. to write it to a key file.
+    pub fn to_bytes(&self) -> [u8; SIGNING_KEY_SIZE] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Returns the id of the key, embedded in every token it signs.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Returns the public key, to be handed to relying services.
+    pub fn verifying_key(&self) -> TokenVerifyingKey {
+        TokenVerifyingKey {
+            key_id: self.key_id.clone(),
+            verifying_key: self.signing_key.verifying_key(),
+        }
+    }
+
+    /// Signs the claims into a session token.
+    pub fn sign(&self, claims: &SessionClaims) -> String {
+        let claims = serde_json::to_vec(claims).expect("Failed to serialize session claims");
+        let signed_part = format!(
+            "{TOKEN_PREFIX}.{}.{}",
+            self.key_id,
+            URL_SAFE_NO_PAD.encode(claims)
+        );
+        let signature = self.signing_key.sign(signed_part.as_bytes());
+        format!(
+            "{signed_part}.{}",
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+}
+
+/// A server public key, verifying session tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenVerifyingKey {
+    key_id: String,
+    verifying_key: VerifyingKey,
+}
+
+impl TokenVerifyingKey {
+    /// Creates a public key from its bytes, as published by the server.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TokenError> {
+        let bytes = bytes.try_into().map_err(|_| TokenError::Malformed)?;
+        let verifying_key = VerifyingKey::from_bytes(bytes).map_err(|_| TokenError::Malformed)?;
+        Ok(Self {
+            key_id: key_id(&verifying_key),
+            verifying_key,
+        })
+    }
+
+    /// Returns the bytes of the public key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.verifying_key.to_bytes().to_vec()
+    }
+
+    /// Returns the id of the key.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+}
+
+/// Verifies session tokens offline, against a set of trusted server public keys.
+#[derive(Clone, Debug, Default)]
+pub struct TokenVerifier {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl TokenVerifier {
+    /// Creates a verifier trusting no key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts tokens signed with the given key.
+    pub fn with_key(mut self, key: TokenVerifyingKey) -> Self {
+        self.add_key(key);
+        self
+    }
+
+    /// Trusts tokens signed with the given key, e.g. after the server rotated its signing key.
+    pub fn add_key(&mut self, key: TokenVerifyingKey) {
+        self.keys.insert(key.key_id, key.verifying_key);
+    }
+
+    /// Stops trusting tokens signed with the key of the given id, once it has been retired for longer
+    /// than the lifetime of a token.
+    pub fn remove_key(&mut self, key_id: &str) {
+        self.keys.remove(key_id);
+    }
+
+    /// Verifies a session token at time `now`, a Unix timestamp in seconds.
+    ///
+    /// # Returns
+    /// A `Result` containing the token's claims, or the `TokenError` explaining why it is not valid.
+    pub fn verify(&self, token: &str, now: u64) -> Result<SessionClaims, TokenError> {
+        let (signed_part, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+        let (key_id, claims) = signed_part
+            .strip_prefix(TOKEN_PREFIX)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| rest.split_once('.'))
+            .ok_or(TokenError::Malformed)?;
+
+        let verifying_key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| TokenError::UnknownKey(key_id.to_string()))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok())
+            .ok_or(TokenError::Malformed)?;
+        verifying_key
+            .verify_strict(signed_part.as_bytes(), &signature)
+            .map_err(|_| TokenError::InvalidSignature)?;
+
+        let claims: SessionClaims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .ok()
+            .and_then(|claims| serde_json::from_slice(&claims).ok())
+            .ok_or(TokenError::Malformed)?;
+        if claims.exp <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> SessionClaims {
+        SessionClaims {
+            sub: "user".to_string(),
+            sid: "session_id".to_string(),
+            iat: 500,
+            exp: 1_000,
+            scopes: vec!["profile".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_token_verifies_until_expiry() {
+        let signing_key = TokenSigningKey::generate();
+        let verifier = TokenVerifier::new().with_key(signing_key.verifying_key());
+        let token = signing_key.sign(&claims());
+
+        assert!(token.starts_with(&format!("{TOKEN_PREFIX}.{}.", signing_key.key_id())));
+        assert_eq!(verifier.verify(&token, 999), Ok(claims()));
+        assert_eq!(verifier.verify(&token, 1_000), Err(TokenError::Expired));
+    }
+
+    #[test]
+    fn test_tampered_token_fails_verification() {
+        let signing_key = TokenSigningKey::generate();
+        let verifier = TokenVerifier::new().with_key(signing_key.verifying_key());
+        let token = signing_key.sign(&claims());
+
+        let forged_claims = URL_SAFE_NO_PAD.encode(
+            serde_json::to_vec(&SessionClaims {
+                sub: "admin".to_string(),
+                ..claims()
+            })
+            .unwrap(),
+        );
+        let parts = token.split('.').collect::<Vec<_>>();
+        let forged_token = [parts[0], parts[1], parts[2], &forged_claims, parts[4]].join(".");
+        assert_eq!(
+            verifier.verify(&forged_token, 500),
+            Err(TokenError::InvalidSignature)
+        );
+
+        assert_eq!(
+            verifier.verify("not a token", 500),
+            Err(TokenError::Malformed)
+        );
+        assert_eq!(
+            verifier.verify(&token[..token.len() - 2], 500),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old_key = TokenSigningKey::generate();
+        let new_key = TokenSigningKey::generate();
+        assert_ne!(old_key.key_id(), new_key.key_id());
+
+        // tokens of the retired key remain valid while the verifier trusts it
+        let mut verifier = TokenVerifier::new()
+            .with_key(TokenVerifyingKey::from_bytes(&old_key.verifying_key().to_bytes()).unwrap())
+            .with_key(new_key.verifying_key());
+        let old_token = old_key.sign(&claims());
+        let new_token = new_key.sign(&claims());
+        assert!(verifier.verify(&old_token, 500).is_ok());
+        assert!(verifier.verify(&new_token, 500).is_ok());
+
+        verifier.remove_key(old_key.key_id());
+        assert_eq!(
+            verifier.verify(&old_token, 500),
+            Err(TokenError::UnknownKey(old_key.key_id().to_string()))
+        );
+        assert!(verifier.verify(&new_token, 500).is_ok());
+    }
+
+    #[test]
+    fn test_signing_key_loads_from_file() {
+        let signing_key = TokenSigningKey::generate();
+        let path = std::env::temp_dir().join(format!("session-token-{}.key", signing_key.key_id()));
+        fs::write(&path, signing_key.to_bytes()).unwrap();
+
+        let loaded_key = TokenSigningKey::load(&path).unwrap();
+        assert_eq!(loaded_key.key_id(), signing_key.key_id());
+
+        fs::write(&path, [0u8; 16]).unwrap();
+        assert_eq!(
+            TokenSigningKey::load(&path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(path).unwrap();
+    }
+}